@@ -1,12 +1,11 @@
 use std::sync::atomic::{AtomicU64, Ordering};
 use std::sync::{Arc, Mutex};
 
-use lazy_static::lazy_static;
 use log::*;
 use rusqlite::{params, Connection};
 use structopt::StructOpt;
 
-use jbhttp::auth::AuthError;
+use jbhttp::auth::{AuthError, Challenge};
 use jbhttp::prelude::*;
 use jbhttp::router::Router;
 use jbhttp::server::TcpServer;
@@ -27,7 +26,7 @@ fn setup_logging(verbosity: usize) {
 fn main() {
     let opt = Opt::from_args();
     setup_logging(opt.verbose);
-    setup_db();
+    let db = setup_db();
 
     let bind = format!("0.0.0.0:{}", opt.port);
     let mut server = TcpServer::new(
@@ -42,7 +41,7 @@ fn main() {
         // An application is a request handler, which accepts Request<Vec<u8>>
         // and returns Response<Vec<u8>>. Request and Response can hold other
         // payload types, but Server can only deal with bytes.
-        app(),
+        app(db),
     )
     .unwrap();
     info!("listening on {}", &bind);
@@ -70,7 +69,7 @@ fn main() {
 // It can be used to keep track of whatever, for example, the Context
 // type referred to here is implemented later, it is used to keep track of
 // authentication and other details.
-fn app() -> impl Handler<Vec<u8>, Vec<u8>, Vec<u8>, Context> {
+fn app(db: Db) -> impl Handler<Vec<u8>, Vec<u8>, Vec<u8>, Context> {
     // The app is built up in layers. PersonApi is a REST endpoint which handles
     // GET, POST and DELETE operations for Person. It takes Request<Person> and returns
     // Result<Response<Person>, Response<ApiError>>, or Res<Person, ApiError> for short.
@@ -87,47 +86,51 @@ fn app() -> impl Handler<Vec<u8>, Vec<u8>, Vec<u8>, Context> {
     //  - An OkFilter can modify (or replace) Ok responses
     //  - An ErrFilter can modify (or replace) Err responses
     //  - A ResFilter can modify (or replace) both Ok and Err responses
-    let person_api = PersonApi
-        // Api is a convenience trait to help implement handlers with methods
-        // that map to HTTP verbs - see the implementation of PersonApi below.
-        // The handler method just constructs a Handler for types implementing Api.
-        .handler()
-        // returns impl Handler<Person, Person, ApiError, Context>
-        //
-        // jbhttp includes filters for automatic serialization based on Accept
-        // request headers, and deserialization based on Content-Type request headers.
-        // To use them, Serialize and Deserialize traits must be implemented for
-        // Person for the relevant media types. The following methods are
-        // provided for any type implementing Handler:
-        //  - serialized: Serializes Ok response payloads
-        //  - serialized_error: Serializes Err response payloads
-        //  - deserialized:  Deserializes request payloads
-        //  - serdeserialized: Deserializes request payloads and serializes
-        //    Ok response payloads
-        .serdeserialized()
-        // returns impl Handler<Vec<u8>, Vec<u8>, ApiError, Context>
-        //
-        // Media types are represented as Rust types (unit structs).
-        // To support application/json for PersonApi, the following must be implemented:
-        //  - jbhttp::content::Serialize<ApplicationJson> for Person
-        //  - jbhttp::content::Deserialize<Person> for ApplicationJson
-        //
-        // With the json crate feature enabled, since our Person struct
-        // derives serde::Serialize and serde::Deserialize, ApplicationJson
-        // de/serialization traits are automatically implemented for Person.
-        //
-        // with_media_type can be called multiple times; order matters,
-        // if a client accepts multiple types equally, like Accept: */*, the first
-        // acceptable media type is used.
-        .with_media_type::<ApplicationJson>();
+    let person_api = |db: Db| {
+        PersonApi { db }
+            // Api is a convenience trait to help implement handlers with methods
+            // that map to HTTP verbs - see the implementation of PersonApi below.
+            // The handler method just constructs a Handler for types implementing Api.
+            .handler()
+            // returns impl Handler<Person, Person, ApiError, Context>
+            //
+            // jbhttp includes filters for automatic serialization based on Accept
+            // request headers, and deserialization based on Content-Type request headers.
+            // To use them, Serialize and Deserialize traits must be implemented for
+            // Person for the relevant media types. The following methods are
+            // provided for any type implementing Handler:
+            //  - serialized: Serializes Ok response payloads
+            //  - serialized_error: Serializes Err response payloads
+            //  - deserialized:  Deserializes request payloads
+            //  - serdeserialized: Deserializes request payloads and serializes
+            //    Ok response payloads
+            .serdeserialized()
+            // returns impl Handler<Vec<u8>, Vec<u8>, ApiError, Context>
+            //
+            // Media types are represented as Rust types (unit structs).
+            // To support application/json for PersonApi, the following must be implemented:
+            //  - jbhttp::content::Serialize<ApplicationJson> for Person
+            //  - jbhttp::content::Deserialize<Person> for ApplicationJson
+            //
+            // With the json crate feature enabled, since our Person struct
+            // derives serde::Serialize and serde::Deserialize, ApplicationJson
+            // de/serialization traits are automatically implemented for Person.
+            //
+            // with_media_type can be called multiple times; order matters,
+            // if a client accepts multiple types equally, like Accept: */*, the first
+            // acceptable media type is used.
+            .with_media_type::<ApplicationJson>()
+    };
 
     // Router associates URLs with handlers, and produces 404s response otherwise.
     Router::new()
         // Route's have limited pattern matching, see Router documentation for details.
-        // For our case here, we want to match an id parameter; as of now,
-        // Router does not do parameter parsing, so id will be a String
-        // not, necessarily numeric.
-        .with_route("/person/?id", person_api)
+        // New persons are POSTed to /person/, without an id.
+        .with_route("/person/", person_api(db.clone()))
+        // For existing persons, we want to match a numeric id parameter; with
+        // a typed param, Router parses it as an i64 before PersonApi runs, and
+        // responds 400 if it isn't one.
+        .with_route("/person/?id:i64", person_api(db))
         //
         // authenticated is a convenience method on Handler's which constructs
         // a specialized request filter for authentication.
@@ -143,6 +146,9 @@ fn app() -> impl Handler<Vec<u8>, Vec<u8>, Vec<u8>, Context> {
         // while a return of Err(some_response) pre-empts the request and
         // returns some_response.
         .authenticated(authenticate)
+        // 401 responses should tell clients how to authenticate, with a
+        // WWW-Authenticate header.
+        .with_challenge(Challenge::new("ApiKey").with_param("header", "X-Api-Key"))
         //
         // An ErrorFilter takes a callable with signature:
         // Fn(Response<FE>, &mut C) -> Response<E>
@@ -163,6 +169,9 @@ fn app() -> impl Handler<Vec<u8>, Vec<u8>, Vec<u8>, Context> {
         .serialized_error()
         // returns impl Handler<Vec<u8>, Vec<u8>, Vec<u8>, Context>
         .with_media_type::<ApplicationJson>()
+        // If the client doesn't accept any of the error media types, errors
+        // are still serialized as JSON, rather than turning them into 406s.
+        .with_fallback_media_type::<ApplicationJson>()
         //
         // res_filter constructs a ResFilter from a callable of type
         // Fn(Res<O, E>, &mut Context) -> Res<FO, FE>
@@ -193,7 +202,7 @@ fn app() -> impl Handler<Vec<u8>, Vec<u8>, Vec<u8>, Context> {
     //                      Router ∧―――――>―――――V [404]
     //               Authenticator ∧―――――>―――――V [401]
     // ErrorFilter(generate_error) |     |     V []
-    //    MediaTypeErrorSerializer |     |     V [406, 500]
+    //    MediaTypeErrorSerializer |     |     V [500]
     //   ResFilter(add_request_id) |     V     V []
     //                   TcpServer ∧―――――>―――――V [400, 500]
     //
@@ -213,7 +222,12 @@ struct Person {
     age: u16,
 }
 
-struct PersonApi;
+// Api implementors can hold shared state, like this database connection,
+// in their own fields. Handlers are shared between threads, so the state
+// must be Send + Sync, hence Arc<Mutex<...>>.
+struct PersonApi {
+    db: Db,
+}
 
 impl PersonApi {
     fn error(&self, code: u16, reason: &str, context: &Context) -> Response<ApiError> {
@@ -226,43 +240,50 @@ impl PersonApi {
 // default implementations returning 405 for unimplemented verbs.
 // The Api trait provides a handler() method, it does not impl Handler itself.
 //
+// The api! macro implements Api without repeating the full method signatures;
+// parameter and return types are filled in from the Api type parameters, so
+// request is a Request<Person>, context a &mut Context, and each method
+// returns Res<Person, ApiError>.
+//
 // This is just a basic CRUD REST API (well, without the U) for demonstration.
-impl Api<Person, Person, ApiError, Context> for PersonApi {
-    fn get(&self, request: Request<Person>, context: &mut Context) -> Res<Person, ApiError> {
-        // All params are String, so it must be parsed here
-        let id = match request.params.get_any("id") {
-            Some(id) => match str::parse::<i64>(&id) {
-                Ok(id) => id,
-                _ => return Err(self.error(400, "id must be an integer", context)),
-            },
-            None => return Err(self.error(400, "missing parameter id", context)),
-        };
-        let conn = CONNECTION.lock().unwrap();
-        match Person::select(&conn, id) {
-            Some(person) => Ok(Response::new(200).with_payload(person)),
-            None => Err(self.error(404, "resource not found", context)),
+api! {
+    impl Api<Person, Person, ApiError, Context> for PersonApi {
+        fn get(&self, request, context) {
+            // The id was parsed by Router, from the typed route param
+            let id = match request.param::<i64>("id") {
+                Some(id) => *id,
+                None => return Err(self.error(400, "missing parameter id", context)),
+            };
+            let conn = self.db.lock().unwrap();
+            match Person::select(&conn, id) {
+                Some(person) => Ok(Response::new(200).with_payload(person)),
+                None => Err(self.error(404, "resource not found", context)),
+            }
         }
-    }
-    fn post(&self, request: Request<Person>, context: &mut Context) -> Res<Person, ApiError> {
-        let mut person = match request.payload {
-            Some(person) => person,
-            _ => return Err(self.error(400, "expected request body", context)),
-        };
-        if person.id != 0 {
-            return Err(self.error(400, "id must be 0", context));
+        fn post(&self, request, context) {
+            let mut person = match request.payload {
+                Some(person) => person,
+                _ => return Err(self.error(400, "expected request body", context)),
+            };
+            if person.id != 0 {
+                return Err(self.error(400, "id must be 0", context));
+            }
+            let conn = self.db.lock().unwrap();
+            person.insert(&conn);
+            if let Some(User(username)) = &context.user {
+                info!("person {} created by {}", person.id, username);
+            }
+            Ok(Response::new(201).with_header("Location", &format!("/person/{}", person.id)))
         }
-        let conn = CONNECTION.lock().unwrap();
-        person.insert(&conn);
-        Ok(Response::new(201).with_header("Location", &format!("/person/{}", person.id)))
-    }
-    fn delete(&self, request: Request<Person>, context: &mut Context) -> Res<Person, ApiError> {
-        match self.get(request, context) {
-            Ok(response) => {
-                let conn = CONNECTION.lock().unwrap();
-                response.payload.unwrap().delete(&conn);
-                Ok(Response::new(204))
+        fn delete(&self, request, context) {
+            match self.get(request, context) {
+                Ok(response) => {
+                    let conn = self.db.lock().unwrap();
+                    response.payload.unwrap().delete(&conn);
+                    Ok(Response::new(204))
+                }
+                Err(response) => Err(response),
             }
-            Err(response) => Err(response),
         }
     }
 }
@@ -291,6 +312,11 @@ impl Default for Context {
     }
 }
 
+// Contexts must also implement Deferrals, which lets handlers defer work until
+// after the response is written; this app doesn't use it, so the default
+// (no deferred work) is fine.
+impl Deferrals for Context {}
+
 impl Context {
     fn set_user(&mut self, user: User) {
         self.user = Some(user)
@@ -372,14 +398,12 @@ struct Opt {
 
 // Everything beyond this point is just the most terrible, minimal
 // sqlite setup to get the example working with no external DB setup.
-lazy_static! {
-    static ref CONNECTION: Arc<Mutex<Connection>> =
-        Arc::new(Mutex::new(Connection::open_in_memory().unwrap()));
-}
+type Db = Arc<Mutex<Connection>>;
 
-fn setup_db() {
-    let conn = CONNECTION.lock().unwrap();
+fn setup_db() -> Db {
+    let conn = Connection::open_in_memory().unwrap();
     conn.execute(CREATE_TABLE_PERSON, []).unwrap();
+    Arc::new(Mutex::new(conn))
 }
 
 const CREATE_TABLE_PERSON: &str = "CREATE TABLE person
@@ -0,0 +1,82 @@
+//! Count the allocations made by the server thread per request, with and
+//! without the buffer pool, for kept-alive requests on one connection.
+use std::alloc::{GlobalAlloc, Layout, System};
+use std::cell::Cell;
+use std::io::prelude::*;
+use std::net::{TcpListener, TcpStream};
+use std::time::Instant;
+
+use jbhttp::prelude::*;
+use jbhttp::server::TcpServer;
+
+struct CountingAllocator;
+
+thread_local! {
+    static ALLOCATIONS: Cell<usize> = const { Cell::new(0) };
+}
+
+unsafe impl GlobalAlloc for CountingAllocator {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        ALLOCATIONS.with(|n| n.set(n.get() + 1));
+        System.alloc(layout)
+    }
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        System.dealloc(ptr, layout)
+    }
+}
+
+#[global_allocator]
+static ALLOCATOR: CountingAllocator = CountingAllocator;
+
+const REQUESTS: usize = 10_000;
+
+fn hello(_: RawRequest, _: &mut ()) -> Res<Vec<u8>, Vec<u8>> {
+    Ok(Response::new(200).with_payload(vec![b'x'; 4096]))
+}
+
+fn measure(buffer_pool: bool) {
+    let mut server = TcpServer::builder(hello)
+        .listener(TcpListener::bind("127.0.0.1:0").unwrap())
+        .buffer_pool(buffer_pool)
+        .build()
+        .unwrap();
+    let addr = server.local_addr().unwrap();
+    let client = std::thread::spawn(move || {
+        let mut stream = TcpStream::connect(addr).unwrap();
+        stream.set_nodelay(true).unwrap();
+        let mut buf = vec![0; 8192];
+        for i in 0..REQUESTS {
+            let connection = if i + 1 == REQUESTS {
+                "close"
+            } else {
+                "keep-alive"
+            };
+            let request = format!("GET / HTTP/1.1\r\nConnection: {}\r\n\r\n", connection);
+            stream.write_all(request.as_bytes()).unwrap();
+            // The response ends with the 4096 byte payload
+            let mut read = vec![];
+            while !(read.len() > 4096 && read.ends_with(b"xxxx")) {
+                let n = stream.read(&mut buf).unwrap();
+                read.extend(&buf[..n]);
+            }
+        }
+    });
+    // Served on this thread, with a single thread
+    let before = ALLOCATIONS.with(Cell::get);
+    let start = Instant::now();
+    Server::<()>::serve_one(&mut server).unwrap();
+    let elapsed = start.elapsed();
+    let allocations = ALLOCATIONS.with(Cell::get) - before;
+    client.join().unwrap();
+    println!(
+        "buffer_pool({}): {:.1} allocations/request, {:.0} requests/s",
+        buffer_pool,
+        allocations as f64 / REQUESTS as f64,
+        REQUESTS as f64 / elapsed.as_secs_f64()
+    );
+}
+
+fn main() {
+    measure(false);
+    measure(true);
+}
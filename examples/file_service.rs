@@ -43,13 +43,12 @@ fn main() {
 
     let handler = DirectoryHandler::new(&opt.dir).unwrap();
     let serve_dir = handler.root.clone();
-    let mut server = TcpServer::new(
-        &format!("0.0.0.0:{}", opt.port),
-        opt.threads,
-        timeout(opt.timeout),
-        handler,
-    )
-    .unwrap();
+    let mut server = TcpServer::builder(handler)
+        .bind(&format!("0.0.0.0:{}", opt.port))
+        .threads(opt.threads)
+        .timeout(timeout(opt.timeout))
+        .build()
+        .unwrap();
     println!(
         "Serving {0}, check out: http://localhost:{1}",
         &serve_dir.to_string_lossy(),
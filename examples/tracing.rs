@@ -0,0 +1,33 @@
+use std::time::Duration;
+
+use tracing_subscriber::fmt::format::FmtSpan;
+
+use jbhttp::prelude::*;
+use jbhttp::server::TcpServer;
+
+fn hello(req: RawRequest, _: &mut ()) -> Res<Vec<u8>, Vec<u8>> {
+    // Handler events are recorded in the request span
+    tracing::info!(query = %req.query, "saying hello");
+    Ok(Response::new(200).with_payload(b"Hello!".to_vec()))
+}
+
+fn main() {
+    // With the tracing feature, TcpServer opens a "connection" span for each
+    // connection, and a "request" span for each request in it, with method,
+    // path, status and duration_ms. The subscriber also collects the log
+    // records of jbhttp, as events in those spans, and prints span durations
+    // when they close.
+    tracing_subscriber::fmt()
+        .with_max_level(tracing::Level::DEBUG)
+        .with_span_events(FmtSpan::CLOSE)
+        .init();
+
+    let mut server = TcpServer::builder(hello)
+        .bind("127.0.0.1:8080")
+        .threads(4)
+        .timeout(Duration::from_secs(10))
+        .build()
+        .unwrap();
+    tracing::info!("listening on 127.0.0.1:8080");
+    Server::<()>::serve_forever(&mut server);
+}
@@ -9,6 +9,35 @@ fn not_implemented<O, E>() -> Res<O, E> {
 
 /// Implement get, post, etc. to handle requests with the corresponding
 /// HTTP verb. Unimplemented methods return 405.
+///
+/// `handler` moves the implementor into the handler, so it can hold shared
+/// state (a connection pool, configuration, etc.) in its own fields; use
+/// `Arc` and `Mutex` as needed, since requests may be handled concurrently.
+///
+/// # Example
+/// ```
+/// use std::sync::{Arc, Mutex};
+/// use jbhttp::prelude::*;
+///
+/// struct Counter {
+///     count: Arc<Mutex<u64>>,
+/// }
+///
+/// impl Api<Vec<u8>, String, (), ()> for Counter {
+///     fn post(&self, _request: RawRequest, _context: &mut ()) -> Res<String, ()> {
+///         let mut count = self.count.lock().unwrap();
+///         *count += 1;
+///         Ok(Response::new(200).with_payload(count.to_string()))
+///     }
+/// }
+///
+/// let count = Arc::new(Mutex::new(0));
+/// let handler = Counter { count: count.clone() }.handler();
+/// let mut request = Request::default();
+/// request.method = Method::POST;
+/// handler.handle(request, &mut ()).unwrap();
+/// assert_eq!(*count.lock().unwrap(), 1);
+/// ```
 pub trait Api<I, O, E, C> {
     fn get(&self, _request: Request<I>, _context: &mut C) -> Res<O, E> {
         not_implemented()
@@ -42,3 +71,133 @@ pub trait Api<I, O, E, C> {
         ))
     }
 }
+
+/// Implement [`Api`] with only the verbs you need, without writing out
+/// the full method signatures. The trait and its type parameters
+/// (request, response, error and context types) stay explicit;
+/// parameter types and the return type are filled in from them.
+///
+/// ```text
+/// api! {
+///     impl Api<I, O, E, C> for T {
+///         fn get(&self, request, context) { ... }
+///     }
+/// }
+/// ```
+/// expands to:
+/// ```text
+/// impl Api<I, O, E, C> for T {
+///     fn get(&self, request: Request<I>, context: &mut C) -> Res<O, E> { ... }
+/// }
+/// ```
+///
+/// # Example
+/// ```
+/// use jbhttp::prelude::*;
+///
+/// struct Greeter;
+///
+/// api! {
+///     impl Api<Vec<u8>, String, (), ()> for Greeter {
+///         fn get(&self, request, _context) {
+///             Ok(Response::new(200).with_payload(format!("hello {}", request.path)))
+///         }
+///     }
+/// }
+///
+/// let handler = Greeter.handler();
+/// let response = handler.handle(Request::default(), &mut ()).unwrap();
+/// assert_eq!(response.payload, Some("hello /".to_string()));
+///
+/// let mut request = Request::default();
+/// request.method = Method::DELETE;
+/// assert_eq!(handler.handle(request, &mut ()).unwrap_err().status_code, 405);
+/// ```
+#[macro_export]
+macro_rules! api {
+    (
+        impl Api<$i:ty, $o:ty, $e:ty, $c:ty> for $t:ty {
+            $(
+                fn $verb:ident(&$self:ident, $request:pat, $context:pat) $body:block
+            )*
+        }
+    ) => {
+        impl $crate::api::Api<$i, $o, $e, $c> for $t {
+            $(
+                fn $verb(
+                    &$self,
+                    $request: $crate::request::Request<$i>,
+                    $context: &mut $c,
+                ) -> $crate::handler::Res<$o, $e> $body
+            )*
+        }
+    };
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::handler::Handler;
+    use std::collections::HashMap;
+    use std::sync::{Arc, Mutex};
+
+    /// Stands in for a database connection pool.
+    #[derive(Default)]
+    struct Pool {
+        rows: Mutex<HashMap<String, String>>,
+    }
+
+    struct KeyValueApi {
+        pool: Arc<Pool>,
+    }
+
+    api! {
+        impl Api<String, String, (), ()> for KeyValueApi {
+            fn get(&self, request, _context) {
+                match self.pool.rows.lock().unwrap().get(&request.path) {
+                    Some(value) => Ok(Response::new(200).with_payload(value.clone())),
+                    None => Err(Response::new(404)),
+                }
+            }
+            fn put(&self, request, _context) {
+                let value = request.payload.unwrap_or_default();
+                self.pool.rows.lock().unwrap().insert(request.path, value);
+                Ok(Response::new(204))
+            }
+        }
+    }
+
+    fn request(method: Method, path: &str, payload: Option<&str>) -> Request<String> {
+        Request {
+            method,
+            path: path.to_string(),
+            payload: payload.map(|p| p.to_string()),
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn test_api_with_shared_state() {
+        let pool = Arc::new(Pool::default());
+        let handler = Arc::new(KeyValueApi { pool: pool.clone() }.handler());
+
+        let writer = {
+            let handler = handler.clone();
+            std::thread::spawn(move || {
+                let put = request(Method::PUT, "/a", Some("1"));
+                handler.handle(put, &mut ()).unwrap();
+            })
+        };
+        writer.join().unwrap();
+
+        let response = handler
+            .handle(request(Method::GET, "/a", None), &mut ())
+            .unwrap();
+        assert_eq!(response.payload, Some("1".to_string()));
+        assert_eq!(pool.rows.lock().unwrap().len(), 1);
+        let response = handler.handle(request(Method::GET, "/b", None), &mut ());
+        assert_eq!(response.unwrap_err().status_code, 404);
+        let response = handler.handle(request(Method::DELETE, "/a", None), &mut ());
+        assert_eq!(response.unwrap_err().status_code, 405);
+    }
+}
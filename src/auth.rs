@@ -1,28 +1,123 @@
+use std::fmt;
+
 use crate::handler::{Handler, Res};
 use crate::request::Request;
 use crate::response::Response;
 
+/// An authentication challenge, sent in *WWW-Authenticate* headers of 401
+/// responses to tell clients how to authenticate.
+///
+/// # Example
+/// ```
+/// use jbhttp::auth::Challenge;
+///
+/// let challenge = Challenge::bearer()
+///     .with_param("realm", "api")
+///     .with_param("error", "invalid_token");
+/// assert_eq!(challenge.to_string(), r#"Bearer realm="api", error="invalid_token""#);
+/// ```
+#[derive(Debug, Clone, PartialEq)]
+pub struct Challenge {
+    scheme: String,
+    params: Vec<(String, String)>,
+}
+
+impl Challenge {
+    pub fn new(scheme: &str) -> Self {
+        Self {
+            scheme: scheme.to_string(),
+            params: vec![],
+        }
+    }
+    /// `Basic realm="<realm>"`
+    pub fn basic(realm: &str) -> Self {
+        Self::new("Basic").with_param("realm", realm)
+    }
+    /// `Bearer`, add `realm`, `error`, `scope`, etc. with `with_param`.
+    pub fn bearer() -> Self {
+        Self::new("Bearer")
+    }
+    /// Add an auth parameter; the value is quoted.
+    pub fn with_param(mut self, name: &str, value: &str) -> Self {
+        self.params.push((name.to_string(), value.to_string()));
+        self
+    }
+}
+
+impl fmt::Display for Challenge {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.scheme)?;
+        for (i, (name, value)) in self.params.iter().enumerate() {
+            let sep = if i == 0 { " " } else { ", " };
+            let value = value.replace('\\', "\\\\").replace('"', "\\\"");
+            write!(f, "{}{}=\"{}\"", sep, name, value)?;
+        }
+        Ok(())
+    }
+}
+
 #[derive(Debug)]
 pub struct AuthError {
     reason: String,
+    status_code: u16,
+    challenges: Vec<Challenge>,
 }
 
 impl AuthError {
+    /// Authentication failed or is missing, responds with 401.
     pub fn new(reason: &str) -> Self {
         Self {
             reason: reason.to_string(),
+            status_code: 401,
+            challenges: vec![],
         }
     }
+    /// Authenticated, but not allowed, responds with 403.
+    pub fn forbidden(reason: &str) -> Self {
+        Self {
+            status_code: 403,
+            ..Self::new(reason)
+        }
+    }
+    /// Add a challenge to the response, instead of the
+    /// [`Authenticator`]'s default challenges.
+    pub fn with_challenge(mut self, challenge: Challenge) -> Self {
+        self.challenges.push(challenge);
+        self
+    }
 }
 
+impl fmt::Display for AuthError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> Result<(), fmt::Error> {
+        write!(f, "authentication error: {}", &self.reason)
+    }
+}
+
+impl std::error::Error for AuthError {}
+
+/// Authenticates requests before passing them to the handler. Requests
+/// failing authentication get a 401 (or 403) response, with a
+/// *WWW-Authenticate* header for each challenge.
 pub struct Authenticator<F, H> {
     handler: H,
     fauth: F,
+    challenges: Vec<Challenge>,
 }
 
 impl<F, H> Authenticator<F, H> {
     pub fn new(fauth: F, handler: H) -> Self {
-        Self { handler, fauth }
+        Self {
+            handler,
+            fauth,
+            challenges: vec![],
+        }
+    }
+    /// Add a default challenge to 401 responses, used unless the
+    /// [`AuthError`] has its own. Can be called more than once to
+    /// offer several schemes.
+    pub fn with_challenge(mut self, challenge: Challenge) -> Self {
+        self.challenges.push(challenge);
+        self
     }
 }
 
@@ -37,7 +132,90 @@ where
     fn handle(&self, request: Request<I>, context: &mut C) -> Res<O, E> {
         match (self.fauth)(&request, context) {
             Ok(()) => self.handler.handle(request, context),
-            Err(_) => Err(Response::new(401)),
+            Err(e) => {
+                let challenges = if e.challenges.is_empty() && e.status_code == 401 {
+                    &self.challenges
+                } else {
+                    &e.challenges
+                };
+                let mut response = Response::new(e.status_code);
+                for challenge in challenges {
+                    response = response.with_header("WWW-Authenticate", &challenge.to_string());
+                }
+                Err(response)
+            }
         }
     }
 }
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::request::RawRequest;
+
+    fn hello(_req: RawRequest, _context: &mut ()) -> Res<Vec<u8>, Vec<u8>> {
+        Ok(Response::new(200))
+    }
+
+    fn challenges(response: &Response<Vec<u8>>) -> Vec<String> {
+        let bytes = response.to_bytes();
+        std::str::from_utf8(&bytes)
+            .unwrap()
+            .lines()
+            .filter_map(|l| l.strip_prefix("WWW-Authenticate: "))
+            .map(|l| l.to_string())
+            .collect()
+    }
+
+    #[test]
+    fn test_challenge_format() {
+        assert_eq!(Challenge::basic("x").to_string(), r#"Basic realm="x""#);
+        assert_eq!(Challenge::bearer().to_string(), "Bearer");
+        assert_eq!(
+            Challenge::basic(r#"say "hi" \ bye"#).to_string(),
+            r#"Basic realm="say \"hi\" \\ bye""#
+        );
+    }
+
+    #[test]
+    fn test_authenticator_default_challenges() {
+        let handler = Authenticator::new(
+            |_: &RawRequest, _: &mut ()| Err(AuthError::new("no credentials")),
+            hello,
+        )
+        .with_challenge(Challenge::bearer().with_param("realm", "api"))
+        .with_challenge(Challenge::basic("api"));
+        let response = handler.handle(Request::default(), &mut ()).unwrap_err();
+        assert_eq!(response.status_code, 401);
+        assert_eq!(
+            challenges(&response),
+            vec![r#"Bearer realm="api""#, r#"Basic realm="api""#]
+        );
+    }
+
+    #[test]
+    fn test_authenticator_error_challenge() {
+        let handler = Authenticator::new(
+            |req: &RawRequest, _: &mut ()| match req.path.as_str() {
+                "/admin" => Err(AuthError::forbidden("not an admin")),
+                _ => Err(AuthError::new("expired")
+                    .with_challenge(Challenge::bearer().with_param("error", "invalid_token"))),
+            },
+            hello,
+        )
+        .with_challenge(Challenge::basic("api"));
+        let response = handler.handle(Request::default(), &mut ()).unwrap_err();
+        assert_eq!(response.status_code, 401);
+        assert_eq!(
+            challenges(&response),
+            vec![r#"Bearer error="invalid_token""#]
+        );
+        let request = RawRequest {
+            path: "/admin".to_string(),
+            ..Default::default()
+        };
+        let response = handler.handle(request, &mut ()).unwrap_err();
+        assert_eq!(response.status_code, 403);
+        assert!(challenges(&response).is_empty());
+    }
+}
@@ -0,0 +1,132 @@
+//! Character set negotiation for text responses, see *Accept-Charset*.
+use std::convert::TryFrom;
+
+use crate::request::{AcceptCharset, ContentType};
+use crate::response::RawResponse;
+
+/// Charsets text can be transcoded to, in order of server preference.
+const CHARSETS: [&str; 3] = ["utf-8", "iso-8859-1", "us-ascii"];
+
+/// Charsets accepted by the client, by client quality then server preference.
+fn acceptable(accept: &AcceptCharset) -> Vec<&'static str> {
+    let mut charsets: Vec<(&str, f32)> = CHARSETS
+        .iter()
+        .map(|charset| (*charset, accept.quality(charset)))
+        .filter(|(_, q)| *q > 0.0)
+        .collect();
+    // Stable, so ties keep server preference
+    charsets.sort_by(|a, b| b.1.partial_cmp(&a.1).unwrap());
+    charsets.into_iter().map(|(charset, _)| charset).collect()
+}
+
+fn encode(text: &str, charset: &str) -> Option<Vec<u8>> {
+    match charset {
+        "utf-8" => Some(text.as_bytes().to_vec()),
+        "iso-8859-1" => text.chars().map(|c| u8::try_from(c).ok()).collect(),
+        "us-ascii" if text.is_ascii() => Some(text.as_bytes().to_vec()),
+        _ => None,
+    }
+}
+
+/// Transcode a `text/*` response from UTF-8 to the client's preferred charset
+/// that can represent it, and add a `charset` parameter to its *Content-Type*.
+///
+/// Without *Accept-Charset*, the response is unchanged except for *Vary*;
+/// other responses are unchanged. None if no acceptable charset can represent
+/// the text.
+pub(crate) fn negotiate(
+    mut response: RawResponse,
+    accept: &Option<AcceptCharset>,
+) -> Option<RawResponse> {
+    let content_type = match response
+        .header("Content-Type")
+        .map(str::parse::<ContentType>)
+    {
+        Some(Ok(content_type)) if content_type.mime_type == "text" => content_type,
+        _ => return Some(response),
+    };
+    if content_type
+        .charset
+        .as_ref()
+        .is_some_and(|charset| !charset.eq_ignore_ascii_case("utf-8"))
+    {
+        return Some(response);
+    }
+    response.add_vary("Accept-Charset");
+    let accept = match accept {
+        Some(accept) => accept,
+        None => return Some(response),
+    };
+    let text = match response.payload.as_deref().map(std::str::from_utf8) {
+        Some(Ok(text)) => text,
+        _ => return Some(response),
+    };
+    for charset in acceptable(accept) {
+        if let Some(payload) = encode(text, charset) {
+            response.payload = Some(payload);
+            response.set_header(
+                "Content-Type",
+                &format!(
+                    "{}/{}; charset={}",
+                    content_type.mime_type, content_type.mime_subtype, charset
+                ),
+            );
+            return Some(response);
+        }
+    }
+    None
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn text(body: &str, accept_charset: Option<&str>) -> Option<RawResponse> {
+        let response = RawResponse::new(200)
+            .with_header("Content-Type", "text/plain")
+            .with_payload(body.as_bytes().to_vec());
+        negotiate(response, &accept_charset.map(|s| s.parse().unwrap()))
+    }
+
+    #[test]
+    fn test_negotiate_charset() {
+        let response = text("café", None).unwrap();
+        assert_eq!(response.header("Content-Type"), Some("text/plain"));
+        assert_eq!(response.payload, Some("café".as_bytes().to_vec()));
+
+        let response = text("café", Some("iso-8859-1, utf-8;q=0.5")).unwrap();
+        assert_eq!(
+            response.header("Content-Type"),
+            Some("text/plain; charset=iso-8859-1")
+        );
+        assert_eq!(response.payload, Some(b"caf\xe9".to_vec()));
+
+        let response = text("café", Some("*")).unwrap();
+        assert_eq!(
+            response.header("Content-Type"),
+            Some("text/plain; charset=utf-8")
+        );
+    }
+
+    #[test]
+    fn test_negotiate_charset_fallback() {
+        // Not representable in ISO-8859-1, falls back to the next charset
+        let response = text("€", Some("iso-8859-1, utf-8;q=0.5")).unwrap();
+        assert_eq!(
+            response.header("Content-Type"),
+            Some("text/plain; charset=utf-8")
+        );
+        assert!(text("€", Some("iso-8859-1, us-ascii")).is_none());
+        assert!(text("abc", Some("shift_jis")).is_none());
+    }
+
+    #[test]
+    fn test_negotiate_charset_not_text() {
+        let response = RawResponse::new(200)
+            .with_header("Content-Type", "application/json")
+            .with_payload(b"\"\xe2\x82\xac\"".to_vec());
+        let accept = Some("iso-8859-1".parse().unwrap());
+        let response = negotiate(response, &accept).unwrap();
+        assert_eq!(response.header("Content-Type"), Some("application/json"));
+    }
+}
@@ -0,0 +1,423 @@
+//! Content-Encoding negotiation, to compress responses, see *Accept-Encoding*.
+use std::io;
+
+use log::error;
+
+use crate::handler::{Handler, Res};
+use crate::request::{AcceptEncoding, Request};
+use crate::response::RawResponse;
+
+/// Don't bother compressing payloads smaller than this, by default.
+const DEFAULT_MIN_SIZE: usize = 256;
+
+/// A content coding, to compress response payloads with [`Compress`].
+///
+/// Implement it to add a coding, such as `br` with a brotli library.
+pub trait Encoder: Send + Sync {
+    /// The coding name, as used in *Accept-Encoding* and *Content-Encoding*.
+    fn name(&self) -> &str;
+    fn encode(&self, data: &[u8]) -> io::Result<Vec<u8>>;
+}
+
+/// The `gzip` coding, with the `compression` feature.
+#[cfg(feature = "compression")]
+pub struct Gzip {
+    level: u32,
+}
+
+#[cfg(feature = "compression")]
+impl Gzip {
+    /// Compress with a level from 0 (none) to 9 (best).
+    pub fn with_level(level: u32) -> Self {
+        Self { level }
+    }
+}
+
+#[cfg(feature = "compression")]
+impl Default for Gzip {
+    fn default() -> Self {
+        Self::with_level(flate2::Compression::default().level())
+    }
+}
+
+#[cfg(feature = "compression")]
+impl Encoder for Gzip {
+    fn name(&self) -> &str {
+        "gzip"
+    }
+    fn encode(&self, data: &[u8]) -> io::Result<Vec<u8>> {
+        use std::io::Write;
+        let mut writer = crate::io::GzipWriter::with_level(vec![], self.level);
+        writer.write_all(data)?;
+        writer.finish()
+    }
+}
+
+/// The `deflate` coding (zlib format), with the `compression` feature.
+#[cfg(feature = "compression")]
+pub struct Deflate {
+    level: u32,
+}
+
+#[cfg(feature = "compression")]
+impl Deflate {
+    /// Compress with a level from 0 (none) to 9 (best).
+    pub fn with_level(level: u32) -> Self {
+        Self { level }
+    }
+}
+
+#[cfg(feature = "compression")]
+impl Default for Deflate {
+    fn default() -> Self {
+        Self::with_level(flate2::Compression::default().level())
+    }
+}
+
+#[cfg(feature = "compression")]
+impl Encoder for Deflate {
+    fn name(&self) -> &str {
+        "deflate"
+    }
+    fn encode(&self, data: &[u8]) -> io::Result<Vec<u8>> {
+        use std::io::Write;
+        let mut writer = crate::io::DeflateWriter::with_level(vec![], self.level);
+        writer.write_all(data)?;
+        writer.finish()
+    }
+}
+
+/// The `br` coding (brotli), with the `brotli` feature.
+#[cfg(feature = "brotli")]
+pub struct Brotli {
+    quality: u32,
+}
+
+#[cfg(feature = "brotli")]
+impl Brotli {
+    /// Compress with a quality from 0 (fastest) to 11 (best).
+    pub fn with_quality(quality: u32) -> Self {
+        Self { quality }
+    }
+}
+
+/// Quality 5, fast enough to compress responses as they are sent, with
+/// better ratios than gzip for text.
+#[cfg(feature = "brotli")]
+impl Default for Brotli {
+    fn default() -> Self {
+        Self::with_quality(5)
+    }
+}
+
+#[cfg(feature = "brotli")]
+impl Encoder for Brotli {
+    fn name(&self) -> &str {
+        "br"
+    }
+    fn encode(&self, data: &[u8]) -> io::Result<Vec<u8>> {
+        use std::io::Write;
+        let mut writer = brotli::CompressorWriter::new(vec![], 4096, self.quality, 22);
+        writer.write_all(data)?;
+        writer.flush()?;
+        Ok(writer.into_inner())
+    }
+}
+
+/// The encoder the client prefers, by quality then server preference (the
+/// order of `encoders`). None if it prefers no coding, by giving *identity*
+/// a higher quality, or accepts none of them.
+fn negotiate<'a>(
+    accept: &AcceptEncoding,
+    encoders: &'a [Box<dyn Encoder>],
+) -> Option<&'a dyn Encoder> {
+    let mut best: Option<(&dyn Encoder, f32)> = None;
+    for encoder in encoders {
+        let q = accept.quality(encoder.name());
+        if q > 0.0 && best.is_none_or(|(_, best_q)| q > best_q) {
+            best = Some((encoder.as_ref(), q));
+        }
+    }
+    let (encoder, q) = best?;
+    if accept.quality("identity") > q {
+        return None;
+    }
+    Some(encoder)
+}
+
+/// Compress response payloads (`Ok` and `Err`) with the coding the client
+/// prefers, from its *Accept-Encoding* header, and add *Vary:
+/// Accept-Encoding*.
+///
+/// With the `compression` feature, `gzip` and `deflate` are supported by
+/// default, in that order of preference, and with the `brotli` feature,
+/// `br`, preferred over both; add other codings with `with_encoder`. Streamed bodies, payloads which already have a
+/// *Content-Encoding* and small payloads (see `with_min_size`) are left
+/// as is.
+///
+/// # Example
+/// ```
+/// use jbhttp::prelude::*;
+///
+/// fn page(req: RawRequest, _: &mut ()) -> Res<Vec<u8>, Vec<u8>> {
+///     Ok(Response::new(200).with_payload(b"Hello!".repeat(100)))
+/// }
+///
+/// let handler = page.with_compression();
+/// let request = Request::default().with_header("Accept-Encoding", "deflate, gzip;q=0.5");
+/// let response = handler.handle(request, &mut ()).unwrap();
+/// # #[cfg(feature = "compression")]
+/// assert_eq!(response.header("Content-Encoding"), Some("deflate"));
+/// assert_eq!(response.header("Vary"), Some("Accept-Encoding"));
+/// ```
+pub struct Compress<H> {
+    handler: H,
+    encoders: Vec<Box<dyn Encoder>>,
+    min_size: usize,
+}
+
+impl<H> Compress<H> {
+    pub fn new(handler: H) -> Self {
+        #[allow(unused_mut)]
+        let mut encoders: Vec<Box<dyn Encoder>> = vec![];
+        #[cfg(feature = "brotli")]
+        encoders.push(Box::new(Brotli::default()));
+        #[cfg(feature = "compression")]
+        {
+            encoders.push(Box::new(Gzip::default()));
+            encoders.push(Box::new(Deflate::default()));
+        }
+        Self {
+            handler,
+            encoders,
+            min_size: DEFAULT_MIN_SIZE,
+        }
+    }
+    /// Add a coding, with a lower server preference than those before it.
+    /// Replaces an existing coding with the same name, in place.
+    pub fn with_encoder<T: 'static + Encoder>(mut self, encoder: T) -> Self {
+        let encoder: Box<dyn Encoder> = Box::new(encoder);
+        match self
+            .encoders
+            .iter_mut()
+            .find(|e| e.name().eq_ignore_ascii_case(encoder.name()))
+        {
+            Some(existing) => *existing = encoder,
+            None => self.encoders.push(encoder),
+        }
+        self
+    }
+    /// Payloads smaller than this many bytes are not compressed, 256 by
+    /// default.
+    pub fn with_min_size(mut self, min_size: usize) -> Self {
+        self.min_size = min_size;
+        self
+    }
+    fn compress(&self, mut response: RawResponse, accept: &Option<AcceptEncoding>) -> RawResponse {
+        if response.is_streaming() || response.header("Content-Encoding").is_some() {
+            return response;
+        }
+        match &response.payload {
+            Some(payload) if payload.len() >= self.min_size => (),
+            _ => return response,
+        }
+        response.add_vary("Accept-Encoding");
+        let encoder = match accept
+            .as_ref()
+            .and_then(|accept| negotiate(accept, &self.encoders))
+        {
+            Some(encoder) => encoder,
+            None => return response,
+        };
+        match encoder.encode(response.payload.as_ref().unwrap()) {
+            Ok(encoded) => {
+                response.payload = Some(encoded);
+                response.set_header("Content-Encoding", encoder.name());
+            }
+            Err(e) => error!("{} encoding failed: {}", encoder.name(), e),
+        }
+        response
+    }
+}
+
+impl<H, I, C> Handler<I, Vec<u8>, Vec<u8>, C> for Compress<H>
+where
+    H: Handler<I, Vec<u8>, Vec<u8>, C>,
+    I: 'static + Sync,
+{
+    fn handle(&self, request: Request<I>, context: &mut C) -> Res<Vec<u8>, Vec<u8>> {
+        // An invalid header is treated as no header, identity only
+        let accept = request.accept_encoding().ok().flatten();
+        match self.handler.handle(request, context) {
+            Ok(response) => Ok(self.compress(response, &accept)),
+            Err(response) => Err(self.compress(response, &accept)),
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::handler::RawResult;
+    use crate::request::RawRequest;
+    use crate::response::Response;
+
+    /// Reverses the payload, as a custom coding.
+    struct Reverse;
+
+    impl Encoder for Reverse {
+        fn name(&self) -> &str {
+            "reverse"
+        }
+        fn encode(&self, data: &[u8]) -> io::Result<Vec<u8>> {
+            Ok(data.iter().rev().cloned().collect())
+        }
+    }
+
+    fn text(_: RawRequest, _: &mut ()) -> RawResult {
+        Ok(Response::new(200).with_payload(b"abc".repeat(100)))
+    }
+
+    fn get<H: Handler<Vec<u8>, Vec<u8>, Vec<u8>, ()>>(
+        handler: &H,
+        accept_encoding: Option<&str>,
+    ) -> RawResponse {
+        let mut request = RawRequest::default();
+        if let Some(accept_encoding) = accept_encoding {
+            request = request.with_header("Accept-Encoding", accept_encoding);
+        }
+        handler.handle(request, &mut ()).unwrap()
+    }
+
+    #[test]
+    fn test_compress_custom_encoder() {
+        let handler = text.with_compression().with_encoder(Reverse);
+        let response = get(&handler, Some("reverse"));
+        assert_eq!(response.header("Content-Encoding"), Some("reverse"));
+        assert_eq!(response.payload, Some(b"cba".repeat(100)));
+        assert_eq!(response.header("Vary"), Some("Accept-Encoding"));
+
+        for accept_encoding in [None, Some("identity"), Some("reverse;q=0, identity")] {
+            let response = get(&handler, accept_encoding);
+            assert_eq!(response.header("Content-Encoding"), None);
+            assert_eq!(response.payload, Some(b"abc".repeat(100)));
+            assert_eq!(response.header("Vary"), Some("Accept-Encoding"));
+        }
+        let response = get(&handler, Some("reverse;q=0.5, identity"));
+        assert_eq!(response.header("Content-Encoding"), None);
+    }
+
+    #[test]
+    fn test_compress_skipped() {
+        let handler = text.with_compression().with_encoder(Reverse);
+        let small = handler.with_min_size(301);
+        let response = get(&small, Some("reverse"));
+        assert_eq!(response.header("Content-Encoding"), None);
+        assert_eq!(response.header("Vary"), None);
+
+        let encoded = |_: RawRequest, _: &mut ()| -> RawResult {
+            Ok(Response::new(200)
+                .with_header("Content-Encoding", "gzip")
+                .with_payload(b"abc".repeat(100)))
+        };
+        let handler = encoded.with_compression().with_encoder(Reverse);
+        let response = get(&handler, Some("reverse"));
+        assert_eq!(response.header("Content-Encoding"), Some("gzip"));
+        assert_eq!(response.payload, Some(b"abc".repeat(100)));
+    }
+
+    #[cfg(any(feature = "compression", feature = "brotli"))]
+    fn decode(response: &RawResponse) -> Vec<u8> {
+        use std::io::Read;
+        let payload = response.payload.as_deref().unwrap();
+        let mut decoded = vec![];
+        match response.header("Content-Encoding") {
+            #[cfg(feature = "compression")]
+            Some("gzip") => crate::io::GzipReader::new(payload).read_to_end(&mut decoded),
+            #[cfg(feature = "compression")]
+            Some("deflate") => crate::io::DeflateReader::new(payload).read_to_end(&mut decoded),
+            #[cfg(feature = "brotli")]
+            Some("br") => brotli::Decompressor::new(payload, 4096).read_to_end(&mut decoded),
+            _ => panic!("unexpected encoding"),
+        }
+        .unwrap();
+        decoded
+    }
+
+    #[cfg(feature = "compression")]
+    #[test]
+    fn test_compress_gzip_deflate() {
+        let handler = text.with_compression();
+        let response = get(&handler, Some("gzip"));
+        assert_eq!(response.header("Content-Encoding"), Some("gzip"));
+        assert!(response.payload.as_ref().unwrap().len() < 300);
+        assert_eq!(decode(&response), b"abc".repeat(100));
+
+        let response = get(&handler, Some("deflate"));
+        assert_eq!(response.header("Content-Encoding"), Some("deflate"));
+        assert_eq!(decode(&response), b"abc".repeat(100));
+    }
+
+    #[cfg(feature = "brotli")]
+    #[test]
+    fn test_compress_brotli() {
+        let handler = text.with_compression();
+        let response = get(&handler, Some("gzip, deflate, br"));
+        assert_eq!(response.header("Content-Encoding"), Some("br"));
+        assert!(response.payload.as_ref().unwrap().len() < 300);
+        assert_eq!(decode(&response), b"abc".repeat(100));
+
+        let handler = text
+            .with_compression()
+            .with_encoder(Brotli::with_quality(11));
+        let response = get(&handler, Some("br"));
+        assert_eq!(decode(&response), b"abc".repeat(100));
+    }
+
+    #[cfg(all(feature = "compression", feature = "brotli"))]
+    #[test]
+    fn test_compress_quality_brotli() {
+        let handler = text.with_compression();
+        let encoding = |accept_encoding| {
+            get(&handler, Some(accept_encoding))
+                .header("Content-Encoding")
+                .map(str::to_string)
+        };
+        assert_eq!(encoding("*").as_deref(), Some("br"));
+        assert_eq!(encoding("gzip, br;q=0.9").as_deref(), Some("gzip"));
+        assert_eq!(
+            encoding("deflate;q=0.5, br;q=0.2").as_deref(),
+            Some("deflate")
+        );
+    }
+
+    #[cfg(feature = "compression")]
+    #[test]
+    fn test_compress_quality() {
+        let handler = text.with_compression().with_encoder(Reverse);
+        let encoding = |accept_encoding| {
+            get(&handler, Some(accept_encoding))
+                .header("Content-Encoding")
+                .map(str::to_string)
+        };
+        // br, with the brotli feature, is preferred over gzip and deflate
+        let brotli = cfg!(feature = "brotli");
+        // Server preference on ties
+        assert_eq!(encoding("deflate, gzip").as_deref(), Some("gzip"));
+        assert_eq!(
+            encoding("*").as_deref(),
+            Some(if brotli { "br" } else { "gzip" })
+        );
+        // Client preference otherwise
+        assert_eq!(encoding("gzip;q=0.5, deflate").as_deref(), Some("deflate"));
+        assert_eq!(
+            encoding("gzip;q=0.2, deflate;q=0.5, reverse;q=0.8").as_deref(),
+            Some("reverse")
+        );
+        assert_eq!(
+            encoding("*;q=0.5, gzip;q=0").as_deref(),
+            Some(if brotli { "br" } else { "deflate" })
+        );
+        assert_eq!(encoding("br").as_deref(), brotli.then_some("br"));
+    }
+}
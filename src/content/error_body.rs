@@ -0,0 +1,183 @@
+//! Default bodies for empty error responses.
+use super::negotiate_media_type;
+use crate::handler::{Handler, Res};
+use crate::request::Request;
+use crate::response::RawResponse;
+
+#[derive(Clone, Copy)]
+enum Format {
+    Text,
+    Problem,
+}
+
+/// Fill empty error responses (status 400 and up, `Ok` or `Err`) with a body
+/// describing the status, so errors generated by the framework, like the 404
+/// of a [`Router`](crate::router::Router) or the 406 of a serializer, don't
+/// reach clients without one.
+///
+/// The body is negotiated with *Accept*: plain text like `404 Not Found`, or
+/// a problem details object (RFC 7807) like
+/// `{"type":"about:blank","title":"Not Found","status":404}`, as
+/// `application/problem+json`, or `application/json` if the client only
+/// accepts that. Clients which accept neither get plain text. Responses
+/// with a payload, a streamed body or a *Content-Type* are left as is.
+///
+/// # Example
+/// ```
+/// use jbhttp::prelude::*;
+/// use jbhttp::router::Router;
+///
+/// let handler = Router::<Vec<u8>, Vec<u8>, Vec<u8>, ()>::new().default_error_bodies();
+///
+/// let request = Request::default().with_header("Accept", "application/problem+json");
+/// let response = handler.handle(request, &mut ()).unwrap_err();
+/// assert_eq!(response.status_code, 404);
+/// assert_eq!(response.header("Content-Type"), Some("application/problem+json"));
+/// assert_eq!(
+///     response.payload,
+///     Some(br#"{"type":"about:blank","title":"Not Found","status":404}"#.to_vec())
+/// );
+/// ```
+pub struct DefaultErrorBody<H> {
+    handler: H,
+    formats: Vec<(String, String, Format)>,
+}
+
+impl<H> DefaultErrorBody<H> {
+    pub fn new(handler: H) -> Self {
+        let format = |mime_type: &str, mime_subtype: &str, format| {
+            (mime_type.to_string(), mime_subtype.to_string(), format)
+        };
+        Self {
+            handler,
+            formats: vec![
+                format("text", "plain", Format::Text),
+                format("application", "problem+json", Format::Problem),
+                format("application", "json", Format::Problem),
+            ],
+        }
+    }
+    fn fill(&self, mut response: RawResponse, request: &Request<()>) -> RawResponse {
+        if response.status_code < 400
+            || response.payload.as_ref().is_some_and(|p| !p.is_empty())
+            || response.is_streaming()
+            || response.is_aborted()
+            || response.header("Content-Type").is_some()
+        {
+            return response;
+        }
+        let accept = request.accept().ok().flatten();
+        let (mime_type, mime_subtype, format) =
+            negotiate_media_type(&accept, &self.formats).unwrap_or(&self.formats[0]);
+        let payload = match format {
+            Format::Text => format!("{} {}", response.status_code, response.status),
+            Format::Problem => format!(
+                r#"{{"type":"about:blank","title":"{}","status":{}}}"#,
+                escape_json(&response.status),
+                response.status_code
+            ),
+        };
+        response.add_vary("Accept");
+        response.set_header("Content-Type", &format!("{}/{}", mime_type, mime_subtype));
+        response.payload = Some(payload.into_bytes());
+        response
+    }
+}
+
+impl<H, I, C> Handler<I, Vec<u8>, Vec<u8>, C> for DefaultErrorBody<H>
+where
+    H: Handler<I, Vec<u8>, Vec<u8>, C>,
+    I: 'static + Sync,
+{
+    fn handle(&self, request: Request<I>, context: &mut C) -> Res<Vec<u8>, Vec<u8>> {
+        // Only the headers are needed once the request is handled
+        let headers = Request {
+            headers: request.headers.clone(),
+            ..Default::default()
+        };
+        match self.handler.handle(request, context) {
+            Ok(response) => Ok(self.fill(response, &headers)),
+            Err(response) => Err(self.fill(response, &headers)),
+        }
+    }
+}
+
+fn escape_json(s: &str) -> String {
+    let mut escaped = String::with_capacity(s.len());
+    for c in s.chars() {
+        match c {
+            '"' => escaped.push_str("\\\""),
+            '\\' => escaped.push_str("\\\\"),
+            c if c.is_control() => escaped.push_str(&format!("\\u{:04x}", c as u32)),
+            c => escaped.push(c),
+        }
+    }
+    escaped
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::handler::RawResult;
+    use crate::request::RawRequest;
+    use crate::response::Response;
+
+    fn fail(request: RawRequest, _: &mut ()) -> RawResult {
+        match request.path.as_str() {
+            "/filled" => Err(Response::new(400).with_payload(b"bad name".to_vec())),
+            "/ok" => Ok(Response::new(204)),
+            "/custom" => Err(Response::new(599).with_status("Say \"What\"")),
+            _ => Err(Response::new(431)),
+        }
+    }
+
+    fn get(path: &str, accept: Option<&str>) -> RawResponse {
+        let mut request = Request {
+            path: path.to_string(),
+            ..Default::default()
+        };
+        if let Some(accept) = accept {
+            request = request.with_header("Accept", accept);
+        }
+        match fail.default_error_bodies().handle(request, &mut ()) {
+            Ok(response) => response,
+            Err(response) => response,
+        }
+    }
+
+    #[test]
+    fn test_default_error_body() {
+        for accept in [None, Some("text/*"), Some("image/png")] {
+            let response = get("/", accept);
+            assert_eq!(response.header("Content-Type"), Some("text/plain"));
+            assert_eq!(
+                response.payload,
+                Some(b"431 Request Header Fields Too Large".to_vec())
+            );
+            assert_eq!(response.header("Vary"), Some("Accept"));
+        }
+        let response = get("/", Some("application/json, text/plain;q=0.5"));
+        assert_eq!(response.header("Content-Type"), Some("application/json"));
+        assert_eq!(
+            response.payload,
+            Some(
+                br#"{"type":"about:blank","title":"Request Header Fields Too Large","status":431}"#
+                    .to_vec()
+            )
+        );
+        let response = get("/custom", Some("application/problem+json"));
+        assert_eq!(
+            response.payload,
+            Some(br#"{"type":"about:blank","title":"Say \"What\"","status":599}"#.to_vec())
+        );
+    }
+
+    #[test]
+    fn test_default_error_body_unchanged() {
+        let response = get("/filled", None);
+        assert_eq!(response.payload, Some(b"bad name".to_vec()));
+        assert_eq!(response.header("Content-Type"), None);
+        let response = get("/ok", None);
+        assert_eq!(response.payload, None);
+    }
+}
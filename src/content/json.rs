@@ -9,8 +9,20 @@
 //! use jbhttp::media_type;
 //! media_type!(CustomApplicationJson, "application", "json");
 //! ```
-use crate::content::mediatypes::ApplicationJson;
-use crate::content::{Deserialize, SerializationError, Serialize};
+//!
+//! Also provides helpers for handling PATCH requests with JSON Merge Patch
+//! ([`apply_merge_patch`]) and JSON Patch ([`apply_json_patch`]) bodies, and
+//! [`JsonArray`] to stream large collections.
+use std::fmt;
+use std::io;
+
+use serde_json::{Map, Value};
+
+use crate::content::mediatypes::{
+    ApplicationJson, ApplicationJsonPatchJson, ApplicationMergePatchJson,
+};
+use crate::content::{Deserialize, SerializationError, Serialize, SerializeStream};
+use crate::response::BodyStream;
 
 impl<T> Serialize<ApplicationJson> for T
 where
@@ -35,3 +47,499 @@ where
         }
     }
 }
+
+impl<T> Deserialize<T> for ApplicationMergePatchJson
+where
+    T: serde::de::DeserializeOwned,
+{
+    fn deserialize(bytes: Vec<u8>) -> Result<T, SerializationError> {
+        <ApplicationJson as Deserialize<T>>::deserialize(bytes)
+    }
+}
+
+impl<T> Deserialize<T> for ApplicationJsonPatchJson
+where
+    T: serde::de::DeserializeOwned,
+{
+    fn deserialize(bytes: Vec<u8>) -> Result<T, SerializationError> {
+        <ApplicationJson as Deserialize<T>>::deserialize(bytes)
+    }
+}
+
+/// Streams the items of an iterator as a JSON array, serializing each item
+/// while the response is written, see [`SerializeStream`].
+///
+/// # Example
+/// ```
+/// use jbhttp::prelude::*;
+/// use jbhttp::content::json::JsonArray;
+///
+/// type Rows = JsonArray<std::ops::Range<u32>>;
+///
+/// fn numbers(_: RawRequest, _: &mut ()) -> Res<Rows, Vec<u8>> {
+///     // e.g. rows read lazily from a database cursor
+///     Ok(Response::new(200).with_payload(JsonArray(0..1_000_000)))
+/// }
+///
+/// let handler = numbers
+///     .serialized()
+///     .with_streaming_media_type::<ApplicationJson>();
+/// let response = handler.handle(Request::default(), &mut ()).unwrap();
+/// assert!(response.is_streaming());
+/// ```
+pub struct JsonArray<I>(pub I);
+
+impl<I> SerializeStream<ApplicationJson> for JsonArray<I>
+where
+    I: Iterator + Send + 'static,
+    I::Item: serde::Serialize,
+{
+    fn serialize_stream(self) -> Result<BodyStream, SerializationError> {
+        let items = self.0.enumerate().map(|(i, item)| {
+            let mut chunk = if i == 0 { vec![] } else { vec![b','] };
+            serde_json::to_writer(&mut chunk, &item)
+                .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?;
+            Ok(chunk)
+        });
+        let chunks = std::iter::once(Ok(b"[".to_vec()))
+            .chain(items)
+            .chain(std::iter::once(Ok(b"]".to_vec())));
+        Ok(BodyStream::try_from_chunks(chunks))
+    }
+}
+
+/// Apply a JSON Merge Patch ([RFC 7386](https://tools.ietf.org/html/rfc7386))
+/// to a JSON value, as sent with *Content-Type: application/merge-patch+json*.
+///
+/// # Example
+/// ```
+/// use jbhttp::content::json::apply_merge_patch;
+/// use serde_json::json;
+///
+/// let mut person = json!({"name": "John", "age": 42, "email": "john@example.com"});
+/// apply_merge_patch(&mut person, &json!({"age": 43, "email": null}));
+/// assert_eq!(person, json!({"name": "John", "age": 43}));
+/// ```
+pub fn apply_merge_patch(target: &mut Value, patch: &Value) {
+    let patch = match patch {
+        Value::Object(patch) => patch,
+        _ => {
+            *target = patch.clone();
+            return;
+        }
+    };
+    if !target.is_object() {
+        *target = Value::Object(Map::new());
+    }
+    if let Value::Object(target) = target {
+        for (key, value) in patch {
+            if value.is_null() {
+                target.remove(key);
+            } else {
+                apply_merge_patch(target.entry(key.clone()).or_insert(Value::Null), value);
+            }
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct JsonPatchError {
+    reason: String,
+}
+
+impl JsonPatchError {
+    pub fn new(reason: &str) -> Self {
+        Self {
+            reason: reason.to_string(),
+        }
+    }
+}
+
+impl fmt::Display for JsonPatchError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> Result<(), fmt::Error> {
+        write!(f, "JSON patch error: {}", self.reason)
+    }
+}
+
+impl std::error::Error for JsonPatchError {}
+
+/// Apply a JSON Patch ([RFC 6902](https://tools.ietf.org/html/rfc6902))
+/// to a JSON value, as sent with *Content-Type: application/json-patch+json*.
+///
+/// The patch is applied atomically: if any operation fails, `target` is
+/// left unchanged.
+///
+/// # Example
+/// ```
+/// use jbhttp::content::json::apply_json_patch;
+/// use serde_json::json;
+///
+/// let mut person = json!({"name": "John", "tags": ["a"]});
+/// let patch = json!([
+///     {"op": "test", "path": "/name", "value": "John"},
+///     {"op": "replace", "path": "/name", "value": "Jane"},
+///     {"op": "add", "path": "/tags/-", "value": "b"},
+/// ]);
+/// apply_json_patch(&mut person, &patch).unwrap();
+/// assert_eq!(person, json!({"name": "Jane", "tags": ["a", "b"]}));
+/// ```
+pub fn apply_json_patch(target: &mut Value, patch: &Value) -> Result<(), JsonPatchError> {
+    let operations = match patch {
+        Value::Array(operations) => operations,
+        _ => return Err(JsonPatchError::new("patch must be an array")),
+    };
+    let mut patched = target.clone();
+    for operation in operations {
+        apply_json_patch_operation(&mut patched, operation)?;
+    }
+    *target = patched;
+    Ok(())
+}
+
+fn apply_json_patch_operation(target: &mut Value, operation: &Value) -> Result<(), JsonPatchError> {
+    let member = |name: &str| {
+        operation
+            .get(name)
+            .ok_or_else(|| JsonPatchError::new(&format!("missing member '{}'", name)))
+    };
+    let pointer = |name: &str| match member(name)? {
+        Value::String(s) => Ok(s.clone()),
+        _ => Err(JsonPatchError::new(&format!("'{}' must be a string", name))),
+    };
+    match member("op")?.as_str() {
+        Some("add") => json_pointer_add(target, &pointer("path")?, member("value")?.clone()),
+        Some("remove") => json_pointer_remove(target, &pointer("path")?).map(|_| ()),
+        Some("replace") => {
+            let path = pointer("path")?;
+            json_pointer_remove(target, &path)?;
+            json_pointer_add(target, &path, member("value")?.clone())
+        }
+        Some("move") => {
+            let (from, path) = (pointer("from")?, pointer("path")?);
+            if path.starts_with(&format!("{}/", from)) {
+                return Err(JsonPatchError::new("cannot move a value into itself"));
+            }
+            let value = json_pointer_remove(target, &from)?;
+            json_pointer_add(target, &path, value)
+        }
+        Some("copy") => {
+            let value = json_pointer_get(target, &pointer("from")?)?.clone();
+            json_pointer_add(target, &pointer("path")?, value)
+        }
+        Some("test") => {
+            if json_pointer_get(target, &pointer("path")?)? == member("value")? {
+                Ok(())
+            } else {
+                Err(JsonPatchError::new("test failed"))
+            }
+        }
+        _ => Err(JsonPatchError::new("invalid op")),
+    }
+}
+
+/// Split a JSON pointer into its parent pointer and unescaped last token.
+fn json_pointer_split(pointer: &str) -> Result<(&str, String), JsonPatchError> {
+    match pointer.rfind('/') {
+        Some(i) => Ok((
+            &pointer[..i],
+            pointer[i + 1..].replace("~1", "/").replace("~0", "~"),
+        )),
+        None => Err(JsonPatchError::new(&format!("invalid path '{}'", pointer))),
+    }
+}
+
+fn json_pointer_get<'a>(target: &'a Value, pointer: &str) -> Result<&'a Value, JsonPatchError> {
+    target
+        .pointer(pointer)
+        .ok_or_else(|| JsonPatchError::new(&format!("path '{}' not found", pointer)))
+}
+
+fn json_array_index(token: &str, len: usize) -> Result<usize, JsonPatchError> {
+    match str::parse::<usize>(token) {
+        Ok(i) if i <= len && (token == "0" || !token.starts_with('0')) => Ok(i),
+        _ => Err(JsonPatchError::new(&format!(
+            "invalid array index '{}'",
+            token
+        ))),
+    }
+}
+
+fn json_pointer_add(target: &mut Value, pointer: &str, value: Value) -> Result<(), JsonPatchError> {
+    if pointer.is_empty() {
+        *target = value;
+        return Ok(());
+    }
+    let (parent, token) = json_pointer_split(pointer)?;
+    match target.pointer_mut(parent) {
+        Some(Value::Object(map)) => {
+            map.insert(token, value);
+            Ok(())
+        }
+        Some(Value::Array(array)) => {
+            let i = match &token[..] {
+                "-" => array.len(),
+                _ => json_array_index(&token, array.len())?,
+            };
+            array.insert(i, value);
+            Ok(())
+        }
+        _ => Err(JsonPatchError::new(&format!("path '{}' not found", parent))),
+    }
+}
+
+fn json_pointer_remove(target: &mut Value, pointer: &str) -> Result<Value, JsonPatchError> {
+    // The root pointer removes the whole document, leaving null
+    if pointer.is_empty() {
+        return Ok(target.take());
+    }
+    let (parent, token) = json_pointer_split(pointer)?;
+    let not_found = || JsonPatchError::new(&format!("path '{}' not found", pointer));
+    match target.pointer_mut(parent) {
+        Some(Value::Object(map)) => map.remove(&token).ok_or_else(not_found),
+        Some(Value::Array(array)) => match json_array_index(&token, array.len()) {
+            Ok(i) if i < array.len() => Ok(array.remove(i)),
+            _ => Err(not_found()),
+        },
+        _ => Err(not_found()),
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use serde_json::json;
+
+    fn stream_json<I>(items: I) -> String
+    where
+        I: Iterator + Send + 'static,
+        I::Item: serde::Serialize,
+    {
+        let mut body = String::new();
+        io::Read::read_to_string(&mut JsonArray(items).serialize_stream().unwrap(), &mut body)
+            .unwrap();
+        body
+    }
+
+    #[test]
+    fn test_json_array_stream() {
+        assert_eq!(stream_json(std::iter::empty::<u8>()), "[]");
+        assert_eq!(stream_json(vec!["a"].into_iter()), r#"["a"]"#);
+        let rows = (1..=3).map(|i| json!({"id": i}));
+        assert_eq!(stream_json(rows), r#"[{"id":1},{"id":2},{"id":3}]"#);
+    }
+
+    fn merge(mut target: Value, patch: Value) -> Value {
+        apply_merge_patch(&mut target, &patch);
+        target
+    }
+
+    #[test]
+    fn test_merge_patch_rfc_examples() {
+        assert_eq!(
+            merge(json!({"a": "b"}), json!({"a": "c"})),
+            json!({"a": "c"})
+        );
+        assert_eq!(
+            merge(json!({"a": "b"}), json!({"b": "c"})),
+            json!({"a": "b", "b": "c"})
+        );
+        assert_eq!(merge(json!({"a": "b"}), json!({"a": null})), json!({}));
+        assert_eq!(
+            merge(json!({"a": ["b"]}), json!({"a": "c"})),
+            json!({"a": "c"})
+        );
+        assert_eq!(
+            merge(json!({"a": "c"}), json!({"a": ["b"]})),
+            json!({"a": ["b"]})
+        );
+        assert_eq!(
+            merge(
+                json!({"a": {"b": "c"}}),
+                json!({"a": {"b": "d", "c": null}})
+            ),
+            json!({"a": {"b": "d"}})
+        );
+        assert_eq!(
+            merge(json!(["a", "b"]), json!(["c", "d"])),
+            json!(["c", "d"])
+        );
+        assert_eq!(merge(json!({"a": "b"}), json!(["c"])), json!(["c"]));
+        assert_eq!(
+            merge(json!({"e": null}), json!({"a": 1})),
+            json!({"e": null, "a": 1})
+        );
+        assert_eq!(
+            merge(json!([1, 2]), json!({"a": "b", "c": null})),
+            json!({"a": "b"})
+        );
+        assert_eq!(
+            merge(json!({}), json!({"a": {"bb": {"ccc": null}}})),
+            json!({"a": {"bb": {}}})
+        );
+    }
+
+    fn patch(target: Value, patch: Value) -> Result<Value, JsonPatchError> {
+        let mut target = target;
+        apply_json_patch(&mut target, &patch)?;
+        Ok(target)
+    }
+
+    #[test]
+    fn test_json_patch_operations() {
+        let doc = json!({"foo": "bar", "baz": [1, 2], "a/b": {"c~d": 1}});
+        assert_eq!(
+            patch(
+                doc.clone(),
+                json!([{"op": "add", "path": "/baz/1", "value": 5}])
+            )
+            .unwrap(),
+            json!({"foo": "bar", "baz": [1, 5, 2], "a/b": {"c~d": 1}})
+        );
+        assert_eq!(
+            patch(doc.clone(), json!([{"op": "remove", "path": "/a~1b/c~0d"}])).unwrap(),
+            json!({"foo": "bar", "baz": [1, 2], "a/b": {}})
+        );
+        assert_eq!(
+            patch(
+                doc.clone(),
+                json!([{"op": "replace", "path": "/foo", "value": 1}])
+            )
+            .unwrap(),
+            json!({"foo": 1, "baz": [1, 2], "a/b": {"c~d": 1}})
+        );
+        assert_eq!(
+            patch(
+                doc.clone(),
+                json!([{"op": "move", "from": "/foo", "path": "/qux"}])
+            )
+            .unwrap(),
+            json!({"qux": "bar", "baz": [1, 2], "a/b": {"c~d": 1}})
+        );
+        assert_eq!(
+            patch(
+                doc.clone(),
+                json!([{"op": "copy", "from": "/baz/0", "path": "/baz/-"}])
+            )
+            .unwrap(),
+            json!({"foo": "bar", "baz": [1, 2, 1], "a/b": {"c~d": 1}})
+        );
+        assert_eq!(
+            patch(
+                doc.clone(),
+                json!([{"op": "test", "path": "/baz", "value": [1, 2]}])
+            )
+            .unwrap(),
+            doc
+        );
+        assert_eq!(
+            patch(doc, json!([{"op": "add", "path": "", "value": 1}])).unwrap(),
+            json!(1)
+        );
+    }
+
+    #[test]
+    fn test_json_patch_root() {
+        let doc = json!({"foo": [1]});
+        assert_eq!(
+            patch(
+                doc.clone(),
+                json!([{"op": "replace", "path": "", "value": [2]}])
+            )
+            .unwrap(),
+            json!([2])
+        );
+        assert_eq!(
+            patch(doc.clone(), json!([{"op": "remove", "path": ""}])).unwrap(),
+            Value::Null
+        );
+        assert_eq!(
+            patch(doc, json!([{"op": "move", "from": "/foo", "path": ""}])).unwrap(),
+            json!([1])
+        );
+    }
+
+    #[test]
+    fn test_json_patch_errors_are_atomic() {
+        let mut doc = json!({"foo": "bar"});
+        let result = apply_json_patch(
+            &mut doc,
+            &json!([
+                {"op": "replace", "path": "/foo", "value": "baz"},
+                {"op": "test", "path": "/foo", "value": "bar"},
+            ]),
+        );
+        assert_eq!(result, Err(JsonPatchError::new("test failed")));
+        assert_eq!(doc, json!({"foo": "bar"}));
+    }
+
+    #[test]
+    fn test_json_patch_invalid() {
+        let doc = json!({"foo": [1]});
+        assert!(patch(doc.clone(), json!({"op": "add"})).is_err());
+        assert!(patch(doc.clone(), json!([{"op": "nope", "path": "/foo"}])).is_err());
+        assert!(patch(doc.clone(), json!([{"op": "remove", "path": "/bar"}])).is_err());
+        assert!(patch(
+            doc.clone(),
+            json!([{"op": "add", "path": "/foo/2", "value": 1}])
+        )
+        .is_err());
+        assert!(patch(
+            doc.clone(),
+            json!([{"op": "add", "path": "/foo/01", "value": 1}])
+        )
+        .is_err());
+        assert!(patch(
+            doc.clone(),
+            json!([{"op": "add", "path": "/x/y", "value": 1}])
+        )
+        .is_err());
+        assert!(patch(
+            doc.clone(),
+            json!([{"op": "replace", "path": "/x", "value": 1}])
+        )
+        .is_err());
+        assert!(patch(
+            doc,
+            json!([{"op": "move", "from": "/foo", "path": "/foo/0"}])
+        )
+        .is_err());
+    }
+
+    #[test]
+    fn test_chunked_request_deserialized() {
+        use crate::handler::{Handler, Res};
+        use crate::io::MemoryStream;
+        use crate::request::Request;
+        use crate::response::Response;
+        use crate::server::{Server, StreamServer};
+
+        fn echo(request: Request<Value>, _: &mut ()) -> Res<Value, Vec<u8>> {
+            Ok(Response::new(200).with_payload(json!({
+                "payload": request.payload,
+                "content_length": request.content_length,
+            })))
+        }
+        let post = |headers: &str, body: &str| {
+            let input = format!(
+                "POST / HTTP/1.1\r\nContent-Type: application/json\r\n{}\r\n{}",
+                headers, body
+            );
+            let handler = echo.serdeserialized().with_media_type::<ApplicationJson>();
+            let mut server = StreamServer::new(MemoryStream::new(input.as_bytes()), handler);
+            Server::<()>::serve_forever(&mut server);
+            let output = String::from_utf8(server.get_ref().output().to_vec()).unwrap();
+            assert!(output.starts_with("HTTP/1.1 200 OK\r\n"), "{}", output);
+            let (_, payload) = output.split_once("\r\n\r\n").unwrap();
+            serde_json::from_str::<Value>(payload).unwrap()
+        };
+        let body = r#"{"name":"Bob","tags":["a","b"]}"#;
+        let expected = json!({
+            "payload": {"name": "Bob", "tags": ["a", "b"]},
+            "content_length": body.len(),
+        });
+        let chunked = "d\r\n{\"name\":\"Bob\"\r\n12\r\n,\"tags\":[\"a\",\"b\"]}\r\n0\r\n\r\n";
+        assert_eq!(post("Transfer-Encoding: chunked\r\n", chunked), expected);
+        let content_length = format!("Content-Length: {}\r\n", body.len());
+        assert_eq!(post(&content_length, body), expected);
+    }
+}
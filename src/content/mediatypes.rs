@@ -3,7 +3,9 @@ use crate::media_type;
 media_type!(ApplicationEpubZip, "application", "epub+zip");
 media_type!(ApplicationGzip, "application", "gzip");
 media_type!(ApplicationJson, "application", "json");
+media_type!(ApplicationJsonPatchJson, "application", "json-patch+json");
 media_type!(ApplicationLdJson, "application", "ld+json");
+media_type!(ApplicationMergePatchJson, "application", "merge-patch+json");
 media_type!(ApplicationOctetStream, "application", "octet-stream");
 media_type!(ApplicationOgg, "application", "ogg");
 media_type!(ApplicationPdf, "application", "pdf");
@@ -5,11 +5,16 @@ use std::marker::PhantomData;
 
 use crate::handler::{Handler, Res};
 use crate::request::{Accept, ContentType, HeaderParseError, Request};
-use crate::response::Response;
+use crate::response::{BodyStream, Response};
 
+mod charset;
+pub mod encoding;
+pub mod error_body;
 #[cfg(feature = "json")]
 pub mod json;
 pub mod mediatypes;
+#[cfg(feature = "json")]
+pub mod template;
 
 #[derive(Debug)]
 pub enum Error {
@@ -34,6 +39,16 @@ impl fmt::Display for Error {
     }
 }
 
+impl std::error::Error for Error {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            Self::Serialization(e) => Some(e),
+            Self::UnsupportedMediaType(_) => None,
+            Self::HeaderParse(e) => Some(e),
+        }
+    }
+}
+
 impl From<HeaderParseError> for Error {
     fn from(err: HeaderParseError) -> Self {
         Self::HeaderParse(err)
@@ -62,26 +77,136 @@ pub trait MediaTypeMatch {
     fn matches(&self, mime_type: &str, mime_subtype: &str) -> bool;
 }
 
-fn match_media_type<M: MediaTypeMatch, T>(
-    media_type: M,
-    choices: &[(String, String, T)],
-) -> Option<&T> {
-    for (mime_type, mime_subtype, item) in choices.iter() {
-        if media_type.matches(mime_type, mime_subtype) {
+/// Custom predicate deciding whether a *Content-Type* matches, see
+/// [`MediaTypeDeserializer::with_media_type_matching`].
+type ContentTypePredicate = Box<dyn Fn(&ContentType) -> bool + Send + Sync>;
+
+/// How a registered media type is matched against a request's *Content-Type*.
+enum ContentTypeMatcher {
+    MediaType(String, String),
+    Predicate(ContentTypePredicate),
+}
+
+impl ContentTypeMatcher {
+    fn matches(&self, content_type: &ContentType) -> bool {
+        match self {
+            Self::MediaType(mime_type, mime_subtype) => {
+                content_type.matches(mime_type, mime_subtype)
+            }
+            Self::Predicate(predicate) => predicate(content_type),
+        }
+    }
+}
+
+fn match_media_type<'a, T>(
+    content_type: &ContentType,
+    choices: &'a [(ContentTypeMatcher, T)],
+) -> Option<&'a T> {
+    for (matcher, item) in choices.iter() {
+        if matcher.matches(content_type) {
             return Some(item);
         }
     }
     None
 }
 
+/// Choose the media type of a response, among the media types supported by the
+/// server, in order of server preference (registration order):
+///  - the client's quality for each type is taken from the most specific
+///    matching range in *Accept* (`text/html` over `text/*` over `*/*`);
+///    types with no matching range, or quality 0, are not acceptable;
+///  - the type with the highest client quality is chosen;
+///  - ties are broken by server preference, so `Accept: */*` gets the
+///    server's first type.
+///
+/// Without an *Accept* header, the client accepts any type, so the server's
+/// first type is chosen.
+fn negotiate_media_type<'a, T>(
+    accept: &Option<Accept>,
+    choices: &'a [(String, String, T)],
+) -> Option<&'a (String, String, T)> {
+    let accept = match accept {
+        Some(accept) => accept,
+        None => return choices.first(),
+    };
+    let mut best: Option<(f32, &(String, String, T))> = None;
+    for choice in choices.iter() {
+        let (mime_type, mime_subtype, _) = choice;
+        let quality = accept
+            .iter()
+            .filter(|pref| pref.matches(mime_type, mime_subtype))
+            .max_by_key(|pref| match (&pref.mime_type[..], &pref.mime_subtype[..]) {
+                ("*", _) => 0,
+                (_, "*") => 1,
+                _ => 2,
+            })
+            .map_or(0.0, |pref| pref.quality());
+        // Strictly greater, to keep the server's first choice on ties
+        if quality > 0.0 && best.is_none_or(|(best_quality, _)| quality > best_quality) {
+            best = Some((quality, choice));
+        }
+    }
+    best.map(|(_, choice)| choice)
+}
+
 /// Implement this trait to enable Content-Type based serialization on
 /// your types, like `impl Serialize<ApplicationJson> for MyType {..}`
+///
+/// With the `json` feature, `Serialize<ApplicationJson>` is implemented
+/// for all types implementing `serde::Serialize`.
+#[diagnostic::on_unimplemented(
+    message = "`{Self}` cannot be serialized as `{M}`",
+    note = "implement `Serialize<{M}>` for `{Self}`, or serialize it as a media type it supports"
+)]
 pub trait Serialize<M: MediaType> {
     fn serialize(self) -> Result<Vec<u8>, SerializationError>;
 }
 
+/// The payload type of responses which never have one, like `204 No
+/// Content`, to serialize them as any media type. It has no values, so a
+/// response with it has no payload, and gets no *Content-Type*.
+///
+/// # Example
+/// ```
+/// use jbhttp::prelude::*;
+///
+/// fn delete(req: RawRequest, _: &mut ()) -> Res<NoPayload, NoPayload> {
+///     Ok(Response::new(204))
+/// }
+///
+/// let handler = delete.serialized().with_media_type::<TextPlain>();
+/// let response = handler.handle(Request::default(), &mut ()).unwrap();
+/// assert_eq!(response.payload, None);
+/// assert_eq!(response.header("Content-Type"), None);
+/// ```
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum NoPayload {}
+
+impl<M: MediaType> Serialize<M> for NoPayload {
+    fn serialize(self) -> Result<Vec<u8>, SerializationError> {
+        match self {}
+    }
+}
+
+/// Implement this trait to serialize a type lazily, into a body produced while
+/// the response is written instead of held in memory, like rows read from a
+/// database cursor. See [`MediaTypeSerializer::with_streaming_media_type`].
+///
+/// With the `json` feature, [`json::JsonArray`] streams the items of an
+/// iterator as a JSON array.
+pub trait SerializeStream<M: MediaType> {
+    fn serialize_stream(self) -> Result<BodyStream, SerializationError>;
+}
+
 /// Implement this trait to enable Content-Type based deserialization on
 /// your types, like `impl Deserialize<MyType> for ApplicationJson {..}`
+///
+/// With the `json` feature, `Deserialize<T> for ApplicationJson` is implemented
+/// for all types implementing `serde::Deserialize`.
+#[diagnostic::on_unimplemented(
+    message = "`{T}` cannot be deserialized from `{Self}`",
+    note = "implement `Deserialize<{T}>` for `{Self}`, or deserialize it from a media type it supports"
+)]
 pub trait Deserialize<T> {
     fn deserialize(bytes: Vec<u8>) -> Result<T, SerializationError>;
 }
@@ -159,6 +284,7 @@ where
             deserializer: MediaTypeDeserializer {
                 handler: None,
                 deserializers: Vec::new(),
+                raw_body: false,
                 phantom_o: PhantomData,
             },
         }
@@ -178,6 +304,18 @@ where
         self.deserializer = self.deserializer.with_media_type::<M>();
         self
     }
+    /// Deserialize with a custom *Content-Type* predicate, see
+    /// [`MediaTypeDeserializer::with_media_type_matching`].
+    pub fn with_media_type_deserial_matching<M, F>(mut self, predicate: F) -> Self
+    where
+        M: 'static + MediaType + Send + Sync + Deserialize<I>,
+        F: 'static + Fn(&ContentType) -> bool + Send + Sync,
+    {
+        self.deserializer = self
+            .deserializer
+            .with_media_type_matching::<M, F>(predicate);
+        self
+    }
     pub fn with_media_type<M>(mut self) -> Self
     where
         M: 'static + MediaType + Send + Sync + Deserialize<I>,
@@ -187,6 +325,12 @@ where
         self.deserializer = self.deserializer.with_media_type::<M>();
         self
     }
+    /// Keep a copy of the raw request body, see
+    /// [`MediaTypeDeserializer::with_raw_body`].
+    pub fn with_raw_body(mut self) -> Self {
+        self.deserializer = self.deserializer.with_raw_body();
+        self
+    }
 }
 
 impl<H, I, O, E, C> Handler<Vec<u8>, Vec<u8>, E, C> for MediaTypeSerde<H, I, O>
@@ -206,6 +350,10 @@ where
         if self.serializer.get_serializer(&accept).is_none() {
             return Err(Response::new(406));
         }
+        let accept_charset = match request.accept_charset() {
+            Ok(accept_charset) => accept_charset,
+            _ => return Err(Response::new(406)),
+        };
         let request = match self.deserializer.deserialize(request) {
             Ok(request) => request,
             Err(Error::Serialization(_)) => return Err(Response::new(400)),
@@ -214,7 +362,9 @@ where
         };
         match self.handler.handle(request, context) {
             Ok(response) => match self.serializer.serialize(&accept, response) {
-                Ok(response) => Ok(response),
+                Ok(response) => {
+                    charset::negotiate(response, &accept_charset).ok_or_else(|| Response::new(406))
+                }
                 Err(Error::Serialization(_)) => Err(Response::new(500)),
                 Err(Error::UnsupportedMediaType(_)) => Err(Response::new(406)),
                 Err(Error::HeaderParse(_)) => Err(Response::new(400)),
@@ -228,6 +378,32 @@ where
 ///
 /// Converts `Response<T>` to `Response<Vec<u8>>` for types that implementing
 /// [`Serialize`](crate::content::Serialize)`<MediaType> for T`.
+///
+/// # Content negotiation
+/// The response media type is chosen by the client's quality value (`q`)
+/// in the *Accept* header first, then by server preference, which is the order
+/// in which media types are added with `with_media_type`. For example, with
+/// JSON added before XML:
+///  - `Accept: */*` or no *Accept* header gets JSON;
+///  - `Accept: application/xml, application/json` gets JSON;
+///  - `Accept: application/xml, application/json;q=0.9` gets XML;
+///  - `Accept: */*, application/json;q=0` gets XML.
+///
+/// The same rules apply to [`MediaTypeErrorSerializer`] and [`NegotiatingHandler`].
+/// Negotiated responses get `Accept` added to their *Vary* header, so that
+/// caches keep one copy per representation.
+///
+/// # Responses without payload
+/// Handlers which never respond with a payload, like for `204 No Content`,
+/// can use [`NoPayload`] as their payload type, serializable as any media
+/// type.
+///
+/// # Charset negotiation
+/// `text/*` responses are UTF-8. When the request has an *Accept-Charset*
+/// header, they are transcoded to the client's preferred charset among UTF-8,
+/// ISO-8859-1 and US-ASCII that can represent the text, with a matching
+/// `charset` parameter; if there is none, the response is 406 Not Acceptable.
+/// `Accept-Charset` is added to their *Vary* header.
 pub struct MediaTypeSerializer<H, I, O>
 where
     I: 'static,
@@ -261,21 +437,48 @@ where
             .push((M::mime_type(), M::mime_subtype(), Box::new(serializer)));
         self
     }
-    fn get_serializer<'a>(
-        &'a self,
-        accept: &Option<Accept>,
-    ) -> Option<&Box<dyn ResponseSerializer<O>>> {
-        match accept {
-            Some(accept) => {
-                for pref in accept.iter() {
-                    if let Some(serializer) = match_media_type(pref, &self.serializers) {
-                        return Some(serializer);
-                    }
-                }
-                None
-            }
-            None => None,
-        }
+    /// Like `with_media_type`, but the payload is serialized into a streamed
+    /// body, see [`Response::with_stream`].
+    ///
+    /// # Example
+    /// ```
+    /// use jbhttp::prelude::*;
+    /// use jbhttp::content::SerializeStream;
+    /// use jbhttp::response::BodyStream;
+    ///
+    /// struct Lines(Vec<String>);
+    ///
+    /// impl SerializeStream<TextPlain> for Lines {
+    ///     fn serialize_stream(self) -> Result<BodyStream, SerializationError> {
+    ///         let lines = self.0.into_iter().map(|line| format!("{}\n", line).into_bytes());
+    ///         Ok(BodyStream::from_chunks(lines))
+    ///     }
+    /// }
+    ///
+    /// fn lines(_: RawRequest, _: &mut ()) -> Res<Lines, Vec<u8>> {
+    ///     Ok(Response::new(200).with_payload(Lines(vec!["a".into(), "b".into()])))
+    /// }
+    ///
+    /// let handler = lines.serialized().with_streaming_media_type::<TextPlain>();
+    /// let response = handler.handle(Request::default(), &mut ()).unwrap();
+    /// assert!(response.is_streaming());
+    /// assert_eq!(response.header("Content-Type"), Some("text/plain"));
+    /// ```
+    pub fn with_streaming_media_type<M>(mut self) -> Self
+    where
+        M: 'static + MediaType + Send + Sync,
+        O: SerializeStream<M>,
+    {
+        let serializer: StreamingMediaTypeSerializer<M, O> = StreamingMediaTypeSerializer {
+            phantom_m: PhantomData,
+            phantom_o: PhantomData,
+        };
+        self.serializers
+            .push((M::mime_type(), M::mime_subtype(), Box::new(serializer)));
+        self
+    }
+    fn get_serializer(&self, accept: &Option<Accept>) -> Option<&Box<dyn ResponseSerializer<O>>> {
+        negotiate_media_type(accept, &self.serializers).map(|(_, _, serializer)| serializer)
     }
     fn serialize(
         &self,
@@ -284,7 +487,7 @@ where
     ) -> Result<Response<Vec<u8>>, Error> {
         match self.get_serializer(accept) {
             Some(serializer) => match serializer.serialize(response) {
-                Ok(response) => Ok(response),
+                Ok(response) => Ok(response.with_vary("Accept")),
                 Err(e) => Err(Error::Serialization(e)),
             },
             None => Err(Error::UnsupportedMediaType(None)),
@@ -307,9 +510,15 @@ where
         if self.get_serializer(&accept).is_none() {
             return Err(Response::new(406));
         }
+        let accept_charset = match request.accept_charset() {
+            Ok(accept_charset) => accept_charset,
+            _ => return Err(Response::new(406)),
+        };
         match self.handler.as_ref().unwrap().handle(request, context) {
             Ok(response) => match self.serialize(&accept, response) {
-                Ok(response) => Ok(response),
+                Ok(response) => {
+                    charset::negotiate(response, &accept_charset).ok_or_else(|| Response::new(406))
+                }
                 Err(Error::Serialization(_)) => Err(Response::new(500)),
                 Err(Error::UnsupportedMediaType(_)) => Err(Response::new(406)),
                 Err(Error::HeaderParse(_)) => Err(Response::new(400)),
@@ -331,6 +540,7 @@ where
     // These are all SingleMediaTypeSerializer's, but since they have different
     // types for M, I still need boxdyns
     serializers: Vec<(String, String, Box<dyn ResponseSerializer<E>>)>,
+    fallback: Option<Box<dyn ResponseSerializer<E>>>,
     phantom_i: PhantomData<&'static I>,
 }
 
@@ -343,9 +553,47 @@ where
         Self {
             handler: Some(handler),
             serializers: Vec::new(),
+            fallback: None,
             phantom_i: PhantomData,
         }
     }
+    /// Serialize errors with this media type when the *Accept* header matches
+    /// none of the registered media types, instead of responding 406.
+    ///
+    /// # Example
+    /// ```
+    /// use jbhttp::prelude::*;
+    ///
+    /// struct Error(String);
+    ///
+    /// impl Serialize<TextPlain> for Error {
+    ///     fn serialize(self) -> Result<Vec<u8>, SerializationError> {
+    ///         Ok(self.0.into_bytes())
+    ///     }
+    /// }
+    ///
+    /// fn not_found(req: RawRequest, _: &mut ()) -> Res<Vec<u8>, Error> {
+    ///     Err(Response::new(404).with_payload(Error("not found".to_string())))
+    /// }
+    ///
+    /// let handler = not_found
+    ///     .serialized_error()
+    ///     .with_fallback_media_type::<TextPlain>();
+    ///
+    /// let request = Request::default().with_header("Accept", "image/png");
+    /// let response = handler.handle(request, &mut ()).unwrap_err();
+    /// assert_eq!(response.status_code, 404);
+    /// assert_eq!(response.payload, Some(b"not found".to_vec()));
+    /// ```
+    pub fn with_fallback_media_type<M>(mut self) -> Self
+    where
+        M: 'static + MediaType + Send + Sync,
+        E: Serialize<M>,
+    {
+        let serializer: SingleMediaTypeSerializer<M, E> = SingleMediaTypeSerializer::new();
+        self.fallback = Some(Box::new(serializer));
+        self
+    }
     pub fn with_media_type<M>(mut self) -> Self
     where
         M: 'static + MediaType + Send + Sync,
@@ -356,21 +604,10 @@ where
             .push((M::mime_type(), M::mime_subtype(), Box::new(serializer)));
         self
     }
-    fn get_serializer<'a>(
-        &'a self,
-        accept: &Option<Accept>,
-    ) -> Option<&Box<dyn ResponseSerializer<E>>> {
-        match accept {
-            Some(accept) => {
-                for pref in accept.iter() {
-                    if let Some(serializer) = match_media_type(pref, &self.serializers) {
-                        return Some(serializer);
-                    }
-                }
-                None
-            }
-            None => None,
-        }
+    fn get_serializer(&self, accept: &Option<Accept>) -> Option<&Box<dyn ResponseSerializer<E>>> {
+        negotiate_media_type(accept, &self.serializers)
+            .map(|(_, _, serializer)| serializer)
+            .or(self.fallback.as_ref())
     }
     fn serialize(
         &self,
@@ -379,7 +616,7 @@ where
     ) -> Result<Response<Vec<u8>>, Error> {
         match self.get_serializer(accept) {
             Some(serializer) => match serializer.serialize(response) {
-                Ok(response) => Ok(response),
+                Ok(response) => Ok(response.with_vary("Accept")),
                 Err(e) => Err(Error::Serialization(e)),
             },
             None => Err(Error::UnsupportedMediaType(None)),
@@ -414,6 +651,105 @@ where
     }
 }
 
+/// Dispatch requests to one of several handlers based on the *Accept* header,
+/// each handler producing its own media type. Unlike
+/// [`MediaTypeSerializer`], the handlers can produce completely different
+/// responses, like an HTML page and a JSON document.
+///
+/// The handler for the client's most preferred media type is used; a
+/// `Content-Type` header is added to responses with a payload. Responds 406
+/// if no media type is acceptable. Requests without *Accept* are sent to the
+/// first handler.
+///
+/// # Example
+/// ```
+/// use jbhttp::content::NegotiatingHandler;
+/// use jbhttp::prelude::*;
+///
+/// fn html(req: RawRequest, _: &mut ()) -> Res<Vec<u8>, Vec<u8>> {
+///     Ok(Response::new(200).with_payload(b"<p>Hello!</p>".to_vec()))
+/// }
+///
+/// fn json(req: RawRequest, _: &mut ()) -> Res<Vec<u8>, Vec<u8>> {
+///     Ok(Response::new(200).with_payload(b"{\"message\": \"Hello!\"}".to_vec()))
+/// }
+///
+/// let handler = NegotiatingHandler::new()
+///     .when::<TextHtml, _>(html)
+///     .when::<ApplicationJson, _>(json);
+///
+/// let request = Request::default().with_header("Accept", "application/json");
+/// let response = handler.handle(request, &mut ()).unwrap();
+/// assert_eq!(
+///     response.headers().get("Content-Type"),
+///     Some(&"application/json".to_string())
+/// );
+/// ```
+pub struct NegotiatingHandler<I, E, C> {
+    handlers: Vec<(String, String, RawHandler<I, E, C>)>,
+}
+
+type RawHandler<I, E, C> = Box<dyn Handler<I, Vec<u8>, E, C>>;
+
+impl<I, E, C> NegotiatingHandler<I, E, C>
+where
+    I: 'static + Sync,
+    E: 'static + Sync,
+{
+    pub fn new() -> Self {
+        Self { handlers: vec![] }
+    }
+    /// Use handler for requests accepting media type `M`.
+    pub fn when<M, H>(mut self, handler: H) -> Self
+    where
+        M: MediaType,
+        H: 'static + Handler<I, Vec<u8>, E, C>,
+    {
+        self.handlers
+            .push((M::mime_type(), M::mime_subtype(), Box::new(handler)));
+        self
+    }
+    fn get_handler(&self, accept: &Option<Accept>) -> Option<(String, &RawHandler<I, E, C>)> {
+        negotiate_media_type(accept, &self.handlers).map(|(mime_type, mime_subtype, handler)| {
+            (format!("{}/{}", mime_type, mime_subtype), handler)
+        })
+    }
+}
+
+impl<I, E, C> Default for NegotiatingHandler<I, E, C>
+where
+    I: 'static + Sync,
+    E: 'static + Sync,
+{
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<I, E, C> Handler<I, Vec<u8>, E, C> for NegotiatingHandler<I, E, C>
+where
+    I: 'static + Sync,
+    E: 'static + Sync,
+{
+    fn handle(&self, request: Request<I>, context: &mut C) -> Res<Vec<u8>, E> {
+        let accept = match request.accept() {
+            Ok(accept) => accept,
+            _ => return Err(Response::new(406)),
+        };
+        let (media_type, handler) = match self.get_handler(&accept) {
+            Some(found) => found,
+            None => return Err(Response::new(406)),
+        };
+        match handler.handle(request, context) {
+            Ok(response) if response.payload.is_some() => Ok(response
+                .with_header("Content-Type", &media_type)
+                .with_vary("Accept")),
+            Ok(response) => Ok(response.with_vary("Accept")),
+            Err(response) => Err(response.with_vary("Accept")),
+        }
+    }
+}
+
 trait ResponseSerializer<O>: Send + Sync {
     fn serialize(&self, r: Response<O>) -> Result<Response<Vec<u8>>, SerializationError>;
 }
@@ -450,7 +786,7 @@ where
         &self,
         mut response: Response<O>,
     ) -> Result<Response<Vec<u8>>, SerializationError> {
-        let body = std::mem::replace(&mut response.payload, None);
+        let body = response.payload.take();
         if let Some(body) = body {
             Ok(response
                 .into_raw()
@@ -462,6 +798,36 @@ where
     }
 }
 
+struct StreamingMediaTypeSerializer<M, O>
+where
+    M: 'static + MediaType + Sync + Send,
+    O: 'static + SerializeStream<M>,
+{
+    phantom_m: PhantomData<&'static M>,
+    phantom_o: PhantomData<&'static O>,
+}
+
+impl<M, O> ResponseSerializer<O> for StreamingMediaTypeSerializer<M, O>
+where
+    M: MediaType + Send + Sync,
+    O: SerializeStream<M> + Sync,
+{
+    fn serialize(
+        &self,
+        mut response: Response<O>,
+    ) -> Result<Response<Vec<u8>>, SerializationError> {
+        let body = response.payload.take();
+        if let Some(body) = body {
+            Ok(response
+                .into_raw()
+                .with_stream(body.serialize_stream()?)
+                .with_header("Content-Type", &M::media_type()))
+        } else {
+            Ok(response.into_raw())
+        }
+    }
+}
+
 /// Deserialize request payloads based on their  *Content-Type* headers.
 ///
 /// Converts `Request<Vec<u8>>` to `Request<T>` for types T implementing
@@ -473,10 +839,16 @@ where
     handler: Option<H>,
     // These are all SingleMediaTypeDeserializer's, but since they have different
     // types for M, I still need boxdyns
-    deserializers: Vec<(String, String, Box<dyn RequestDeserializer<I>>)>,
+    deserializers: Vec<(ContentTypeMatcher, Box<dyn RequestDeserializer<I>>)>,
+    raw_body: bool,
     phantom_o: PhantomData<&'static O>,
 }
 
+/// Raw request body, added to request extensions by deserializers
+/// when enabled with `with_raw_body`.
+#[derive(Debug, Clone, PartialEq)]
+pub struct RawBody(pub Vec<u8>);
+
 impl<H, I, O> MediaTypeDeserializer<H, I, O>
 where
     I: 'static + Sync,
@@ -486,21 +858,99 @@ where
         Self {
             handler: Some(handler),
             deserializers: Vec::new(),
+            raw_body: false,
             phantom_o: PhantomData,
         }
     }
+    /// Keep a copy of the raw request body as a [`RawBody`] request
+    /// extension, for example to verify a signature computed over the exact
+    /// bytes. Doubles the memory used by request bodies.
+    ///
+    /// # Example
+    /// ```
+    /// use jbhttp::content::{MediaTypeDeserializer, RawBody};
+    /// use jbhttp::prelude::*;
+    ///
+    /// struct Message(String);
+    ///
+    /// impl Deserialize<Message> for TextPlain {
+    ///     fn deserialize(bytes: Vec<u8>) -> Result<Message, SerializationError> {
+    ///         Ok(Message(String::from_utf8(bytes).unwrap()))
+    ///     }
+    /// }
+    ///
+    /// fn handle(req: Request<Message>, _: &mut ()) -> Res<Vec<u8>, Vec<u8>> {
+    ///     let raw = req.extensions.get::<RawBody>().unwrap();
+    ///     assert_eq!(raw.0, req.payload.unwrap().0.as_bytes());
+    ///     Ok(Response::new(200))
+    /// }
+    ///
+    /// let handler = MediaTypeDeserializer::new(handle)
+    ///     .with_media_type::<TextPlain>()
+    ///     .with_raw_body();
+    ///
+    /// let mut request = Request::default().with_header("Content-Type", "text/plain");
+    /// request.payload = Some(b"hello".to_vec());
+    /// assert!(handler.handle(request, &mut ()).is_ok());
+    /// ```
+    pub fn with_raw_body(mut self) -> Self {
+        self.raw_body = true;
+        self
+    }
 
     pub fn with_media_type<M>(mut self) -> Self
     where
         M: 'static + MediaType + Send + Sync + Deserialize<I>,
     {
         let deserializer: SingleMediaTypeDeserializer<M, I> = SingleMediaTypeDeserializer::new();
-        self.deserializers
-            .push((M::mime_type(), M::mime_subtype(), Box::new(deserializer)));
+        let matcher = ContentTypeMatcher::MediaType(M::mime_type(), M::mime_subtype());
+        self.deserializers.push((matcher, Box::new(deserializer)));
         self
     }
-    fn get_deserializer<'a>(
-        &'a self,
+    /// Deserialize requests with media type M when their *Content-Type*
+    /// satisfies a custom predicate, instead of matching M exactly. Like other
+    /// media types, predicates are tried in registration order.
+    ///
+    /// # Example
+    ///
+    /// Accept any `text/*` body as plain text:
+    /// ```
+    /// use jbhttp::content::MediaTypeDeserializer;
+    /// use jbhttp::prelude::*;
+    /// use jbhttp::request::ContentType;
+    ///
+    /// struct Message(String);
+    ///
+    /// impl Deserialize<Message> for TextPlain {
+    ///     fn deserialize(bytes: Vec<u8>) -> Result<Message, SerializationError> {
+    ///         Ok(Message(String::from_utf8(bytes).unwrap()))
+    ///     }
+    /// }
+    ///
+    /// fn handle(req: Request<Message>, _: &mut ()) -> Res<Vec<u8>, Vec<u8>> {
+    ///     assert_eq!(req.payload.unwrap().0, "# Title");
+    ///     Ok(Response::new(200))
+    /// }
+    ///
+    /// let handler = MediaTypeDeserializer::new(handle)
+    ///     .with_media_type_matching::<TextPlain, _>(|ct: &ContentType| ct.mime_type == "text");
+    ///
+    /// let mut request = Request::default().with_header("Content-Type", "text/markdown");
+    /// request.payload = Some(b"# Title".to_vec());
+    /// assert!(handler.handle(request, &mut ()).is_ok());
+    /// ```
+    pub fn with_media_type_matching<M, F>(mut self, predicate: F) -> Self
+    where
+        M: 'static + MediaType + Send + Sync + Deserialize<I>,
+        F: 'static + Fn(&ContentType) -> bool + Send + Sync,
+    {
+        let deserializer: SingleMediaTypeDeserializer<M, I> = SingleMediaTypeDeserializer::new();
+        let matcher = ContentTypeMatcher::Predicate(Box::new(predicate));
+        self.deserializers.push((matcher, Box::new(deserializer)));
+        self
+    }
+    fn get_deserializer(
+        &self,
         content_type: &Option<ContentType>,
     ) -> Option<&Box<dyn RequestDeserializer<I>>> {
         match content_type {
@@ -508,9 +958,14 @@ where
             None => None,
         }
     }
-    fn deserialize(&self, request: Request<Vec<u8>>) -> Result<Request<I>, Error> {
-        if request.payload.is_none() {
-            return Ok(request.into_type());
+    fn deserialize(&self, mut request: Request<Vec<u8>>) -> Result<Request<I>, Error> {
+        let payload = match &request.payload {
+            Some(payload) => payload,
+            None => return Ok(request.into_type()),
+        };
+        if self.raw_body {
+            let raw_body = RawBody(payload.clone());
+            request.extensions.insert(raw_body);
         }
         let content_type = request.content_type()?;
         match self.get_deserializer(&content_type) {
@@ -575,7 +1030,7 @@ where
     I: Sync,
 {
     fn deserialize(&self, mut request: Request<Vec<u8>>) -> Result<Request<I>, SerializationError> {
-        let body = std::mem::replace(&mut request.payload, None);
+        let body = request.payload.take();
         match body {
             Some(body) => {
                 let body = M::deserialize(body)?;
@@ -607,6 +1062,8 @@ impl fmt::Display for SerializationError {
     }
 }
 
+impl std::error::Error for SerializationError {}
+
 /// Define a new unit struct implementing MediaType.
 #[macro_export]
 macro_rules! media_type {
@@ -623,3 +1080,266 @@ macro_rules! media_type {
         }
     };
 }
+
+/// Register many media types at once, in order, as chained calls of
+/// `with_media_type` (or another registration method) on a
+/// [`MediaTypeSerde`], [`MediaTypeSerializer`], [`MediaTypeErrorSerializer`]
+/// or [`MediaTypeDeserializer`].
+///
+/// The first type stays the default, used when the client accepts any type.
+///
+/// # Example
+/// ```
+/// use jbhttp::prelude::*;
+///
+/// struct Greeting;
+///
+/// impl Serialize<TextPlain> for Greeting {
+///     fn serialize(self) -> Result<Vec<u8>, SerializationError> {
+///         Ok(b"Hello!".to_vec())
+///     }
+/// }
+///
+/// impl Serialize<TextHtml> for Greeting {
+///     fn serialize(self) -> Result<Vec<u8>, SerializationError> {
+///         Ok(b"<p>Hello!</p>".to_vec())
+///     }
+/// }
+///
+/// fn hello(req: RawRequest, _: &mut ()) -> Res<Greeting, Vec<u8>> {
+///     Ok(Response::new(200).with_payload(Greeting))
+/// }
+///
+/// // Same as .with_media_type::<TextPlain>().with_media_type::<TextHtml>()
+/// let handler = with_media_types!(hello.serialized(), [TextPlain, TextHtml]);
+///
+/// let response = handler.handle(Request::default(), &mut ()).unwrap();
+/// assert_eq!(response.header("Content-Type"), Some("text/plain"));
+/// let request = Request::default().with_header("Accept", "text/html");
+/// let response = handler.handle(request, &mut ()).unwrap();
+/// assert_eq!(response.payload, Some(b"<p>Hello!</p>".to_vec()));
+/// ```
+///
+/// Another registration method is given after the handler, like
+/// `with_media_types!(handler, with_media_type_serial, [ApplicationJson, TextPlain])`.
+#[macro_export]
+macro_rules! with_media_types {
+    ( $handler:expr, [ $( $m:ty ),* $(,)? ] ) => {
+        $crate::with_media_types!($handler, with_media_type, [ $( $m ),* ])
+    };
+    ( $handler:expr, $method:ident, [ $( $m:ty ),* $(,)? ] ) => {
+        $handler $( .$method::<$m>() )*
+    };
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::content::mediatypes::*;
+    use crate::request::RawRequest;
+
+    fn respond(body: &'static str) -> impl Handler<Vec<u8>, Vec<u8>, Vec<u8>, ()> {
+        move |_req: RawRequest, _context: &mut ()| -> Res<Vec<u8>, Vec<u8>> {
+            Ok(Response::new(200).with_payload(body.as_bytes().to_vec()))
+        }
+    }
+
+    fn negotiate<H>(handler: &H, accept: Option<&str>) -> Res<Vec<u8>, Vec<u8>>
+    where
+        H: Handler<Vec<u8>, Vec<u8>, Vec<u8>, ()>,
+    {
+        let mut request = Request::default();
+        if let Some(accept) = accept {
+            request = request.with_header("Accept", accept);
+        }
+        handler.handle(request, &mut ())
+    }
+
+    fn html_or_json() -> NegotiatingHandler<Vec<u8>, Vec<u8>, ()> {
+        NegotiatingHandler::new()
+            .when::<TextHtml, _>(respond("html"))
+            .when::<ApplicationJson, _>(respond("json"))
+    }
+
+    #[test]
+    fn test_negotiating_handler() {
+        let handler = html_or_json();
+        let response = negotiate(&handler, Some("application/json")).unwrap();
+        assert_eq!(response.payload, Some(b"json".to_vec()));
+        assert_eq!(
+            response.headers().get("Content-Type"),
+            Some(&"application/json".to_string())
+        );
+        assert_eq!(response.header("Vary"), Some("Accept"));
+        let response = negotiate(&handler, Some("text/*")).unwrap();
+        assert_eq!(response.payload, Some(b"html".to_vec()));
+        assert_eq!(
+            response.headers().get("Content-Type"),
+            Some(&"text/html".to_string())
+        );
+    }
+
+    #[test]
+    fn test_negotiating_handler_quality() {
+        let handler = html_or_json();
+        let response = negotiate(&handler, Some("text/html;q=0.5, application/json")).unwrap();
+        assert_eq!(response.payload, Some(b"json".to_vec()));
+        let response = negotiate(&handler, Some("image/png, */*;q=0.1")).unwrap();
+        assert_eq!(response.payload, Some(b"html".to_vec()));
+    }
+
+    #[test]
+    fn test_negotiating_handler_not_acceptable() {
+        let handler = html_or_json();
+        let response = negotiate(&handler, Some("image/png")).unwrap_err();
+        assert_eq!(response.status_code, 406);
+    }
+
+    #[test]
+    fn test_negotiating_handler_no_accept() {
+        let handler = html_or_json();
+        let response = negotiate(&handler, None).unwrap();
+        assert_eq!(response.payload, Some(b"html".to_vec()));
+    }
+
+    struct Greeting;
+
+    impl Serialize<ApplicationXml> for Greeting {
+        fn serialize(self) -> Result<Vec<u8>, SerializationError> {
+            Ok(b"<greeting/>".to_vec())
+        }
+    }
+
+    impl Serialize<TextHtml> for Greeting {
+        fn serialize(self) -> Result<Vec<u8>, SerializationError> {
+            Ok(b"<p>hello</p>".to_vec())
+        }
+    }
+
+    impl Serialize<TextPlain> for Greeting {
+        fn serialize(self) -> Result<Vec<u8>, SerializationError> {
+            Ok(b"hello".to_vec())
+        }
+    }
+
+    fn greeting_serializer() -> impl Handler<Vec<u8>, Vec<u8>, Vec<u8>, ()> {
+        let greet = |_req: RawRequest, _: &mut ()| -> Res<Greeting, Vec<u8>> {
+            Ok(Response::new(200).with_payload(Greeting))
+        };
+        MediaTypeSerializer::new(greet)
+            .with_media_type::<ApplicationXml>()
+            .with_media_type::<TextHtml>()
+            .with_media_type::<TextPlain>()
+    }
+
+    /// Returns the negotiated Content-Type, or the error status code.
+    fn negotiated_type(accept: Option<&str>) -> Result<String, u16> {
+        match negotiate(&greeting_serializer(), accept) {
+            Ok(response) => Ok(response.header("Content-Type").unwrap().to_string()),
+            Err(response) => Err(response.status_code),
+        }
+    }
+
+    #[test]
+    fn test_no_payload() {
+        let no_content = |req: RawRequest, _: &mut ()| -> Res<NoPayload, NoPayload> {
+            match req.path.as_str() {
+                "/error" => Err(Response::new(409)),
+                _ => Ok(Response::new(204)),
+            }
+        };
+        let handler = no_content
+            .serialized()
+            .with_media_type::<ApplicationJson>()
+            .with_media_type::<TextPlain>()
+            .serialized_error()
+            .with_media_type::<ApplicationJson>();
+        for path in ["/", "/error"] {
+            for accept in [None, Some("application/json"), Some("text/plain")] {
+                let mut request = RawRequest {
+                    path: path.to_string(),
+                    ..Default::default()
+                };
+                if let Some(accept) = accept {
+                    request = request.with_header("Accept", accept);
+                }
+                let response = match handler.handle(request, &mut ()) {
+                    Ok(response) => response,
+                    Err(response) => response,
+                };
+                assert_eq!(response.payload, None);
+                assert_eq!(response.header("Content-Type"), None);
+            }
+        }
+    }
+
+    #[test]
+    fn test_negotiation_server_preference() {
+        let xml = Ok("application/xml".to_string());
+        let html = Ok("text/html".to_string());
+        assert_eq!(negotiated_type(None), xml);
+        assert_eq!(negotiated_type(Some("*/*")), xml);
+        assert_eq!(negotiated_type(Some("text/plain, text/html")), html);
+        assert_eq!(negotiated_type(Some("text/*")), html);
+    }
+
+    #[test]
+    fn test_negotiation_client_quality() {
+        let plain = Ok("text/plain".to_string());
+        let html = Ok("text/html".to_string());
+        assert_eq!(
+            negotiated_type(Some("text/plain, application/xml;q=0.9")),
+            plain
+        );
+        assert_eq!(negotiated_type(Some("*/*;q=0.1, text/plain")), plain);
+        assert_eq!(negotiated_type(Some("text/*;q=0.5, text/plain")), plain);
+        assert_eq!(negotiated_type(Some("*/*, application/xml;q=0")), html);
+        assert_eq!(negotiated_type(Some("text/html;q=0")), Err(406));
+        assert_eq!(negotiated_type(Some("image/png")), Err(406));
+    }
+
+    struct Text(String);
+
+    impl Deserialize<Text> for TextPlain {
+        fn deserialize(bytes: Vec<u8>) -> Result<Text, SerializationError> {
+            Ok(Text(String::from_utf8(bytes).unwrap()))
+        }
+    }
+
+    #[test]
+    fn test_media_type_matching() {
+        let echo = |req: Request<Text>, _: &mut ()| -> Res<Vec<u8>, Vec<u8>> {
+            Ok(Response::new(200).with_payload(req.payload.unwrap().0.into_bytes()))
+        };
+        let handler = MediaTypeDeserializer::new(echo)
+            .with_media_type::<TextPlain>()
+            .with_media_type_matching::<TextPlain, _>(|ct: &ContentType| {
+                ct.mime_type == "application" && ct.mime_subtype.ends_with("+text")
+            });
+        let deserialize = |content_type: &str| {
+            let mut request = Request::default().with_header("Content-Type", content_type);
+            request.payload = Some(b"hello".to_vec());
+            match handler.handle(request, &mut ()) {
+                Ok(response) => Ok(response.payload.unwrap()),
+                Err(response) => Err(response.status_code),
+            }
+        };
+        assert_eq!(deserialize("text/plain"), Ok(b"hello".to_vec()));
+        assert_eq!(
+            deserialize("application/vnd.foo+text"),
+            Ok(b"hello".to_vec())
+        );
+        assert_eq!(deserialize("application/text"), Err(415));
+    }
+
+    #[test]
+    fn test_negotiation_vary() {
+        let handler = greeting_serializer();
+        let response = negotiate(&handler, Some("application/xml")).unwrap();
+        assert_eq!(response.header("Vary"), Some("Accept"));
+        let response = negotiate(&handler, None).unwrap();
+        assert_eq!(response.header("Vary"), Some("Accept"));
+        let response = negotiate(&handler, Some("text/html")).unwrap();
+        assert_eq!(response.header("Vary"), Some("Accept, Accept-Charset"));
+    }
+}
@@ -0,0 +1,265 @@
+//! Server-rendered HTML pages from templates, with the `json` feature.
+//!
+//! Handlers respond with a [`Template`] payload, a template name and a
+//! context; [`RenderTemplates`] renders it with a [`TemplateEngine`].
+//! Implement the trait to plug in an engine such as `tinytemplate` or
+//! `handlebars`, which take any serializable context, including the
+//! [`serde_json::Value`] it is given; [`SimpleTemplates`] is a minimal
+//! engine for simple pages.
+use std::collections::HashMap;
+
+use log::error;
+use serde_json::Value;
+
+use crate::content::SerializationError;
+use crate::handler::{Handler, Res};
+use crate::request::Request;
+use crate::response::Response;
+
+/// A template engine, to render [`Template`] payloads with
+/// [`RenderTemplates`].
+pub trait TemplateEngine: Send + Sync {
+    /// Render the named template, with the context.
+    fn render(&self, name: &str, context: &Value) -> Result<String, SerializationError>;
+}
+
+/// A response payload to be rendered by a template engine, see
+/// [`RenderTemplates`].
+#[derive(Debug)]
+pub struct Template {
+    name: String,
+    context: Result<Value, SerializationError>,
+}
+
+impl Template {
+    /// The named template, with a context which can be serialized with
+    /// serde, like a struct or a `serde_json::json!` object. A context
+    /// which fails to serialize fails rendering.
+    pub fn new<T: serde::Serialize>(name: &str, context: &T) -> Self {
+        Self {
+            name: name.to_string(),
+            context: serde_json::to_value(context)
+                .map_err(|e| SerializationError::new(&e.to_string())),
+        }
+    }
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+    fn render(&self, engine: &dyn TemplateEngine) -> Result<String, SerializationError> {
+        match &self.context {
+            Ok(context) => engine.render(&self.name, context),
+            Err(e) => Err(SerializationError::new(&e.to_string())),
+        }
+    }
+}
+
+/// Render [`Template`] payloads of `Ok` responses into HTML, with
+/// *Content-Type: text/html; charset=utf-8*. Responses without payload are
+/// left as is; templates which fail to render get a 500 response.
+///
+/// # Example
+/// ```
+/// use jbhttp::content::template::{SimpleTemplates, Template};
+/// use jbhttp::prelude::*;
+/// use serde_json::json;
+///
+/// fn hello(req: RawRequest, _: &mut ()) -> Res<Template, Vec<u8>> {
+///     let context = json!({"name": "<World>"});
+///     Ok(Response::new(200).with_payload(Template::new("hello", &context)))
+/// }
+///
+/// let templates = SimpleTemplates::new().with_template("hello", "<p>Hello {{ name }}!</p>");
+/// let handler = hello.render_templates(templates);
+///
+/// let response = handler.handle(Request::default(), &mut ()).unwrap();
+/// assert_eq!(response.header("Content-Type"), Some("text/html; charset=utf-8"));
+/// assert_eq!(response.payload, Some(b"<p>Hello &lt;World&gt;!</p>".to_vec()));
+/// ```
+pub struct RenderTemplates<H, T> {
+    handler: H,
+    engine: T,
+}
+
+impl<H, T> RenderTemplates<H, T> {
+    pub fn new(engine: T, handler: H) -> Self {
+        Self { handler, engine }
+    }
+}
+
+impl<H, T, I, E, C> Handler<I, Vec<u8>, E, C> for RenderTemplates<H, T>
+where
+    H: Handler<I, Template, E, C>,
+    T: TemplateEngine,
+    I: 'static + Sync,
+    E: 'static + Sync,
+{
+    fn handle(&self, request: Request<I>, context: &mut C) -> Res<Vec<u8>, E> {
+        let mut response = self.handler.handle(request, context)?;
+        let template = match response.payload.take() {
+            Some(template) => template,
+            None => return Ok(response.into_type()),
+        };
+        match template.render(&self.engine) {
+            Ok(html) => Ok(response
+                .into_type()
+                .with_payload(html.into_bytes())
+                .with_header("Content-Type", "text/html; charset=utf-8")),
+            Err(e) => {
+                error!("error rendering template {}: {}", template.name(), e);
+                Err(Response::new(500))
+            }
+        }
+    }
+}
+
+/// A minimal template engine, which replaces `{{ path }}` with values from
+/// the context, HTML-escaped. Paths are field names and array indices
+/// separated by dots, like `user.name` or `items.0`; a missing value fails
+/// rendering.
+#[derive(Debug, Clone, Default)]
+pub struct SimpleTemplates {
+    templates: HashMap<String, String>,
+}
+
+impl SimpleTemplates {
+    pub fn new() -> Self {
+        Self::default()
+    }
+    /// Add a template, replacing one with the same name.
+    pub fn with_template(mut self, name: &str, source: &str) -> Self {
+        self.templates.insert(name.to_string(), source.to_string());
+        self
+    }
+}
+
+impl TemplateEngine for SimpleTemplates {
+    fn render(&self, name: &str, context: &Value) -> Result<String, SerializationError> {
+        let mut rest = match self.templates.get(name) {
+            Some(source) => &source[..],
+            None => return Err(SerializationError::new(&format!("no template {}", name))),
+        };
+        let mut html = String::with_capacity(rest.len());
+        while let Some(start) = rest.find("{{") {
+            html.push_str(&rest[..start]);
+            let end = match rest[start..].find("}}") {
+                Some(end) => start + end,
+                None => return Err(SerializationError::new("unclosed {{")),
+            };
+            let path = rest[start + 2..end].trim();
+            let value = path
+                .split('.')
+                .try_fold(context, |value, key| match value {
+                    Value::Array(items) => key.parse::<usize>().ok().and_then(|i| items.get(i)),
+                    _ => value.get(key),
+                })
+                .ok_or_else(|| SerializationError::new(&format!("no value for {}", path)))?;
+            match value {
+                Value::String(s) => escape_html(s, &mut html),
+                Value::Null => (),
+                value => escape_html(&value.to_string(), &mut html),
+            }
+            rest = &rest[end + 2..];
+        }
+        html.push_str(rest);
+        Ok(html)
+    }
+}
+
+fn escape_html(s: &str, html: &mut String) {
+    for c in s.chars() {
+        match c {
+            '&' => html.push_str("&amp;"),
+            '<' => html.push_str("&lt;"),
+            '>' => html.push_str("&gt;"),
+            '"' => html.push_str("&quot;"),
+            '\'' => html.push_str("&#39;"),
+            c => html.push(c),
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::request::RawRequest;
+    use serde_json::json;
+
+    fn templates() -> SimpleTemplates {
+        SimpleTemplates::new()
+            .with_template(
+                "user",
+                "<h1>{{ user.name }}</h1><p>{{user.tags.1}} {{ age }}{{ none }}</p>",
+            )
+            .with_template("broken", "<h1>{{ user.name </h1>")
+    }
+
+    #[test]
+    fn test_simple_templates() {
+        let context = json!({
+            "user": {"name": "Bob & \"Alice\"", "tags": ["a", "<b>"]},
+            "age": 42,
+            "none": null,
+        });
+        assert_eq!(
+            templates().render("user", &context).unwrap(),
+            "<h1>Bob &amp; &quot;Alice&quot;</h1><p>&lt;b&gt; 42</p>"
+        );
+        assert!(templates().render("user", &json!({})).is_err());
+        assert!(templates().render("broken", &context).is_err());
+        assert!(templates().render("missing", &context).is_err());
+    }
+
+    #[test]
+    fn test_render_templates() {
+        let page = |request: RawRequest, _: &mut ()| -> Res<Template, Vec<u8>> {
+            match request.path.as_str() {
+                "/empty" => Ok(Response::new(204)),
+                "/error" => Err(Response::new(404).with_payload(b"not found".to_vec())),
+                path => Ok(Response::new(200)
+                    .with_header("Cache-Control", "no-cache")
+                    .with_payload(Template::new(&path[1..], &json!({"user": {"name": "Bob"}})))),
+            }
+        };
+        let handler = page.render_templates(templates());
+        let get = |path: &str| {
+            handler.handle(
+                RawRequest {
+                    path: path.to_string(),
+                    ..Default::default()
+                },
+                &mut (),
+            )
+        };
+        // Render errors are 500s
+        for path in ["/user", "/broken", "/missing"] {
+            assert_eq!(get(path).unwrap_err().status_code, 500);
+        }
+        let handler = page.render_templates(
+            SimpleTemplates::new().with_template("user", "<h1>{{ user.name }}</h1>"),
+        );
+        let response = handler
+            .handle(
+                RawRequest {
+                    path: "/user".to_string(),
+                    ..Default::default()
+                },
+                &mut (),
+            )
+            .unwrap();
+        assert_eq!(response.payload, Some(b"<h1>Bob</h1>".to_vec()));
+        assert_eq!(response.header("Cache-Control"), Some("no-cache"));
+        assert_eq!(
+            response.header("Content-Type"),
+            Some("text/html; charset=utf-8")
+        );
+
+        let response = get("/empty").unwrap();
+        assert_eq!(response.status_code, 204);
+        assert_eq!(response.payload, None);
+        assert_eq!(response.header("Content-Type"), None);
+        assert_eq!(
+            get("/error").unwrap_err().payload,
+            Some(b"not found".to_vec())
+        );
+    }
+}
@@ -0,0 +1,134 @@
+//! Tamper-proof cookies, signed with HMAC-SHA256 (requires the `crypto` feature).
+use hmac::{Hmac, Mac};
+use sha2::Sha256;
+
+use crate::request::Request;
+use crate::response::Response;
+
+type HmacSha256 = Hmac<Sha256>;
+
+/// Signs cookie values with a server secret, so that values read back from
+/// clients can be trusted not to have been modified. The value itself is
+/// *not* encrypted, don't store secrets in it.
+///
+/// The cookie name is part of the signature, so a signed value can't be
+/// moved to another cookie. Cookies that fail verification are treated as
+/// if they were absent.
+///
+/// # Example
+/// ```
+/// use jbhttp::cookie::SignedCookie;
+/// use jbhttp::prelude::*;
+///
+/// let cookies = SignedCookie::new(b"server secret");
+///
+/// let response: Response<Vec<u8>> = cookies.set(Response::new(200), "user", "bob");
+/// let set_cookie = response.headers().get("Set-Cookie").unwrap().clone();
+///
+/// let request: Request<Vec<u8>> = Request::default().with_header("Cookie", &set_cookie);
+/// assert_eq!(cookies.get(&request, "user"), Some("bob".to_string()));
+///
+/// let forged: Request<Vec<u8>> = Request::default().with_header("Cookie", "user=admin");
+/// assert_eq!(cookies.get(&forged, "user"), None);
+/// ```
+pub struct SignedCookie {
+    secret: Vec<u8>,
+}
+
+impl SignedCookie {
+    pub fn new(secret: &[u8]) -> Self {
+        Self {
+            secret: secret.to_vec(),
+        }
+    }
+    fn mac(&self, name: &str, value: &str) -> HmacSha256 {
+        let mut mac =
+            HmacSha256::new_from_slice(&self.secret).expect("HMAC accepts keys of any size");
+        mac.update(name.as_bytes());
+        mac.update(b"=");
+        mac.update(value.as_bytes());
+        mac
+    }
+    /// Sign a value, returns the value with its signature appended.
+    pub fn sign(&self, name: &str, value: &str) -> String {
+        let tag = self.mac(name, value).finalize().into_bytes();
+        format!("{}.{}", value, to_hex(&tag))
+    }
+    /// Verify a signed value, returns the original value if the signature matches.
+    pub fn verify(&self, name: &str, signed: &str) -> Option<String> {
+        let (value, tag) = signed.rsplit_once('.')?;
+        let tag = from_hex(tag)?;
+        match self.mac(name, value).verify_slice(&tag) {
+            Ok(()) => Some(value.to_string()),
+            Err(_) => None,
+        }
+    }
+    /// Get the verified value of a cookie from a request.
+    pub fn get<I>(&self, request: &Request<I>, name: &str) -> Option<String> {
+        self.verify(name, &request.cookie(name)?)
+    }
+    /// Add a signed cookie to a response.
+    pub fn set<T>(&self, response: Response<T>, name: &str, value: &str) -> Response<T> {
+        response.with_header(
+            "Set-Cookie",
+            &format!("{}={}", name, self.sign(name, value)),
+        )
+    }
+}
+
+fn to_hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|b| format!("{:02x}", b)).collect()
+}
+
+fn from_hex(s: &str) -> Option<Vec<u8>> {
+    if !s.len().is_multiple_of(2) || !s.is_ascii() {
+        return None;
+    }
+    (0..s.len())
+        .step_by(2)
+        .map(|i| u8::from_str_radix(&s[i..i + 2], 16).ok())
+        .collect()
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_sign_verify() {
+        let cookies = SignedCookie::new(b"secret");
+        let signed = cookies.sign("session", "a.b.c");
+        assert_eq!(
+            cookies.verify("session", &signed),
+            Some("a.b.c".to_string())
+        );
+    }
+
+    #[test]
+    fn test_verify_tampered() {
+        let cookies = SignedCookie::new(b"secret");
+        let signed = cookies.sign("session", "user");
+        let tampered = signed.replacen("user", "admin", 1);
+        assert_eq!(cookies.verify("session", &tampered), None);
+        assert_eq!(cookies.verify("session", "user"), None);
+        assert_eq!(cookies.verify("session", "user.zz"), None);
+    }
+
+    #[test]
+    fn test_verify_other_name_or_secret() {
+        let cookies = SignedCookie::new(b"secret");
+        let signed = cookies.sign("session", "user");
+        assert_eq!(cookies.verify("other", &signed), None);
+        assert_eq!(SignedCookie::new(b"other").verify("session", &signed), None);
+    }
+
+    #[test]
+    fn test_get_from_request() {
+        let cookies = SignedCookie::new(b"secret");
+        let header = format!("theme=dark; session={}", cookies.sign("session", "bob"));
+        let request: Request<Vec<u8>> = Request::default().with_header("Cookie", &header);
+        assert_eq!(cookies.get(&request, "session"), Some("bob".to_string()));
+        assert_eq!(cookies.get(&request, "theme"), None);
+        assert_eq!(cookies.get(&request, "missing"), None);
+    }
+}
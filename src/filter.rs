@@ -1,7 +1,14 @@
 use crate::handler::{Handler, Res};
-use crate::request::Request;
+use crate::request::{Deadline, ETag, Header, HttpDate, Method, Request};
 use crate::response::Response;
+use log::{error, warn};
 use std::marker::PhantomData;
+use std::net::{AddrParseError, IpAddr};
+use std::panic::{self, AssertUnwindSafe};
+use std::str::FromStr;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Condvar, Mutex};
+use std::time::{Duration, Instant, SystemTime};
 
 pub struct RequestFilter<H, F, I: 'static> {
     f: F,
@@ -135,3 +142,1052 @@ where
         (self.f)(self.handler.handle(request, context), context)
     }
 }
+
+/// Handle HEAD requests as GET requests. Servers remove the body from
+/// responses to HEAD requests, keeping its *Content-Length*.
+pub struct HeadAsGet<H> {
+    handler: H,
+}
+
+impl<H> HeadAsGet<H> {
+    pub fn new(handler: H) -> Self {
+        Self { handler }
+    }
+}
+
+impl<H, I, O, E, C> Handler<I, O, E, C> for HeadAsGet<H>
+where
+    H: Handler<I, O, E, C>,
+    I: 'static + Sync,
+    O: 'static + Sync,
+    E: 'static + Sync,
+{
+    fn handle(&self, mut request: Request<I>, context: &mut C) -> Res<O, E> {
+        if request.method == Method::HEAD {
+            request.method = Method::GET;
+        }
+        self.handler.handle(request, context)
+    }
+}
+
+/// Respond to all OPTIONS requests with a function, for example for CORS
+/// preflight requests, before they reach routing. Other requests are passed
+/// to the handler.
+///
+/// # Example
+/// ```
+/// use jbhttp::prelude::*;
+///
+/// fn hello(req: RawRequest, _: &mut ()) -> Res<Vec<u8>, Vec<u8>> {
+///     Ok(Response::new(200).with_payload(b"Hello!".to_vec()))
+/// }
+///
+/// let handler = hello.handle_options(|_req: RawRequest, _: &mut ()| {
+///     Ok(Response::new(204).with_header("Allow", "GET, HEAD, OPTIONS"))
+/// });
+///
+/// let mut request = Request::default();
+/// request.method = Method::OPTIONS;
+/// let response = handler.handle(request, &mut ()).unwrap();
+/// assert_eq!(response.header("Allow"), Some("GET, HEAD, OPTIONS"));
+/// ```
+pub struct HandleOptions<H, F> {
+    f: F,
+    handler: H,
+}
+
+impl<H, F> HandleOptions<H, F> {
+    pub fn new(f: F, handler: H) -> Self {
+        Self { f, handler }
+    }
+}
+
+impl<H, F, I, O, E, C> Handler<I, O, E, C> for HandleOptions<H, F>
+where
+    H: Handler<I, O, E, C>,
+    F: Fn(Request<I>, &mut C) -> Res<O, E> + Send + Sync,
+    I: 'static + Sync,
+    O: 'static + Sync,
+    E: 'static + Sync,
+{
+    fn handle(&self, request: Request<I>, context: &mut C) -> Res<O, E> {
+        if request.method == Method::OPTIONS {
+            (self.f)(request, context)
+        } else {
+            self.handler.handle(request, context)
+        }
+    }
+}
+
+/// Rewrite requests in place before passing them to the handler, typically
+/// to change `path` before routing, as for API versioning: strip a prefix,
+/// or map paths depending on a header.
+///
+/// Query parameters are already parsed into `params`; changing `query` does
+/// not update them.
+///
+/// # Example
+/// ```
+/// use jbhttp::prelude::*;
+/// use jbhttp::router::Router;
+///
+/// fn items(req: RawRequest, _: &mut ()) -> Res<Vec<u8>, Vec<u8>> {
+///     Ok(Response::new(200).with_payload(b"items".to_vec()))
+/// }
+///
+/// let router = Router::new().with_route("/v2/items", items);
+/// let handler = router.rewrite_path(|req: &mut RawRequest| {
+///     if let Some(path) = req.path.strip_prefix("/legacy") {
+///         req.path = format!("/v2{}", path);
+///     }
+/// });
+///
+/// let request = Request {
+///     path: "/legacy/items".to_string(),
+///     ..Default::default()
+/// };
+/// let response = handler.handle(request, &mut ()).unwrap();
+/// assert_eq!(response.payload, Some(b"items".to_vec()));
+/// ```
+pub struct RewritePath<H, F> {
+    f: F,
+    handler: H,
+}
+
+impl<H, F> RewritePath<H, F> {
+    pub fn new(f: F, handler: H) -> Self {
+        Self { f, handler }
+    }
+}
+
+impl<H, F, I, O, E, C> Handler<I, O, E, C> for RewritePath<H, F>
+where
+    H: Handler<I, O, E, C>,
+    F: Fn(&mut Request<I>) + Send + Sync,
+    I: 'static + Sync,
+    O: 'static + Sync,
+    E: 'static + Sync,
+{
+    fn handle(&self, mut request: Request<I>, context: &mut C) -> Res<O, E> {
+        (self.f)(&mut request);
+        self.handler.handle(request, context)
+    }
+}
+
+/// A range of IPv4 or IPv6 addresses in CIDR notation, like `10.0.0.0/8` or
+/// `fd00::/8`; a single address is parsed as a range of one.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct IpCidr {
+    addr: IpAddr,
+    prefix_len: u8,
+}
+
+impl IpCidr {
+    /// Whether the range contains an address. IPv4 addresses mapped to IPv6
+    /// (`::ffff:a.b.c.d`) are treated as IPv4.
+    pub fn contains(&self, addr: IpAddr) -> bool {
+        match (self.addr, addr.to_canonical()) {
+            (IpAddr::V4(net), IpAddr::V4(addr)) => {
+                prefix_matches(&net.octets(), &addr.octets(), self.prefix_len)
+            }
+            (IpAddr::V6(net), IpAddr::V6(addr)) => {
+                prefix_matches(&net.octets(), &addr.octets(), self.prefix_len)
+            }
+            _ => false,
+        }
+    }
+}
+
+fn prefix_matches(net: &[u8], addr: &[u8], prefix_len: u8) -> bool {
+    let full = prefix_len as usize / 8;
+    let bits = prefix_len % 8;
+    net[..full] == addr[..full] && (bits == 0 || (net[full] ^ addr[full]) >> (8 - bits) == 0)
+}
+
+impl FromStr for IpCidr {
+    type Err = AddrParseError;
+    fn from_str(s: &str) -> Result<Self, AddrParseError> {
+        let (addr, prefix_len) = match s.split_once('/') {
+            Some((addr, prefix_len)) => (addr, Some(prefix_len)),
+            None => (s, None),
+        };
+        let addr: IpAddr = addr.parse()?;
+        let max = if addr.is_ipv4() { 32 } else { 128 };
+        let prefix_len = match prefix_len {
+            // An address can't be parsed from an empty string, to make an AddrParseError
+            Some(prefix_len) => match prefix_len.parse::<u8>() {
+                Ok(n) if n <= max && !prefix_len.starts_with('+') => n,
+                _ => return Err("".parse::<IpAddr>().unwrap_err()),
+            },
+            None => max,
+        };
+        Ok(Self { addr, prefix_len })
+    }
+}
+
+/// Allow or deny requests by client IP address, see
+/// [`Request::peer_addr`]: requests from denied addresses get
+/// `403 Forbidden` before reaching the handler.
+///
+/// Addresses in a `deny` range are denied, then addresses in an `allow`
+/// range are allowed; others are allowed by default, or denied after
+/// `deny_by_default`. Requests without a peer address (not served over a
+/// network) are only allowed by default.
+///
+/// # Example
+/// ```
+/// use jbhttp::prelude::*;
+///
+/// fn admin(req: RawRequest, _: &mut ()) -> Res<Vec<u8>, Vec<u8>> {
+///     Ok(Response::new(200))
+/// }
+///
+/// let handler = admin
+///     .ip_filter()
+///     .allow("10.0.0.0/8".parse().unwrap())
+///     .allow("::1".parse().unwrap())
+///     .deny("10.0.66.0/24".parse().unwrap())
+///     .deny_by_default();
+/// # assert_eq!(handler.handle(Request::default(), &mut ()).unwrap_err().status_code, 403);
+/// ```
+pub struct IpFilter<H> {
+    handler: H,
+    allow: Vec<IpCidr>,
+    deny: Vec<IpCidr>,
+    allow_by_default: bool,
+}
+
+impl<H> IpFilter<H> {
+    pub fn new(handler: H) -> Self {
+        Self {
+            handler,
+            allow: vec![],
+            deny: vec![],
+            allow_by_default: true,
+        }
+    }
+    pub fn allow(mut self, range: IpCidr) -> Self {
+        self.allow.push(range);
+        self
+    }
+    pub fn deny(mut self, range: IpCidr) -> Self {
+        self.deny.push(range);
+        self
+    }
+    /// Deny addresses not in an `allow` range.
+    pub fn deny_by_default(mut self) -> Self {
+        self.allow_by_default = false;
+        self
+    }
+    fn is_allowed(&self, addr: Option<IpAddr>) -> bool {
+        let addr = match addr {
+            Some(addr) => addr,
+            None => return self.allow_by_default,
+        };
+        if self.deny.iter().any(|range| range.contains(addr)) {
+            false
+        } else if self.allow.iter().any(|range| range.contains(addr)) {
+            true
+        } else {
+            self.allow_by_default
+        }
+    }
+}
+
+impl<H, I, O, E, C> Handler<I, O, E, C> for IpFilter<H>
+where
+    H: Handler<I, O, E, C>,
+    I: 'static + Sync,
+    O: 'static + Sync,
+    E: 'static + Sync,
+{
+    fn handle(&self, request: Request<I>, context: &mut C) -> Res<O, E> {
+        if self.is_allowed(request.peer_addr().map(|addr| addr.ip())) {
+            self.handler.handle(request, context)
+        } else {
+            Err(Response::new(403))
+        }
+    }
+}
+
+/// Current validators of a resource, for checking request preconditions.
+#[derive(Debug, Clone, Default)]
+pub struct Validators {
+    pub etag: Option<ETag>,
+    pub last_modified: Option<SystemTime>,
+}
+
+impl Validators {
+    pub fn new() -> Self {
+        Self::default()
+    }
+    pub fn with_etag(mut self, etag: ETag) -> Self {
+        self.etag = Some(etag);
+        self
+    }
+    pub fn with_last_modified(mut self, last_modified: SystemTime) -> Self {
+        self.last_modified = Some(last_modified);
+        self
+    }
+}
+
+/// Enforce *If-Match* and *If-Unmodified-Since* preconditions on unsafe
+/// methods (PUT, PATCH, etc.), to prevent lost updates. The function gets
+/// the current validators of the requested resource, or `None` if it does
+/// not exist. Responds with 412 Precondition Failed if a precondition is
+/// not met, or 400 Bad Request if *If-Match* is invalid.
+///
+/// *If-Unmodified-Since* is ignored when *If-Match* is present, or when the
+/// resource has no last modification date, as per RFC 7232.
+///
+/// # Example
+/// ```
+/// use jbhttp::filter::Validators;
+/// use jbhttp::prelude::*;
+/// use jbhttp::request::ETag;
+///
+/// fn update(req: RawRequest, _: &mut ()) -> Res<Vec<u8>, Vec<u8>> {
+///     Ok(Response::new(204))
+/// }
+///
+/// let handler = update.preconditions(|_req: &RawRequest, _: &mut ()| {
+///     Some(Validators::new().with_etag(ETag::strong("v2")))
+/// });
+///
+/// let mut stale = Request::default().with_header("If-Match", r#""v1""#);
+/// stale.method = Method::PUT;
+/// assert_eq!(handler.handle(stale, &mut ()).unwrap_err().status_code, 412);
+/// ```
+pub struct Preconditions<H, F> {
+    f: F,
+    handler: H,
+}
+
+impl<H, F> Preconditions<H, F> {
+    pub fn new(f: F, handler: H) -> Self {
+        Self { f, handler }
+    }
+}
+
+impl<H, F, I, O, E, C> Handler<I, O, E, C> for Preconditions<H, F>
+where
+    H: Handler<I, O, E, C>,
+    F: Fn(&Request<I>, &mut C) -> Option<Validators> + Send + Sync,
+    I: 'static + Sync,
+    O: 'static + Sync,
+    E: 'static + Sync,
+{
+    fn handle(&self, request: Request<I>, context: &mut C) -> Res<O, E> {
+        if request.method.is_safe() {
+            return self.handler.handle(request, context);
+        }
+        let if_match = match request.if_match() {
+            Ok(if_match) => if_match,
+            Err(_) => return Err(Response::new(400)),
+        };
+        let if_unmodified_since = request.if_unmodified_since();
+        if if_match.is_none() && if_unmodified_since.is_none() {
+            return self.handler.handle(request, context);
+        }
+        let validators = (self.f)(&request, context);
+        let passed = match (if_match, if_unmodified_since) {
+            (Some(if_match), _) => {
+                if_match.matches(validators.as_ref().and_then(|v| v.etag.as_ref()))
+            }
+            (None, Some(date)) => match validators.and_then(|v| v.last_modified) {
+                Some(last_modified) => HttpDate::from(last_modified) <= date,
+                // Without a modification date, there is nothing to compare
+                None => true,
+            },
+            (None, None) => true,
+        };
+        if passed {
+            self.handler.handle(request, context)
+        } else {
+            Err(Response::new(412))
+        }
+    }
+}
+
+/// Request id, stored in request extensions by [`CatchPanic`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RequestId(pub String);
+
+static REQUEST_ID_COUNTER: AtomicU64 = AtomicU64::new(1);
+
+impl RequestId {
+    /// Get the id of a request: an id already in its extensions, the value of
+    /// its *X-Request-Id* header, or a new id.
+    pub fn of<I>(request: &Request<I>) -> Self {
+        if let Some(id) = request.extensions.get::<RequestId>() {
+            return id.clone();
+        }
+        match request.headers.get(&Header::new("x-request-id")) {
+            Some(id) => Self(id.clone()),
+            None => Self(
+                REQUEST_ID_COUNTER
+                    .fetch_add(1, Ordering::Relaxed)
+                    .to_string(),
+            ),
+        }
+    }
+}
+
+/// A panic caught by [`CatchPanic`], to build an error response from.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Panic {
+    pub request_id: RequestId,
+    /// The panic message, if it was a string. Not meant for clients.
+    pub message: String,
+}
+
+type ErrorResponse<E> = Box<dyn Fn(&Panic) -> Response<E> + Send + Sync>;
+
+/// Catch panics in the handler and respond with 500 instead. The panic is
+/// logged along with the request id, which is also sent in the response's
+/// *X-Request-Id* header, so that users can report it and operators can
+/// find it in the logs. The panic message is never sent to the client.
+///
+/// The request id is added to request extensions, see [`RequestId`].
+///
+/// Panics are contained to the request: other requests to the handler
+/// keep being served. Handlers sharing state behind a `Mutex` should still
+/// expect it to be poisoned if they panic while holding the lock, and
+/// recover with `PoisonError::into_inner`.
+///
+/// # Example
+/// ```
+/// use jbhttp::prelude::*;
+///
+/// fn fragile(_req: RawRequest, _: &mut ()) -> Res<Vec<u8>, Vec<u8>> {
+///     panic!("oops")
+/// }
+///
+/// let handler = fragile.catch_panic().with_error_body(|id| {
+///     format!("internal error, request id: {}", id.0).into_bytes()
+/// });
+/// let request = Request::default().with_header("X-Request-Id", "abc123");
+/// let response = handler.handle(request, &mut ()).unwrap_err();
+/// assert_eq!(response.status_code, 500);
+/// assert_eq!(response.header("X-Request-Id"), Some("abc123"));
+/// assert_eq!(response.payload, Some(b"internal error, request id: abc123".to_vec()));
+/// ```
+///
+/// Or with a typed error response, which can then go through an error
+/// serializer:
+/// ```
+/// use jbhttp::prelude::*;
+///
+/// enum AppError {
+///     Internal(String),
+/// }
+///
+/// fn fragile(_req: RawRequest, _: &mut ()) -> Res<Vec<u8>, AppError> {
+///     panic!("oops")
+/// }
+///
+/// let handler = fragile.catch_panic().with_error_response(|panic| {
+///     Response::new(503).with_payload(AppError::Internal(panic.request_id.0.clone()))
+/// });
+/// let response = handler.handle(Request::default(), &mut ()).unwrap_err();
+/// assert_eq!(response.status_code, 503);
+/// assert!(matches!(response.payload, Some(AppError::Internal(_))));
+/// ```
+pub struct CatchPanic<H, E> {
+    handler: H,
+    error_response: Option<ErrorResponse<E>>,
+}
+
+impl<H, E> CatchPanic<H, E> {
+    pub fn new(handler: H) -> Self {
+        Self {
+            handler,
+            error_response: None,
+        }
+    }
+    /// Generate a body for 500 responses; by default they have no body.
+    pub fn with_error_body<F>(self, f: F) -> Self
+    where
+        F: Fn(&RequestId) -> E + 'static + Send + Sync,
+    {
+        self.with_error_response(move |panic| Response::new(500).with_payload(f(&panic.request_id)))
+    }
+    /// Generate the whole error response, instead of an empty 500 response.
+    /// The *X-Request-Id* header is added unless it is set.
+    pub fn with_error_response<F>(mut self, f: F) -> Self
+    where
+        F: Fn(&Panic) -> Response<E> + 'static + Send + Sync,
+    {
+        self.error_response = Some(Box::new(f));
+        self
+    }
+}
+
+fn panic_message(payload: &(dyn std::any::Any + Send)) -> &str {
+    if let Some(message) = payload.downcast_ref::<&str>() {
+        message
+    } else if let Some(message) = payload.downcast_ref::<String>() {
+        message
+    } else {
+        "<unknown>"
+    }
+}
+
+impl<H, I, O, E, C> Handler<I, O, E, C> for CatchPanic<H, E>
+where
+    H: Handler<I, O, E, C>,
+    I: 'static + Sync,
+    O: 'static + Sync,
+    E: 'static + Sync,
+{
+    fn handle(&self, mut request: Request<I>, context: &mut C) -> Res<O, E> {
+        let request_id = RequestId::of(&request);
+        request.extensions.insert(request_id.clone());
+        let result =
+            panic::catch_unwind(AssertUnwindSafe(|| self.handler.handle(request, context)));
+        match result {
+            Ok(res) => res,
+            Err(payload) => {
+                let panic = Panic {
+                    request_id,
+                    message: panic_message(payload.as_ref()).to_string(),
+                };
+                error!("request {} panicked: {}", panic.request_id.0, panic.message);
+                let mut response = match &self.error_response {
+                    Some(f) => f(&panic),
+                    None => Response::new(500),
+                };
+                if response.header("X-Request-Id").is_none() {
+                    response.set_header("X-Request-Id", &panic.request_id.0);
+                }
+                Err(response)
+            }
+        }
+    }
+}
+
+/// Counting semaphore, for [`ConcurrencyLimit`].
+struct Semaphore {
+    available: Mutex<usize>,
+    released: Condvar,
+}
+
+/// Released when dropped, including on panic.
+struct Permit<'a> {
+    semaphore: &'a Semaphore,
+}
+
+impl Semaphore {
+    fn new(permits: usize) -> Self {
+        Self {
+            available: Mutex::new(permits),
+            released: Condvar::new(),
+        }
+    }
+    /// Acquire a permit, waiting up to `timeout` for one to be released; a
+    /// timeout too long for a deadline waits indefinitely.
+    fn acquire(&self, timeout: Duration) -> Option<Permit<'_>> {
+        let deadline = Instant::now().checked_add(timeout);
+        let mut available = self.available.lock().unwrap();
+        while *available == 0 {
+            available = match deadline {
+                Some(deadline) => {
+                    let now = Instant::now();
+                    if now >= deadline {
+                        return None;
+                    }
+                    self.released
+                        .wait_timeout(available, deadline - now)
+                        .unwrap()
+                        .0
+                }
+                None => self.released.wait(available).unwrap(),
+            };
+        }
+        *available -= 1;
+        Some(Permit { semaphore: self })
+    }
+}
+
+impl<'a> Drop for Permit<'a> {
+    fn drop(&mut self) {
+        *self.semaphore.available.lock().unwrap() += 1;
+        self.semaphore.released.notify_one();
+    }
+}
+
+/// Limit how many requests the handler handles at the same time, to protect
+/// expensive endpoints. Requests over the limit get a 503 response, or
+/// another status set with `with_status`, such as 429; they can wait a
+/// little for a request to finish first, see `with_wait`.
+///
+/// # Example
+/// ```
+/// use std::time::Duration;
+/// use jbhttp::prelude::*;
+///
+/// fn report(req: RawRequest, _: &mut ()) -> Res<Vec<u8>, Vec<u8>> {
+///     Ok(Response::new(200).with_payload(b"...".to_vec()))
+/// }
+///
+/// let handler = report
+///     .with_concurrency_limit(2)
+///     .with_status(429)
+///     .with_wait(Duration::from_millis(100));
+/// # assert!(handler.handle(Request::default(), &mut ()).is_ok());
+/// ```
+pub struct ConcurrencyLimit<H> {
+    handler: H,
+    semaphore: Arc<Semaphore>,
+    status_code: u16,
+    wait: Duration,
+}
+
+impl<H> ConcurrencyLimit<H> {
+    pub fn new(limit: usize, handler: H) -> Self {
+        Self {
+            handler,
+            semaphore: Arc::new(Semaphore::new(limit)),
+            status_code: 503,
+            wait: Duration::ZERO,
+        }
+    }
+    /// Status code of responses to requests over the limit, 503 by default.
+    pub fn with_status(mut self, status_code: u16) -> Self {
+        self.status_code = status_code;
+        self
+    }
+    /// How long requests over the limit wait for another request to finish,
+    /// no wait by default.
+    pub fn with_wait(mut self, wait: Duration) -> Self {
+        self.wait = wait;
+        self
+    }
+}
+
+impl<H, I, O, E, C> Handler<I, O, E, C> for ConcurrencyLimit<H>
+where
+    H: Handler<I, O, E, C>,
+    I: 'static + Sync,
+    O: 'static + Sync,
+    E: 'static + Sync,
+{
+    fn handle(&self, request: Request<I>, context: &mut C) -> Res<O, E> {
+        match self.semaphore.acquire(self.wait) {
+            Some(_permit) => self.handler.handle(request, context),
+            None => Err(Response::new(self.status_code)),
+        }
+    }
+}
+
+/// Give the handler its own timeout, overriding the server's handler
+/// timeout (see [`Timeouts`](crate::server::Timeouts)) for this branch, like
+/// a longer one for a report or a shorter one for a health check.
+///
+/// Handlers can't be interrupted: the deadline is given to them with
+/// [`Request::deadline`], for long-running handlers to check and give up
+/// early. A response which comes after the deadline anyway is dropped, and
+/// the client gets a 503 response instead, or another status set with
+/// `with_status`, such as 504.
+///
+/// # Example
+/// ```
+/// use std::time::Duration;
+/// use jbhttp::prelude::*;
+/// use jbhttp::router::Router;
+///
+/// fn export(req: RawRequest, _: &mut ()) -> Res<Vec<u8>, Vec<u8>> {
+///     Ok(Response::new(200).with_payload(b"...".to_vec()))
+/// }
+///
+/// let router = Router::new().with_route("/export", export.with_timeout(Duration::from_secs(60)));
+/// # let request = Request { path: "/export".to_string(), ..Default::default() };
+/// # assert!(router.handle(request, &mut ()).is_ok());
+/// ```
+pub struct Timeout<H> {
+    handler: H,
+    timeout: Duration,
+    status_code: u16,
+}
+
+impl<H> Timeout<H> {
+    pub fn new(timeout: Duration, handler: H) -> Self {
+        Self {
+            handler,
+            timeout,
+            status_code: 503,
+        }
+    }
+    /// Status code of responses to requests which time out, 503 by default.
+    pub fn with_status(mut self, status_code: u16) -> Self {
+        self.status_code = status_code;
+        self
+    }
+}
+
+impl<H, I, O, E, C> Handler<I, O, E, C> for Timeout<H>
+where
+    H: Handler<I, O, E, C>,
+    I: 'static + Sync,
+    O: 'static + Sync,
+    E: 'static + Sync,
+{
+    fn handle(&self, mut request: Request<I>, context: &mut C) -> Res<O, E> {
+        let deadline = match Instant::now().checked_add(self.timeout) {
+            Some(deadline) => deadline,
+            None => {
+                request.extensions.remove::<Deadline>();
+                return self.handler.handle(request, context);
+            }
+        };
+        request.extensions.insert(Deadline(deadline));
+        let response = self.handler.handle(request, context);
+        if Instant::now() > deadline {
+            warn!("handler overran its timeout of {:?}", self.timeout);
+            return Err(Response::new(self.status_code));
+        }
+        response
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::handler::RawResult;
+    use crate::request::{PeerAddr, RawRequest};
+    use crate::router::Router;
+    use std::net::SocketAddr;
+    use std::time::{Duration, UNIX_EPOCH};
+
+    fn update(_: RawRequest, _: &mut ()) -> RawResult {
+        Ok(Response::new(204))
+    }
+
+    fn current(_: &RawRequest, _: &mut ()) -> Option<Validators> {
+        Some(
+            Validators::new()
+                .with_etag(ETag::strong("v2"))
+                .with_last_modified(UNIX_EPOCH + Duration::from_millis(784_111_777_500)),
+        )
+    }
+
+    fn status(method: Method, header: &str, value: &str) -> u16 {
+        let handler = update.preconditions(current);
+        let mut request = Request::default().with_header(header, value);
+        request.method = method;
+        match handler.handle(request, &mut ()) {
+            Ok(r) => r.status_code,
+            Err(r) => r.status_code,
+        }
+    }
+
+    #[test]
+    fn test_preconditions_if_match() {
+        assert_eq!(status(Method::PUT, "If-Match", r#""v2""#), 204);
+        assert_eq!(status(Method::PUT, "If-Match", r#""v1", "v2""#), 204);
+        assert_eq!(status(Method::PUT, "If-Match", "*"), 204);
+        assert_eq!(status(Method::PATCH, "If-Match", r#""v1""#), 412);
+        assert_eq!(status(Method::DELETE, "If-Match", r#"W/"v2""#), 412);
+        assert_eq!(status(Method::PUT, "If-Match", "v2"), 400);
+        assert_eq!(status(Method::GET, "If-Match", r#""v1""#), 204);
+        assert_eq!(status(Method::PUT, "X-Other", "v1"), 204);
+    }
+
+    #[test]
+    fn test_preconditions_if_unmodified_since() {
+        let since = "If-Unmodified-Since";
+        assert_eq!(
+            status(Method::PUT, since, "Sun, 06 Nov 1994 08:49:37 GMT"),
+            204
+        );
+        assert_eq!(
+            status(Method::PUT, since, "Sun, 06 Nov 1994 08:49:36 GMT"),
+            412
+        );
+        assert_eq!(status(Method::PUT, since, "not a date"), 204);
+
+        // Ignored without a modification date
+        let handler = update.preconditions(|_: &RawRequest, _: &mut ()| {
+            Some(Validators::new().with_etag(ETag::strong("v2")))
+        });
+        let mut request = Request::default().with_header(since, "Sun, 06 Nov 1994 08:49:36 GMT");
+        request.method = Method::PUT;
+        assert_eq!(handler.handle(request, &mut ()).unwrap().status_code, 204);
+    }
+
+    #[test]
+    fn test_preconditions_missing_resource() {
+        let handler = update.preconditions(|_: &RawRequest, _: &mut ()| None);
+        let mut request = Request::default().with_header("If-Match", "*");
+        request.method = Method::PUT;
+        assert_eq!(
+            handler.handle(request, &mut ()).unwrap_err().status_code,
+            412
+        );
+    }
+
+    #[test]
+    fn test_handle_options() {
+        let handler = update.handle_options(|_: RawRequest, _: &mut ()| Ok(Response::new(200)));
+        let request = Request {
+            method: Method::OPTIONS,
+            ..Default::default()
+        };
+        assert_eq!(handler.handle(request, &mut ()).unwrap().status_code, 200);
+        let request = Request::default();
+        assert_eq!(handler.handle(request, &mut ()).unwrap().status_code, 204);
+    }
+
+    #[test]
+    fn test_catch_panic() {
+        fn fragile(request: RawRequest, _: &mut ()) -> RawResult {
+            match request.path.as_str() {
+                "/panic" => panic!("secret details"),
+                _ => Ok(Response::new(200).with_payload(
+                    request
+                        .extensions
+                        .get::<RequestId>()
+                        .unwrap()
+                        .0
+                        .clone()
+                        .into_bytes(),
+                )),
+            }
+        }
+        let handler = fragile.catch_panic();
+        let request = |path: &str| RawRequest {
+            path: path.to_string(),
+            ..Default::default()
+        };
+
+        let response = handler.handle(request("/panic"), &mut ()).unwrap_err();
+        assert_eq!(response.status_code, 500);
+        assert_eq!(response.payload, None);
+        let id = response.header("X-Request-Id").unwrap().to_string();
+        let next = handler.handle(request("/panic"), &mut ()).unwrap_err();
+        assert_ne!(next.header("X-Request-Id"), Some(&id[..]));
+
+        let response = handler
+            .handle(
+                request("/panic").with_header("X-Request-Id", "req-1"),
+                &mut (),
+            )
+            .unwrap_err();
+        assert_eq!(response.header("X-Request-Id"), Some("req-1"));
+
+        let response = handler
+            .handle(request("/ok").with_header("X-Request-Id", "req-2"), &mut ())
+            .unwrap();
+        assert_eq!(response.payload, Some(b"req-2".to_vec()));
+    }
+
+    #[test]
+    fn test_catch_panic_error_response() {
+        #[derive(Debug, PartialEq)]
+        enum AppError {
+            Crashed(String),
+        }
+
+        // Flaky shared state: panics every other call while holding the lock
+        let calls = Mutex::new(0u32);
+        let flaky = move |_: RawRequest, _: &mut ()| -> Res<u32, AppError> {
+            let mut calls = calls.lock().unwrap_or_else(|e| e.into_inner());
+            *calls += 1;
+            if (*calls).is_multiple_of(2) {
+                panic!("call {}", calls);
+            }
+            Ok(Response::new(200).with_payload(*calls))
+        };
+        let handler = flaky.catch_panic().with_error_response(|panic| {
+            Response::new(502)
+                .with_header("X-Request-Id", "custom")
+                .with_payload(AppError::Crashed(format!(
+                    "{}: {}",
+                    panic.request_id.0, panic.message
+                )))
+        });
+        let request = || RawRequest::default().with_header("X-Request-Id", "req-1");
+
+        assert_eq!(handler.handle(request(), &mut ()).unwrap().payload, Some(1));
+        let response = handler.handle(request(), &mut ()).unwrap_err();
+        assert_eq!(response.status_code, 502);
+        assert_eq!(response.header("X-Request-Id"), Some("custom"));
+        assert_eq!(
+            response.payload,
+            Some(AppError::Crashed("req-1: call 2".to_string()))
+        );
+        // The handler keeps working, with its state intact
+        assert_eq!(handler.handle(request(), &mut ()).unwrap().payload, Some(3));
+        assert!(handler.handle(request(), &mut ()).is_err());
+    }
+
+    #[test]
+    fn test_concurrency_limit() {
+        use std::sync::atomic::{AtomicBool, AtomicUsize};
+
+        let entered = Arc::new(AtomicUsize::new(0));
+        let release = Arc::new(AtomicBool::new(false));
+        let slow = {
+            let (entered, release) = (entered.clone(), release.clone());
+            move |_: RawRequest, _: &mut ()| -> RawResult {
+                entered.fetch_add(1, Ordering::SeqCst);
+                while !release.load(Ordering::SeqCst) {
+                    std::thread::sleep(Duration::from_millis(1));
+                }
+                Ok(Response::new(200))
+            }
+        };
+        let handler = Arc::new(
+            slow.with_concurrency_limit(2)
+                .with_status(429)
+                .with_wait(Duration::from_millis(20)),
+        );
+        let status = move |handler: &dyn Handler<Vec<u8>, Vec<u8>, Vec<u8>, ()>| match handler
+            .handle(Request::default(), &mut ())
+        {
+            Ok(r) => r.status_code,
+            Err(r) => r.status_code,
+        };
+        let busy: Vec<_> = (0..2)
+            .map(|_| {
+                let handler = handler.clone();
+                std::thread::spawn(move || status(handler.as_ref()))
+            })
+            .collect();
+        while entered.load(Ordering::SeqCst) < 2 {
+            std::thread::sleep(Duration::from_millis(1));
+        }
+        let start = Instant::now();
+        assert_eq!(status(handler.as_ref()), 429);
+        assert!(start.elapsed() >= Duration::from_millis(20));
+
+        release.store(true, Ordering::SeqCst);
+        for thread in busy {
+            assert_eq!(thread.join().unwrap(), 200);
+        }
+        assert_eq!(status(handler.as_ref()), 200);
+    }
+
+    #[test]
+    fn test_semaphore_wait_forever() {
+        let semaphore = Arc::new(Semaphore::new(1));
+        let permit = semaphore.acquire(Duration::MAX).unwrap();
+        let waiting = {
+            let semaphore = semaphore.clone();
+            std::thread::spawn(move || semaphore.acquire(Duration::MAX).is_some())
+        };
+        std::thread::sleep(Duration::from_millis(20));
+        assert!(!waiting.is_finished());
+        drop(permit);
+        assert!(waiting.join().unwrap());
+    }
+
+    #[test]
+    fn test_ip_cidr() {
+        let cidr = |s: &str| s.parse::<IpCidr>().unwrap();
+        let ip = |s: &str| s.parse::<IpAddr>().unwrap();
+        assert!(cidr("10.0.0.0/8").contains(ip("10.255.0.1")));
+        assert!(!cidr("10.0.0.0/8").contains(ip("11.0.0.1")));
+        assert!(cidr("192.168.4.0/22").contains(ip("192.168.7.255")));
+        assert!(!cidr("192.168.4.0/22").contains(ip("192.168.8.0")));
+        assert!(cidr("0.0.0.0/0").contains(ip("1.2.3.4")));
+        assert!(cidr("1.2.3.4").contains(ip("1.2.3.4")));
+        assert!(!cidr("1.2.3.4").contains(ip("1.2.3.5")));
+        assert!(cidr("10.0.0.0/8").contains(ip("::ffff:10.1.2.3")));
+        assert!(!cidr("10.0.0.0/8").contains(ip("::1")));
+        assert!(cidr("fd00::/8").contains(ip("fd12:3456::1")));
+        assert!(!cidr("fd00::/8").contains(ip("fe80::1")));
+        assert!(cidr("2001:db8::/33").contains(ip("2001:db8:7fff::1")));
+        assert!(!cidr("2001:db8::/33").contains(ip("2001:db8:8000::1")));
+        for invalid in [
+            "10.0.0.0/33",
+            "::/129",
+            "10.0.0.0/",
+            "10.0.0/8",
+            "10.0.0.0/+8",
+        ] {
+            assert!(invalid.parse::<IpCidr>().is_err(), "{}", invalid);
+        }
+    }
+
+    #[test]
+    fn test_ip_filter() {
+        let status = |handler: &dyn Handler<Vec<u8>, Vec<u8>, Vec<u8>, ()>, addr: Option<&str>| {
+            let mut request = RawRequest::default();
+            if let Some(addr) = addr {
+                let addr = SocketAddr::new(addr.parse().unwrap(), 1234);
+                request.extensions.insert(PeerAddr(addr));
+            }
+            match handler.handle(request, &mut ()) {
+                Ok(response) => response.status_code,
+                Err(response) => response.status_code,
+            }
+        };
+        let handler = update
+            .ip_filter()
+            .allow("10.0.0.0/8".parse().unwrap())
+            .allow("fd00::/8".parse().unwrap())
+            .deny("10.0.66.0/24".parse().unwrap());
+        assert_eq!(status(&handler, Some("10.1.2.3")), 204);
+        assert_eq!(status(&handler, Some("fd00::1")), 204);
+        assert_eq!(status(&handler, Some("10.0.66.1")), 403);
+        // Allowed by default
+        assert_eq!(status(&handler, Some("192.168.0.1")), 204);
+        assert_eq!(status(&handler, Some("::1")), 204);
+        assert_eq!(status(&handler, None), 204);
+
+        let handler = handler.deny_by_default();
+        assert_eq!(status(&handler, Some("10.1.2.3")), 204);
+        assert_eq!(status(&handler, Some("::ffff:10.1.2.3")), 204);
+        assert_eq!(status(&handler, Some("fd00::1")), 204);
+        assert_eq!(status(&handler, Some("10.0.66.1")), 403);
+        assert_eq!(status(&handler, Some("192.168.0.1")), 403);
+        assert_eq!(status(&handler, Some("fe80::1")), 403);
+        assert_eq!(status(&handler, None), 403);
+    }
+
+    #[test]
+    fn test_timeout() {
+        fn slow(request: RawRequest, _: &mut ()) -> RawResult {
+            std::thread::sleep(Duration::from_millis(50));
+            let status = if request.deadline().is_some() {
+                200
+            } else {
+                500
+            };
+            Ok(Response::new(status))
+        }
+        fn fast(request: RawRequest, _: &mut ()) -> RawResult {
+            let remaining = request.deadline().unwrap() - Instant::now();
+            assert!(remaining > Duration::from_secs(50));
+            Ok(Response::new(200))
+        }
+        let router = Router::new()
+            .with_route("/slow", slow.with_timeout(Duration::from_millis(10)))
+            .with_route(
+                "/slow/504",
+                slow.with_timeout(Duration::from_millis(10))
+                    .with_status(504),
+            )
+            .with_route("/patient", slow.with_timeout(Duration::from_secs(60)))
+            .with_route("/fast", fast.with_timeout(Duration::from_secs(60)))
+            .with_route("/forever", slow.with_timeout(Duration::MAX));
+        let status = |path: &str| {
+            // The server's deadline is overridden
+            let mut request = RawRequest {
+                path: path.to_string(),
+                ..Default::default()
+            };
+            request.extensions.insert(Deadline(Instant::now()));
+            match router.handle(request, &mut ()) {
+                Ok(response) => response.status_code,
+                Err(response) => response.status_code,
+            }
+        };
+        assert_eq!(status("/slow"), 503);
+        assert_eq!(status("/slow/504"), 504);
+        assert_eq!(status("/patient"), 200);
+        assert_eq!(status("/fast"), 200);
+        assert_eq!(status("/forever"), 500);
+    }
+}
@@ -6,14 +6,21 @@ use std::path::{Path, PathBuf};
 use log::warn;
 
 use crate::handler::{Handler, Res};
-use crate::request::Request;
+use crate::request::{AcceptEncoding, ContentDisposition, Request};
 use crate::response::Response;
 
 /// Handler which serves files under the given root directory.
 pub struct DirectoryHandler {
     pub root: PathBuf,
+    precompressed: bool,
+    download: bool,
+    not_found_page: Option<PathBuf>,
+    spa_fallback: bool,
 }
 
+/// Sidecar file extensions and their content encoding, in order of preference.
+const PRECOMPRESSED: [(&str, &str); 2] = [("br", "br"), ("gz", "gzip")];
+
 impl DirectoryHandler {
     /// Create a new DirectoryHandler.
     ///
@@ -22,8 +29,105 @@ impl DirectoryHandler {
     pub fn new(root: &Path) -> Result<Self, io::Error> {
         Ok(Self {
             root: root.canonicalize()?,
+            precompressed: false,
+            download: false,
+            not_found_page: None,
+            spa_fallback: false,
         })
     }
+    /// Serve pre-compressed `.br` or `.gz` sidecar files, when they exist
+    /// next to the requested file and the client accepts the encoding.
+    pub fn with_precompressed(mut self, precompressed: bool) -> Self {
+        self.precompressed = precompressed;
+        self
+    }
+    /// Serve files as downloads, with a *Content-Disposition: attachment*
+    /// header naming the file, so browsers save them rather than display
+    /// them.
+    pub fn with_download(mut self, download: bool) -> Self {
+        self.download = download;
+        self
+    }
+    /// Serve this page, relative to the root, like `404.html`, as the body
+    /// of 404 responses.
+    pub fn with_not_found_page(mut self, path: &Path) -> Self {
+        self.not_found_page = Some(path.to_path_buf());
+        self
+    }
+    /// Serve `index.html` from the root, with a 200 status, for paths which
+    /// are not found, so a single-page app can route them. Paths with a file
+    /// extension, like `/app.js`, are still not found, so missing assets
+    /// aren't masked.
+    pub fn with_spa_fallback(mut self, spa_fallback: bool) -> Self {
+        self.spa_fallback = spa_fallback;
+        self
+    }
+    /// Response for a path which is not found.
+    fn not_found(&self, path: &str) -> Res<Vec<u8>, Vec<u8>> {
+        if self.spa_fallback && Path::new(path).extension().is_none() {
+            if let Some(page) = self.page(Path::new("index.html")) {
+                return Ok(Response::new(200)
+                    .with_payload(page)
+                    .with_header("Content-Type", "text/html"));
+            }
+        }
+        let page = match &self.not_found_page {
+            Some(page) => page,
+            None => return Err(Response::new(404)),
+        };
+        match self.page(page) {
+            Some(contents) => Err(Response::new(404)
+                .with_payload(contents)
+                .with_header("Content-Type", page_content_type(page))),
+            None => Err(Response::new(404)),
+        }
+    }
+    /// Read a page under the root.
+    fn page(&self, path: &Path) -> Option<Vec<u8>> {
+        let path = self.root.join(path).canonicalize().ok()?;
+        if !is_parent(&self.root, &path) {
+            return None;
+        }
+        fs::read(path).ok()
+    }
+    /// Find the pre-compressed version of a file the client prefers, by
+    /// quality then the order of `PRECOMPRESSED`; returns its path and
+    /// content encoding. None if the client prefers no coding, by giving
+    /// *identity* a higher quality, or accepts none of the existing ones.
+    fn precompressed_file(
+        &self,
+        filepath: &Path,
+        accept: &AcceptEncoding,
+    ) -> Option<(PathBuf, &'static str)> {
+        let mut best: Option<(PathBuf, &'static str, f32)> = None;
+        for (extension, encoding) in PRECOMPRESSED.iter() {
+            let q = accept.quality(encoding);
+            if q <= 0.0 || best.as_ref().is_some_and(|(_, _, best_q)| q <= *best_q) {
+                continue;
+            }
+            let mut sidecar = filepath.as_os_str().to_owned();
+            sidecar.push(".");
+            sidecar.push(extension);
+            if let Ok(sidecar) = PathBuf::from(sidecar).canonicalize() {
+                if sidecar.is_file() && is_parent(&self.root, &sidecar) {
+                    best = Some((sidecar, encoding, q));
+                }
+            }
+        }
+        let (sidecar, encoding, q) = best?;
+        if accept.quality("identity") > q {
+            return None;
+        }
+        Some((sidecar, encoding))
+    }
+}
+
+/// Content type of a page, HTML or unknown.
+fn page_content_type(page: &Path) -> &'static str {
+    match page.extension().and_then(|e| e.to_str()) {
+        Some("html") | Some("htm") => "text/html",
+        _ => "application/octet-stream",
+    }
 }
 
 /// Check if root is parent of target. Make sure both are canonical
@@ -43,9 +147,18 @@ fn is_parent(root: &Path, target: &Path) -> bool {
 
 impl Handler<Vec<u8>, Vec<u8>, Vec<u8>, ()> for DirectoryHandler {
     fn handle(&self, request: Request<Vec<u8>>, _context: &mut ()) -> Res<Vec<u8>, Vec<u8>> {
-        let filepath = match self.root.join(&request.path[1..]).canonicalize() {
+        let mut filepath = self.root.clone();
+        for segment in request.path_segments() {
+            // Decoded segments could otherwise climb up, or be absolute
+            if segment == ".." || segment.contains(['/', '\\', '\0']) {
+                warn!("invalid path segment: {:?}", segment);
+                return Err(Response::new(404));
+            }
+            filepath.push(&*segment);
+        }
+        let filepath = match filepath.canonicalize() {
             Ok(p) => p,
-            Err(_) => return Err(Response::new(400)),
+            Err(_) => return self.not_found(&request.path),
         };
 
         // Prevent serving files above root from path traversals like
@@ -55,34 +168,288 @@ impl Handler<Vec<u8>, Vec<u8>, Vec<u8>, ()> for DirectoryHandler {
             return Err(Response::new(404));
         }
 
+        let mut headers = vec![];
+        let disposition;
         let (contents, content_type) = if filepath.is_file() {
+            if self.download {
+                let filename = filepath.file_name().unwrap_or_default().to_string_lossy();
+                disposition = ContentDisposition::attachment(&filename).to_string();
+                headers.push(("Content-Disposition", &disposition[..]));
+            }
+            let mut filepath = filepath;
+            if self.precompressed {
+                headers.push(("Vary", "Accept-Encoding"));
+                // An invalid header is treated as no header, identity only
+                let accept = request.accept_encoding().ok().flatten();
+                if let Some((sidecar, encoding)) =
+                    accept.and_then(|accept| self.precompressed_file(&filepath, &accept))
+                {
+                    headers.push(("Content-Encoding", encoding));
+                    filepath = sidecar;
+                }
+            }
             match fs::read(&filepath) {
                 Ok(contents) => (contents, "application/octet-stream"),
-                Err(_) => return Err(Response::new(404)),
+                Err(_) => return self.not_found(&request.path),
             }
         } else if filepath.is_dir() {
             match fs::read_dir(&filepath) {
                 Ok(dirs) => {
                     let mut dirs_vec = vec![];
-                    for dir in dirs {
-                        if let Ok(dir) = dir {
-                            let path = dir.path();
-                            if let Some(file_name) = path.file_name() {
-                                dirs_vec.push(file_name.to_str().unwrap().to_string());
-                            }
+                    for dir in dirs.flatten() {
+                        let path = dir.path();
+                        if let Some(file_name) = path.file_name() {
+                            dirs_vec.push(file_name.to_str().unwrap().to_string());
                         }
                     }
                     dirs_vec.push("".to_string());
                     (dirs_vec.join("\n").into_bytes(), "text/plain")
                 }
-                Err(_) => return Err(Response::new(404)),
+                Err(_) => return self.not_found(&request.path),
             }
         } else {
-            return Err(Response::new(404));
+            return self.not_found(&request.path);
         };
 
         Ok(Response::new(200)
             .with_payload(contents)
-            .with_header("Content-Type", content_type))
+            .with_header("Content-Type", content_type)
+            .with_headers(&headers))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::request::parser::RequestParser;
+
+    /// A temporary directory with files, removed on drop.
+    struct TempDir {
+        root: PathBuf,
+    }
+
+    impl TempDir {
+        fn new(name: &str, files: &[(&str, &[u8])]) -> Self {
+            let root = std::env::temp_dir().join(format!("jbhttp-{}-{}", name, std::process::id()));
+            for (path, contents) in files {
+                let path = root.join(path);
+                fs::create_dir_all(path.parent().unwrap()).unwrap();
+                fs::write(path, contents).unwrap();
+            }
+            Self { root }
+        }
+        fn handler(&self) -> DirectoryHandler {
+            DirectoryHandler::new(&self.root).unwrap()
+        }
+    }
+
+    impl Drop for TempDir {
+        fn drop(&mut self) {
+            let _ = fs::remove_dir_all(&self.root);
+        }
+    }
+
+    fn get(
+        handler: &DirectoryHandler,
+        path: &str,
+        accept_encoding: Option<&str>,
+    ) -> Response<Vec<u8>> {
+        let mut request: Request<Vec<u8>> = Request {
+            path: path.to_string(),
+            ..Default::default()
+        };
+        if let Some(accept_encoding) = accept_encoding {
+            request = request.with_header("Accept-Encoding", accept_encoding);
+        }
+        handler.handle(request, &mut ()).unwrap()
+    }
+
+    #[test]
+    fn test_precompressed() {
+        let dir = TempDir::new(
+            "precompressed",
+            &[
+                ("style.css", b"plain"),
+                ("style.css.gz", b"gzipped"),
+                ("app.js", b"plain"),
+                ("app.js.gz", b"gzipped"),
+                ("app.js.br", b"brotli"),
+            ],
+        );
+        let handler = dir.handler().with_precompressed(true);
+
+        let response = get(&handler, "/style.css", Some("gzip, br"));
+        assert_eq!(response.payload, Some(b"gzipped".to_vec()));
+        assert_eq!(response.header("Content-Encoding"), Some("gzip"));
+        assert_eq!(
+            response.header("Content-Type"),
+            Some("application/octet-stream")
+        );
+
+        let response = get(&handler, "/app.js", Some("gzip, br"));
+        assert_eq!(response.payload, Some(b"brotli".to_vec()));
+        assert_eq!(response.header("Content-Encoding"), Some("br"));
+
+        // By quality, then server preference
+        let encoding = |path, accept_encoding| {
+            get(&handler, path, Some(accept_encoding))
+                .header("Content-Encoding")
+                .map(str::to_string)
+        };
+        assert_eq!(
+            encoding("/app.js", "gzip, br;q=0.5").as_deref(),
+            Some("gzip")
+        );
+        assert_eq!(encoding("/app.js", "*").as_deref(), Some("br"));
+        assert_eq!(encoding("/app.js", "br;q=0, *").as_deref(), Some("gzip"));
+        assert_eq!(encoding("/app.js", "GZIP;q=0.8, identity").as_deref(), None);
+        assert_eq!(
+            encoding("/style.css", "br, gzip;q=0.1").as_deref(),
+            Some("gzip")
+        );
+        assert_eq!(encoding("/style.css", "deflate").as_deref(), None);
+
+        let response = get(&handler, "/style.css", None);
+        assert_eq!(response.payload, Some(b"plain".to_vec()));
+        assert_eq!(response.header("Content-Encoding"), None);
+        assert_eq!(response.header("Vary"), Some("Accept-Encoding"));
+
+        let response = get(&dir.handler(), "/style.css", Some("gzip"));
+        assert_eq!(response.payload, Some(b"plain".to_vec()));
+        assert_eq!(response.header("Content-Encoding"), None);
+    }
+
+    #[test]
+    fn test_download() {
+        let dir = TempDir::new("download", &[("été \"menu\".txt", b"coffee")]);
+        let handler = dir.handler().with_download(true);
+
+        let request =
+            RequestParser::new(&b"GET /%C3%A9t%C3%A9%20%22menu%22.txt HTTP/1.1\r\n\r\n"[..])
+                .parse()
+                .unwrap();
+        let response = handler.handle(request, &mut ()).unwrap();
+        assert_eq!(response.status_code, 200);
+        assert_eq!(response.payload, Some(b"coffee".to_vec()));
+        assert_eq!(
+            response.header("Content-Disposition"),
+            Some(
+                "attachment; filename=\"_t_ \\\"menu\\\".txt\"; \
+                 filename*=UTF-8''%C3%A9t%C3%A9%20%22menu%22.txt"
+            )
+        );
+        // Directory listings are not downloads
+        let response = get(&handler, "/", None);
+        assert_eq!(response.header("Content-Disposition"), None);
+
+        let response = get(&dir.handler(), "/%C3%A9t%C3%A9%20%22menu%22.txt", None);
+        assert_eq!(response.payload, Some(b"coffee".to_vec()));
+        assert_eq!(response.header("Content-Disposition"), None);
+    }
+
+    /// Parse a GET request for the path, as sent by a client.
+    fn parse_get(path: &str) -> Request<Vec<u8>> {
+        let request = format!("GET {} HTTP/1.1\r\n\r\n", path);
+        RequestParser::new(request.as_bytes()).parse().unwrap()
+    }
+
+    #[test]
+    fn test_percent_encoded_path() {
+        let dir = TempDir::new(
+            "percent-encoded",
+            &[("my file.txt", b"mine"), ("été/100%.txt", b"full")],
+        );
+        let handler = dir.handler();
+        let response = handler
+            .handle(parse_get("/my%20file.txt"), &mut ())
+            .unwrap();
+        assert_eq!(response.payload, Some(b"mine".to_vec()));
+        let response = handler
+            .handle(parse_get("/%C3%A9t%C3%A9/100%25.txt"), &mut ())
+            .unwrap();
+        assert_eq!(response.payload, Some(b"full".to_vec()));
+
+        // Escapes can't be used to climb out of the root
+        let dir = TempDir::new(
+            "percent-encoded-root",
+            &[("secret.txt", b"secret"), ("public/index.html", b"")],
+        );
+        let handler = DirectoryHandler::new(&dir.root.join("public")).unwrap();
+        for path in [
+            "/..%2Fsecret.txt",
+            "/%2E%2E/secret.txt",
+            "/..%5Csecret.txt",
+            "/secret.txt%00",
+        ] {
+            let response = handler.handle(parse_get(path), &mut ()).unwrap_err();
+            assert_eq!(response.status_code, 404, "{}", path);
+        }
+    }
+
+    fn status(handler: &DirectoryHandler, path: &str) -> (u16, Option<Vec<u8>>) {
+        let request: Request<Vec<u8>> = Request {
+            path: path.to_string(),
+            ..Default::default()
+        };
+        match handler.handle(request, &mut ()) {
+            Ok(response) => (response.status_code, response.payload),
+            Err(response) => (response.status_code, response.payload),
+        }
+    }
+
+    #[test]
+    fn test_not_found() {
+        let dir = TempDir::new(
+            "not-found",
+            &[
+                ("index.html", b"app"),
+                ("404.html", b"not here"),
+                ("assets/app.js", b"js"),
+            ],
+        );
+
+        assert_eq!(status(&dir.handler(), "/missing"), (404, None));
+
+        let handler = dir.handler().with_not_found_page(Path::new("404.html"));
+        assert_eq!(
+            status(&handler, "/missing"),
+            (404, Some(b"not here".to_vec()))
+        );
+        assert_eq!(
+            status(&handler, "/assets/missing.js"),
+            (404, Some(b"not here".to_vec()))
+        );
+        assert_eq!(
+            status(&handler, "/assets/app.js"),
+            (200, Some(b"js".to_vec()))
+        );
+
+        let handler = handler.with_spa_fallback(true);
+        let response = handler
+            .handle(
+                Request {
+                    path: "/users/42".to_string(),
+                    ..Default::default()
+                },
+                &mut (),
+            )
+            .unwrap();
+        assert_eq!(response.payload, Some(b"app".to_vec()));
+        assert_eq!(response.header("Content-Type"), Some("text/html"));
+        assert_eq!(
+            status(&handler, "/assets/missing.js"),
+            (404, Some(b"not here".to_vec()))
+        );
+        assert_eq!(
+            status(&handler, "/assets/app.js"),
+            (200, Some(b"js".to_vec()))
+        );
+
+        // Pages outside the root are never served
+        let handler = DirectoryHandler::new(&dir.root.join("assets"))
+            .unwrap()
+            .with_not_found_page(Path::new("../404.html"))
+            .with_spa_fallback(true);
+        assert_eq!(status(&handler, "/missing"), (404, None));
     }
 }
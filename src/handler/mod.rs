@@ -1,11 +1,19 @@
 //! Base for all request handlers.
 use crate::auth::{AuthError, Authenticator};
+use crate::content::encoding::Compress;
+use crate::content::error_body::DefaultErrorBody;
+#[cfg(feature = "json")]
+use crate::content::template::{RenderTemplates, TemplateEngine};
 use crate::content::{
     MediaTypeDeserializer, MediaTypeErrorSerializer, MediaTypeSerde, MediaTypeSerializer,
 };
-use crate::filter::{ErrFilter, OkFilter, RequestFilter, ResFilter};
+use crate::filter::{
+    CatchPanic, ConcurrencyLimit, ErrFilter, HandleOptions, HeadAsGet, IpFilter, OkFilter,
+    Preconditions, RequestFilter, ResFilter, RewritePath, Timeout, Validators,
+};
 use crate::request::Request;
 use crate::response::Response;
+use std::time::Duration;
 
 pub mod directory;
 
@@ -59,6 +67,94 @@ where
     {
         ErrFilter::new(f, self)
     }
+    /// Respond with an error, 500 by default, when the handler panics, see
+    /// [`CatchPanic`].
+    fn catch_panic(self) -> CatchPanic<Self, E>
+    where
+        Self: Sized,
+    {
+        CatchPanic::new(self)
+    }
+    /// Fill empty error responses with a body describing the status, see
+    /// [`DefaultErrorBody`].
+    fn default_error_bodies(self) -> DefaultErrorBody<Self>
+    where
+        Self: Sized,
+    {
+        DefaultErrorBody::new(self)
+    }
+    /// Compress response payloads as negotiated with *Accept-Encoding*, see
+    /// [`Compress`].
+    fn with_compression(self) -> Compress<Self>
+    where
+        Self: Sized,
+    {
+        Compress::new(self)
+    }
+    /// Limit how many requests are handled at the same time, see
+    /// [`ConcurrencyLimit`].
+    fn with_concurrency_limit(self, limit: usize) -> ConcurrencyLimit<Self>
+    where
+        Self: Sized,
+    {
+        ConcurrencyLimit::new(limit, self)
+    }
+    /// Give the handler its own timeout, overriding the server's, see
+    /// [`Timeout`].
+    fn with_timeout(self, timeout: Duration) -> Timeout<Self>
+    where
+        Self: Sized,
+    {
+        Timeout::new(timeout, self)
+    }
+    /// Render [`Template`](crate::content::template::Template) payloads into
+    /// HTML with a template engine, see [`RenderTemplates`].
+    #[cfg(feature = "json")]
+    fn render_templates<T>(self, engine: T) -> RenderTemplates<Self, T>
+    where
+        Self: Sized,
+        T: TemplateEngine,
+    {
+        RenderTemplates::new(engine, self)
+    }
+    /// Handle HEAD requests like GET requests.
+    fn head_as_get(self) -> HeadAsGet<Self>
+    where
+        Self: Sized,
+    {
+        HeadAsGet::new(self)
+    }
+    /// Respond to all OPTIONS requests with a function, see [`HandleOptions`].
+    fn handle_options<F>(self, f: F) -> HandleOptions<Self, F>
+    where
+        F: Fn(Request<I>, &mut C) -> Res<O, E> + Send + Sync,
+        Self: Sized,
+    {
+        HandleOptions::new(f, self)
+    }
+    /// Allow or deny requests by client IP address, see [`IpFilter`].
+    fn ip_filter(self) -> IpFilter<Self>
+    where
+        Self: Sized,
+    {
+        IpFilter::new(self)
+    }
+    /// Rewrite requests, such as their path before routing, see [`RewritePath`].
+    fn rewrite_path<F>(self, f: F) -> RewritePath<Self, F>
+    where
+        F: Fn(&mut Request<I>) + Send + Sync,
+        Self: Sized,
+    {
+        RewritePath::new(f, self)
+    }
+    /// Enforce *If-Match* and *If-Unmodified-Since* on unsafe methods, see [`Preconditions`].
+    fn preconditions<F>(self, f: F) -> Preconditions<Self, F>
+    where
+        F: Fn(&Request<I>, &mut C) -> Option<Validators> + Send + Sync,
+        Self: Sized,
+    {
+        Preconditions::new(f, self)
+    }
     fn serialized(self) -> MediaTypeSerializer<Self, I, O>
     where
         Self: Sized,
@@ -1,6 +1,8 @@
 //! I/O utilities.
+use std::cell::RefCell;
 use std::io;
 use std::io::prelude::*;
+use std::time::{Duration, Instant};
 
 /// Converts LF to CRLF in the inner stream.
 pub struct ConvertLFtoCRLF<R> {
@@ -64,10 +66,451 @@ impl<R: Read, W: Write> Write for ReadWriteAdapter<R, W> {
     }
 }
 
+/// An in-memory stream which reads from a fixed input and records all
+/// output, for serving requests without a network connection.
+///
+/// # Example
+/// ```
+/// use jbhttp::io::MemoryStream;
+/// use jbhttp::prelude::*;
+/// use jbhttp::server::StreamServer;
+///
+/// fn handle_hello(req: RawRequest, _: &mut ()) -> Res<Vec<u8>, Vec<u8>> {
+///     Ok(Response::new(200).with_payload(req.path.into_bytes()))
+/// }
+///
+/// let stream = MemoryStream::new(b"GET /a HTTP/1.1\r\n\r\nGET /b HTTP/1.1\r\n\r\n");
+/// let mut server = StreamServer::new(stream, handle_hello);
+/// Server::<()>::serve_forever(&mut server);
+///
+/// let output = std::str::from_utf8(server.get_ref().output()).unwrap();
+/// assert_eq!(output.matches("HTTP/1.1 200 OK").count(), 2);
+/// assert!(output.find("/a").unwrap() < output.find("/b").unwrap());
+/// ```
+pub struct MemoryStream {
+    input: io::Cursor<Vec<u8>>,
+    output: Vec<u8>,
+}
+
+impl MemoryStream {
+    pub fn new(input: &[u8]) -> Self {
+        Self {
+            input: io::Cursor::new(input.to_vec()),
+            output: vec![],
+        }
+    }
+    /// Get everything written to the stream so far.
+    pub fn output(&self) -> &[u8] {
+        &self.output
+    }
+}
+
+impl Read for MemoryStream {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        self.input.read(buf)
+    }
+}
+
+impl Write for MemoryStream {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.output.write(buf)
+    }
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+/// Counts the bytes written to the inner writer.
+pub struct CountingWriter<W> {
+    inner: W,
+    count: usize,
+}
+
+impl<W: Write> CountingWriter<W> {
+    pub fn new(inner: W) -> Self {
+        Self { inner, count: 0 }
+    }
+    /// Bytes written so far.
+    pub fn count(&self) -> usize {
+        self.count
+    }
+}
+
+impl<W: Write> Write for CountingWriter<W> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let n = self.inner.write(buf)?;
+        self.count += n;
+        Ok(n)
+    }
+    fn flush(&mut self) -> io::Result<()> {
+        self.inner.flush()
+    }
+}
+
+/// Enforces a wall-clock deadline across successive reads of the inner
+/// stream, failing with [`io::ErrorKind::TimedOut`] once it has passed.
+///
+/// Unlike socket timeouts, which limit each individual read, this limits the
+/// total time spent reading, so a client can't keep a request open by sending
+/// it one byte at a time. It works with any `Read`, but can't interrupt a read
+/// which is already blocked; combine it with a per-read timeout where available.
+///
+/// # Example
+/// ```
+/// use std::io::Read;
+/// use std::time::Duration;
+/// use jbhttp::io::TimeoutReader;
+///
+/// let mut reader = TimeoutReader::new(&b"data"[..], Duration::from_secs(5));
+/// let mut buf = String::new();
+/// reader.read_to_string(&mut buf).unwrap();
+/// assert_eq!(buf, "data");
+/// ```
+pub struct TimeoutReader<R> {
+    inner: R,
+    timeout: Duration,
+    deadline: Option<Instant>,
+}
+
+impl<R> TimeoutReader<R> {
+    /// The deadline starts now. A timeout too large to represent never expires.
+    pub fn new(inner: R, timeout: Duration) -> Self {
+        Self {
+            inner,
+            timeout,
+            deadline: Instant::now().checked_add(timeout),
+        }
+    }
+    /// Restart the deadline from now, e.g. before reading the next request.
+    pub fn reset(&mut self) {
+        self.deadline = Instant::now().checked_add(self.timeout);
+    }
+    /// Change the timeout, restarting the deadline from now, e.g. to allow
+    /// a different time for reading the body than for the headers.
+    pub fn set_timeout(&mut self, timeout: Duration) {
+        self.timeout = timeout;
+        self.reset();
+    }
+    pub fn get_ref(&self) -> &R {
+        &self.inner
+    }
+    pub fn get_mut(&mut self) -> &mut R {
+        &mut self.inner
+    }
+    pub fn into_inner(self) -> R {
+        self.inner
+    }
+}
+
+impl<R: Read> Read for TimeoutReader<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        match self.deadline {
+            Some(deadline) if Instant::now() >= deadline => Err(io::Error::new(
+                io::ErrorKind::TimedOut,
+                format!("read timed out after {}ms", self.timeout.as_millis()),
+            )),
+            _ => self.inner.read(buf),
+        }
+    }
+}
+
+/// Writes are passed through, without a deadline.
+impl<R: Write> Write for TimeoutReader<R> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.inner.write(buf)
+    }
+    fn flush(&mut self) -> io::Result<()> {
+        self.inner.flush()
+    }
+}
+
+/// Maximum number of buffers kept per thread by [`BufferPool`].
+const BUFFER_POOL_SIZE: usize = 16;
+/// Buffers which grew larger than this are dropped instead of pooled.
+const BUFFER_POOL_MAX_CAPACITY: usize = 64 * 1024;
+
+thread_local! {
+    static BUFFERS: RefCell<Vec<Vec<u8>>> = const { RefCell::new(Vec::new()) };
+}
+
+/// A per-thread pool of byte buffers, reused across requests served by the
+/// same worker thread to avoid allocating a fresh buffer for each one.
+///
+/// # Example
+/// ```
+/// use jbhttp::io::BufferPool;
+///
+/// let mut buf = BufferPool::take();
+/// buf.extend(b"response");
+/// BufferPool::give(buf);
+///
+/// // The same allocation is reused, emptied
+/// let buf = BufferPool::take();
+/// assert!(buf.is_empty());
+/// assert!(buf.capacity() >= 8);
+/// ```
+pub struct BufferPool;
+
+impl BufferPool {
+    /// Take an empty buffer from the current thread's pool, or a new one if
+    /// the pool is empty.
+    pub fn take() -> Vec<u8> {
+        BUFFERS
+            .with(|buffers| buffers.borrow_mut().pop())
+            .unwrap_or_default()
+    }
+    /// Return a buffer to the current thread's pool. It is dropped if the
+    /// pool is full, or if it grew too large to be worth keeping.
+    pub fn give(mut buf: Vec<u8>) {
+        if buf.capacity() > BUFFER_POOL_MAX_CAPACITY {
+            return;
+        }
+        buf.clear();
+        BUFFERS.with(|buffers| {
+            let mut buffers = buffers.borrow_mut();
+            if buffers.len() < BUFFER_POOL_SIZE {
+                buffers.push(buf);
+            }
+        });
+    }
+}
+
+#[cfg(feature = "compression")]
+pub use self::compression::*;
+
+/// Compression adapters, with the `compression` feature.
+#[cfg(feature = "compression")]
+mod compression {
+    use std::io;
+    use std::io::prelude::*;
+
+    use flate2::Compression;
+
+    /// Decompresses gzip data read from the inner stream.
+    ///
+    /// Concatenated gzip members are decoded as one stream, as allowed for
+    /// *Content-Encoding: gzip*.
+    ///
+    /// # Example
+    /// ```
+    /// use std::io::{Read, Write};
+    /// use jbhttp::io::{GzipReader, GzipWriter};
+    ///
+    /// let mut writer = GzipWriter::new(Vec::new());
+    /// writer.write_all(b"Hello!").unwrap();
+    /// let compressed = writer.finish().unwrap();
+    ///
+    /// let mut reader = GzipReader::new(&compressed[..]);
+    /// let mut buf = String::new();
+    /// reader.read_to_string(&mut buf).unwrap();
+    /// assert_eq!(buf, "Hello!");
+    /// ```
+    pub struct GzipReader<R> {
+        inner: flate2::read::MultiGzDecoder<R>,
+    }
+
+    impl<R: Read> GzipReader<R> {
+        pub fn new(inner: R) -> Self {
+            Self {
+                inner: flate2::read::MultiGzDecoder::new(inner),
+            }
+        }
+        pub fn get_ref(&self) -> &R {
+            self.inner.get_ref()
+        }
+        pub fn get_mut(&mut self) -> &mut R {
+            self.inner.get_mut()
+        }
+        pub fn into_inner(self) -> R {
+            self.inner.into_inner()
+        }
+    }
+
+    impl<R: Read> Read for GzipReader<R> {
+        fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+            self.inner.read(buf)
+        }
+    }
+
+    /// Compresses data written to it as gzip, into the inner stream.
+    ///
+    /// Call [`GzipWriter::finish`] when done, to write the gzip trailer
+    /// and get errors; dropping the writer finishes it too, ignoring errors.
+    pub struct GzipWriter<W: Write> {
+        inner: flate2::write::GzEncoder<W>,
+    }
+
+    impl<W: Write> GzipWriter<W> {
+        /// Compress with the default level.
+        pub fn new(inner: W) -> Self {
+            Self::with_level(inner, Compression::default().level())
+        }
+        /// Compress with a level from 0 (none) to 9 (best).
+        pub fn with_level(inner: W, level: u32) -> Self {
+            Self {
+                inner: flate2::write::GzEncoder::new(inner, Compression::new(level)),
+            }
+        }
+        pub fn get_ref(&self) -> &W {
+            self.inner.get_ref()
+        }
+        pub fn get_mut(&mut self) -> &mut W {
+            self.inner.get_mut()
+        }
+        /// Write the remaining compressed data and return the inner stream.
+        pub fn finish(self) -> io::Result<W> {
+            self.inner.finish()
+        }
+    }
+
+    impl<W: Write> Write for GzipWriter<W> {
+        fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+            self.inner.write(buf)
+        }
+        fn flush(&mut self) -> io::Result<()> {
+            self.inner.flush()
+        }
+    }
+
+    /// Decompresses deflate data read from the inner stream.
+    ///
+    /// As for *Content-Encoding: deflate*, this is the zlib format
+    /// (RFC 1950), not raw deflate.
+    pub struct DeflateReader<R> {
+        inner: flate2::read::ZlibDecoder<R>,
+    }
+
+    impl<R: Read> DeflateReader<R> {
+        pub fn new(inner: R) -> Self {
+            Self {
+                inner: flate2::read::ZlibDecoder::new(inner),
+            }
+        }
+        pub fn get_ref(&self) -> &R {
+            self.inner.get_ref()
+        }
+        pub fn get_mut(&mut self) -> &mut R {
+            self.inner.get_mut()
+        }
+        pub fn into_inner(self) -> R {
+            self.inner.into_inner()
+        }
+    }
+
+    impl<R: Read> Read for DeflateReader<R> {
+        fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+            self.inner.read(buf)
+        }
+    }
+
+    /// Compresses data written to it as deflate (zlib format), into the
+    /// inner stream.
+    ///
+    /// Call [`DeflateWriter::finish`] when done, to write the remaining data
+    /// and get errors; dropping the writer finishes it too, ignoring errors.
+    pub struct DeflateWriter<W: Write> {
+        inner: flate2::write::ZlibEncoder<W>,
+    }
+
+    impl<W: Write> DeflateWriter<W> {
+        /// Compress with the default level.
+        pub fn new(inner: W) -> Self {
+            Self::with_level(inner, Compression::default().level())
+        }
+        /// Compress with a level from 0 (none) to 9 (best).
+        pub fn with_level(inner: W, level: u32) -> Self {
+            Self {
+                inner: flate2::write::ZlibEncoder::new(inner, Compression::new(level)),
+            }
+        }
+        pub fn get_ref(&self) -> &W {
+            self.inner.get_ref()
+        }
+        pub fn get_mut(&mut self) -> &mut W {
+            self.inner.get_mut()
+        }
+        /// Write the remaining compressed data and return the inner stream.
+        pub fn finish(self) -> io::Result<W> {
+            self.inner.finish()
+        }
+    }
+
+    impl<W: Write> Write for DeflateWriter<W> {
+        fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+            self.inner.write(buf)
+        }
+        fn flush(&mut self) -> io::Result<()> {
+            self.inner.flush()
+        }
+    }
+}
+
 #[cfg(test)]
 mod test {
     use super::*;
 
+    /// Reads one byte at a time, sleeping before each read.
+    struct SlowReader {
+        data: Vec<u8>,
+        delay: Duration,
+    }
+
+    impl Read for SlowReader {
+        fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+            std::thread::sleep(self.delay);
+            if self.data.is_empty() || buf.is_empty() {
+                return Ok(0);
+            }
+            buf[0] = self.data.remove(0);
+            Ok(1)
+        }
+    }
+
+    fn slow_reader(data: &[u8]) -> SlowReader {
+        SlowReader {
+            data: data.to_vec(),
+            delay: Duration::from_millis(20),
+        }
+    }
+
+    #[test]
+    fn test_timeout_reader_expires() {
+        let mut reader = TimeoutReader::new(slow_reader(b"slow loris"), Duration::from_millis(50));
+        let mut buf = vec![];
+        let err = reader.read_to_end(&mut buf).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::TimedOut);
+        assert!(!buf.is_empty() && buf.len() < 10);
+    }
+
+    #[test]
+    fn test_timeout_reader_within_deadline() {
+        let mut reader = TimeoutReader::new(slow_reader(b"quick"), Duration::from_secs(10));
+        let mut buf = vec![];
+        reader.read_to_end(&mut buf).unwrap();
+        assert_eq!(buf, b"quick");
+    }
+
+    #[test]
+    fn test_timeout_reader_reset() {
+        let mut reader = TimeoutReader::new(slow_reader(b"ab"), Duration::from_millis(100));
+        let mut buf = [0; 1];
+        reader.read_exact(&mut buf).unwrap();
+        std::thread::sleep(Duration::from_millis(100));
+        let err = reader.read_exact(&mut buf).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::TimedOut);
+        reader.reset();
+        reader.read_exact(&mut buf).unwrap();
+        assert_eq!(&buf, b"b");
+    }
+
+    #[test]
+    fn test_timeout_reader_no_deadline() {
+        let mut reader = TimeoutReader::new(&b"abc"[..], Duration::MAX);
+        let mut buf = vec![];
+        reader.read_to_end(&mut buf).unwrap();
+        assert_eq!(buf, b"abc");
+    }
+
     #[test]
     fn test_converter() {
         let data = b"this is a test\nreally\n";
@@ -105,4 +548,64 @@ mod test {
         assert_eq!(16, write_size);
         assert_eq!(data[..], writebuf[0..write_size]);
     }
+
+    #[test]
+    fn test_memory_stream() {
+        let mut stream = MemoryStream::new(b"input");
+        let mut buf = vec![0; 1024];
+        let read_size = stream.read(&mut buf).unwrap();
+        assert_eq!(b"input"[..], buf[0..read_size]);
+        assert_eq!(0, stream.read(&mut buf).unwrap());
+
+        stream.write_all(b"out").unwrap();
+        stream.write_all(b"put").unwrap();
+        assert_eq!(b"output"[..], stream.output()[..]);
+    }
+
+    #[cfg(feature = "compression")]
+    #[test]
+    fn test_gzip_round_trip() {
+        let data = "I love spaghetti. ".repeat(100);
+        let mut writer = GzipWriter::new(Vec::new());
+        writer.write_all(data.as_bytes()).unwrap();
+        let compressed = writer.finish().unwrap();
+        assert!(compressed.len() < data.len());
+
+        // Concatenated members decode as one stream
+        let mut twice = compressed.clone();
+        twice.extend(&compressed);
+        let mut buf = String::new();
+        GzipReader::new(&twice[..])
+            .read_to_string(&mut buf)
+            .unwrap();
+        assert_eq!(buf, data.repeat(2));
+    }
+
+    #[cfg(feature = "compression")]
+    #[test]
+    fn test_deflate_round_trip() {
+        let data = "I love spaghetti. ".repeat(100);
+        let mut writer = DeflateWriter::with_level(Vec::new(), 9);
+        writer.write_all(data.as_bytes()).unwrap();
+        let compressed = writer.finish().unwrap();
+        assert!(compressed.len() < data.len());
+
+        let mut buf = String::new();
+        DeflateReader::new(&compressed[..])
+            .read_to_string(&mut buf)
+            .unwrap();
+        assert_eq!(buf, data);
+    }
+
+    #[cfg(feature = "compression")]
+    #[test]
+    fn test_decompress_invalid() {
+        let mut buf = vec![];
+        assert!(GzipReader::new(&b"not gzip"[..])
+            .read_to_end(&mut buf)
+            .is_err());
+        assert!(DeflateReader::new(&b"not deflate"[..])
+            .read_to_end(&mut buf)
+            .is_err());
+    }
 }
@@ -1,8 +1,10 @@
+pub use crate::api;
 pub use crate::api::Api;
 pub use crate::content::mediatypes::*;
-pub use crate::content::{Deserialize, MediaType, SerializationError, Serialize};
+pub use crate::content::{Deserialize, MediaType, NoPayload, SerializationError, Serialize};
 pub use crate::handler::{Handler, Res};
 pub use crate::media_type;
 pub use crate::request::{Header, Method, Param, RawRequest, Request};
 pub use crate::response::{RawResponse, Response};
-pub use crate::server::Server;
+pub use crate::server::{Deferrals, Server};
+pub use crate::with_media_types;
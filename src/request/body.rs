@@ -0,0 +1,150 @@
+//! Request bodies read from the connection as they arrive, see
+//! [`Request::body_reader`](crate::request::Request::body_reader).
+use std::io;
+use std::io::prelude::*;
+
+/// Maximum size of a chunk size line, extensions included, or of a trailer.
+const MAX_LINE_SIZE: usize = 8 * 1024;
+
+/// How the end of a body is found.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub(crate) enum Framing {
+    /// After *Content-Length* bytes.
+    Length(usize),
+    /// After the last chunk, with *Transfer-Encoding: chunked*.
+    Chunked,
+}
+
+/// Reads a body up to its end, as framed by *Content-Length* or by chunked
+/// transfer coding, which is decoded; trailers are ignored.
+pub(crate) struct BodyReader<R> {
+    inner: R,
+    chunked: bool,
+    state: State,
+}
+
+enum State {
+    /// Bytes left in the body, or in the current chunk.
+    Data(usize),
+    /// Before a chunk size line.
+    ChunkSize,
+    Done,
+}
+
+impl<R: BufRead> BodyReader<R> {
+    pub(crate) fn new(inner: R, framing: Framing) -> Self {
+        let (chunked, state) = match framing {
+            Framing::Length(length) => (false, State::Data(length)),
+            Framing::Chunked => (true, State::ChunkSize),
+        };
+        Self {
+            inner,
+            chunked,
+            state,
+        }
+    }
+    /// Read a line, without its CRLF.
+    fn read_line(&mut self) -> io::Result<Vec<u8>> {
+        let mut line = vec![];
+        (&mut self.inner)
+            .take(MAX_LINE_SIZE as u64)
+            .read_until(b'\n', &mut line)?;
+        if !line.ends_with(b"\r\n") {
+            return Err(invalid("invalid chunk framing"));
+        }
+        line.truncate(line.len() - 2);
+        Ok(line)
+    }
+    fn chunk_size(&mut self) -> io::Result<usize> {
+        let line = self.read_line()?;
+        // Skip chunk extensions
+        let size = line.split(|b| *b == b';').next().unwrap_or_default();
+        if size.is_empty() || !size.iter().all(u8::is_ascii_hexdigit) {
+            return Err(invalid("invalid chunk size"));
+        }
+        let size = std::str::from_utf8(size).map_err(|_| invalid("invalid chunk size"))?;
+        usize::from_str_radix(size, 16).map_err(|_| invalid("invalid chunk size"))
+    }
+}
+
+impl<R: BufRead> Read for BodyReader<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        if buf.is_empty() {
+            return Ok(0);
+        }
+        loop {
+            match self.state {
+                State::Data(0) if self.chunked => {
+                    if !self.read_line()?.is_empty() {
+                        return Err(invalid("invalid chunk framing"));
+                    }
+                    self.state = State::ChunkSize;
+                }
+                State::Data(0) => self.state = State::Done,
+                State::Data(left) => {
+                    let n = left.min(buf.len());
+                    let n = self.inner.read(&mut buf[..n])?;
+                    if n == 0 {
+                        return Err(io::Error::new(
+                            io::ErrorKind::UnexpectedEof,
+                            "unexpected end of body",
+                        ));
+                    }
+                    self.state = State::Data(left - n);
+                    return Ok(n);
+                }
+                State::ChunkSize => match self.chunk_size()? {
+                    0 => {
+                        while !self.read_line()?.is_empty() {}
+                        self.state = State::Done;
+                    }
+                    size => self.state = State::Data(size),
+                },
+                State::Done => return Ok(0),
+            }
+        }
+    }
+}
+
+fn invalid(reason: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, reason)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn read(body: &[u8], framing: Framing) -> io::Result<Vec<u8>> {
+        let mut reader = BodyReader::new(body, framing);
+        let mut buf = vec![];
+        reader.read_to_end(&mut buf)?;
+        Ok(buf)
+    }
+
+    #[test]
+    fn test_body_reader_length() {
+        let body = b"foobar!GET / HTTP/1.1\r\n\r\n";
+        assert_eq!(read(body, Framing::Length(7)).unwrap(), b"foobar!");
+        assert_eq!(read(body, Framing::Length(0)).unwrap(), b"");
+        let err = read(b"foo", Framing::Length(7)).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::UnexpectedEof);
+    }
+
+    #[test]
+    fn test_body_reader_chunked() {
+        let body = b"3\r\nfoo\r\n4;ext=1\r\nbar!\r\n0\r\nChecksum: abc\r\n\r\nGET /";
+        assert_eq!(read(body, Framing::Chunked).unwrap(), b"foobar!");
+        assert_eq!(read(b"0\r\n\r\n", Framing::Chunked).unwrap(), b"");
+        for body in [
+            &b"x\r\nfoo\r\n0\r\n\r\n"[..],
+            b"+3\r\nfoo\r\n0\r\n\r\n",
+            b"3\r\nfoobar\r\n0\r\n\r\n",
+            b"3\nfoo\n0\n\n",
+        ] {
+            let err = read(body, Framing::Chunked).unwrap_err();
+            assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+        }
+        let err = read(b"5\r\nfoo", Framing::Chunked).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::UnexpectedEof);
+    }
+}
@@ -1,6 +1,8 @@
+use std::collections::HashMap;
 use std::fmt;
 use std::hash;
 use std::str::FromStr;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
 
 use crate::content::MediaTypeMatch;
 
@@ -88,6 +90,8 @@ impl fmt::Display for HeaderParseError {
     }
 }
 
+impl std::error::Error for HeaderParseError {}
+
 type Result<T> = std::result::Result<T, HeaderParseError>;
 
 // Accept: <MIME_type>/<MIME_subtype>
@@ -98,15 +102,17 @@ type Result<T> = std::result::Result<T, HeaderParseError>;
 impl FromStr for MediaTypePreference {
     type Err = HeaderParseError;
     fn from_str(s: &str) -> Result<Self> {
-        let parts: Vec<&str> = s.split(';').collect();
-        let (content_type, q) = match &parts[..] {
-            [content_type] => (content_type.to_string(), 1.0),
-            [content_type, q] => match str::parse::<f32>(q) {
-                Ok(q) => (content_type.to_string(), q),
-                Err(_) => return Err(HeaderParseError::new("Accept", "invalid q value")),
-            },
-            _ => return Err(HeaderParseError::new("Accept", "invalid mimetype format")),
-        };
+        let mut parts = s.split(';');
+        let content_type = parts.next().unwrap_or("").trim();
+        let mut q = 1.0;
+        for param in parts {
+            if let Some(value) = param.trim().strip_prefix("q=") {
+                q = match str::parse::<f32>(value) {
+                    Ok(q) if (0.0..=1.0).contains(&q) => q,
+                    _ => return Err(HeaderParseError::new("Accept", "invalid q value")),
+                };
+            }
+        }
         let parts: Vec<&str> = content_type.split('/').collect();
         let (mime_type, mime_subtype) = match &parts[..] {
             [mime_type, mime_subtype] => (mime_type.to_string(), mime_subtype.to_string()),
@@ -125,7 +131,7 @@ pub struct Accept {
 }
 
 impl Accept {
-    pub fn iter(&self) -> std::slice::Iter<MediaTypePreference> {
+    pub fn iter(&self) -> std::slice::Iter<'_, MediaTypePreference> {
         self.prefs.iter()
     }
 }
@@ -140,16 +146,118 @@ impl FromStr for Accept {
                 vec.push(ctp);
             }
         }
-        vec.sort_by(|a, b| a.quality.partial_cmp(&b.quality).unwrap());
+        // Most preferred first; stable, so equal qualities keep header order
+        vec.sort_by(|a, b| b.quality.partial_cmp(&a.quality).unwrap());
         Ok(Self { prefs: vec })
     }
 }
 
+/// An *Accept-Charset* header.
+pub struct AcceptCharset {
+    prefs: Vec<(String, f32)>,
+}
+
+impl AcceptCharset {
+    /// The client's quality for a charset (case-insensitive), from its own
+    /// entry or else `*`; 0 when neither is listed.
+    pub fn quality(&self, charset: &str) -> f32 {
+        let find = |name: &str| {
+            self.prefs
+                .iter()
+                .find(|(pref, _)| pref.eq_ignore_ascii_case(name))
+                .map(|(_, q)| *q)
+        };
+        find(charset).or_else(|| find("*")).unwrap_or(0.0)
+    }
+}
+
+// Accept-Charset: utf-8, iso-8859-1;q=0.5, *;q=0.1
+impl FromStr for AcceptCharset {
+    type Err = HeaderParseError;
+    fn from_str(s: &str) -> Result<Self> {
+        Ok(Self {
+            prefs: parse_prefs(s),
+        })
+    }
+}
+
+/// An *Accept-Encoding* header.
+pub struct AcceptEncoding {
+    prefs: Vec<(String, f32)>,
+}
+
+impl AcceptEncoding {
+    /// The client's quality for a content coding (case-insensitive), from
+    /// its own entry or else `*`; 0 when neither is listed.
+    pub fn quality(&self, coding: &str) -> f32 {
+        let find = |name: &str| {
+            self.prefs
+                .iter()
+                .find(|(pref, _)| pref.eq_ignore_ascii_case(name))
+                .map(|(_, q)| *q)
+        };
+        find(coding).or_else(|| find("*")).unwrap_or(0.0)
+    }
+}
+
+// Accept-Encoding: gzip, deflate;q=0.5, *;q=0
+impl FromStr for AcceptEncoding {
+    type Err = HeaderParseError;
+    fn from_str(s: &str) -> Result<Self> {
+        Ok(Self {
+            prefs: parse_prefs(s),
+        })
+    }
+}
+
+/// Parse a list of names with quality values, as in *Accept-Charset*.
+fn parse_prefs(s: &str) -> Vec<(String, f32)> {
+    let mut prefs = vec![];
+    for part in s.split(',') {
+        let mut params = part.split(';');
+        let name = params.next().unwrap_or("").trim();
+        let mut q = Some(1.0);
+        for param in params {
+            if let Some(value) = param.trim().strip_prefix("q=") {
+                q = str::parse::<f32>(value)
+                    .ok()
+                    .filter(|q| (0.0..=1.0).contains(q));
+            }
+        }
+        // Invalid parts are ignored, as for Accept
+        if let (false, Some(q)) = (name.is_empty(), q) {
+            prefs.push((name.to_lowercase(), q));
+        }
+    }
+    prefs
+}
+
 pub struct ContentType {
     pub mime_type: String,
     pub mime_subtype: String,
     pub charset: Option<String>,
     pub boundary: Option<String>,
+    params: HashMap<String, String>,
+}
+
+impl ContentType {
+    /// Whether this is the media type, or a type with its structured syntax
+    /// suffix: `application/vnd.api+json` is `application/json`. Types are
+    /// case-insensitive.
+    pub fn is_media_type(&self, mime_type: &str, mime_subtype: &str) -> bool {
+        if !self.mime_type.eq_ignore_ascii_case(mime_type) {
+            return false;
+        }
+        match self.mime_subtype.rsplit_once('+') {
+            Some((_, suffix)) if suffix.eq_ignore_ascii_case(mime_subtype) => true,
+            _ => self.mime_subtype.eq_ignore_ascii_case(mime_subtype),
+        }
+    }
+    /// Get a media type parameter, like `version` in
+    /// `application/vnd.api+json; version=2`. Names are case-insensitive.
+    pub fn param(&self, name: &str) -> Option<&str> {
+        self.params.get(&name.to_lowercase()).map(|v| &v[..])
+    }
 }
 
 impl MediaTypeMatch for &ContentType {
@@ -158,16 +266,69 @@ impl MediaTypeMatch for &ContentType {
     }
 }
 
+/// Parse media type parameters: `; key=value; key="quoted value"`
+fn parse_media_type_params(header: &str, s: &str) -> Result<HashMap<String, String>> {
+    let mut params = HashMap::new();
+    let mut chars = s.chars().peekable();
+    loop {
+        while let Some(c) = chars.peek() {
+            if *c == ';' || c.is_whitespace() {
+                chars.next();
+            } else {
+                break;
+            }
+        }
+        if chars.peek().is_none() {
+            return Ok(params);
+        }
+        let mut key = String::new();
+        while let Some(c) = chars.next_if(|c| *c != '=' && *c != ';') {
+            key.push(c);
+        }
+        if chars.next() != Some('=') || key.trim().is_empty() {
+            return Err(HeaderParseError::new(header, "invalid key-value"));
+        }
+        let mut value = String::new();
+        if chars.next_if_eq(&'"').is_some() {
+            loop {
+                match chars.next() {
+                    Some('"') => break,
+                    Some('\\') => match chars.next() {
+                        Some(c) => value.push(c),
+                        None => return Err(HeaderParseError::new(header, "unterminated quote")),
+                    },
+                    Some(c) => value.push(c),
+                    None => return Err(HeaderParseError::new(header, "unterminated quote")),
+                }
+            }
+            while chars.next_if(|c| c.is_whitespace()).is_some() {}
+            if !matches!(chars.peek(), Some(';') | None) {
+                return Err(HeaderParseError::new(header, "invalid key-value"));
+            }
+        } else {
+            while let Some(c) = chars.next_if(|c| *c != ';') {
+                value.push(c);
+            }
+            value = value.trim().to_string();
+        }
+        params.insert(key.trim().to_lowercase(), value);
+    }
+}
+
 // Content-Type: text/html; charset=UTF-8
 // Content-Type: multipart/form-data; boundary=something
+// Content-Type: application/vnd.api+json; version="2"
 impl FromStr for ContentType {
     type Err = HeaderParseError;
     fn from_str(s: &str) -> Result<Self> {
-        let parts: Vec<&str> = s.split(';').collect();
-        if parts.is_empty() {
+        let (media_type, params) = match s.split_once(';') {
+            Some((media_type, params)) => (media_type, params),
+            None => (s, ""),
+        };
+        if media_type.trim().is_empty() {
             return Err(HeaderParseError::new("Content-Type", "empty"));
         }
-        let media_type_parts: Vec<&str> = parts[0].trim().split('/').collect();
+        let media_type_parts: Vec<&str> = media_type.trim().split('/').collect();
         let (mime_type, mime_subtype) = match &media_type_parts[..] {
             [mime_type, mime_subtype] => (mime_type.to_string(), mime_subtype.to_string()),
             _ => {
@@ -177,24 +338,590 @@ impl FromStr for ContentType {
                 ))
             }
         };
-        let mut charset = None;
-        let mut boundary = None;
-        for part in &parts[1..] {
-            let parts: Vec<&str> = part.trim().split('=').collect();
-            match parts[..] {
-                [key, value] => match key {
-                    "charset" => charset = Some(value.to_string()),
-                    "boundary" => boundary = Some(value.to_string()),
-                    _ => (),
-                },
-                _ => return Err(HeaderParseError::new("Content-Type", "invalid key-value")),
-            }
-        }
+        let params = parse_media_type_params("Content-Type", params)?;
         Ok(ContentType {
             mime_type,
             mime_subtype,
-            charset,
-            boundary,
+            charset: params.get("charset").cloned(),
+            boundary: params.get("boundary").cloned(),
+            params,
+        })
+    }
+}
+
+/// A *Content-Disposition* header, as used for the parts of
+/// `multipart/form-data` bodies, and for downloads in responses.
+#[derive(Debug, Clone, PartialEq)]
+pub struct ContentDisposition {
+    /// Disposition type, like `form-data` or `attachment`, in lowercase.
+    pub disposition: String,
+    params: HashMap<String, String>,
+}
+
+impl ContentDisposition {
+    /// Get a parameter, like `name` or `filename`. Names are case-insensitive.
+    pub fn param(&self, name: &str) -> Option<&str> {
+        self.params.get(&name.to_lowercase()).map(|v| &v[..])
+    }
+    /// An `attachment` disposition, for a response to be downloaded as a
+    /// file rather than displayed. Non-ASCII names get a `filename*`
+    /// parameter (RFC 6266) with the UTF-8 name, and an ASCII `filename`
+    /// fallback for older clients.
+    pub fn attachment(filename: &str) -> Self {
+        let mut params = HashMap::new();
+        let fallback: String = filename
+            .chars()
+            .map(|c| {
+                if c.is_ascii() && !c.is_ascii_control() {
+                    c
+                } else {
+                    '_'
+                }
+            })
+            .collect();
+        if fallback != filename {
+            params.insert("filename*".to_string(), encode_ext_value(filename));
+        }
+        params.insert("filename".to_string(), fallback);
+        Self {
+            disposition: "attachment".to_string(),
+            params,
+        }
+    }
+    /// The form field name.
+    pub fn name(&self) -> Option<&str> {
+        self.param("name")
+    }
+    /// The original name of an uploaded file.
+    pub fn filename(&self) -> Option<&str> {
+        self.param("filename")
+    }
+}
+
+impl fmt::Display for ContentDisposition {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.disposition)?;
+        let mut names: Vec<&String> = self.params.keys().collect();
+        names.sort();
+        for name in names {
+            let value = &self.params[name];
+            if name.ends_with('*') {
+                // Extended values are already percent-encoded, never quoted
+                write!(f, "; {}={}", name, value)?;
+            } else {
+                write!(f, "; {}=\"", name)?;
+                for c in value.chars() {
+                    if c == '"' || c == '\\' {
+                        write!(f, "\\")?;
+                    }
+                    write!(f, "{}", c)?;
+                }
+                write!(f, "\"")?;
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Encode an extended parameter value (RFC 8187), like `UTF-8''%C3%A9t%C3%A9`.
+fn encode_ext_value(value: &str) -> String {
+    let mut encoded = "UTF-8''".to_string();
+    for byte in value.bytes() {
+        if byte.is_ascii_alphanumeric() || b"!#$&+-.^_`|~".contains(&byte) {
+            encoded.push(byte as char);
+        } else {
+            encoded.push_str(&format!("%{:02X}", byte));
+        }
+    }
+    encoded
+}
+
+// Content-Disposition: form-data; name="file"; filename="a.txt"
+impl FromStr for ContentDisposition {
+    type Err = HeaderParseError;
+    fn from_str(s: &str) -> Result<Self> {
+        let (disposition, params) = match s.split_once(';') {
+            Some((disposition, params)) => (disposition, params),
+            None => (s, ""),
+        };
+        if disposition.trim().is_empty() {
+            return Err(HeaderParseError::new("Content-Disposition", "empty"));
+        }
+        Ok(ContentDisposition {
+            disposition: disposition.trim().to_lowercase(),
+            params: parse_media_type_params("Content-Disposition", params)?,
         })
     }
 }
+
+/// An entity tag, as used in the *ETag*, *If-Match* and *If-None-Match* headers.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ETag {
+    pub weak: bool,
+    pub tag: String,
+}
+
+impl ETag {
+    pub fn strong(tag: &str) -> Self {
+        Self {
+            weak: false,
+            tag: tag.to_string(),
+        }
+    }
+    pub fn weak(tag: &str) -> Self {
+        Self {
+            weak: true,
+            tag: tag.to_string(),
+        }
+    }
+    /// Strong comparison: both tags must be strong and identical.
+    pub fn strong_eq(&self, other: &ETag) -> bool {
+        !self.weak && !other.weak && self.tag == other.tag
+    }
+    /// Weak comparison: tags must be identical, weakness is ignored.
+    pub fn weak_eq(&self, other: &ETag) -> bool {
+        self.tag == other.tag
+    }
+}
+
+impl fmt::Display for ETag {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if self.weak {
+            write!(f, "W/")?;
+        }
+        write!(f, "\"{}\"", self.tag)
+    }
+}
+
+// ETag: "xyzzy"
+// ETag: W/"xyzzy"
+impl FromStr for ETag {
+    type Err = HeaderParseError;
+    fn from_str(s: &str) -> Result<Self> {
+        let s = s.trim();
+        let (weak, s) = match s.strip_prefix("W/") {
+            Some(s) => (true, s),
+            None => (false, s),
+        };
+        match s.strip_prefix('"').and_then(|s| s.strip_suffix('"')) {
+            Some(tag) if !tag.contains('"') => Ok(Self {
+                weak,
+                tag: tag.to_string(),
+            }),
+            _ => Err(HeaderParseError::new("ETag", "invalid entity tag")),
+        }
+    }
+}
+
+/// The *If-Match* header, either `*` or a list of entity tags.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum IfMatch {
+    Any,
+    Tags(Vec<ETag>),
+}
+
+impl IfMatch {
+    /// Check the precondition against the current entity tag of the resource,
+    /// `None` if the resource does not exist. Uses strong comparison.
+    pub fn matches(&self, current: Option<&ETag>) -> bool {
+        match (self, current) {
+            (_, None) => false,
+            (Self::Any, Some(_)) => true,
+            (Self::Tags(tags), Some(current)) => tags.iter().any(|t| t.strong_eq(current)),
+        }
+    }
+}
+
+// If-Match: *
+// If-Match: "xyzzy", "r2d2xxxx"
+impl FromStr for IfMatch {
+    type Err = HeaderParseError;
+    fn from_str(s: &str) -> Result<Self> {
+        if s.trim() == "*" {
+            return Ok(Self::Any);
+        }
+        let tags = s
+            .split(',')
+            .filter(|t| !t.trim().is_empty())
+            .map(|t| {
+                str::parse::<ETag>(t)
+                    .map_err(|_| HeaderParseError::new("If-Match", "invalid entity tag"))
+            })
+            .collect::<Result<Vec<ETag>>>()?;
+        Ok(Self::Tags(tags))
+    }
+}
+
+/// A range of bytes in a *Range* header.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ByteRange {
+    /// `start-end`, inclusive.
+    FromTo(u64, u64),
+    /// `start-`, to the end.
+    From(u64),
+    /// `-length`, the last bytes.
+    Suffix(u64),
+}
+
+impl ByteRange {
+    /// Get the inclusive first and last byte positions of the range, clamped
+    /// to a resource of `len` bytes; None if the range is not satisfiable.
+    pub fn resolve(&self, len: u64) -> Option<(u64, u64)> {
+        match *self {
+            Self::FromTo(start, _) | Self::From(start) if start >= len => None,
+            Self::FromTo(start, end) => Some((start, end.min(len - 1))),
+            Self::From(start) => Some((start, len - 1)),
+            Self::Suffix(n) if n == 0 || len == 0 => None,
+            Self::Suffix(n) => Some((len - n.min(len), len - 1)),
+        }
+    }
+}
+
+impl FromStr for ByteRange {
+    type Err = HeaderParseError;
+    fn from_str(s: &str) -> Result<Self> {
+        let invalid = || HeaderParseError::new("Range", "invalid byte range");
+        let pos = |s: &str| -> Result<u64> {
+            if s.is_empty() || !s.bytes().all(|b| b.is_ascii_digit()) {
+                return Err(invalid());
+            }
+            s.parse().map_err(|_| invalid())
+        };
+        match s.trim().split_once('-').ok_or_else(invalid)? {
+            ("", suffix) => Ok(Self::Suffix(pos(suffix)?)),
+            (start, "") => Ok(Self::From(pos(start)?)),
+            (start, end) => {
+                let (start, end) = (pos(start)?, pos(end)?);
+                if end < start {
+                    return Err(invalid());
+                }
+                Ok(Self::FromTo(start, end))
+            }
+        }
+    }
+}
+
+/// The *Range* header; only the `bytes` unit is supported.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Range {
+    pub ranges: Vec<ByteRange>,
+}
+
+impl Range {
+    /// Resolve the satisfiable ranges for a resource of `len` bytes, see
+    /// [`ByteRange::resolve`]. None if no range is satisfiable, in which case
+    /// servers respond 416 Range Not Satisfiable.
+    pub fn resolve(&self, len: u64) -> Option<Vec<(u64, u64)>> {
+        let ranges: Vec<(u64, u64)> = self.ranges.iter().filter_map(|r| r.resolve(len)).collect();
+        if ranges.is_empty() {
+            None
+        } else {
+            Some(ranges)
+        }
+    }
+}
+
+// Range: bytes=0-499
+// Range: bytes=0-499, 1000-, -500
+impl FromStr for Range {
+    type Err = HeaderParseError;
+    fn from_str(s: &str) -> Result<Self> {
+        let ranges = match s.trim().split_once('=') {
+            Some((unit, ranges)) if unit.trim().eq_ignore_ascii_case("bytes") => ranges,
+            _ => return Err(HeaderParseError::new("Range", "unsupported range unit")),
+        };
+        let ranges = ranges
+            .split(',')
+            .filter(|r| !r.trim().is_empty())
+            .map(str::parse::<ByteRange>)
+            .collect::<Result<Vec<ByteRange>>>()?;
+        if ranges.is_empty() {
+            return Err(HeaderParseError::new("Range", "no ranges"));
+        }
+        Ok(Self { ranges })
+    }
+}
+
+const WEEKDAYS: [&str; 7] = ["Thu", "Fri", "Sat", "Sun", "Mon", "Tue", "Wed"];
+const MONTHS: [&str; 12] = [
+    "Jan", "Feb", "Mar", "Apr", "May", "Jun", "Jul", "Aug", "Sep", "Oct", "Nov", "Dec",
+];
+
+/// A date as used in HTTP headers (*Last-Modified*, *If-Unmodified-Since*, etc.),
+/// with a precision of one second. Only the preferred IMF-fixdate format
+/// is supported, e.g. `Sun, 06 Nov 1994 08:49:37 GMT`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub struct HttpDate(u64);
+
+impl From<SystemTime> for HttpDate {
+    fn from(time: SystemTime) -> Self {
+        Self(
+            time.duration_since(UNIX_EPOCH)
+                .unwrap_or_default()
+                .as_secs(),
+        )
+    }
+}
+
+impl From<HttpDate> for SystemTime {
+    fn from(date: HttpDate) -> Self {
+        UNIX_EPOCH + Duration::from_secs(date.0)
+    }
+}
+
+// Days since 1970-01-01 to civil date, and back; see
+// http://howardhinnant.github.io/date_algorithms.html
+fn days_to_civil(days: u64) -> (u64, u64, u64) {
+    let z = days + 719_468;
+    let era = z / 146_097;
+    let doe = z - era * 146_097;
+    let yoe = (doe - doe / 1460 + doe / 36524 - doe / 146_096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = doy - (153 * mp + 2) / 5 + 1;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 };
+    let year = yoe + era * 400 + if month <= 2 { 1 } else { 0 };
+    (year, month, day)
+}
+
+fn civil_to_days(year: u64, month: u64, day: u64) -> u64 {
+    let year = if month <= 2 { year - 1 } else { year };
+    let era = year / 400;
+    let yoe = year - era * 400;
+    let mp = if month > 2 { month - 3 } else { month + 9 };
+    let doy = (153 * mp + 2) / 5 + day - 1;
+    let doe = yoe * 365 + yoe / 4 - yoe / 100 + doy;
+    era * 146_097 + doe - 719_468
+}
+
+impl fmt::Display for HttpDate {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let days = self.0 / 86400;
+        let secs = self.0 % 86400;
+        let (year, month, day) = days_to_civil(days);
+        write!(
+            f,
+            "{}, {:02} {} {} {:02}:{:02}:{:02} GMT",
+            WEEKDAYS[(days % 7) as usize],
+            day,
+            MONTHS[(month - 1) as usize],
+            year,
+            secs / 3600,
+            secs % 3600 / 60,
+            secs % 60
+        )
+    }
+}
+
+impl FromStr for HttpDate {
+    type Err = HeaderParseError;
+    fn from_str(s: &str) -> Result<Self> {
+        let err = || HeaderParseError::new("Date", "invalid HTTP date");
+        let num = |s: &str, len: usize| {
+            if s.len() == len && s.bytes().all(|b| b.is_ascii_digit()) {
+                s.parse::<u64>().map_err(|_| err())
+            } else {
+                Err(err())
+            }
+        };
+        let parts: Vec<&str> = s.trim().split(' ').collect();
+        let (day, month, year, time) = match &parts[..] {
+            [weekday, day, month, year, time, "GMT"]
+                if weekday
+                    .strip_suffix(',')
+                    .is_some_and(|w| WEEKDAYS.contains(&w)) =>
+            {
+                (num(day, 2)?, *month, num(year, 4)?, *time)
+            }
+            _ => return Err(err()),
+        };
+        let month = MONTHS.iter().position(|m| *m == month).ok_or_else(err)? as u64 + 1;
+        let hms: Vec<&str> = time.split(':').collect();
+        let (h, m, sec) = match &hms[..] {
+            [h, m, s] => (num(h, 2)?, num(m, 2)?, num(s, 2)?),
+            _ => return Err(err()),
+        };
+        if year < 1970 || !(1..=31).contains(&day) || h > 23 || m > 59 || sec > 60 {
+            return Err(err());
+        }
+        Ok(Self(
+            civil_to_days(year, month, day) * 86400 + h * 3600 + m * 60 + sec,
+        ))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn accept_types(s: &str) -> Vec<(String, f32)> {
+        str::parse::<Accept>(s)
+            .unwrap()
+            .iter()
+            .map(|p| (format!("{}/{}", p.mime_type, p.mime_subtype), p.quality()))
+            .collect()
+    }
+
+    #[test]
+    fn test_accept_quality() {
+        assert_eq!(
+            accept_types("text/html;q=0.5, application/json, */*; q=0.1, text/plain;level=1"),
+            vec![
+                ("application/json".to_string(), 1.0),
+                ("text/plain".to_string(), 1.0),
+                ("text/html".to_string(), 0.5),
+                ("*/*".to_string(), 0.1),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_accept_invalid_parts_ignored() {
+        assert_eq!(
+            accept_types("text/html;q=2, nonsense, application/json;q=x, text/plain"),
+            vec![("text/plain".to_string(), 1.0)]
+        );
+    }
+
+    #[test]
+    fn test_accept_charset() {
+        let ac =
+            str::parse::<AcceptCharset>("UTF-8;q=0.5, iso-8859-1, *;q=0.1, ascii;q=x").unwrap();
+        assert_eq!(ac.quality("utf-8"), 0.5);
+        assert_eq!(ac.quality("ISO-8859-1"), 1.0);
+        assert_eq!(ac.quality("us-ascii"), 0.1);
+        assert_eq!(ac.quality("ascii"), 0.1);
+        let ac = str::parse::<AcceptCharset>("iso-8859-1").unwrap();
+        assert_eq!(ac.quality("utf-8"), 0.0);
+    }
+
+    #[test]
+    fn test_content_type() {
+        let ct = str::parse::<ContentType>("text/html; charset=UTF-8").unwrap();
+        assert_eq!(ct.mime_type, "text");
+        assert_eq!(ct.mime_subtype, "html");
+        assert_eq!(ct.charset, Some("UTF-8".to_string()));
+        assert_eq!(ct.boundary, None);
+        assert_eq!(ct.param("Charset"), Some("UTF-8"));
+    }
+
+    #[test]
+    fn test_content_type_params() {
+        let ct = str::parse::<ContentType>(
+            r#"application/vnd.api+json; version=2;profile="http://x/a;b \"c\"" ; boundary=xyz"#,
+        )
+        .unwrap();
+        assert_eq!(ct.mime_subtype, "vnd.api+json");
+        assert_eq!(ct.param("version"), Some("2"));
+        assert_eq!(ct.param("profile"), Some(r#"http://x/a;b "c""#));
+        assert_eq!(ct.boundary, Some("xyz".to_string()));
+        assert_eq!(ct.param("missing"), None);
+    }
+
+    #[test]
+    fn test_content_type_invalid() {
+        assert!(str::parse::<ContentType>("").is_err());
+        assert!(str::parse::<ContentType>("text").is_err());
+        assert!(str::parse::<ContentType>("text/plain; charset").is_err());
+        assert!(str::parse::<ContentType>(r#"text/plain; charset="utf-8"#).is_err());
+        assert!(str::parse::<ContentType>(r#"text/plain; a="b"c"#).is_err());
+    }
+
+    #[test]
+    fn test_content_disposition_attachment() {
+        let header = |filename| ContentDisposition::attachment(filename).to_string();
+        assert_eq!(header("report.pdf"), r#"attachment; filename="report.pdf""#);
+        assert_eq!(
+            header(r#"say "hi" \o/.txt"#),
+            r#"attachment; filename="say \"hi\" \\o/.txt""#
+        );
+        assert_eq!(
+            header("résumé 2024.pdf"),
+            "attachment; filename=\"r_sum_ 2024.pdf\"; \
+             filename*=UTF-8''r%C3%A9sum%C3%A9%202024.pdf"
+        );
+        let parsed: ContentDisposition = header("a\"b.txt").parse().unwrap();
+        assert_eq!(parsed.disposition, "attachment");
+        assert_eq!(parsed.filename(), Some("a\"b.txt"));
+    }
+
+    #[test]
+    fn test_etag() {
+        assert_eq!(str::parse::<ETag>(r#""abc""#).unwrap(), ETag::strong("abc"));
+        assert_eq!(str::parse::<ETag>(r#"W/"abc""#).unwrap(), ETag::weak("abc"));
+        assert_eq!(ETag::weak("abc").to_string(), r#"W/"abc""#);
+        assert!(str::parse::<ETag>("abc").is_err());
+        assert!(!ETag::weak("abc").strong_eq(&ETag::weak("abc")));
+        assert!(ETag::weak("abc").weak_eq(&ETag::strong("abc")));
+    }
+
+    #[test]
+    fn test_if_match() {
+        let current = ETag::strong("b");
+        assert!(IfMatch::Any.matches(Some(&current)));
+        assert!(!IfMatch::Any.matches(None));
+        let if_match = str::parse::<IfMatch>(r#""a", "b""#).unwrap();
+        assert!(if_match.matches(Some(&current)));
+        assert!(!if_match.matches(Some(&ETag::weak("b"))));
+        assert_eq!(str::parse::<IfMatch>(" * ").unwrap(), IfMatch::Any);
+        assert!(str::parse::<IfMatch>(r#""a", b"#).is_err());
+    }
+
+    #[test]
+    fn test_range() {
+        let range = |s: &str| str::parse::<Range>(s).map(|r| r.ranges);
+        assert_eq!(
+            range("bytes=0-499").unwrap(),
+            vec![ByteRange::FromTo(0, 499)]
+        );
+        assert_eq!(
+            range("bytes=500- , -200,1-1").unwrap(),
+            vec![
+                ByteRange::From(500),
+                ByteRange::Suffix(200),
+                ByteRange::FromTo(1, 1)
+            ]
+        );
+        assert!(range("bytes=").is_err());
+        assert!(range("items=0-1").is_err());
+        assert!(range("0-1").is_err());
+        assert!(range("bytes=-").is_err());
+        assert!(range("bytes=5-1").is_err());
+        assert!(range("bytes=a-1").is_err());
+        assert!(range("bytes=+1-2").is_err());
+        assert!(range("bytes=1-2-3").is_err());
+    }
+
+    #[test]
+    fn test_range_resolve() {
+        assert_eq!(ByteRange::FromTo(0, 499).resolve(1000), Some((0, 499)));
+        assert_eq!(ByteRange::FromTo(900, 1999).resolve(1000), Some((900, 999)));
+        assert_eq!(ByteRange::FromTo(1000, 1999).resolve(1000), None);
+        assert_eq!(ByteRange::From(100).resolve(1000), Some((100, 999)));
+        assert_eq!(ByteRange::From(1000).resolve(1000), None);
+        assert_eq!(ByteRange::Suffix(200).resolve(1000), Some((800, 999)));
+        assert_eq!(ByteRange::Suffix(2000).resolve(1000), Some((0, 999)));
+        assert_eq!(ByteRange::Suffix(0).resolve(1000), None);
+        assert_eq!(ByteRange::Suffix(10).resolve(0), None);
+
+        let range = str::parse::<Range>("bytes=2000-, -10").unwrap();
+        assert_eq!(range.resolve(1000), Some(vec![(990, 999)]));
+        assert_eq!(range.resolve(0), None);
+    }
+
+    #[test]
+    fn test_http_date() {
+        let date = str::parse::<HttpDate>("Sun, 06 Nov 1994 08:49:37 GMT").unwrap();
+        assert_eq!(
+            SystemTime::from(date),
+            UNIX_EPOCH + Duration::from_secs(784111777)
+        );
+        assert_eq!(date.to_string(), "Sun, 06 Nov 1994 08:49:37 GMT");
+        let leap = HttpDate::from(UNIX_EPOCH + Duration::from_secs(951782400));
+        assert_eq!(leap.to_string(), "Tue, 29 Feb 2000 00:00:00 GMT");
+        assert_eq!(str::parse::<HttpDate>(&leap.to_string()).unwrap(), leap);
+        assert!(str::parse::<HttpDate>("Sunday, 06-Nov-94 08:49:37 GMT").is_err());
+        assert!(str::parse::<HttpDate>("Sun, 06 Nov 1994 08:49:37 UTC").is_err());
+        assert!(str::parse::<HttpDate>("Sun, 06 Foo 1994 08:49:37 GMT").is_err());
+    }
+}
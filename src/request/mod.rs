@@ -1,22 +1,38 @@
 //! HTTP request and parser.
+use std::any::{Any, TypeId};
+use std::borrow::Cow;
 use std::collections::HashMap;
+use std::fmt;
+use std::io::{self, Read, Write};
+use std::net::SocketAddr;
+use std::sync::{Arc, Mutex};
+use std::time::Instant;
+
+use crate::content::MediaType;
+use crate::response::RawResponse;
 
 pub use header::*;
 
+pub(crate) mod body;
 pub mod header;
+pub mod multipart;
 pub mod parser;
 
 /// An HTTP Request.
 #[derive(Debug, Clone, PartialEq)]
 pub struct Request<T> {
     pub method: Method,
+    pub version: HttpVersion,
     pub path: String,
     pub query: String,
     pub fragment: String,
     pub headers: HashMap<Header, String>,
     pub payload: Option<T>,
+    /// Length of the body, decoded for a chunked body, whatever its
+    /// *Content-Length* header says.
     pub content_length: usize,
     pub params: Params,
+    pub extensions: Extensions,
 }
 
 pub type RawRequest = Request<Vec<u8>>;
@@ -25,6 +41,7 @@ impl<T> Default for Request<T> {
     fn default() -> Self {
         Self {
             method: Method::GET,
+            version: HttpVersion::Http11,
             path: "/".to_string(),
             query: "".to_string(),
             fragment: "".to_string(),
@@ -34,6 +51,7 @@ impl<T> Default for Request<T> {
             payload: None,
             content_length: 0,
             params: Params::new(),
+            extensions: Extensions::new(),
         }
     }
 }
@@ -46,6 +64,7 @@ impl<T> Request<T> {
     pub fn into_type<S>(self) -> Request<S> {
         Request {
             method: self.method,
+            version: self.version,
             path: self.path,
             query: self.query,
             fragment: self.fragment,
@@ -53,6 +72,110 @@ impl<T> Request<T> {
             payload: None,
             content_length: self.content_length,
             params: self.params,
+            extensions: self.extensions,
+        }
+    }
+    /// Iterate over the non-empty segments of the path, percent-decoded;
+    /// `/a//b/` gives `a` and `b`, `/` gives nothing. Invalid escapes are kept
+    /// as-is, and invalid UTF-8 is replaced.
+    pub fn path_segments(&self) -> impl Iterator<Item = Cow<'_, str>> {
+        self.path
+            .split('/')
+            .filter(|segment| !segment.is_empty())
+            .map(percent_decode)
+    }
+    /// Get the header block as received, between the request line and the
+    /// blank line, for example to verify a signature computed over the exact
+    /// bytes. Only kept if enabled on the parser, see
+    /// [`RequestParser::with_raw_headers`](parser::RequestParser::with_raw_headers).
+    pub fn raw_headers(&self) -> Option<&[u8]> {
+        self.extensions.get::<RawHeaders>().map(|h| &h.0[..])
+    }
+    /// Size of the request as it was read, request line and headers
+    /// included, when parsed by [`RequestParser`](parser::RequestParser).
+    /// A chunked body counts its chunk framing.
+    pub fn bytes_read(&self) -> Option<usize> {
+        self.extensions.get::<BytesRead>().map(|b| b.0)
+    }
+    /// A typed path param, parsed by [`Router`](crate::router::Router) from a
+    /// typed route pattern, like `request.param::<i64>("id")` for
+    /// `/person/?id:i64`. `None` if the param was not captured, or if it was
+    /// captured with a different type; untyped params are `String`.
+    pub fn param<P: Any + Send + Sync>(&self, name: &str) -> Option<&P> {
+        self.extensions.get::<PathParams>()?.get(name)
+    }
+    /// The address of the client, when served over a network, see
+    /// [`PeerAddr`].
+    pub fn peer_addr(&self) -> Option<SocketAddr> {
+        self.extensions.get::<PeerAddr>().map(|p| p.0)
+    }
+    /// When the server wants the handler to be done, if it set a handler
+    /// timeout, see [`Timeouts`](crate::server::Timeouts). Handlers can't be
+    /// interrupted, but long-running ones can check this to give up early.
+    pub fn deadline(&self) -> Option<Instant> {
+        self.extensions.get::<Deadline>().map(|d| d.0)
+    }
+    /// Send an informational (1xx) response to the client right away, while
+    /// the handler is still working, such as `103 Early Hints` with *Link*
+    /// headers for resources to preload. Only the status line and headers
+    /// are sent; nothing is sent to HTTP/1.0 clients. See
+    /// [`Response::with_interim`] to send them with the final response
+    /// instead.
+    ///
+    /// Fails with [`io::ErrorKind::Unsupported`] when the server can't write
+    /// to the connection while handling a request; [`TcpServer`] can, see
+    /// [`InterimWriter`].
+    ///
+    /// # Panics
+    /// If the response is not informational.
+    ///
+    /// # Example
+    /// ```
+    /// # use jbhttp::prelude::*;
+    /// fn page(req: RawRequest, _: &mut ()) -> Res<Vec<u8>, Vec<u8>> {
+    ///     let hints = RawResponse::new(103).with_header("Link", "</style.css>; rel=preload");
+    ///     if let Err(e) = req.send_interim(&hints) {
+    ///         log::debug!("no early hints: {}", e);
+    ///     }
+    ///     // ...render the page...
+    ///     Ok(Response::new(200).with_payload(b"<html>...</html>".to_vec()))
+    /// }
+    /// ```
+    ///
+    /// [`Response::with_interim`]: crate::response::Response::with_interim
+    /// [`TcpServer`]: crate::server::TcpServer
+    pub fn send_interim(&self, response: &RawResponse) -> io::Result<()> {
+        assert!(
+            response.is_informational(),
+            "interim response with status {}",
+            response.status_code
+        );
+        let writer = match self.extensions.get::<InterimWriter>() {
+            Some(writer) => writer,
+            None => return Err(io::ErrorKind::Unsupported.into()),
+        };
+        if self.version == HttpVersion::Http10 {
+            return Ok(());
+        }
+        let mut bytes = vec![];
+        response.write_interim(&mut bytes);
+        let mut writer = writer.0.lock().unwrap();
+        writer.write_all(&bytes)?;
+        writer.flush()
+    }
+    /// Whether the client wants to keep the connection open after the
+    /// response: HTTP/1.1 connections are persistent unless the request has
+    /// *Connection: close*, HTTP/1.0 connections only with
+    /// *Connection: keep-alive*.
+    pub fn keep_alive(&self) -> bool {
+        let has_option = |option: &str| {
+            self.headers
+                .get(&Header::new("connection"))
+                .is_some_and(|v| v.split(',').any(|o| o.trim().eq_ignore_ascii_case(option)))
+        };
+        match self.version {
+            HttpVersion::Http10 => has_option("keep-alive"),
+            HttpVersion::Http11 => !has_option("close"),
         }
     }
     pub fn accept(&self) -> Result<Option<Accept>, HeaderParseError> {
@@ -61,12 +184,204 @@ impl<T> Request<T> {
             None => Ok(None),
         }
     }
+    pub fn accept_charset(&self) -> Result<Option<AcceptCharset>, HeaderParseError> {
+        match self.headers.get(&Header::new("accept-charset")) {
+            Some(s) => Ok(Some(str::parse::<AcceptCharset>(s)?)),
+            None => Ok(None),
+        }
+    }
+    pub fn accept_encoding(&self) -> Result<Option<AcceptEncoding>, HeaderParseError> {
+        match self.headers.get(&Header::new("accept-encoding")) {
+            Some(s) => Ok(Some(str::parse::<AcceptEncoding>(s)?)),
+            None => Ok(None),
+        }
+    }
+    pub fn range(&self) -> Result<Option<Range>, HeaderParseError> {
+        match self.headers.get(&Header::new("range")) {
+            Some(s) => Ok(Some(str::parse::<Range>(s)?)),
+            None => Ok(None),
+        }
+    }
+    pub fn if_match(&self) -> Result<Option<IfMatch>, HeaderParseError> {
+        match self.headers.get(&Header::new("if-match")) {
+            Some(s) => Ok(Some(str::parse::<IfMatch>(s)?)),
+            None => Ok(None),
+        }
+    }
+    /// Get the *If-Unmodified-Since* date; invalid dates are ignored, as per RFC 7232.
+    pub fn if_unmodified_since(&self) -> Option<HttpDate> {
+        let date = self.headers.get(&Header::new("if-unmodified-since"))?;
+        str::parse::<HttpDate>(date).ok()
+    }
+    /// Get the value of a cookie from the *Cookie* header.
+    pub fn cookie(&self, name: &str) -> Option<String> {
+        let cookies = self.headers.get(&Header::new("cookie"))?;
+        for pair in cookies.split(';') {
+            if let Some((key, value)) = pair.trim().split_once('=') {
+                if key == name {
+                    return Some(value.to_string());
+                }
+            }
+        }
+        None
+    }
     pub fn content_type(&self) -> Result<Option<ContentType>, HeaderParseError> {
         match self.headers.get(&Header::new("content-type")) {
             Some(s) => Ok(Some(str::parse::<ContentType>(s)?)),
             None => Ok(None),
         }
     }
+    /// Whether the *Content-Type* is the media type `M`, including with its
+    /// structured syntax suffix, like `application/vnd.api+json` for
+    /// [`ApplicationJson`](crate::content::mediatypes::ApplicationJson). False
+    /// without a valid *Content-Type*.
+    pub fn content_type_matches<M: MediaType>(&self) -> bool {
+        match self.content_type() {
+            Ok(Some(content_type)) => {
+                content_type.is_media_type(&M::mime_type(), &M::mime_subtype())
+            }
+            _ => false,
+        }
+    }
+}
+
+impl Request<Vec<u8>> {
+    /// Read the body as a stream. A body the server left unread, see
+    /// [`TcpServer::with_streamed_multipart`], is read from the connection
+    /// as it arrives, and only once; otherwise the payload is read.
+    ///
+    /// [`TcpServer::with_streamed_multipart`]: crate::server::TcpServer::with_streamed_multipart
+    pub fn body_reader(&self) -> Box<dyn Read + Send + '_> {
+        let unread = self
+            .extensions
+            .get::<UnreadBody>()
+            .and_then(|body| body.0.lock().unwrap().take());
+        match unread {
+            Some(body) => body,
+            None => Box::new(self.payload.as_deref().unwrap_or(&[])),
+        }
+    }
+    /// Read a `multipart/*` body part by part, see
+    /// [`MultipartReader`](multipart::MultipartReader), from the connection
+    /// if the server left it unread, see [`Request::body_reader`]. None if
+    /// the request has no multipart *Content-Type* with a boundary.
+    pub fn multipart(&self) -> Option<multipart::MultipartReader<Box<dyn Read + Send + '_>>> {
+        let content_type = self.content_type().ok()??;
+        if content_type.mime_type != "multipart" {
+            return None;
+        }
+        Some(multipart::MultipartReader::new(
+            self.body_reader(),
+            content_type.boundary.as_deref()?,
+        ))
+    }
+}
+
+/// Raw request header block, added to request extensions by the parser when
+/// enabled, see [`Request::raw_headers`].
+#[derive(Debug, Clone, PartialEq)]
+pub struct RawHeaders(pub Vec<u8>);
+
+/// A body left unread by the server, to be read from the connection, see
+/// [`Request::body_reader`].
+pub(crate) struct UnreadBody(pub(crate) Mutex<Option<Box<dyn Read + Send>>>);
+
+/// Size of the request as read, request line and headers included, added
+/// to request extensions by the parser, see [`Request::bytes_read`].
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct BytesRead(pub usize);
+
+/// Address of the client, added to request extensions by servers which
+/// know it, like [`TcpServer`](crate::server::TcpServer), see
+/// [`Request::peer_addr`]. Behind a proxy, this is the proxy's address.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct PeerAddr(pub SocketAddr);
+
+/// Handler deadline, added to request extensions by servers with a handler
+/// timeout, see [`Request::deadline`].
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Deadline(pub Instant);
+
+/// A writer to the connection, added to request extensions by servers
+/// which can write to it while handling a request, like
+/// [`TcpServer`](crate::server::TcpServer), see [`Request::send_interim`].
+#[derive(Clone)]
+pub struct InterimWriter(Arc<Mutex<Box<dyn Write + Send>>>);
+
+impl InterimWriter {
+    pub fn new(writer: Box<dyn Write + Send>) -> Self {
+        Self(Arc::new(Mutex::new(writer)))
+    }
+}
+
+/// Typed path params, parsed by [`Router`](crate::router::Router) from
+/// typed route patterns like `/person/?id:i64`, see [`Request::param`].
+#[derive(Clone, Default)]
+pub struct PathParams(HashMap<String, Arc<dyn Any + Send + Sync>>);
+
+impl PathParams {
+    pub fn new() -> Self {
+        Self::default()
+    }
+    /// Insert a value, unless the param already has one.
+    pub fn insert(&mut self, name: &str, value: Arc<dyn Any + Send + Sync>) {
+        self.0.entry(name.to_string()).or_insert(value);
+    }
+    pub fn get<T: Any + Send + Sync>(&self, name: &str) -> Option<&T> {
+        self.0.get(name)?.downcast_ref()
+    }
+}
+
+impl fmt::Debug for PathParams {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let mut names: Vec<&String> = self.0.keys().collect();
+        names.sort();
+        write!(f, "PathParams({:?})", names)
+    }
+}
+
+fn percent_decode(s: &str) -> Cow<'_, str> {
+    if !s.contains('%') {
+        return Cow::Borrowed(s);
+    }
+    let bytes = s.as_bytes();
+    let mut decoded = Vec::with_capacity(bytes.len());
+    let mut i = 0;
+    while i < bytes.len() {
+        let escaped = bytes
+            .get(i + 1..i + 3)
+            .filter(|hex| bytes[i] == b'%' && hex.iter().all(u8::is_ascii_hexdigit))
+            .and_then(|hex| std::str::from_utf8(hex).ok())
+            .and_then(|hex| u8::from_str_radix(hex, 16).ok());
+        match escaped {
+            Some(byte) => {
+                decoded.push(byte);
+                i += 3;
+            }
+            None => {
+                decoded.push(bytes[i]);
+                i += 1;
+            }
+        }
+    }
+    Cow::Owned(String::from_utf8_lossy(&decoded).into_owned())
+}
+
+/// HTTP protocol version of a request or response.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum HttpVersion {
+    Http10,
+    #[default]
+    Http11,
+}
+
+impl fmt::Display for HttpVersion {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Http10 => write!(f, "HTTP/1.0"),
+            Self::Http11 => write!(f, "HTTP/1.1"),
+        }
+    }
 }
 
 #[derive(Debug, Clone, PartialEq)]
@@ -82,6 +397,13 @@ pub enum Method {
     TRACE,
 }
 
+impl Method {
+    /// Safe methods are read-only, see RFC 7231 section 4.2.1.
+    pub fn is_safe(&self) -> bool {
+        matches!(self, Self::GET | Self::HEAD | Self::OPTIONS | Self::TRACE)
+    }
+}
+
 #[derive(Debug, Clone, PartialEq, Eq, Hash)]
 pub enum Param {
     Path(String),
@@ -89,12 +411,12 @@ pub enum Param {
     Body(String),
 }
 
-impl ToString for Param {
-    fn to_string(&self) -> String {
+impl fmt::Display for Param {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
         match self {
-            Self::Path(s) => s.clone(),
-            Self::Query(s) => s.clone(),
-            Self::Body(s) => s.clone(),
+            Self::Path(s) => write!(f, "{}", s),
+            Self::Query(s) => write!(f, "{}", s),
+            Self::Body(s) => write!(f, "{}", s),
         }
     }
 }
@@ -113,7 +435,7 @@ impl Params {
         Params(HashMap::new())
     }
     pub fn add(&mut self, p: Param, s: String) {
-        self.0.entry(p).or_insert_with(Vec::new).push(s);
+        self.0.entry(p).or_default().push(s);
     }
     // Get all named params of specific type (path, query or body)
     pub fn get_all(&self, p: &Param) -> Option<&Vec<String>> {
@@ -142,3 +464,127 @@ impl Params {
         None
     }
 }
+
+/// Arbitrary typed values attached to a request, at most one per type.
+///
+/// Values are shared between clones of a request. Extensions are compared
+/// by type only, since values can't be compared.
+///
+/// # Example
+/// ```
+/// use jbhttp::prelude::*;
+///
+/// struct UserId(u64);
+///
+/// let mut request: RawRequest = Request::default();
+/// request.extensions.insert(UserId(42));
+/// assert_eq!(request.extensions.get::<UserId>().unwrap().0, 42);
+/// ```
+#[derive(Clone, Default)]
+pub struct Extensions(HashMap<TypeId, Arc<dyn Any + Send + Sync>>);
+
+impl Extensions {
+    pub fn new() -> Self {
+        Self(HashMap::new())
+    }
+    /// Insert a value, replacing any previous value of the same type.
+    pub fn insert<T: Any + Send + Sync>(&mut self, value: T) {
+        self.0.insert(TypeId::of::<T>(), Arc::new(value));
+    }
+    pub fn get<T: Any + Send + Sync>(&self) -> Option<&T> {
+        self.0.get(&TypeId::of::<T>())?.downcast_ref()
+    }
+    /// Remove the value of a type, returning whether there was one.
+    pub fn remove<T: Any + Send + Sync>(&mut self) -> bool {
+        self.0.remove(&TypeId::of::<T>()).is_some()
+    }
+    pub fn contains<T: Any + Send + Sync>(&self) -> bool {
+        self.0.contains_key(&TypeId::of::<T>())
+    }
+    pub fn len(&self) -> usize {
+        self.0.len()
+    }
+    pub fn is_empty(&self) -> bool {
+        self.0.is_empty()
+    }
+}
+
+impl fmt::Debug for Extensions {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "Extensions({} values)", self.0.len())
+    }
+}
+
+impl PartialEq for Extensions {
+    fn eq(&self, other: &Self) -> bool {
+        self.0.len() == other.0.len() && self.0.keys().all(|k| other.0.contains_key(k))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn segments(path: &str) -> Vec<String> {
+        let request = RawRequest {
+            path: path.to_string(),
+            ..Default::default()
+        };
+        request.path_segments().map(|s| s.into_owned()).collect()
+    }
+
+    #[test]
+    fn test_content_type_matches() {
+        use crate::content::mediatypes::{ApplicationJson, TextPlain};
+        let request =
+            |content_type: &str| RawRequest::default().with_header("Content-Type", content_type);
+        for content_type in [
+            "application/json",
+            "application/json; charset=utf-8",
+            "Application/JSON",
+            "application/vnd.x+json",
+            "application/vnd.api+json; version=2",
+        ] {
+            assert!(
+                request(content_type).content_type_matches::<ApplicationJson>(),
+                "{}",
+                content_type
+            );
+        }
+        for content_type in [
+            "application/xml",
+            "text/json",
+            "application/jsonx",
+            "application/json+xml",
+            "json",
+        ] {
+            assert!(
+                !request(content_type).content_type_matches::<ApplicationJson>(),
+                "{}",
+                content_type
+            );
+        }
+        assert!(!request("application/vnd.x+json").content_type_matches::<TextPlain>());
+        assert!(!RawRequest::default().content_type_matches::<ApplicationJson>());
+    }
+
+    #[test]
+    fn test_path_segments() {
+        assert_eq!(segments("/a/b/c"), vec!["a", "b", "c"]);
+        assert_eq!(segments("/a/b/c/"), vec!["a", "b", "c"]);
+        assert_eq!(segments("/a//b"), vec!["a", "b"]);
+        assert!(segments("/").is_empty());
+        assert!(segments("").is_empty());
+    }
+
+    #[test]
+    fn test_path_segments_percent_decoded() {
+        assert_eq!(
+            segments("/hello%20world/caf%C3%A9"),
+            vec!["hello world", "café"]
+        );
+        assert_eq!(segments("/a%2Fb"), vec!["a/b"]);
+        assert_eq!(segments("/100%/%zz/%4"), vec!["100%", "%zz", "%4"]);
+        assert_eq!(segments("/%+1/%-1/%+A"), vec!["%+1", "%-1", "%+A"]);
+    }
+}
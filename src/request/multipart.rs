@@ -0,0 +1,348 @@
+//! Streaming `multipart/form-data` parser.
+use std::io;
+use std::io::prelude::*;
+
+use crate::request::{ContentDisposition, ContentType, Header};
+
+/// Maximum size of the headers of a single part.
+const MAX_HEADERS_SIZE: usize = 16 * 1024;
+const READ_SIZE: usize = 8 * 1024;
+
+/// Reads the parts of a `multipart/form-data` body one at a time, from any
+/// stream, without buffering whole parts in memory.
+///
+/// Parts are read in order with [`MultipartReader::next_part`]; each part
+/// borrows the reader, and its body is read through [`Read`], so it can be
+/// copied to a file as it arrives. Moving to the next part skips what is left
+/// of the current one.
+///
+/// See [`Request::multipart`] to read a request body. Servers buffer request
+/// bodies in memory before handling requests, unless they leave multipart
+/// bodies unread, see [`TcpServer::with_streamed_multipart`]; only then is
+/// the body read from the connection as it arrives.
+///
+/// [`Request::multipart`]: crate::request::Request::multipart
+/// [`TcpServer::with_streamed_multipart`]: crate::server::TcpServer::with_streamed_multipart
+///
+/// # Example
+/// ```
+/// use std::io::Read;
+/// use jbhttp::request::multipart::MultipartReader;
+///
+/// let body = b"--xyz\r\n\
+///     Content-Disposition: form-data; name=\"file\"; filename=\"a.txt\"\r\n\
+///     \r\n\
+///     contents\r\n\
+///     --xyz--\r\n";
+/// let mut multipart = MultipartReader::new(&body[..], "xyz");
+/// while let Some(mut part) = multipart.next_part().unwrap() {
+///     assert_eq!(part.filename().as_deref(), Some("a.txt"));
+///     let mut contents = String::new();
+///     part.read_to_string(&mut contents).unwrap();
+///     assert_eq!(contents, "contents");
+/// }
+/// ```
+pub struct MultipartReader<R> {
+    inner: R,
+    /// `\r\n--boundary`
+    delimiter: Vec<u8>,
+    /// Data read from the inner stream but not consumed yet.
+    buf: Vec<u8>,
+    eof: bool,
+    /// Whether the current part's body has been read up to the delimiter.
+    part_done: bool,
+    finished: bool,
+}
+
+impl<R: Read> MultipartReader<R> {
+    pub fn new(inner: R, boundary: &str) -> Self {
+        let mut delimiter = b"\r\n--".to_vec();
+        delimiter.extend(boundary.as_bytes());
+        Self {
+            inner,
+            delimiter,
+            // The first delimiter isn't preceded by CRLF, unless there is a
+            // preamble; add one so it is found like the others.
+            buf: b"\r\n".to_vec(),
+            eof: false,
+            part_done: true,
+            finished: false,
+        }
+    }
+    /// Get the next part, or None after the last one.
+    pub fn next_part(&mut self) -> io::Result<Option<Part<'_, R>>> {
+        if self.finished {
+            return Ok(None);
+        }
+        if !self.part_done {
+            io::copy(&mut Body { reader: self }, &mut io::sink())?;
+        }
+        // Skip the preamble, or the rest of the previous part
+        loop {
+            if let Some(i) = find(&self.buf, &self.delimiter) {
+                self.buf.drain(..i + self.delimiter.len());
+                break;
+            }
+            let keep = self.delimiter.len().min(self.buf.len());
+            self.buf.drain(..self.buf.len() - keep);
+            if self.fill()? == 0 {
+                return Err(invalid("missing multipart boundary"));
+            }
+        }
+        // The closing delimiter is followed by "--", and maybe not by CRLF
+        while self.buf.len() < 2 && self.fill()? > 0 {}
+        if self.buf.starts_with(b"--") {
+            self.finished = true;
+            return Ok(None);
+        }
+        self.read_line()?;
+        let mut headers = vec![];
+        let mut size = 0;
+        loop {
+            let line = self.read_line()?;
+            if line.is_empty() {
+                break;
+            }
+            size += line.len();
+            if size > MAX_HEADERS_SIZE {
+                return Err(invalid("multipart headers too large"));
+            }
+            let line = String::from_utf8(line).map_err(|_| invalid("invalid multipart header"))?;
+            match line.split_once(':') {
+                Some((name, value)) => headers.push((Header::new(name), value.trim().to_string())),
+                None => return Err(invalid("invalid multipart header")),
+            }
+        }
+        self.part_done = false;
+        Ok(Some(Part {
+            headers,
+            body: Body { reader: self },
+        }))
+    }
+    pub fn get_ref(&self) -> &R {
+        &self.inner
+    }
+    pub fn into_inner(self) -> R {
+        self.inner
+    }
+    /// Read more data from the inner stream into the buffer.
+    fn fill(&mut self) -> io::Result<usize> {
+        if self.eof {
+            return Ok(0);
+        }
+        let len = self.buf.len();
+        self.buf.resize(len + READ_SIZE, 0);
+        let n = match self.inner.read(&mut self.buf[len..]) {
+            Ok(n) => n,
+            Err(e) => {
+                self.buf.truncate(len);
+                return Err(e);
+            }
+        };
+        self.buf.truncate(len + n);
+        self.eof = n == 0;
+        Ok(n)
+    }
+    /// Read a line, without its CRLF.
+    fn read_line(&mut self) -> io::Result<Vec<u8>> {
+        loop {
+            if let Some(i) = find(&self.buf, b"\r\n") {
+                let mut line: Vec<u8> = self.buf.drain(..i + 2).collect();
+                line.truncate(i);
+                return Ok(line);
+            }
+            if self.buf.len() > MAX_HEADERS_SIZE {
+                return Err(invalid("multipart headers too large"));
+            }
+            if self.fill()? == 0 {
+                return Err(io::Error::new(
+                    io::ErrorKind::UnexpectedEof,
+                    "unexpected end of multipart body",
+                ));
+            }
+        }
+    }
+}
+
+/// A part of a multipart body. Its body is read through [`Read`].
+pub struct Part<'a, R> {
+    headers: Vec<(Header, String)>,
+    body: Body<'a, R>,
+}
+
+impl<'a, R> Part<'a, R> {
+    /// Get a header of the part (case-insensitive).
+    pub fn header(&self, name: &str) -> Option<&str> {
+        let name = Header::new(name);
+        self.headers
+            .iter()
+            .find(|(h, _)| *h == name)
+            .map(|(_, v)| &v[..])
+    }
+    /// The parsed *Content-Disposition* header.
+    pub fn content_disposition(&self) -> Option<ContentDisposition> {
+        self.header("Content-Disposition")?.parse().ok()
+    }
+    /// The parsed *Content-Type* header; parts without one are plain text.
+    pub fn content_type(&self) -> Option<ContentType> {
+        self.header("Content-Type")?.parse().ok()
+    }
+    /// The form field name.
+    pub fn name(&self) -> Option<String> {
+        Some(self.content_disposition()?.name()?.to_string())
+    }
+    /// The original name of an uploaded file.
+    pub fn filename(&self) -> Option<String> {
+        Some(self.content_disposition()?.filename()?.to_string())
+    }
+}
+
+impl<'a, R: Read> Read for Part<'a, R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        self.body.read(buf)
+    }
+}
+
+struct Body<'a, R> {
+    reader: &'a mut MultipartReader<R>,
+}
+
+impl<'a, R: Read> Read for Body<'a, R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let reader = &mut *self.reader;
+        if reader.part_done {
+            return Ok(0);
+        }
+        loop {
+            // Data before a possible partial delimiter at the end of the
+            // buffer is part of the body.
+            let available = match find(&reader.buf, &reader.delimiter) {
+                Some(i) => i,
+                None => reader.buf.len().saturating_sub(reader.delimiter.len() - 1),
+            };
+            if available > 0 {
+                let n = available.min(buf.len());
+                buf[..n].copy_from_slice(&reader.buf[..n]);
+                reader.buf.drain(..n);
+                return Ok(n);
+            }
+            if find(&reader.buf, &reader.delimiter) == Some(0) {
+                reader.part_done = true;
+                return Ok(0);
+            }
+            if reader.fill()? == 0 {
+                return Err(io::Error::new(
+                    io::ErrorKind::UnexpectedEof,
+                    "unexpected end of multipart body",
+                ));
+            }
+        }
+    }
+}
+
+fn find(haystack: &[u8], needle: &[u8]) -> Option<usize> {
+    haystack.windows(needle.len()).position(|w| w == needle)
+}
+
+fn invalid(reason: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, reason)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::request::Request;
+
+    /// Returns at most n bytes per read, to split delimiters across reads.
+    struct Trickle<'a> {
+        data: &'a [u8],
+        n: usize,
+    }
+
+    impl<'a> Read for Trickle<'a> {
+        fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+            let n = self.n.min(buf.len()).min(self.data.len());
+            buf[..n].copy_from_slice(&self.data[..n]);
+            self.data = &self.data[n..];
+            Ok(n)
+        }
+    }
+
+    const BODY: &[u8] = b"preamble\r\n\
+        --xyz\r\n\
+        Content-Disposition: form-data; name=\"field\"\r\n\
+        \r\n\
+        value\r\n\
+        --xyz\r\n\
+        Content-Disposition: form-data; name=\"file\"; filename=\"a.txt\"\r\n\
+        Content-Type: text/plain\r\n\
+        \r\n\
+        line 1\r\n--xy\r\nline 2\r\n\
+        --xyz--\r\n\
+        epilogue";
+
+    fn read_parts<R: Read>(mut multipart: MultipartReader<R>) -> Vec<(String, String)> {
+        let mut parts = vec![];
+        while let Some(mut part) = multipart.next_part().unwrap() {
+            let mut body = String::new();
+            part.read_to_string(&mut body).unwrap();
+            parts.push((part.name().unwrap(), body));
+        }
+        parts
+    }
+
+    #[test]
+    fn test_multipart() {
+        let expected = vec![
+            ("field".to_string(), "value".to_string()),
+            ("file".to_string(), "line 1\r\n--xy\r\nline 2".to_string()),
+        ];
+        assert_eq!(read_parts(MultipartReader::new(BODY, "xyz")), expected);
+        for n in 1..8 {
+            let trickle = Trickle { data: BODY, n };
+            assert_eq!(read_parts(MultipartReader::new(trickle, "xyz")), expected);
+        }
+    }
+
+    #[test]
+    fn test_multipart_skip_parts() {
+        let mut multipart = MultipartReader::new(Trickle { data: BODY, n: 3 }, "xyz");
+        let part = multipart.next_part().unwrap().unwrap();
+        assert_eq!(part.name(), Some("field".to_string()));
+        let part = multipart.next_part().unwrap().unwrap();
+        assert_eq!(part.filename(), Some("a.txt".to_string()));
+        assert_eq!(part.content_type().unwrap().mime_subtype, "plain");
+        assert!(multipart.next_part().unwrap().is_none());
+        assert!(multipart.next_part().unwrap().is_none());
+    }
+
+    #[test]
+    fn test_multipart_no_final_crlf() {
+        let body = b"--xyz\r\nContent-Disposition: form-data; name=\"a\"\r\n\r\n1\r\n--xyz--";
+        let parts = read_parts(MultipartReader::new(&body[..], "xyz"));
+        assert_eq!(parts, vec![("a".to_string(), "1".to_string())]);
+    }
+
+    #[test]
+    fn test_request_multipart() {
+        let mut request =
+            Request::default().with_header("Content-Type", "multipart/form-data; boundary=xyz");
+        request.payload = Some(BODY.to_vec());
+        assert_eq!(read_parts(request.multipart().unwrap()).len(), 2);
+
+        let request = request.with_header("Content-Type", "text/plain");
+        assert!(request.multipart().is_none());
+    }
+
+    #[test]
+    fn test_multipart_invalid() {
+        let mut multipart = MultipartReader::new(&b"no boundary here"[..], "xyz");
+        assert!(multipart.next_part().is_err());
+
+        let truncated = b"--xyz\r\nContent-Disposition: form-data; name=\"a\"\r\n\r\nval";
+        let mut multipart = MultipartReader::new(&truncated[..], "xyz");
+        let mut part = multipart.next_part().unwrap().unwrap();
+        let err = part.read_to_end(&mut vec![]).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::UnexpectedEof);
+    }
+}
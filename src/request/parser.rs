@@ -4,7 +4,10 @@ use std::io::prelude::*;
 use std::str::FromStr;
 use std::str::Utf8Error;
 
-use crate::request::{Header, Method, Param, Params, Request};
+use crate::request::{
+    body::Framing, BytesRead, Extensions, Header, HttpVersion, Method, Param, Params, RawHeaders,
+    Request,
+};
 
 impl FromStr for Method {
     type Err = RequestParserError;
@@ -25,8 +28,16 @@ impl FromStr for Method {
 }
 
 const REQUEST_PARSER_BUFFER_SIZE: usize = 1024;
+pub(crate) const DEFAULT_MAX_PARAMS: usize = 1000;
+/// Empty lines skipped before a request line, see RFC 7230 section 3.5.
+const MAX_LEADING_CRLFS: usize = 8;
 
 /// A not very good HTTP/1.x request parser.
+///
+/// Header line folding (obs-fold, a line continued on the next one starting
+/// with a space or tab) is deprecated by RFC 7230 and not supported by
+/// design: folded headers are rejected rather than unfolded, since servers
+/// and proxies disagreeing on them can be used to smuggle requests.
 pub struct RequestParser<T: Read> {
     buffer: [u8; REQUEST_PARSER_BUFFER_SIZE],
     buffer_position: usize,
@@ -34,6 +45,16 @@ pub struct RequestParser<T: Read> {
     peek: Option<u8>,
     stream_position: usize,
     eof: bool,
+    semicolon_separator: bool,
+    raw_headers: bool,
+    lenient_line_endings: bool,
+    reject_body_on: Vec<Method>,
+    max_params: usize,
+    streamed_multipart: bool,
+    /// Framing of the body of the last request, when it was left unread.
+    unread_body: Option<Framing>,
+    /// Bytes consumed by `next` are copied here while capturing.
+    capture: Option<Vec<u8>>,
     stream: T,
 }
 
@@ -41,6 +62,7 @@ const WHITESPACE: [u8; 2] = *b" \t";
 const PATH: [u8; 67] = *b"/ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789-._~";
 const QUERY: [u8; 77] =
     *b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789-._~!$&'()*+,;=";
+const HEX: [u8; 22] = *b"0123456789abcdefABCDEF";
 const FRAGMENT: [u8; 81] =
     *b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789-._~!$&'()*+,;=?/:@";
 
@@ -48,6 +70,11 @@ fn one_of(chars: &'static [u8]) -> impl Fn(u8) -> bool {
     move |c: u8| chars.contains(&c)
 }
 
+/// Token characters, as in header names, see RFC 7230 section 3.2.6.
+fn token() -> impl Fn(u8) -> bool {
+    |c: u8| c.is_ascii_alphanumeric() || b"!#$%&'*+-.^_`|~".contains(&c)
+}
+
 fn whitespace() -> impl Fn(u8) -> bool {
     one_of(&WHITESPACE[..])
 }
@@ -66,14 +93,98 @@ impl<R: Read> RequestParser<R> {
             buffer_read_size: 0,
             stream_position: 0,
             eof: false,
+            semicolon_separator: false,
+            raw_headers: false,
+            lenient_line_endings: false,
+            reject_body_on: vec![],
+            max_params: DEFAULT_MAX_PARAMS,
+            streamed_multipart: false,
+            unread_body: None,
+            capture: None,
+        }
+    }
+    /// Also accept `;` as a separator between query and form parameters,
+    /// as in `a=1;b=2`, for interop with older clients. By default only
+    /// `&` is a separator.
+    pub fn with_semicolon_separator(mut self, enabled: bool) -> Self {
+        self.semicolon_separator = enabled;
+        self
+    }
+    /// Keep a copy of the raw header block of each request, as received,
+    /// see [`Request::raw_headers`]. Disabled by default to save memory.
+    pub fn with_raw_headers(mut self, enabled: bool) -> Self {
+        self.raw_headers = enabled;
+        self
+    }
+    /// Also accept a bare LF as a line ending, instead of CRLF, for
+    /// interop with telnet-style or buggy clients. Disabled by default:
+    /// servers and proxies disagreeing on line endings can be used to
+    /// smuggle requests.
+    pub fn lenient_line_endings(mut self, enabled: bool) -> Self {
+        self.lenient_line_endings = enabled;
+        self
+    }
+    /// Reject requests with a body, with an error (servers respond 400),
+    /// for these methods, as a defense against request smuggling. By default,
+    /// bodies are accepted for any method, including GET and DELETE.
+    pub fn reject_body_on(mut self, methods: &[Method]) -> Self {
+        self.reject_body_on = methods.to_vec();
+        self
+    }
+    /// Maximum number of query and form parameters in a request, together;
+    /// requests with more fail with an error (servers respond 400). Defaults
+    /// to 1000.
+    pub fn with_max_params(mut self, max_params: usize) -> Self {
+        self.max_params = max_params;
+        self
+    }
+    /// Leave `multipart/*` bodies unread, so they can be read from the
+    /// stream as they arrive, see [`RequestParser::take_unread_body`]; such
+    /// requests have no payload. The rest of the stream can't be parsed
+    /// after them. Disabled by default.
+    pub(crate) fn with_streamed_multipart(mut self, enabled: bool) -> Self {
+        self.streamed_multipart = enabled;
+        self
+    }
+    /// Take the framing of the body of the last request, if it was left
+    /// unread, and the start of it, already buffered by the parser; the rest
+    /// is to be read from the stream.
+    pub(crate) fn take_unread_body(&mut self) -> Option<(Framing, Vec<u8>)> {
+        let framing = self.unread_body.take()?;
+        let buffered = self.buffer[self.buffer_position..self.buffer_read_size].to_vec();
+        self.buffer_position = self.buffer_read_size;
+        Some((framing, buffered))
+    }
+    /// Get a reference to the underlying stream.
+    pub fn get_ref(&self) -> &R {
+        &self.stream
+    }
+    /// Get a mutable reference to the underlying stream. Reading directly
+    /// from it will skip over any input already buffered by the parser.
+    pub fn get_mut(&mut self) -> &mut R {
+        &mut self.stream
+    }
+    /// Check if the stream has no more input. Blocks until there is
+    /// input to read if none is buffered.
+    pub fn is_eof(&mut self) -> Result<bool> {
+        if self.buffer_position == self.buffer_read_size {
+            self.read()?;
         }
+        Ok(self.buffer_read_size == 0)
     }
     fn error(&self, reason: &str) -> RequestParserError {
         RequestParserError::new(self.stream_position, reason)
     }
-    /// Read next chunk from the input stream.
+    /// Read next chunk from the input stream. Blocks until some input is
+    /// available; a short read is not the end of the stream, only a read of
+    /// 0 bytes is.
     fn read(&mut self) -> Result<()> {
-        self.buffer_read_size = self.stream.read(&mut self.buffer)?;
+        self.buffer_read_size = loop {
+            match self.stream.read(&mut self.buffer) {
+                Err(e) if e.kind() == std::io::ErrorKind::Interrupted => continue,
+                result => break result?,
+            }
+        };
         self.buffer_position = 0;
         Ok(())
     }
@@ -83,6 +194,9 @@ impl<R: Read> RequestParser<R> {
     /// is expected, otherwise it will hang on `read`.
     fn next(&mut self) -> Result<Option<u8>> {
         let curr = self.peek;
+        if let (Some(capture), Some(b)) = (&mut self.capture, curr) {
+            capture.push(b);
+        }
         if self.eof {
             self.peek = None;
             return Ok(curr);
@@ -135,14 +249,10 @@ impl<R: Read> RequestParser<R> {
         let mut out = vec![];
         loop {
             match self.peek {
-                Some(peek) => {
-                    if predicate(peek) {
-                        out.push(self.next()?.unwrap());
-                    } else {
-                        return Ok(out);
-                    }
+                Some(peek) if predicate(peek) => {
+                    out.push(self.next()?.unwrap());
                 }
-                None => return Ok(out),
+                _ => return Ok(out),
             }
         }
     }
@@ -155,28 +265,54 @@ impl<R: Read> RequestParser<R> {
         Ok(out)
     }
     fn crlf(&mut self) -> Result<()> {
+        if self.lenient_line_endings && self.peek == Some(b'\n') {
+            return self.expect(b'\n');
+        }
         self.expects(b"\r\n")
     }
-    fn until(&mut self, b: u8) -> Result<Vec<u8>> {
-        let mut word: Vec<u8> = vec![];
-        while self.peek != Some(b) {
-            word.push(
+    /// Expect the CRLF which ends a request, without reading past it.
+    fn last_crlf(&mut self) -> Result<()> {
+        if !(self.lenient_line_endings && self.peek == Some(b'\n')) {
+            self.expect(b'\r')?;
+        }
+        self.eof = true;
+        self.expect(b'\n')
+    }
+    fn is_eol(&self) -> bool {
+        self.peek == Some(b'\r') || (self.lenient_line_endings && self.peek == Some(b'\n'))
+    }
+    /// Read up to the end of the line, not including the line ending.
+    fn line(&mut self) -> Result<Vec<u8>> {
+        let mut line = vec![];
+        while !self.is_eol() {
+            line.push(
                 self.next()?
                     .ok_or_else(|| self.error("unexpected end of input"))?,
             )
         }
-        Ok(word)
+        Ok(line)
     }
     fn method(&mut self) -> Result<Method> {
         let method = self.plus(&in_range(b'A', b'Z'))?;
         let method = std::str::from_utf8(&method)?;
-        Ok(Method::from_str(method)?)
+        Method::from_str(method)
     }
     fn path(&mut self) -> Result<String> {
         if self.peek != Some(b'/') {
             return Err(self.error("expected path starting with /"));
         }
-        let path = self.plus(&one_of(&PATH[..]))?;
+        // Percent-encoded octets are kept as is, see Request::path_segments
+        let mut path = self.plus(&one_of(&PATH[..]))?;
+        while self.peek == Some(b'%') {
+            path.append(&mut self.one(&one_of(b"%"))?);
+            for _ in 0..2 {
+                if !self.peek.is_some_and(one_of(&HEX[..])) {
+                    return Err(self.error("invalid percent-encoding in path"));
+                }
+                path.append(&mut self.one(&one_of(&HEX[..]))?);
+            }
+            path.append(&mut self.star(&one_of(&PATH[..]))?);
+        }
         Ok(std::str::from_utf8(&path)?.to_string())
     }
     fn query(&mut self) -> Result<String> {
@@ -201,11 +337,18 @@ impl<R: Read> RequestParser<R> {
         Ok((self.path()?, self.query()?, self.fragment()?))
     }
     fn header(&mut self) -> Result<(Header, String)> {
-        // TODO: only get allowed characters instead, don't just check delimiters
-        let header = self.until(b':')?;
-        self.expects(b":")?;
+        // Names are tokens, no whitespace before the colon (RFC 7230 section
+        // 3.2.4); values go up to the end of the line, colons included
+        if self.peek.is_some_and(whitespace()) {
+            return Err(self.error("obsolete line folding is not supported"));
+        }
+        let header = self.star(&token())?;
+        if header.is_empty() || self.peek != Some(b':') {
+            return Err(self.error("invalid header name"));
+        }
+        self.expect(b':')?;
         self.star(&whitespace())?;
-        let value = self.until(b'\r')?;
+        let value = self.line()?;
         self.crlf()?;
         Ok((
             Header::new(std::str::from_utf8(&header)?),
@@ -214,7 +357,7 @@ impl<R: Read> RequestParser<R> {
     }
     fn headers(&mut self) -> Result<Vec<(Header, String)>> {
         let mut headers = vec![];
-        while self.peek != Some(b'\r') {
+        while !self.is_eol() {
             headers.push(self.header()?);
         }
         Ok(headers)
@@ -233,37 +376,184 @@ impl<R: Read> RequestParser<R> {
         }
         Ok(buf)
     }
-    /// Parse next HTTP request in stream.
+    /// Read a chunked body, up to and including the final CRLF after the
+    /// (optional) trailer section, so the stream is positioned at the start
+    /// of the next request. Trailers are ignored.
+    fn chunked_body(&mut self) -> Result<Vec<u8>> {
+        let mut buf = vec![];
+        loop {
+            let size = self.plus(&one_of(&HEX[..]))?;
+            let size = usize::from_str_radix(std::str::from_utf8(&size)?, 16)
+                .map_err(|_| self.error("invalid chunk size"))?;
+            // Skip chunk extensions
+            self.line()?;
+            self.crlf()?;
+            if size == 0 {
+                break;
+            }
+            for _ in 0..size {
+                match self.next()? {
+                    Some(b) => buf.push(b),
+                    None => return Err(self.error("unexpected end of chunk")),
+                }
+            }
+            self.crlf()?;
+        }
+        self.headers()?;
+        self.last_crlf()?;
+        Ok(buf)
+    }
+    /// Parse next HTTP request in stream. Can be called repeatedly to
+    /// parse successive requests from the same stream.
+    ///
+    /// `Expect: 100-continue` is accepted, but no interim response is sent,
+    /// see [`RequestParser::parse_with_continue`]. Any other expectation fails
+    /// with an error for which [`RequestParserError::is_expectation_failed`]
+    /// is true, without reading the body; servers respond 417.
     pub fn parse(&mut self) -> Result<Request<Vec<u8>>> {
+        self.parse_request(|_| Ok(()), |_| ())
+    }
+    fn parse_request<F, B>(
+        &mut self,
+        mut send_continue: F,
+        mut before_body: B,
+    ) -> Result<Request<Vec<u8>>>
+    where
+        F: FnMut(&mut R) -> std::io::Result<()>,
+        B: FnMut(&mut R),
+    {
+        self.peek = None;
+        self.eof = false;
+        self.stream_position = 0;
+        self.unread_body = None;
         self.next()?;
+        for _ in 0..MAX_LEADING_CRLFS {
+            if !self.is_eol() {
+                break;
+            }
+            self.crlf()?;
+        }
         let method = self.method()?;
         self.plus(&whitespace())?;
         let (path, query, fragment) = self.uri()?;
         self.plus(&whitespace())?;
         self.expects(b"HTTP/1.")?;
-        self.one(&one_of(&b"01"[..]))?;
+        let minor_version = self.one(&one_of(&b"01"[..]))?;
         self.crlf()?;
-        let headers: HashMap<Header, String> = self.headers()?.into_iter().collect();
-
-        let content_length = match headers.get(&Header::new("content-length")) {
-            Some(cl_str) => match str::parse::<usize>(cl_str) {
+        if self.raw_headers {
+            self.capture = Some(vec![]);
+        }
+        let headers = self.headers();
+        let raw_headers = self.capture.take();
+        let headers = headers?;
+        // Framing headers are checked before repeated headers are collapsed,
+        // see RFC 9112 section 6; a body framed differently than by another
+        // server on the way could be used to smuggle requests
+        let values = |name: &str| -> Vec<String> {
+            let name = Header::new(name);
+            headers
+                .iter()
+                .filter(|(header, _)| *header == name)
+                .flat_map(|(_, value)| value.split(','))
+                .map(|value| value.trim().to_ascii_lowercase())
+                .filter(|value| !value.is_empty())
+                .collect()
+        };
+        let transfer_codings = values("transfer-encoding");
+        let content_lengths = values("content-length");
+        let chunked = match transfer_codings.split_last() {
+            None => false,
+            Some((last, _)) if last != "chunked" => {
+                return Err(self.error("final transfer coding is not chunked"))
+            }
+            Some((_, [])) => true,
+            Some((_, codings)) => {
+                return Err(self.error(&format!(
+                    "unsupported transfer coding '{}'",
+                    codings.join(", ")
+                )))
+            }
+        };
+        if chunked && !content_lengths.is_empty() {
+            return Err(self.error("both transfer-encoding and content-length"));
+        }
+        let mut content_length = match content_lengths.split_first() {
+            Some((cl_str, others)) => match str::parse::<usize>(cl_str) {
+                Ok(_) if others.iter().any(|other| other != cl_str) => {
+                    return Err(self.error("conflicting content-length"))
+                }
                 Ok(cl) => cl,
                 Err(_) => return Err(self.error("invalid content-length")),
             },
             None => 0,
         };
+        let headers: HashMap<Header, String> = headers.into_iter().collect();
+
+        // Expectations are ignored for HTTP/1.0, see RFC 7231 section 5.1.1
+        let expect = match headers.get(&Header::new("expect")) {
+            Some(expect) if minor_version != b"0" => Some(expect),
+            _ => None,
+        };
+        let expect_continue = match expect {
+            Some(expect) if expect.trim().eq_ignore_ascii_case("100-continue") => true,
+            Some(expect) => {
+                let mut error = self.error(&format!("unsupported expectation '{}'", expect));
+                error.expectation_failed = true;
+                return Err(error);
+            }
+            None => false,
+        };
+
+        let has_body = chunked || content_length > 0;
+        if has_body && self.reject_body_on.contains(&method) {
+            return Err(self.error(&format!("unexpected body for {:?} request", method)));
+        }
+        if expect_continue && has_body {
+            send_continue(&mut self.stream)?;
+        }
+        if has_body {
+            before_body(&mut self.stream);
+        }
+        let is_multipart = headers.get(&Header::new("content-type")).is_some_and(|ct| {
+            ct.trim_start()
+                .to_ascii_lowercase()
+                .starts_with("multipart/")
+        });
         let body;
-        if content_length == 0 {
-            self.expect(b'\r')?;
-            self.eof = true;
-            self.expect(b'\n')?;
+        if has_body && is_multipart && self.streamed_multipart {
+            self.last_crlf()?;
+            self.unread_body = Some(if chunked {
+                // Unknown until read
+                content_length = 0;
+                Framing::Chunked
+            } else {
+                Framing::Length(content_length)
+            });
+            body = None;
+        } else if chunked {
+            self.crlf()?;
+            let chunks = self.chunked_body()?;
+            content_length = chunks.len();
+            body = if chunks.is_empty() {
+                None
+            } else {
+                Some(chunks)
+            };
+        } else if content_length == 0 {
+            self.last_crlf()?;
             body = None;
         } else {
             self.crlf()?;
             body = Some(self.body(content_length)?);
         }
+        let version = if minor_version == b"0" {
+            HttpVersion::Http10
+        } else {
+            HttpVersion::Http11
+        };
         let mut request = Request {
             method,
+            version,
             path,
             query,
             fragment,
@@ -271,51 +561,109 @@ impl<R: Read> RequestParser<R> {
             payload: body,
             content_length,
             params: Params::new(),
+            extensions: Extensions::new(),
         };
-        parse_query_params(&mut request);
-        parse_body_params(&mut request);
+        request.extensions.insert(BytesRead(self.stream_position));
+        if let Some(raw_headers) = raw_headers {
+            request.extensions.insert(RawHeaders(raw_headers));
+        }
+        let separators: &[char] = if self.semicolon_separator {
+            &['&', ';']
+        } else {
+            &['&']
+        };
+        let n_params = parse_query_params(&mut request, separators, self.max_params)
+            .and_then(|n| parse_body_params(&mut request, separators, self.max_params - n));
+        if n_params.is_none() {
+            return Err(self.error(&format!("more than {} parameters", self.max_params)));
+        }
         Ok(request)
     }
 }
 
-fn parse_params(params_str: &str) -> Vec<(String, String)> {
+impl<R: Read + Write> RequestParser<R> {
+    /// Like [`RequestParser::parse`], but answers `Expect: 100-continue`
+    /// with an interim *100 Continue* response on the stream before reading
+    /// the body, so clients waiting for it don't stall.
+    pub fn parse_with_continue(&mut self) -> Result<Request<Vec<u8>>> {
+        self.parse_with_continue_then(|_| ())
+    }
+    /// Like [`RequestParser::parse_with_continue`], calling `before_body`
+    /// with the stream once the headers are read, before reading a body, to
+    /// switch to different timeouts for example.
+    pub(crate) fn parse_with_continue_then<B>(&mut self, before_body: B) -> Result<Request<Vec<u8>>>
+    where
+        B: FnMut(&mut R),
+    {
+        self.parse_request(
+            |stream| {
+                stream.write_all(b"HTTP/1.1 100 Continue\r\n\r\n")?;
+                stream.flush()
+            },
+            before_body,
+        )
+    }
+}
+
+/// Parse `name=value` pairs; None if there are more than `max`.
+fn parse_params(
+    params_str: &str,
+    separators: &[char],
+    max: usize,
+) -> Option<Vec<(String, String)>> {
     let mut params = vec![];
-    let pairs = params_str.split('&');
+    let pairs = params_str.split(separators);
     for pair in pairs {
         let parts: Vec<&str> = pair.splitn(2, '=').collect();
         if parts.len() == 2 {
+            if params.len() == max {
+                return None;
+            }
             let name = parts[0].to_string();
             let value = parts[1].to_string();
             params.push((name, value));
         }
     }
-    params
+    Some(params)
 }
 
-fn parse_body_params(req: &mut Request<Vec<u8>>) {
+/// Add form parameters from the body; returns how many, or None if there are
+/// more than `max`.
+fn parse_body_params(req: &mut Request<Vec<u8>>, separators: &[char], max: usize) -> Option<usize> {
     if let Some(body) = &req.payload {
         if let Some(content_type) = req.headers.get(&Header::new("content-type")) {
             if content_type == "application/www-form-urlencoded" {
                 if let Ok(body) = std::str::from_utf8(body) {
-                    for (name, val) in parse_params(body) {
+                    let params = parse_params(body, separators, max)?;
+                    let n = params.len();
+                    for (name, val) in params {
                         req.params.add(Param::Body(name), val);
                     }
+                    return Some(n);
                 }
             }
         }
     }
+    Some(0)
 }
 
-fn parse_query_params<T>(req: &mut Request<T>) {
-    for (name, val) in parse_params(&req.query) {
+/// Add query parameters; returns how many, or None if there are more than
+/// `max`.
+fn parse_query_params<T>(req: &mut Request<T>, separators: &[char], max: usize) -> Option<usize> {
+    let params = parse_params(&req.query, separators, max)?;
+    let n = params.len();
+    for (name, val) in params {
         req.params.add(Param::Query(name), val);
     }
+    Some(n)
 }
 
 #[derive(Debug, Clone, PartialEq)]
 pub struct RequestParserError {
     position: usize,
     reason: String,
+    timed_out: bool,
+    expectation_failed: bool,
 }
 
 impl RequestParserError {
@@ -323,8 +671,19 @@ impl RequestParserError {
         Self {
             position,
             reason: reason.to_string(),
+            timed_out: false,
+            expectation_failed: false,
         }
     }
+    /// Whether parsing failed because reading the request timed out.
+    pub fn is_timeout(&self) -> bool {
+        self.timed_out
+    }
+    /// Whether parsing failed because the request has an *Expect* header
+    /// which can't be met (anything but `100-continue`).
+    pub fn is_expectation_failed(&self) -> bool {
+        self.expectation_failed
+    }
 }
 
 impl fmt::Display for RequestParserError {
@@ -337,9 +696,16 @@ impl fmt::Display for RequestParserError {
     }
 }
 
+impl std::error::Error for RequestParserError {}
+
 impl From<std::io::Error> for RequestParserError {
     fn from(err: std::io::Error) -> Self {
-        RequestParserError::new(0, &err.to_string())
+        let mut error = RequestParserError::new(0, &err.to_string());
+        error.timed_out = matches!(
+            err.kind(),
+            std::io::ErrorKind::TimedOut | std::io::ErrorKind::WouldBlock
+        );
+        error
     }
 }
 
@@ -366,6 +732,7 @@ mod test {
     ) -> Request<Vec<u8>> {
         let mut req = Request {
             method: Method::from_str(method).unwrap(),
+            version: HttpVersion::Http11,
             path: path.to_string(),
             query: query.to_string(),
             fragment: fragment.to_string(),
@@ -376,15 +743,23 @@ mod test {
             content_length: body.map_or(0, |b| b.len()),
             payload: body.map(|b| b.to_vec()),
             params: Params::new(),
+            extensions: Extensions::new(),
         };
-        parse_body_params(&mut req);
-        parse_query_params(&mut req);
+        parse_body_params(&mut req, &['&'], usize::MAX);
+        parse_query_params(&mut req, &['&'], usize::MAX);
         req
     }
 
+    /// Parse the next request, without its size, see test_parser_bytes_read.
+    fn parse<R: Read>(parser: &mut RequestParser<R>) -> Request<Vec<u8>> {
+        let mut request = parser.parse().unwrap();
+        request.extensions.remove::<BytesRead>();
+        request
+    }
+
     fn test_parser(bytes: &[u8], expected: &Request<Vec<u8>>) {
         let mut parser = RequestParser::new(bytes);
-        let actual = parser.parse().unwrap();
+        let actual = parse(&mut parser);
         assert_eq!(&actual, expected);
     }
 
@@ -411,6 +786,29 @@ mod test {
         )
     }
 
+    #[test]
+    fn test_parser_percent_encoded_path() {
+        test_parser(
+            b"GET /a%20b HTTP/1.1\r\nHost: localhost\r\n\r\n",
+            &make_request("GET", "/a%20b", "", "", &[("host", "localhost")], None),
+        );
+        test_parser(
+            b"GET /%C3%A9t%c3%a9/ HTTP/1.1\r\nHost: localhost\r\n\r\n",
+            &make_request(
+                "GET",
+                "/%C3%A9t%c3%a9/",
+                "",
+                "",
+                &[("host", "localhost")],
+                None,
+            ),
+        );
+        test_parser_error(
+            b"GET /a%2 HTTP/1.1\r\n\r\n",
+            &RequestParserError::new(9, "invalid percent-encoding in path"),
+        );
+    }
+
     #[test]
     fn test_parser_post() {
         test_parser(
@@ -441,6 +839,400 @@ mod test {
         )
     }
 
+    #[test]
+    fn test_parser_semicolon_separator() {
+        let bytes = &b"GET /?a=1;b=2&c=3;a=4 HTTP/1.1\r\n\r\n"[..];
+        let query = |req: &Request<Vec<u8>>, name: &str| {
+            req.params.get_all(&Param::Query(name.to_string())).cloned()
+        };
+
+        let req = RequestParser::new(bytes)
+            .with_semicolon_separator(true)
+            .parse()
+            .unwrap();
+        assert_eq!(
+            query(&req, "a"),
+            Some(vec!["1".to_string(), "4".to_string()])
+        );
+        assert_eq!(query(&req, "b"), Some(vec!["2".to_string()]));
+        assert_eq!(query(&req, "c"), Some(vec!["3".to_string()]));
+
+        let req = RequestParser::new(bytes).parse().unwrap();
+        assert_eq!(query(&req, "a"), Some(vec!["1;b=2".to_string()]));
+        assert_eq!(query(&req, "b"), None);
+        assert_eq!(query(&req, "c"), Some(vec!["3;a=4".to_string()]));
+    }
+
+    #[test]
+    fn test_parser_raw_headers() {
+        let headers = &b"X-Signature:  abc\r\nhost: localhost\r\nContent-Length: 3\r\n"[..];
+        let mut bytes = b"POST /hook HTTP/1.1\r\n".to_vec();
+        bytes.extend(headers);
+        bytes.extend(b"\r\nfoo");
+        bytes.extend(b"GET / HTTP/1.1\r\n\r\n");
+
+        let mut parser = RequestParser::new(&bytes[..]).with_raw_headers(true);
+        let req = parser.parse().unwrap();
+        assert_eq!(req.raw_headers(), Some(headers));
+        assert_eq!(req.payload, Some(b"foo".to_vec()));
+        let req = parser.parse().unwrap();
+        assert_eq!(req.raw_headers(), Some(&b""[..]));
+
+        let req = RequestParser::new(&bytes[..]).parse().unwrap();
+        assert_eq!(req.raw_headers(), None);
+    }
+
+    #[test]
+    fn test_parser_reject_body_on() {
+        let get_with_body = &b"GET / HTTP/1.1\r\nContent-Length: 3\r\n\r\nfoo"[..];
+        let chunked_delete =
+            &b"DELETE / HTTP/1.1\r\nTransfer-Encoding: chunked\r\n\r\n3\r\nfoo\r\n0\r\n\r\n"[..];
+        let get = &b"GET / HTTP/1.1\r\n\r\n"[..];
+        let parse = |bytes: &[u8], reject: &[Method]| {
+            RequestParser::new(bytes).reject_body_on(reject).parse()
+        };
+
+        let req = parse(get_with_body, &[]).unwrap();
+        assert_eq!(req.payload, Some(b"foo".to_vec()));
+        assert!(parse(chunked_delete, &[]).is_ok());
+
+        let reject = [Method::GET, Method::DELETE];
+        assert!(parse(get_with_body, &reject).is_err());
+        assert!(parse(chunked_delete, &reject).is_err());
+        assert!(parse(get, &reject).is_ok());
+        assert!(parse(get_with_body, &[Method::DELETE]).is_ok());
+    }
+
+    #[test]
+    fn test_parser_max_params() {
+        let query = vec!["a=1"; 1001].join("&");
+        let bytes = format!("GET /?{} HTTP/1.1\r\n\r\n", query);
+        assert!(RequestParser::new(bytes.as_bytes()).parse().is_err());
+        let req = RequestParser::new(bytes.as_bytes())
+            .with_max_params(2000)
+            .parse()
+            .unwrap();
+        assert_eq!(req.params.get_any("a").unwrap(), "1");
+
+        // Query and form parameters count together
+        let bytes = b"POST /?a=1&b=2 HTTP/1.1\r\n\
+            Content-Type: application/www-form-urlencoded\r\n\
+            Content-Length: 7\r\n\r\nc=3&d=4";
+        assert!(RequestParser::new(&bytes[..])
+            .with_max_params(4)
+            .parse()
+            .is_ok());
+        assert!(RequestParser::new(&bytes[..])
+            .with_max_params(3)
+            .parse()
+            .is_err());
+        assert!(RequestParser::new(&bytes[..])
+            .with_max_params(1)
+            .parse()
+            .is_err());
+    }
+
+    #[test]
+    fn test_parser_leading_crlfs() {
+        let req = RequestParser::new(&b"\r\nGET /a HTTP/1.1\r\n\r\n"[..])
+            .parse()
+            .unwrap();
+        assert_eq!(req.path, "/a");
+
+        let mut parser =
+            RequestParser::new(&b"GET /a HTTP/1.1\r\n\r\n\r\n\r\n\r\nGET /b HTTP/1.1\r\n\r\n"[..]);
+        assert_eq!(parser.parse().unwrap().path, "/a");
+        assert_eq!(parser.parse().unwrap().path, "/b");
+
+        let input = format!("{}GET / HTTP/1.1\r\n\r\n", "\r\n".repeat(9));
+        assert!(RequestParser::new(input.as_bytes()).parse().is_err());
+        assert!(RequestParser::new(&b"\r GET / HTTP/1.1\r\n\r\n"[..])
+            .parse()
+            .is_err());
+    }
+
+    #[test]
+    fn test_parser_version() {
+        let req = RequestParser::new(&b"GET / HTTP/1.0\r\n\r\n"[..])
+            .parse()
+            .unwrap();
+        assert_eq!(req.version, HttpVersion::Http10);
+        assert!(!req.keep_alive());
+        let req = RequestParser::new(&b"GET / HTTP/1.0\r\nConnection: keep-alive\r\n\r\n"[..])
+            .parse()
+            .unwrap();
+        assert!(req.keep_alive());
+        let req = RequestParser::new(&b"GET / HTTP/1.1\r\n\r\n"[..])
+            .parse()
+            .unwrap();
+        assert_eq!(req.version, HttpVersion::Http11);
+        assert!(req.keep_alive());
+        let req = RequestParser::new(&b"GET / HTTP/1.1\r\nConnection: TE, close\r\n\r\n"[..])
+            .parse()
+            .unwrap();
+        assert!(!req.keep_alive());
+    }
+
+    #[test]
+    fn test_parser_bytes_read() {
+        let requests: [&[u8]; 4] = [
+            b"\r\nGET /a HTTP/1.1\r\nHost: localhost\r\n\r\n",
+            b"POST /b HTTP/1.1\r\nContent-Length: 3\r\n\r\nfoo",
+            b"POST /c HTTP/1.1\r\nTransfer-Encoding: chunked\r\n\r\n3\r\nfoo\r\n0\r\n\r\n",
+            b"GET /d HTTP/1.0\r\n\r\n",
+        ];
+        let bytes = requests.concat();
+        let mut parser = RequestParser::new(&bytes[..]);
+        for request in requests {
+            assert_eq!(parser.parse().unwrap().bytes_read(), Some(request.len()));
+        }
+    }
+
+    #[test]
+    fn test_parser_chunked_pipelined() {
+        let mut parser = RequestParser::new(
+            &b"POST /a HTTP/1.1\r\nTransfer-Encoding: chunked\r\n\r\n\
+               3\r\nfoo\r\n4;ext=1\r\nbar!\r\n0\r\n\r\n\
+               POST /b HTTP/1.1\r\nTransfer-Encoding: chunked\r\n\r\n\
+               a\r\n0123456789\r\n0\r\nChecksum: abc\r\nExpires: never\r\n\r\n\
+               POST /c HTTP/1.1\r\nTransfer-Encoding: chunked\r\n\r\n0\r\n\r\n\
+               GET /d HTTP/1.1\r\n\r\n"[..],
+        );
+        let a = parser.parse().unwrap();
+        assert_eq!((&a.path[..], a.payload), ("/a", Some(b"foobar!".to_vec())));
+        assert_eq!(a.content_length, 7);
+        let b = parser.parse().unwrap();
+        assert_eq!(
+            (&b.path[..], b.payload),
+            ("/b", Some(b"0123456789".to_vec()))
+        );
+        assert_eq!(b.headers.get(&Header::new("checksum")), None);
+        let c = parser.parse().unwrap();
+        assert_eq!((&c.path[..], c.payload), ("/c", None));
+        assert_eq!(parser.parse().unwrap().path, "/d");
+        assert!(parser.is_eof().unwrap());
+    }
+
+    #[test]
+    fn test_parser_chunked_invalid() {
+        let mut parser = RequestParser::new(
+            &b"POST / HTTP/1.1\r\nTransfer-Encoding: chunked\r\n\r\nxyz\r\n"[..],
+        );
+        assert!(parser.parse().is_err());
+        let mut parser = RequestParser::new(
+            &b"POST / HTTP/1.1\r\nTransfer-Encoding: chunked\r\n\r\n5\r\nabc"[..],
+        );
+        assert!(parser.parse().is_err());
+    }
+
+    #[test]
+    fn test_parser_transfer_encoding() {
+        let mut parser = RequestParser::new(
+            &b"POST /a HTTP/1.1\r\nTransfer-Encoding: \r\nTransfer-Encoding: Chunked\r\n\r\n\
+               3\r\nfoo\r\n0\r\n\r\n"[..],
+        );
+        assert_eq!(parser.parse().unwrap().payload, Some(b"foo".to_vec()));
+        for (te, reason) in [
+            ("chunked, gzip", "final transfer coding is not chunked"),
+            ("identity", "final transfer coding is not chunked"),
+            ("gzip, chunked", "unsupported transfer coding 'gzip'"),
+            (
+                "gzip\r\nTransfer-Encoding: chunked",
+                "unsupported transfer coding 'gzip'",
+            ),
+        ] {
+            let request = format!(
+                "POST / HTTP/1.1\r\nTransfer-Encoding: {}\r\n\r\n3\r\nfoo\r\n0\r\n\r\n",
+                te
+            );
+            let error = RequestParser::new(request.as_bytes()).parse().unwrap_err();
+            assert_eq!(error.reason, reason);
+        }
+    }
+
+    #[test]
+    fn test_parser_transfer_encoding_and_content_length() {
+        let error = RequestParser::new(
+            &b"POST / HTTP/1.1\r\nContent-Length: 3\r\nTransfer-Encoding: chunked\r\n\r\n\
+               3\r\nfoo\r\n0\r\n\r\n"[..],
+        )
+        .parse()
+        .unwrap_err();
+        assert_eq!(error.reason, "both transfer-encoding and content-length");
+    }
+
+    #[test]
+    fn test_parser_content_length_repeated() {
+        let mut parser = RequestParser::new(
+            &b"POST / HTTP/1.1\r\nContent-Length: 3\r\nContent-Length: 3, 3\r\n\r\nfoo"[..],
+        );
+        assert_eq!(parser.parse().unwrap().payload, Some(b"foo".to_vec()));
+        for cl in ["3\r\nContent-Length: 4", "3, 4", "4\r\nContent-Length: 3"] {
+            let request = format!("POST / HTTP/1.1\r\nContent-Length: {}\r\n\r\nfoo!", cl);
+            let error = RequestParser::new(request.as_bytes()).parse().unwrap_err();
+            assert_eq!(error.reason, "conflicting content-length");
+        }
+    }
+
+    #[test]
+    fn test_parser_pipelined() {
+        let mut parser = RequestParser::new(
+            &b"POST / HTTP/1.1\r\nContent-Length: 3\r\n\r\nfooGET /bar HTTP/1.1\r\n\r\n"[..],
+        );
+        assert_eq!(
+            parse(&mut parser),
+            make_request(
+                "POST",
+                "/",
+                "",
+                "",
+                &[("content-length", "3")],
+                Some(&b"foo"[..])
+            ),
+        );
+        assert_eq!(
+            parse(&mut parser),
+            make_request("GET", "/bar", "", "", &[], None),
+        );
+        assert!(parser.is_eof().unwrap());
+    }
+
+    #[test]
+    fn test_parser_expect_continue() {
+        let mut parser = RequestParser::new(crate::io::MemoryStream::new(
+            b"POST /a HTTP/1.1\r\nExpect: 100-Continue\r\nContent-Length: 3\r\n\r\nfoo\
+              GET /b HTTP/1.1\r\nExpect: 100-continue\r\n\r\n",
+        ));
+        let a = parser.parse_with_continue().unwrap();
+        assert_eq!(a.payload, Some(b"foo".to_vec()));
+        assert_eq!(parser.get_ref().output(), b"HTTP/1.1 100 Continue\r\n\r\n");
+        // No interim response without a body to read
+        assert_eq!(parser.parse_with_continue().unwrap().path, "/b");
+        assert_eq!(parser.get_ref().output(), b"HTTP/1.1 100 Continue\r\n\r\n");
+
+        let mut parser = RequestParser::new(
+            &b"POST / HTTP/1.1\r\nExpect: 100-continue\r\nContent-Length: 3\r\n\r\nfoo"[..],
+        );
+        assert_eq!(parser.parse().unwrap().payload, Some(b"foo".to_vec()));
+    }
+
+    #[test]
+    fn test_parser_expectation_failed() {
+        let mut parser = RequestParser::new(
+            &b"POST / HTTP/1.1\r\nExpect: the-unexpected\r\nContent-Length: 3\r\n\r\nfoo"[..],
+        );
+        let err = parser.parse().unwrap_err();
+        assert!(err.is_expectation_failed());
+        assert!(!RequestParserError::new(0, "other").is_expectation_failed());
+
+        // Ignored for HTTP/1.0
+        let mut parser = RequestParser::new(
+            &b"POST / HTTP/1.0\r\nExpect: the-unexpected\r\nContent-Length: 3\r\n\r\nfoo"[..],
+        );
+        assert_eq!(parser.parse().unwrap().payload, Some(b"foo".to_vec()));
+    }
+
+    /// Returns one segment per read, like packets arriving on an open
+    /// connection; reading past the last one would block forever.
+    struct Segments(std::collections::VecDeque<std::io::Result<Vec<u8>>>);
+
+    impl Read for Segments {
+        fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+            let segment = self.0.pop_front().expect("read would block")?;
+            buf[..segment.len()].copy_from_slice(&segment);
+            Ok(segment.len())
+        }
+    }
+
+    #[test]
+    fn test_parser_body_in_segments() {
+        let interrupted = std::io::Error::from(std::io::ErrorKind::Interrupted);
+        let mut parser = RequestParser::new(Segments(
+            vec![
+                Ok(b"POST / HTTP/1.1\r\nContent-Length: 10\r\n\r\n01".to_vec()),
+                Ok(b"234".to_vec()),
+                Err(interrupted),
+                Ok(b"56789".to_vec()),
+            ]
+            .into(),
+        ));
+        let request = parser.parse().unwrap();
+        assert_eq!(request.payload, Some(b"0123456789".to_vec()));
+    }
+
+    #[test]
+    fn test_parser_header_value_colons() {
+        test_parser(
+            b"GET / HTTP/1.1\r\nLocation: http://x:8080/a\r\nX-Time:12:30:00\r\n\r\n",
+            &make_request(
+                "GET",
+                "/",
+                "",
+                "",
+                &[("location", "http://x:8080/a"), ("x-time", "12:30:00")],
+                None,
+            ),
+        )
+    }
+
+    #[test]
+    fn test_parser_invalid_header_name() {
+        test_parser_error(
+            b"GET / HTTP/1.1\r\nBad Name: x\r\n\r\n",
+            &RequestParserError::new(20, "invalid header name"),
+        );
+        test_parser_error(
+            b"GET / HTTP/1.1\r\nHost : x\r\n\r\n",
+            &RequestParserError::new(21, "invalid header name"),
+        );
+        test_parser_error(
+            b"GET / HTTP/1.1\r\n: x\r\n\r\n",
+            &RequestParserError::new(17, "invalid header name"),
+        );
+        test_parser_error(
+            b"GET / HTTP/1.1\r\nX-\x01: x\r\n\r\n",
+            &RequestParserError::new(19, "invalid header name"),
+        );
+    }
+
+    #[test]
+    fn test_parser_lenient_line_endings() {
+        let bytes = b"\nGET /a?x=1 HTTP/1.1\nHost: localhost\nX-Empty:\n\n\
+                      POST /b HTTP/1.1\nContent-Length: 3\n\nfoo\
+                      POST /c HTTP/1.1\nTransfer-Encoding: chunked\n\n3;ext\nbar\n0\nX-Trailer: 1\n\n\
+                      GET /d HTTP/1.1\r\nHost: localhost\n\r\n";
+        let mut parser = RequestParser::new(&bytes[..]).lenient_line_endings(true);
+        let a = parser.parse().unwrap();
+        assert_eq!((&a.path[..], &a.query[..]), ("/a", "x=1"));
+        assert_eq!(a.headers.get(&Header::new("host")).unwrap(), "localhost");
+        assert_eq!(a.headers.get(&Header::new("x-empty")).unwrap(), "");
+        let b = parser.parse().unwrap();
+        assert_eq!((&b.path[..], b.payload), ("/b", Some(b"foo".to_vec())));
+        let c = parser.parse().unwrap();
+        assert_eq!((&c.path[..], c.payload), ("/c", Some(b"bar".to_vec())));
+        let d = parser.parse().unwrap();
+        assert_eq!(d.headers.get(&Header::new("host")).unwrap(), "localhost");
+        assert!(parser.is_eof().unwrap());
+
+        // Strict by default
+        test_parser_error(
+            b"GET / HTTP/1.1\nHost: localhost\n\n",
+            &RequestParserError::new(16, "expected '\r'"),
+        );
+    }
+
+    #[test]
+    fn test_parser_obs_fold() {
+        test_parser_error(
+            b"GET / HTTP/1.1\r\nX-Long: a\r\n b\r\n\r\n",
+            &RequestParserError::new(28, "obsolete line folding is not supported"),
+        );
+        test_parser_error(
+            b"GET / HTTP/1.1\r\n\tHost: localhost\r\n\r\n",
+            &RequestParserError::new(17, "obsolete line folding is not supported"),
+        );
+    }
+
     #[test]
     fn test_parser_nonsense() {
         test_parser_error(b"FOO", &RequestParserError::new(0, "invalid HTTP method"));
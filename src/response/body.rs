@@ -0,0 +1,104 @@
+//! Streamed response bodies.
+use std::fmt;
+use std::io;
+use std::io::prelude::*;
+
+/// A response body produced lazily while it is written, instead of being
+/// held in memory, see [`Response::with_stream`](super::Response::with_stream).
+///
+/// # Example
+/// ```
+/// use std::io::Read;
+/// use jbhttp::response::BodyStream;
+///
+/// let rows = (1..=3).map(|i| format!("row {}\n", i).into_bytes());
+/// let mut body = String::new();
+/// BodyStream::from_chunks(rows).read_to_string(&mut body).unwrap();
+/// assert_eq!(body, "row 1\nrow 2\nrow 3\n");
+/// ```
+pub struct BodyStream {
+    reader: Box<dyn Read + Send>,
+}
+
+impl BodyStream {
+    pub fn new<R: Read + Send + 'static>(reader: R) -> Self {
+        Self {
+            reader: Box::new(reader),
+        }
+    }
+    /// Stream the chunks produced by an iterator, in order.
+    pub fn from_chunks<I>(chunks: I) -> Self
+    where
+        I: Iterator<Item = Vec<u8>> + Send + 'static,
+    {
+        Self::try_from_chunks(chunks.map(Ok))
+    }
+    /// Stream the chunks produced by an iterator, in order; an error ends
+    /// the stream, and the response is cut short.
+    pub fn try_from_chunks<I>(chunks: I) -> Self
+    where
+        I: Iterator<Item = io::Result<Vec<u8>>> + Send + 'static,
+    {
+        Self::new(ChunkReader {
+            chunks,
+            chunk: vec![],
+            position: 0,
+        })
+    }
+    /// Write the body with chunked transfer coding, until the end of the
+    /// stream.
+    pub(crate) fn write_chunked<W: Write>(&mut self, writer: &mut W) -> io::Result<()> {
+        let mut buf = vec![0; 8 * 1024];
+        loop {
+            let n = match self.reader.read(&mut buf) {
+                Ok(0) => break,
+                Ok(n) => n,
+                Err(e) if e.kind() == io::ErrorKind::Interrupted => continue,
+                Err(e) => return Err(e),
+            };
+            write!(writer, "{:x}\r\n", n)?;
+            writer.write_all(&buf[..n])?;
+            writer.write_all(b"\r\n")?;
+            // Send each chunk right away, for long-lived streams
+            writer.flush()?;
+        }
+        writer.write_all(b"0\r\n\r\n")
+    }
+}
+
+impl Read for BodyStream {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        self.reader.read(buf)
+    }
+}
+
+impl fmt::Debug for BodyStream {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "BodyStream")
+    }
+}
+
+struct ChunkReader<I> {
+    chunks: I,
+    chunk: Vec<u8>,
+    position: usize,
+}
+
+impl<I: Iterator<Item = io::Result<Vec<u8>>>> Read for ChunkReader<I> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        while self.position == self.chunk.len() {
+            match self.chunks.next() {
+                Some(chunk) => {
+                    let chunk = chunk?;
+                    self.chunk = chunk;
+                    self.position = 0;
+                }
+                None => return Ok(0),
+            }
+        }
+        let n = buf.len().min(self.chunk.len() - self.position);
+        buf[..n].copy_from_slice(&self.chunk[self.position..self.position + n]);
+        self.position += n;
+        Ok(n)
+    }
+}
@@ -1,8 +1,20 @@
 //! HTTP response and status codes.
 use std::collections::HashMap;
+use std::io;
+use std::io::prelude::*;
 
+use log::error;
+
+use crate::content::{MediaType, SerializationError, Serialize};
+use crate::request::HttpVersion;
+
+mod body;
+mod sse;
 pub mod status;
 
+pub use body::BodyStream;
+pub use sse::{SseEvent, SseResponse};
+
 /// An HTTP response.
 ///
 /// # Example
@@ -13,7 +25,7 @@ pub mod status;
 ///     .with_header("Content-Type", "text/plain")
 ///     .with_payload(b"Hello!".to_vec());
 ///
-/// # assert_eq!(response.content_length(), 6);
+/// # assert_eq!(response.content_length(), Some(6));
 /// ```
 #[derive(Debug)]
 pub struct Response<T> {
@@ -21,6 +33,10 @@ pub struct Response<T> {
     pub status: String,
     headers: Vec<(String, String)>,
     pub payload: Option<T>,
+    stream: Option<BodyStream>,
+    aborted: bool,
+    version: HttpVersion,
+    interim: Vec<RawResponse>,
 }
 
 pub type RawResponse = Response<Vec<u8>>;
@@ -34,11 +50,103 @@ impl<T> Response<T> {
             status: status::default(status_code),
             headers: vec![],
             payload: None,
+            stream: None,
+            aborted: false,
+            version: HttpVersion::Http11,
+            interim: vec![],
+        }
+    }
+    /// Create a response that aborts the request: servers send nothing
+    /// and close the connection, e.g. after a handler has taken over the
+    /// connection or hit an unrecoverable error.
+    ///
+    /// It can be returned as either `Ok` or `Err`, and is carried through
+    /// filters and serializers that keep the original response. Status is
+    /// 500, for logging only.
+    ///
+    /// # Example
+    /// ```
+    /// # use jbhttp::prelude::*;
+    /// fn handle(request: RawRequest, _: &mut ()) -> Res<Vec<u8>, Vec<u8>> {
+    ///     if request.path == "/hang-up" {
+    ///         return Err(Response::abort());
+    ///     }
+    ///     Ok(Response::new(204))
+    /// }
+    /// ```
+    pub fn abort() -> Self {
+        Self {
+            aborted: true,
+            ..Self::new(500)
         }
     }
+    /// Whether this response aborts the request, see [`Response::abort`].
+    pub fn is_aborted(&self) -> bool {
+        self.aborted
+    }
     pub fn headers(&self) -> HashMap<String, String> {
         self.headers.iter().cloned().collect()
     }
+    /// Get the first value of a header (case-insensitive).
+    pub fn header(&self, name: &str) -> Option<&str> {
+        self.headers
+            .iter()
+            .find(|(h, _)| h.eq_ignore_ascii_case(name))
+            .map(|(_, v)| &v[..])
+    }
+    /// Whether the response has a *Connection: close* header, which tells
+    /// servers to close the connection after sending it.
+    pub fn closes_connection(&self) -> bool {
+        self.header("Connection")
+            .is_some_and(|v| v.trim().eq_ignore_ascii_case("close"))
+    }
+    /// HTTP version of the status line, HTTP/1.1 by default.
+    pub fn version(&self) -> HttpVersion {
+        self.version
+    }
+    /// Change the HTTP version of the status line; servers answer HTTP/1.0
+    /// requests with HTTP/1.0 responses. HTTP/1.0 has no chunked transfer
+    /// coding, so a streamed body is sent as-is, and the connection must be
+    /// closed to end it.
+    pub fn with_version(mut self, version: HttpVersion) -> Self {
+        self.version = version;
+        self
+    }
+    /// Send an informational (1xx) response before this one, such as
+    /// `103 Early Hints` with *Link* headers for resources to preload. Only
+    /// the status line and headers of interim responses are sent, right
+    /// before this response; they are not sent to HTTP/1.0 clients. To send
+    /// them while the handler is still working, see
+    /// [`Request::send_interim`](crate::request::Request::send_interim).
+    ///
+    /// # Panics
+    /// If the interim response is not informational (1xx).
+    ///
+    /// # Example
+    /// ```
+    /// # use jbhttp::prelude::*;
+    /// let response = RawResponse::new(200)
+    ///     .with_interim(
+    ///         RawResponse::new(103).with_header("Link", "</style.css>; rel=preload; as=style"),
+    ///     )
+    ///     .with_payload(b"<html>...</html>".to_vec());
+    ///
+    /// let bytes = response.to_bytes();
+    /// assert!(bytes.starts_with(b"HTTP/1.1 103 Early Hints\r\nLink: "));
+    /// ```
+    pub fn with_interim(mut self, interim: RawResponse) -> Self {
+        assert!(
+            interim.is_informational(),
+            "interim response with status {}",
+            interim.status_code
+        );
+        self.interim.push(interim);
+        self
+    }
+    /// Get the informational responses sent before this one.
+    pub fn interim(&self) -> &[RawResponse] {
+        &self.interim
+    }
     /// Change status code (does not update status).
     pub fn with_status_code(mut self, status_code: u16) -> Self {
         self.status_code = status_code;
@@ -54,54 +162,299 @@ impl<T> Response<T> {
         self.headers.push((header.to_string(), value.to_string()));
         self
     }
+    /// Set a header, replacing any existing values (case-insensitive).
+    pub fn set_header(&mut self, header: &str, value: &str) {
+        self.headers
+            .retain(|(h, _)| !h.eq_ignore_ascii_case(header));
+        self.headers.push((header.to_string(), value.to_string()));
+    }
+    /// Add a request header to *Vary*, keeping the values already there, to
+    /// tell caches the response depends on it.
+    pub fn add_vary(&mut self, header: &str) {
+        let mut vary: Vec<String> = self
+            .headers
+            .iter()
+            .filter(|(h, _)| h.eq_ignore_ascii_case("Vary"))
+            .flat_map(|(_, v)| v.split(','))
+            .map(|v| v.trim().to_string())
+            .filter(|v| !v.is_empty())
+            .collect();
+        if !vary
+            .iter()
+            .any(|v| v == "*" || v.eq_ignore_ascii_case(header))
+        {
+            vary.push(header.to_string());
+        }
+        self.set_header("Vary", &vary.join(", "));
+    }
+    /// Add a request header to *Vary*, see [`Response::add_vary`].
+    pub fn with_vary(mut self, header: &str) -> Self {
+        self.add_vary(header);
+        self
+    }
+    /// Add several headers, in order.
+    pub fn with_headers(mut self, headers: &[(&str, &str)]) -> Self {
+        self.extend_headers(headers.iter().map(|(h, v)| (h.to_string(), v.to_string())));
+        self
+    }
+    /// Add headers from an iterator, in order.
+    pub fn extend_headers<I>(&mut self, headers: I)
+    where
+        I: IntoIterator<Item = (String, String)>,
+    {
+        self.headers.extend(headers);
+    }
     pub fn into_type<S>(self) -> Response<S> {
         Response {
             status_code: self.status_code,
             status: self.status,
             headers: self.headers,
             payload: None,
+            stream: self.stream,
+            aborted: self.aborted,
+            version: self.version,
+            interim: self.interim,
+        }
+    }
+    /// Send a body produced while the response is written, with chunked
+    /// transfer coding, instead of the payload, which is removed.
+    ///
+    /// The stream is carried over by `into_type`, so it can be set on a
+    /// response of any type; [`MediaTypeSerializer`] sets it for types
+    /// implementing [`SerializeStream`].
+    ///
+    /// [`MediaTypeSerializer`]: crate::content::MediaTypeSerializer
+    /// [`SerializeStream`]: crate::content::SerializeStream
+    pub fn with_stream(mut self, stream: BodyStream) -> Self {
+        self.payload = None;
+        self.stream = Some(stream);
+        self
+    }
+    /// Whether the response has a streamed body, see [`Response::with_stream`].
+    pub fn is_streaming(&self) -> bool {
+        self.stream.is_some()
+    }
+    /// Remove the streamed body, if any.
+    pub fn take_stream(&mut self) -> Option<BodyStream> {
+        self.stream.take()
+    }
+    /// Transform the payload, if any, keeping status and headers.
+    ///
+    /// # Example
+    /// ```
+    /// # use jbhttp::prelude::*;
+    /// let response = Response::new(200)
+    ///     .with_header("Content-Type", "text/plain")
+    ///     .with_payload("Hello!")
+    ///     .map_body(|s| s.as_bytes().to_vec());
+    /// assert_eq!(response.payload, Some(b"Hello!".to_vec()));
+    /// ```
+    pub fn map_body<U, F>(mut self, f: F) -> Response<U>
+    where
+        F: FnOnce(T) -> U,
+    {
+        let payload = self.payload.take().map(f);
+        Response {
+            payload,
+            ..self.into_type()
         }
     }
     pub fn into_raw(self) -> RawResponse {
         self.into_type::<Vec<u8>>()
     }
-    /// Sets response payload.
+    /// Sets response payload, removing any streamed body.
     pub fn with_payload(mut self, payload: T) -> Self {
+        self.stream = None;
         self.payload = Some(payload);
         self
     }
 }
 
 impl Response<Vec<u8>> {
-    /// Get content length.
-    pub fn content_length(&self) -> usize {
-        match &self.payload {
-            Some(body) => body.len(),
-            None => 0,
+    /// Create an error response from any error, and log the error.
+    ///
+    /// In debug builds the error message is used as payload; release builds
+    /// leave the payload empty to avoid leaking internal details.
+    ///
+    /// # Example
+    /// ```
+    /// # use jbhttp::prelude::*;
+    /// fn read_config() -> Res<Vec<u8>, Vec<u8>> {
+    ///     let contents = std::fs::read("/does/not/exist")
+    ///         .map_err(|e| Response::from_error(500, &e))?;
+    ///     Ok(Response::new(200).with_payload(contents))
+    /// }
+    /// # assert_eq!(read_config().unwrap_err().status_code, 500);
+    /// ```
+    pub fn from_error<E: std::error::Error>(status_code: u16, err: &E) -> Self {
+        error!("{}", err);
+        let response = Self::new(status_code);
+        if cfg!(debug_assertions) {
+            response.with_payload(err.to_string().into_bytes())
+        } else {
+            response
         }
     }
-    /// Write HTTP response bytes.
-    pub fn into_bytes(mut self) -> Vec<u8> {
-        let mut bytes: Vec<u8> = vec![];
-
-        let status_line = format!("HTTP/1.1 {} {}\r\n", self.status_code, self.status);
-        bytes.extend(status_line.into_bytes());
-
+    /// Serialize a value as media type `M` for the payload, and set
+    /// *Content-Type*, for handlers which don't need content negotiation with
+    /// a [`MediaTypeSerializer`](crate::content::MediaTypeSerializer).
+    ///
+    /// # Example
+    /// ```
+    /// # #[cfg(feature = "json")] {
+    /// use jbhttp::content::mediatypes::ApplicationJson;
+    /// use jbhttp::prelude::*;
+    ///
+    /// #[derive(serde::Serialize)]
+    /// struct Greeting {
+    ///     message: String,
+    /// }
+    ///
+    /// fn hello(_: RawRequest, _: &mut ()) -> Res<Vec<u8>, Vec<u8>> {
+    ///     let greeting = Greeting {
+    ///         message: "Hello!".to_string(),
+    ///     };
+    ///     Response::new(200)
+    ///         .with_payload_serialized::<ApplicationJson, _>(greeting)
+    ///         .map_err(|e| Response::from_error(500, &e))
+    /// }
+    ///
+    /// let response = hello(Request::default(), &mut ()).unwrap();
+    /// assert_eq!(response.header("Content-Type"), Some("application/json"));
+    /// assert_eq!(response.payload, Some(br#"{"message":"Hello!"}"#.to_vec()));
+    /// # }
+    /// ```
+    pub fn with_payload_serialized<M, S>(self, value: S) -> Result<Self, SerializationError>
+    where
+        M: MediaType,
+        S: Serialize<M>,
+    {
+        let payload = value.serialize()?;
+        Ok(self
+            .with_header("Content-Type", &M::media_type())
+            .with_payload(payload))
+    }
+    /// Get content length, `None` for a streamed body, which is sent with
+    /// chunked transfer coding.
+    pub fn content_length(&self) -> Option<usize> {
+        match (&self.stream, &self.payload) {
+            (Some(_), _) => None,
+            (None, Some(body)) => Some(body.len()),
+            (None, None) => Some(0),
+        }
+    }
+    /// Remove the payload, but keep its length as *Content-Length*, as
+    /// required for responses to HEAD requests. A streamed body is dropped
+    /// without being read, so its length is unknown; the response keeps
+    /// *Transfer-Encoding: chunked* instead, in HTTP/1.1.
+    pub fn without_body(mut self) -> Self {
         let content_length = self.content_length();
-        if content_length > 0 {
-            self = self.with_header("Content-Length", &content_length.to_string());
+        self.payload = None;
+        self.stream = None;
+        match content_length {
+            Some(n) if n > 0 => self.with_header("Content-Length", &n.to_string()),
+            None if self.version == HttpVersion::Http11 => {
+                self.with_header("Transfer-Encoding", "chunked")
+            }
+            _ => self,
         }
-
-        for (header, value) in &self.headers {
-            let header_line = format!("{}: {}\r\n", header, value);
-            bytes.extend(header_line.into_bytes());
+    }
+    /// Write HTTP response bytes, including a streamed body, which is read
+    /// to the end, see [`Response::write_to`]; fails if reading it fails.
+    pub fn into_bytes(mut self) -> io::Result<Vec<u8>> {
+        let mut bytes = vec![];
+        self.write_to(&mut bytes)?;
+        Ok(bytes)
+    }
+    /// Write the response to a stream, including a streamed body, which is
+    /// consumed.
+    pub fn write_to<W: Write>(&mut self, writer: &mut W) -> io::Result<()> {
+        self.write_to_buffered(writer, &mut vec![])
+    }
+    /// Like [`Response::write_to`], but serialize the response into `buffer`,
+    /// which is cleared first, instead of a newly allocated one, so buffers
+    /// can be reused across responses, e.g. with [`BufferPool`](crate::io::BufferPool).
+    pub fn write_to_buffered<W: Write>(
+        &mut self,
+        writer: &mut W,
+        buffer: &mut Vec<u8>,
+    ) -> io::Result<()> {
+        buffer.clear();
+        self.write_bytes(buffer);
+        writer.write_all(buffer)?;
+        if let Some(mut stream) = self.stream.take() {
+            match self.version {
+                HttpVersion::Http10 => {
+                    io::copy(&mut stream, writer)?;
+                }
+                HttpVersion::Http11 => stream.write_chunked(writer)?,
+            }
+        }
+        Ok(())
+    }
+    /// Write HTTP response bytes, without consuming the response, preceded
+    /// by interim responses. For a streamed body, which can't be read
+    /// without consuming it, only the status line and headers are written,
+    /// see [`Response::into_bytes`].
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut bytes: Vec<u8> = vec![];
+        self.write_bytes(&mut bytes);
+        bytes
+    }
+    fn write_bytes(&self, bytes: &mut Vec<u8>) {
+        if self.version == HttpVersion::Http11 {
+            for interim in &self.interim {
+                interim.write_interim(bytes);
+            }
+        }
+        self.write_head(bytes);
+        match self.content_length() {
+            None => {
+                if self.version == HttpVersion::Http11 {
+                    bytes.extend(b"Transfer-Encoding: chunked\r\n");
+                }
+                bytes.extend(b"\r\n");
+                return;
+            }
+            // Without a length, clients of a kept-alive connection couldn't
+            // tell where an empty body ends; HEAD responses keep their own
+            Some(0)
+                if !self.has_body()
+                    || self.header("Content-Length").is_some()
+                    || self.header("Transfer-Encoding").is_some() => {}
+            Some(n) => bytes.extend(format!("Content-Length: {}\r\n", n).into_bytes()),
         }
 
         bytes.extend(b"\r\n");
         if let Some(body) = &self.payload {
             bytes.extend(body);
         }
-        bytes
+    }
+    /// Whether this is an informational (1xx) response, which can be sent
+    /// before the final response.
+    pub fn is_informational(&self) -> bool {
+        (100..=199).contains(&self.status_code)
+    }
+    /// Write an interim response: the status line and headers only, without
+    /// a body or its length.
+    pub(crate) fn write_interim(&self, bytes: &mut Vec<u8>) {
+        self.write_head(bytes);
+        bytes.extend(b"\r\n");
+    }
+    /// Whether the status allows a body: 1xx, 204 and 304 responses have
+    /// none, not even an empty one.
+    fn has_body(&self) -> bool {
+        !matches!(self.status_code, 100..=199 | 204 | 304)
+    }
+    /// Write the status line and headers.
+    fn write_head(&self, bytes: &mut Vec<u8>) {
+        let status_line = format!("{} {} {}\r\n", self.version, self.status_code, self.status);
+        bytes.extend(status_line.into_bytes());
+
+        for (header, value) in &self.headers {
+            let header_line = format!("{}: {}\r\n", header, value);
+            bytes.extend(header_line.into_bytes());
+        }
     }
 }
 
@@ -121,8 +474,245 @@ mod test {
             .with_header("Connection", "closed")
             .with_payload(b"foobar!".to_vec());
 
-        let actual = response.into_bytes();
+        let actual = response.into_bytes().unwrap();
         let expected = b"HTTP/1.1 500 Internal Server Error\r\nConnection: closed\r\nContent-Length: 7\r\n\r\nfoobar!";
         assert_eq!(expected[..], actual[..]);
     }
+
+    #[test]
+    fn test_response_with_payload_serialized() {
+        use crate::content::mediatypes::TextPlain;
+
+        struct Count(i32);
+
+        impl Serialize<TextPlain> for Count {
+            fn serialize(self) -> Result<Vec<u8>, SerializationError> {
+                match self.0 {
+                    n if n >= 0 => Ok(n.to_string().into_bytes()),
+                    _ => Err(SerializationError::new("negative count")),
+                }
+            }
+        }
+
+        let response = Response::new(201)
+            .with_header("Location", "/counts/1")
+            .with_payload_serialized::<TextPlain, _>(Count(3))
+            .unwrap();
+        assert_eq!(response.status_code, 201);
+        assert_eq!(response.header("Location"), Some("/counts/1"));
+        assert_eq!(response.header("Content-Type"), Some("text/plain"));
+        assert_eq!(response.payload, Some(b"3".to_vec()));
+
+        assert!(Response::new(200)
+            .with_payload_serialized::<TextPlain, _>(Count(-1))
+            .is_err());
+    }
+
+    #[test]
+    fn test_response_write_to_buffered() {
+        let mut response = RawResponse::new(200).with_payload(b"foobar!".to_vec());
+        let mut buffer = b"leftovers".to_vec();
+        let mut output = vec![];
+        response
+            .write_to_buffered(&mut output, &mut buffer)
+            .unwrap();
+        assert_eq!(output, response.to_bytes());
+        assert_eq!(buffer, output);
+    }
+
+    #[test]
+    fn test_response_stream_into_bytes() {
+        let chunks = vec![b"foo".to_vec(), b"bar!".to_vec()];
+        let response =
+            RawResponse::new(200).with_stream(BodyStream::from_chunks(chunks.into_iter()));
+        assert_eq!(
+            response.into_bytes().unwrap(),
+            b"HTTP/1.1 200 OK\r\nTransfer-Encoding: chunked\r\n\r\n3\r\nfoo\r\n4\r\nbar!\r\n0\r\n\r\n"
+                .to_vec()
+        );
+
+        let chunks = vec![b"foo".to_vec(), b"bar!".to_vec()];
+        let response = RawResponse::new(200)
+            .with_version(HttpVersion::Http10)
+            .with_stream(BodyStream::from_chunks(chunks.into_iter()));
+        assert_eq!(
+            response.into_bytes().unwrap(),
+            b"HTTP/1.0 200 OK\r\n\r\nfoobar!".to_vec()
+        );
+        let chunks = vec![Ok(b"foo".to_vec()), Err(io::ErrorKind::BrokenPipe.into())];
+        let response =
+            RawResponse::new(200).with_stream(BodyStream::try_from_chunks(chunks.into_iter()));
+        let error = response.into_bytes().unwrap_err();
+        assert_eq!(error.kind(), io::ErrorKind::BrokenPipe);
+    }
+
+    #[test]
+    fn test_response_empty_body() {
+        assert_eq!(
+            RawResponse::new(404).into_bytes().unwrap(),
+            b"HTTP/1.1 404 Not Found\r\nContent-Length: 0\r\n\r\n".to_vec()
+        );
+        assert_eq!(
+            RawResponse::new(200)
+                .with_payload(vec![])
+                .into_bytes()
+                .unwrap(),
+            b"HTTP/1.1 200 OK\r\nContent-Length: 0\r\n\r\n".to_vec()
+        );
+        for (status_code, status) in [
+            (103, "Early Hints"),
+            (204, "No Content"),
+            (304, "Not Modified"),
+        ] {
+            assert_eq!(
+                RawResponse::new(status_code).into_bytes().unwrap(),
+                format!("HTTP/1.1 {} {}\r\n\r\n", status_code, status).into_bytes()
+            );
+        }
+    }
+
+    #[test]
+    #[should_panic(expected = "interim response with status 200")]
+    fn test_response_interim_not_informational() {
+        let _ = RawResponse::new(200).with_interim(RawResponse::new(200));
+    }
+
+    #[test]
+    fn test_response_without_body() {
+        let response = RawResponse::new(200)
+            .with_header("Content-Type", "text/plain")
+            .with_payload(b"foobar!".to_vec())
+            .without_body();
+        let actual = response.into_bytes().unwrap();
+        let expected = b"HTTP/1.1 200 OK\r\nContent-Type: text/plain\r\nContent-Length: 7\r\n\r\n";
+        assert_eq!(expected[..], actual[..]);
+    }
+
+    #[test]
+    fn test_response_abort() {
+        let response = Response::<String>::abort().with_header("A", "1");
+        assert!(response.is_aborted());
+        assert!(response.into_raw().is_aborted());
+        assert!(!RawResponse::new(500).is_aborted());
+    }
+
+    #[test]
+    fn test_response_map_body() {
+        let response = Response::new(201)
+            .with_status("Made It")
+            .with_header("Content-Type", "text/plain")
+            .with_payload("hello")
+            .map_body(|s| s.to_uppercase());
+        assert_eq!(response.status_code, 201);
+        assert_eq!(response.status, "Made It");
+        assert_eq!(response.header("Content-Type"), Some("text/plain"));
+        assert_eq!(response.payload, Some("HELLO".to_string()));
+
+        let response = Response::<&str>::new(204).map_body(|s| s.len());
+        assert_eq!(response.payload, None);
+    }
+
+    #[test]
+    fn test_response_stream() {
+        let chunks = vec![b"foo".to_vec(), vec![], b"bar!".to_vec()];
+        let mut response = RawResponse::new(200)
+            .with_header("Content-Type", "text/plain")
+            .with_stream(BodyStream::from_chunks(chunks.into_iter()))
+            .into_type::<()>()
+            .into_raw();
+        assert!(response.is_streaming());
+        let mut bytes = vec![];
+        response.write_to(&mut bytes).unwrap();
+        assert_eq!(
+            std::str::from_utf8(&bytes).unwrap(),
+            "HTTP/1.1 200 OK\r\n\
+             Content-Type: text/plain\r\n\
+             Transfer-Encoding: chunked\r\n\
+             \r\n\
+             3\r\nfoo\r\n4\r\nbar!\r\n0\r\n\r\n"
+        );
+        assert!(!response.is_streaming());
+    }
+
+    #[test]
+    fn test_response_body() {
+        let stream = || BodyStream::from_chunks(vec![b"foo".to_vec()].into_iter());
+        let response = RawResponse::new(200)
+            .with_stream(stream())
+            .with_payload(b"foobar!".to_vec());
+        assert!(!response.is_streaming());
+        assert_eq!(response.content_length(), Some(7));
+
+        let mut response = RawResponse::new(200)
+            .with_payload(b"foobar!".to_vec())
+            .with_stream(stream());
+        assert_eq!(response.payload, None);
+        assert_eq!(response.content_length(), None);
+        assert_eq!(
+            response.to_bytes(),
+            b"HTTP/1.1 200 OK\r\nTransfer-Encoding: chunked\r\n\r\n".to_vec()
+        );
+        assert!(response.take_stream().is_some());
+        assert_eq!(response.content_length(), Some(0));
+        assert_eq!(
+            RawResponse::new(200)
+                .with_stream(stream())
+                .without_body()
+                .into_bytes()
+                .unwrap(),
+            b"HTTP/1.1 200 OK\r\nTransfer-Encoding: chunked\r\n\r\n".to_vec()
+        );
+    }
+
+    #[test]
+    fn test_response_with_headers() {
+        let mut response = RawResponse::new(204).with_header("A", "1").with_headers(&[
+            ("B", "2"),
+            ("C", "3"),
+            ("B", "4"),
+        ]);
+        response.extend_headers(vec![("D".to_string(), "5".to_string())]);
+        let actual = response.into_bytes().unwrap();
+        let expected = b"HTTP/1.1 204 No Content\r\nA: 1\r\nB: 2\r\nC: 3\r\nB: 4\r\nD: 5\r\n\r\n";
+        assert_eq!(expected[..], actual[..]);
+    }
+
+    #[test]
+    fn test_response_vary() {
+        let response = RawResponse::new(200).with_vary("Accept");
+        assert_eq!(response.header("Vary"), Some("Accept"));
+        let response = response
+            .with_header("vary", "Accept-Encoding")
+            .with_vary("accept")
+            .with_vary("Accept-Charset");
+        assert_eq!(
+            response.header("Vary"),
+            Some("Accept, Accept-Encoding, Accept-Charset")
+        );
+        let response = RawResponse::new(200)
+            .with_header("Vary", "*")
+            .with_vary("Accept");
+        assert_eq!(response.header("Vary"), Some("*"));
+    }
+
+    #[test]
+    fn test_response_closes_connection() {
+        let response = RawResponse::new(200).with_header("connection", "Close");
+        assert_eq!(response.header("Connection"), Some("Close"));
+        assert!(response.closes_connection());
+        let response = RawResponse::new(200).with_header("Connection", "keep-alive");
+        assert!(!response.closes_connection());
+        assert!(!RawResponse::new(200).closes_connection());
+    }
+
+    #[test]
+    fn test_response_from_error() {
+        let err = std::io::Error::new(std::io::ErrorKind::NotFound, "no such file");
+        let response = RawResponse::from_error(404, &err);
+        assert_eq!(response.status_code, 404);
+        assert_eq!(response.status, "Not Found");
+        if cfg!(debug_assertions) {
+            assert_eq!(response.payload, Some(b"no such file".to_vec()));
+        }
+    }
 }
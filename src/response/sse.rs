@@ -0,0 +1,208 @@
+//! Server-Sent Events, see the `text/event-stream` format in the HTML
+//! standard.
+use std::fmt::Write;
+use std::sync::mpsc::{Receiver, RecvTimeoutError};
+use std::time::Duration;
+
+use crate::response::{BodyStream, Response};
+
+/// A Server-Sent Event.
+#[derive(Debug, Clone, PartialEq, Default)]
+pub struct SseEvent {
+    pub event: Option<String>,
+    pub data: String,
+    pub id: Option<String>,
+    pub retry: Option<Duration>,
+}
+
+impl SseEvent {
+    /// Create an unnamed event (a `message` event, for clients).
+    pub fn new(data: &str) -> Self {
+        Self {
+            data: data.to_string(),
+            ..Self::default()
+        }
+    }
+    /// Set the event name.
+    pub fn with_event(mut self, event: &str) -> Self {
+        self.event = Some(event.to_string());
+        self
+    }
+    /// Set the event id, sent back by clients in *Last-Event-ID* when they
+    /// reconnect.
+    pub fn with_id(mut self, id: &str) -> Self {
+        self.id = Some(id.to_string());
+        self
+    }
+    /// Set how long clients wait before reconnecting.
+    pub fn with_retry(mut self, retry: Duration) -> Self {
+        self.retry = Some(retry);
+        self
+    }
+    /// Format the event; multi-line data is sent as several `data` fields,
+    /// with lines ending in CRLF, CR or LF. Line breaks are removed from the
+    /// event name and id, which are single fields.
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut s = String::new();
+        if let Some(event) = &self.event {
+            writeln!(s, "event: {}", strip_line_breaks(event)).unwrap();
+        }
+        if let Some(id) = &self.id {
+            writeln!(s, "id: {}", strip_line_breaks(id)).unwrap();
+        }
+        if let Some(retry) = self.retry {
+            writeln!(s, "retry: {}", retry.as_millis()).unwrap();
+        }
+        for line in self.data.replace("\r\n", "\n").split(['\r', '\n']) {
+            writeln!(s, "data: {}", line).unwrap();
+        }
+        s.push('\n');
+        s.into_bytes()
+    }
+}
+
+fn strip_line_breaks(s: &str) -> String {
+    s.chars().filter(|c| *c != '\r' && *c != '\n').collect()
+}
+
+/// A streamed `text/event-stream` response, sending events from a channel
+/// as they are received.
+///
+/// When no event was sent for the heartbeat interval (15 seconds by
+/// default), a `: ping` comment is sent, so that proxies don't close the idle
+/// connection. The response ends when all senders are dropped. When the
+/// client disconnects, the server fails writing the next event or ping, and
+/// drops the receiver: sending then fails, which tells the producer to stop.
+///
+/// # Example
+/// ```
+/// use std::sync::mpsc;
+/// use jbhttp::prelude::*;
+/// use jbhttp::response::{SseEvent, SseResponse};
+///
+/// fn updates(req: RawRequest, _: &mut ()) -> Res<Vec<u8>, Vec<u8>> {
+///     let (sender, receiver) = mpsc::channel();
+///     std::thread::spawn(move || {
+///         for i in 0.. {
+///             let event = SseEvent::new(&i.to_string()).with_event("tick");
+///             if sender.send(event).is_err() {
+///                 break; // client disconnected
+///             }
+///             std::thread::sleep(std::time::Duration::from_secs(1));
+///         }
+///     });
+///     Ok(SseResponse::from_receiver(receiver).into_response())
+/// }
+/// ```
+pub struct SseResponse {
+    receiver: Receiver<SseEvent>,
+    heartbeat: Duration,
+}
+
+impl SseResponse {
+    pub fn from_receiver(receiver: Receiver<SseEvent>) -> Self {
+        Self {
+            receiver,
+            heartbeat: Duration::from_secs(15),
+        }
+    }
+    /// Set the interval after which a ping is sent if there was no event.
+    pub fn with_heartbeat(mut self, heartbeat: Duration) -> Self {
+        self.heartbeat = heartbeat;
+        self
+    }
+    /// Create the streamed response, with `Content-Type: text/event-stream`.
+    pub fn into_response<T>(self) -> Response<T> {
+        let events = SseEvents {
+            receiver: self.receiver,
+            heartbeat: self.heartbeat,
+        };
+        Response::new(200)
+            .with_header("Content-Type", "text/event-stream")
+            .with_header("Cache-Control", "no-cache")
+            .with_stream(BodyStream::from_chunks(events))
+    }
+}
+
+/// Formatted events, and pings when idle.
+struct SseEvents {
+    receiver: Receiver<SseEvent>,
+    heartbeat: Duration,
+}
+
+impl Iterator for SseEvents {
+    type Item = Vec<u8>;
+
+    fn next(&mut self) -> Option<Vec<u8>> {
+        match self.receiver.recv_timeout(self.heartbeat) {
+            Ok(event) => Some(event.to_bytes()),
+            Err(RecvTimeoutError::Timeout) => Some(b": ping\n\n".to_vec()),
+            Err(RecvTimeoutError::Disconnected) => None,
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::io::Read;
+    use std::sync::mpsc;
+
+    #[test]
+    fn test_sse_event_to_bytes() {
+        let event = SseEvent::new("line 1\nline 2")
+            .with_event("update")
+            .with_id("42")
+            .with_retry(Duration::from_secs(3));
+        assert_eq!(
+            event.to_bytes(),
+            b"event: update\nid: 42\nretry: 3000\ndata: line 1\ndata: line 2\n\n".to_vec()
+        );
+        assert_eq!(SseEvent::new("").to_bytes(), b"data: \n\n".to_vec());
+    }
+
+    #[test]
+    fn test_sse_event_line_breaks() {
+        let event = SseEvent::new("a\r\nb\rc\nd\n\re");
+        assert_eq!(
+            event.to_bytes(),
+            b"data: a\ndata: b\ndata: c\ndata: d\ndata: \ndata: e\n\n".to_vec()
+        );
+        // No injected fields
+        let event = SseEvent::new("x")
+            .with_event("update\r\ndata: injected")
+            .with_id("4\n2\r");
+        assert_eq!(
+            event.to_bytes(),
+            b"event: updatedata: injected\nid: 42\ndata: x\n\n".to_vec()
+        );
+    }
+
+    #[test]
+    fn test_sse_response() {
+        let (sender, receiver) = mpsc::channel();
+        let mut response: Response<Vec<u8>> = SseResponse::from_receiver(receiver)
+            .with_heartbeat(Duration::from_millis(10))
+            .into_response();
+        assert_eq!(response.header("Content-Type"), Some("text/event-stream"));
+        let producer = std::thread::spawn(move || {
+            sender.send(SseEvent::new("first")).unwrap();
+            std::thread::sleep(Duration::from_millis(100));
+            sender.send(SseEvent::new("second")).unwrap();
+        });
+        let mut body = String::new();
+        let mut stream = response.take_stream().unwrap();
+        stream.read_to_string(&mut body).unwrap();
+        producer.join().unwrap();
+        assert!(body.starts_with("data: first\n\n: ping\n\n"));
+        assert!(body.ends_with(": ping\n\ndata: second\n\n"));
+    }
+
+    #[test]
+    fn test_sse_client_disconnect() {
+        let (sender, receiver) = mpsc::channel();
+        let response: Response<Vec<u8>> = SseResponse::from_receiver(receiver).into_response();
+        drop(response);
+        assert!(sender.send(SseEvent::new("lost")).is_err());
+    }
+}
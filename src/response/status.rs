@@ -4,6 +4,7 @@ pub fn default(status_code: u16) -> String {
         100 => "Continue",
         101 => "Switching Protocols",
         102 => "Processing",
+        103 => "Early Hints",
         200 => "OK",
         201 => "Created",
         202 => "Accepted",
@@ -1,29 +1,87 @@
 //! Path based request routing.
+use std::any::Any;
+use std::collections::HashMap;
+use std::str::FromStr;
+use std::sync::Arc;
+
+use log::debug;
+
 use crate::handler::{Handler, Res};
-use crate::request::{Param, Request};
+use crate::request::{Param, PathParams, Request};
 use crate::response::Response;
 
+type ParseParam = fn(&str) -> Option<Arc<dyn Any + Send + Sync>>;
+
+fn parse_param<T: FromStr + Any + Send + Sync>(s: &str) -> Option<Arc<dyn Any + Send + Sync>> {
+    s.parse::<T>()
+        .ok()
+        .map(|v| Arc::new(v) as Arc<dyn Any + Send + Sync>)
+}
+
+#[derive(Clone)]
+struct ParamType {
+    name: String,
+    parse: ParseParam,
+}
+
+impl ParamType {
+    fn new<T: FromStr + Any + Send + Sync>(name: &str) -> Self {
+        Self {
+            name: name.to_string(),
+            parse: parse_param::<T>,
+        }
+    }
+}
+
+fn default_param_types() -> HashMap<String, ParamType> {
+    vec![
+        ParamType::new::<String>("String"),
+        ParamType::new::<bool>("bool"),
+        ParamType::new::<char>("char"),
+        ParamType::new::<i8>("i8"),
+        ParamType::new::<i16>("i16"),
+        ParamType::new::<i32>("i32"),
+        ParamType::new::<i64>("i64"),
+        ParamType::new::<i128>("i128"),
+        ParamType::new::<isize>("isize"),
+        ParamType::new::<u8>("u8"),
+        ParamType::new::<u16>("u16"),
+        ParamType::new::<u32>("u32"),
+        ParamType::new::<u64>("u64"),
+        ParamType::new::<u128>("u128"),
+        ParamType::new::<usize>("usize"),
+        ParamType::new::<f32>("f32"),
+        ParamType::new::<f64>("f64"),
+    ]
+    .into_iter()
+    .map(|t| (t.name.clone(), t))
+    .collect()
+}
+
 enum RoutePart {
     Exact(String),
-    Param(String),
+    Param(String, ParamType),
     Any,
 }
 
 impl RoutePart {
-    fn from_str(s: &str) -> Self {
+    fn from_str(s: &str, types: &HashMap<String, ParamType>) -> Self {
         if s == "*" {
             Self::Any
         } else if let Some(s) = s.strip_prefix('?') {
-            Self::Param(s.to_string())
+            let (name, type_name) = s.split_once(':').unwrap_or((s, "String"));
+            match types.get(type_name) {
+                Some(param_type) => Self::Param(name.to_string(), param_type.clone()),
+                None => panic!("unknown path param type {}", type_name),
+            }
         } else {
             Self::Exact(s.to_string())
         }
     }
-    fn matches(&self, s: &str) -> (bool, Option<(String, String)>) {
+    fn matches(&self, s: &str) -> bool {
         match self {
-            Self::Exact(p) => (s == &p[..], None),
-            Self::Any => (true, None),
-            Self::Param(p) => (true, Some((p.clone(), s.to_string()))),
+            Self::Exact(p) => s == p,
+            Self::Any | Self::Param(..) => true,
         }
     }
 }
@@ -31,54 +89,115 @@ impl RoutePart {
 struct RoutePath {
     parts: Vec<RoutePart>,
     is_prefix: bool,
+    has_params: bool,
 }
 
 impl RoutePath {
-    fn from_str(s: &str) -> Self {
-        let mut route_parts = vec![];
-        let mut is_prefix = false;
-        let parts = match s.ends_with("**") {
-            true => {
-                is_prefix = true;
-                s[..s.len() - 2].split('/')
-            }
-            false => s.split('/'),
+    fn from_str(s: &str, types: &HashMap<String, ParamType>) -> Self {
+        let (s, is_prefix) = match s.strip_suffix("**") {
+            Some(prefix) => (prefix.strip_suffix('/').unwrap_or(prefix), true),
+            None => (s, false),
         };
-        for part in parts {
-            route_parts.push(RoutePart::from_str(part));
-        }
+        let parts: Vec<RoutePart> = s
+            .split('/')
+            .map(|part| RoutePart::from_str(part, types))
+            .collect();
+        let has_params = parts.iter().any(|p| matches!(p, RoutePart::Param(..)));
         Self {
-            parts: route_parts,
+            parts,
             is_prefix,
+            has_params,
         }
     }
-    fn matches(&self, s: &str) -> (bool, Vec<(String, String)>) {
-        let parts: Vec<&str> = s.split('/').collect();
-        let mut params = vec![];
-
-        if parts.len() < self.parts.len() {
-            return (false, params);
+    /// Match a path, returns captured params if it matches. Only allocates
+    /// when the path matches a route with params.
+    fn matches(&self, s: &str) -> Option<Vec<(String, String)>> {
+        let n_parts = s.bytes().filter(|b| *b == b'/').count() + 1;
+        // Prefix routes need at least one more segment, possibly empty, as in /foo/
+        let n_matches = if self.is_prefix {
+            n_parts > self.parts.len()
+        } else {
+            n_parts == self.parts.len()
+        };
+        if !n_matches {
+            return None;
         }
-
-        if parts.len() > self.parts.len() && !self.is_prefix {
-            return (false, params);
+        let segments = || self.parts.iter().zip(s.split('/'));
+        if !segments().all(|(part, segment)| part.matches(segment)) {
+            return None;
         }
-
-        for (i, part) in parts.iter().enumerate() {
-            let (matches, param) = self.parts[i].matches(part);
-            if !matches {
-                return (false, params);
-            }
-            if let Some((name, val)) = param {
-                params.push((name, val));
+        if !self.has_params {
+            return Some(Vec::new());
+        }
+        Some(
+            segments()
+                .filter_map(|(part, segment)| match part {
+                    RoutePart::Param(name, _) => Some((name.clone(), segment.to_string())),
+                    _ => None,
+                })
+                .collect(),
+        )
+    }
+    /// Parse params captured by [`matches`](RoutePath::matches) into their
+    /// param types, returns the first param which fails to parse.
+    fn parse(
+        &self,
+        params: &[(String, String)],
+        typed: &mut PathParams,
+    ) -> Result<(), (&str, &ParamType)> {
+        let types = self.parts.iter().filter_map(|part| match part {
+            RoutePart::Param(name, param_type) => Some((name, param_type)),
+            _ => None,
+        });
+        for ((name, param_type), (_, value)) in types.zip(params) {
+            match (param_type.parse)(value) {
+                Some(value) => typed.insert(name, value),
+                None => return Err((name, param_type)),
             }
         }
-        (true, params)
+        Ok(())
+    }
+}
+
+enum QueryPart {
+    Present(String),
+    Value(String, String),
+}
+
+impl QueryPart {
+    fn from_str(s: &str) -> Self {
+        match s.split_once('=') {
+            Some((name, value)) => Self::Value(name.to_string(), value.to_string()),
+            None => Self::Present(s.to_string()),
+        }
+    }
+    fn matches<I>(&self, request: &Request<I>) -> bool {
+        match self {
+            Self::Present(name) => request
+                .params
+                .get_first(&Param::Query(name.clone()))
+                .is_some(),
+            Self::Value(name, value) => match request.params.get_all(&Param::Query(name.clone())) {
+                Some(values) => values.contains(value),
+                None => false,
+            },
+        }
     }
 }
 
+/// Description of a route registered on a [`Router`].
+#[derive(Debug, Clone, PartialEq)]
+pub struct RouteInfo {
+    /// Path pattern, as registered.
+    pub path: String,
+    /// Query patterns, as registered.
+    pub query: Vec<String>,
+}
+
 struct Route<I, O, E, C> {
+    info: RouteInfo,
     path: RoutePath,
+    query: Vec<QueryPart>,
     // Use boxdyn cause I can't have a type parameter H for handler, because
     // Router must hold Vec<Route> for heterogenous H.
     handler: Box<dyn Handler<I, O, E, C>>,
@@ -93,6 +212,52 @@ struct Route<I, O, E, C> {
 /// * `/foo/**`: matches /foo/bar/... (only at end of route)
 /// * `/foo/?name`: matches /foo/bar, add name="bar" to request.params
 ///
+/// The same param name can be captured more than once, like `/?x/foo/?x`;
+/// `request.params.get_all(&Param::Path("x"))` returns all values in path
+/// order, and `get_first` (or `get_any`) the leftmost one.
+///
+/// # Usage - typed params
+/// * `/foo/?name:type`: like `?name`, and also parses the segment as
+///   `type`, available with `request.param::<type>("name")`
+///
+/// Built-in types are `String`, `bool`, `char`, the integer types
+/// `i8`..`i128`, `isize`, `u8`..`u128`, `usize`, and `f32` and `f64`; other
+/// types implementing [`FromStr`] can be added with
+/// [`with_param_type`](Router::with_param_type). Untyped params are
+/// `String`. Typed params are parsed once the route matches, before its
+/// handler runs; a param which fails to parse gets a 400 response, and
+/// the following routes are not tried. For a param captured more than once,
+/// `request.param` returns the leftmost value.
+///
+/// ```
+/// use jbhttp::prelude::*;
+/// use jbhttp::router::Router;
+///
+/// fn get_person(req: RawRequest, _context: &mut ()) -> Res<Vec<u8>, Vec<u8>> {
+///     let id: i64 = *req.param::<i64>("id").unwrap();
+///     Ok(Response::new(200).with_payload(format!("person {}", id).into_bytes()))
+/// }
+///
+/// let router = Router::new().with_route("/person/?id:i64", get_person);
+///
+/// let mut request = Request::default();
+/// request.path = "/person/42".to_string();
+/// let response = router.handle(request, &mut ()).unwrap();
+/// assert_eq!(response.payload, Some(b"person 42".to_vec()));
+///
+/// let mut request = Request::default();
+/// request.path = "/person/bob".to_string();
+/// assert_eq!(router.handle(request, &mut ()).unwrap_err().status_code, 400);
+/// ```
+///
+/// # Usage - query patterns
+/// Routes added with [`with_route_query`](Router::with_route_query) must also
+/// match all the given query patterns:
+/// * `name`: query parameter name must be present, with any value
+/// * `name=value`: query parameter name must be present with exactly this value
+///
+/// Requests which don't match are tried against the following routes.
+///
 /// # Example
 /// ```
 /// use jbhttp::prelude::*;
@@ -122,22 +287,109 @@ struct Route<I, O, E, C> {
 /// ```
 pub struct Router<I, O, E, C> {
     routes: Vec<Route<I, O, E, C>>,
+    param_types: HashMap<String, ParamType>,
 }
 
 impl<I: 'static + Sync, O: 'static + Sync, E: 'static + Sync, C> Router<I, O, E, C> {
     pub fn new() -> Self {
-        Self { routes: vec![] }
+        Self {
+            routes: vec![],
+            param_types: default_param_types(),
+        }
+    }
+    /// Add a type for typed path params, like `?name:type`; it must be added
+    /// before the routes which use it. Replaces a type with the same name,
+    /// including built-in types.
+    ///
+    /// # Example
+    /// ```
+    /// use jbhttp::prelude::*;
+    /// use jbhttp::router::Router;
+    /// use std::str::FromStr;
+    ///
+    /// struct Color(u8, u8, u8);
+    ///
+    /// impl FromStr for Color {
+    ///     type Err = std::num::ParseIntError;
+    ///     fn from_str(s: &str) -> Result<Self, Self::Err> {
+    ///         let n = u32::from_str_radix(s, 16)?;
+    ///         Ok(Color((n >> 16) as u8, (n >> 8) as u8, n as u8))
+    ///     }
+    /// }
+    ///
+    /// fn handle(req: RawRequest, _context: &mut ()) -> Res<Vec<u8>, Vec<u8>> {
+    ///     let Color(r, g, b) = req.param::<Color>("color").unwrap();
+    ///     Ok(Response::new(200).with_payload(vec![*r, *g, *b]))
+    /// }
+    ///
+    /// let router = Router::new()
+    ///     .with_param_type::<Color>("color")
+    ///     .with_route("/color/?color:color", handle);
+    ///
+    /// let mut request = Request::default();
+    /// request.path = "/color/ff8000".to_string();
+    /// let response = router.handle(request, &mut ()).unwrap();
+    /// assert_eq!(response.payload, Some(vec![255, 128, 0]));
+    /// ```
+    pub fn with_param_type<T: FromStr + Any + Send + Sync>(mut self, name: &str) -> Self {
+        self.param_types
+            .insert(name.to_string(), ParamType::new::<T>(name));
+        self
+    }
+    pub fn with_route<H>(self, path: &str, handler: H) -> Self
+    where
+        H: 'static + Handler<I, O, E, C>,
+    {
+        self.with_route_query(path, &[], handler)
     }
-    pub fn with_route<H>(mut self, path: &str, handler: H) -> Self
+    /// Add a route which also requires query parameters, see the
+    /// [`Router`] documentation for query patterns.
+    ///
+    /// # Panics
+    /// If the path has a typed param of unknown type, see
+    /// [`with_param_type`](Router::with_param_type).
+    ///
+    /// # Example
+    /// ```
+    /// use jbhttp::prelude::*;
+    /// use jbhttp::router::Router;
+    ///
+    /// fn handle(name: &'static str) -> impl Handler<Vec<u8>, Vec<u8>, Vec<u8>, ()> {
+    ///     move |_req: RawRequest, _context: &mut ()| -> Res<Vec<u8>, Vec<u8>> {
+    ///         Ok(Response::new(200).with_payload(name.as_bytes().to_vec()))
+    ///     }
+    /// }
+    ///
+    /// let router = Router::new()
+    ///     .with_route_query("/items", &["type=a"], handle("a"))
+    ///     .with_route_query("/items", &["type"], handle("other"))
+    ///     .with_route("/items", handle("all"));
+    ///
+    /// let mut request = Request::default();
+    /// request.path = "/items".to_string();
+    /// request.params.add(Param::Query("type".to_string()), "b".to_string());
+    /// let response = router.handle(request, &mut ()).unwrap();
+    /// assert_eq!(response.payload, Some(b"other".to_vec()));
+    /// ```
+    pub fn with_route_query<H>(mut self, path: &str, query: &[&str], handler: H) -> Self
     where
         H: 'static + Handler<I, O, E, C>,
     {
         self.routes.push(Route {
-            path: RoutePath::from_str(path),
+            info: RouteInfo {
+                path: path.to_string(),
+                query: query.iter().map(|q| q.to_string()).collect(),
+            },
+            path: RoutePath::from_str(path, &self.param_types),
+            query: query.iter().map(|q| QueryPart::from_str(q)).collect(),
             handler: Box::new(handler),
         });
         self
     }
+    /// List registered routes, in matching order.
+    pub fn routes(&self) -> Vec<RouteInfo> {
+        self.routes.iter().map(|r| r.info.clone()).collect()
+    }
 }
 
 impl<I: 'static + Sync, O: 'static + Sync, E: 'static + Sync, C> Default for Router<I, O, E, C> {
@@ -151,14 +403,239 @@ impl<I: 'static + Sync, O: 'static + Sync, E: 'static + Sync, C> Handler<I, O, E
 {
     fn handle(&self, mut request: Request<I>, context: &mut C) -> Res<O, E> {
         for route in &self.routes {
-            let (matches, params) = route.path.matches(&request.path);
-            if matches {
-                for (name, val) in params {
-                    request.params.add(Param::Path(name), val)
+            if let Some(params) = route.path.matches(&request.path) {
+                if route.query.iter().all(|q| q.matches(&request)) {
+                    if route.path.has_params {
+                        let mut typed = request
+                            .extensions
+                            .get::<PathParams>()
+                            .cloned()
+                            .unwrap_or_default();
+                        if let Err((name, param_type)) = route.path.parse(&params, &mut typed) {
+                            debug!("invalid path param {}, expected {}", name, param_type.name);
+                            return Err(Response::new(400));
+                        }
+                        request.extensions.insert(typed);
+                    }
+                    for (name, val) in params {
+                        request.params.add(Param::Path(name), val)
+                    }
+                    return route.handler.handle(request, context);
                 }
-                return route.handler.handle(request, context);
             }
         }
         Err(Response::new(404))
     }
 }
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::request::{Header, RawRequest};
+    use std::net::Ipv4Addr;
+
+    fn respond(body: &'static str) -> impl Handler<Vec<u8>, Vec<u8>, Vec<u8>, ()> {
+        move |_req: RawRequest, _context: &mut ()| -> Res<Vec<u8>, Vec<u8>> {
+            Ok(Response::new(200).with_payload(body.as_bytes().to_vec()))
+        }
+    }
+
+    fn route_path(path: &str) -> RoutePath {
+        RoutePath::from_str(path, &default_param_types())
+    }
+
+    fn request(path: &str, query: &[(&str, &str)]) -> RawRequest {
+        let mut request = Request {
+            path: path.to_string(),
+            ..Request::default()
+        };
+        for (name, value) in query {
+            request
+                .params
+                .add(Param::Query(name.to_string()), value.to_string());
+        }
+        request
+    }
+
+    fn route(
+        router: &Router<Vec<u8>, Vec<u8>, Vec<u8>, ()>,
+        request: RawRequest,
+    ) -> Option<Vec<u8>> {
+        match router.handle(request, &mut ()) {
+            Ok(response) => response.payload,
+            Err(response) => {
+                assert_eq!(response.status_code, 404);
+                None
+            }
+        }
+    }
+
+    #[test]
+    fn test_routes() {
+        let router = Router::new()
+            .with_route("/person/?id", respond("person"))
+            .with_route_query("/search", &["q", "type=a"], respond("search"))
+            .with_route("/static/**", respond("static"));
+        let routes: Vec<(String, Vec<String>)> = router
+            .routes()
+            .into_iter()
+            .map(|r| (r.path, r.query))
+            .collect();
+        assert_eq!(
+            routes,
+            vec![
+                ("/person/?id".to_string(), vec![]),
+                (
+                    "/search".to_string(),
+                    vec!["q".to_string(), "type=a".to_string()]
+                ),
+                ("/static/**".to_string(), vec![]),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_rewrite_path() {
+        let router = Router::new()
+            .with_route("/v1/items", respond("v1"))
+            .with_route("/v2/items", respond("v2"));
+        let handler = router.rewrite_path(|request: &mut RawRequest| {
+            let version = match request.headers.get(&Header::new("Api-Version")) {
+                Some(version) => format!("/v{}", version),
+                None => "/v1".to_string(),
+            };
+            if !request.path.starts_with("/v") {
+                request.path = format!("{}{}", version, request.path);
+            }
+        });
+        let route = |request: RawRequest| handler.handle(request, &mut ()).unwrap().payload;
+        assert_eq!(route(request("/items", &[])), Some(b"v1".to_vec()));
+        assert_eq!(
+            route(request("/items", &[]).with_header("Api-Version", "2")),
+            Some(b"v2".to_vec())
+        );
+        assert_eq!(route(request("/v2/items", &[])), Some(b"v2".to_vec()));
+    }
+
+    #[test]
+    fn test_route_path() {
+        let path = route_path("/a/*/?x");
+        assert_eq!(
+            path.matches("/a/b/c"),
+            Some(vec![("x".to_string(), "c".to_string())])
+        );
+        assert_eq!(path.matches("/a/b"), None);
+        assert_eq!(path.matches("/a/b/c/d"), None);
+        assert_eq!(path.matches("/z/b/c"), None);
+        assert_eq!(route_path("/a").matches("/a"), Some(vec![]));
+        assert_eq!(route_path("/").matches("/"), Some(vec![]));
+        assert_eq!(route_path("/").matches("/a"), None);
+    }
+
+    #[test]
+    fn test_route_path_prefix() {
+        let path = route_path("/static/**");
+        assert_eq!(path.matches("/static/"), Some(vec![]));
+        assert_eq!(path.matches("/static/a"), Some(vec![]));
+        assert_eq!(path.matches("/static/a/b"), Some(vec![]));
+        assert_eq!(path.matches("/static"), None);
+        assert_eq!(path.matches("/other/a"), None);
+        let path = route_path("/?dir/**");
+        assert_eq!(
+            path.matches("/a/b/c"),
+            Some(vec![("dir".to_string(), "a".to_string())])
+        );
+        let path = route_path("/**");
+        assert_eq!(path.matches("/"), Some(vec![]));
+        assert_eq!(path.matches("/a/b"), Some(vec![]));
+    }
+
+    #[test]
+    fn test_route_repeated_param() {
+        let router = Router::new().with_route(
+            "/a/?x/b/?x/?y",
+            |req: RawRequest, _context: &mut ()| -> Res<Vec<u8>, Vec<u8>> {
+                let x = req.params.get_all(&Param::Path("x".to_string())).unwrap();
+                assert_eq!(x, &vec!["1".to_string(), "2".to_string()]);
+                assert_eq!(req.params.get_any("x"), Some(&"1".to_string()));
+                assert_eq!(req.params.get_any("y"), Some(&"3".to_string()));
+                Ok(Response::new(200))
+            },
+        );
+        assert!(router.handle(request("/a/1/b/2/3", &[]), &mut ()).is_ok());
+    }
+
+    #[test]
+    fn test_route_query_present() {
+        let router = Router::new().with_route_query("/search", &["q"], respond("search"));
+        assert_eq!(
+            route(&router, request("/search", &[("q", "x")])),
+            Some(b"search".to_vec())
+        );
+        assert_eq!(route(&router, request("/search", &[("p", "x")])), None);
+        assert_eq!(route(&router, request("/search", &[])), None);
+    }
+
+    #[test]
+    fn test_route_query_value() {
+        let router = Router::new()
+            .with_route_query("/items", &["type=a"], respond("a"))
+            .with_route_query("/items", &["type=b"], respond("b"))
+            .with_route("/items", respond("all"));
+        assert_eq!(
+            route(&router, request("/items", &[("type", "a")])),
+            Some(b"a".to_vec())
+        );
+        assert_eq!(
+            route(&router, request("/items", &[("type", "b")])),
+            Some(b"b".to_vec())
+        );
+        assert_eq!(
+            route(&router, request("/items", &[("type", "c")])),
+            Some(b"all".to_vec())
+        );
+        assert_eq!(
+            route(&router, request("/items", &[])),
+            Some(b"all".to_vec())
+        );
+    }
+
+    #[test]
+    fn test_route_typed_params() {
+        let router = Router::new()
+            .with_param_type::<Ipv4Addr>("ip")
+            .with_route(
+                "/a/?x:i64/?x:u8/?y/?z:ip",
+                |req: RawRequest, _context: &mut ()| -> Res<Vec<u8>, Vec<u8>> {
+                    assert_eq!(req.param::<i64>("x"), Some(&-1));
+                    assert_eq!(req.param::<u8>("x"), None);
+                    assert_eq!(req.param::<String>("y"), Some(&"b".to_string()));
+                    assert_eq!(req.param::<Ipv4Addr>("z"), Some(&Ipv4Addr::LOCALHOST));
+                    assert_eq!(
+                        req.params.get_all(&Param::Path("x".to_string())),
+                        Some(&vec!["-1".to_string(), "2".to_string()])
+                    );
+                    Ok(Response::new(200).with_payload(b"a".to_vec()))
+                },
+            )
+            .with_route("/a/**", respond("fallback"));
+        assert_eq!(
+            route(&router, request("/a/-1/2/b/127.0.0.1", &[])),
+            Some(b"a".to_vec())
+        );
+        for path in ["/a/x/2/b/127.0.0.1", "/a/-1/256/b/127.0.0.1", "/a/-1/2/b/c"] {
+            let response = router.handle(request(path, &[]), &mut ()).unwrap_err();
+            assert_eq!(response.status_code, 400);
+        }
+        assert_eq!(
+            route(&router, request("/a/1", &[])),
+            Some(b"fallback".to_vec())
+        );
+    }
+
+    #[test]
+    #[should_panic]
+    fn test_route_unknown_param_type() {
+        Router::new().with_route("/a/?x:point", respond("a"));
+    }
+}
@@ -39,10 +39,9 @@ impl ThreadRunner {
     {
         self.threads.push(Some(thread::spawn(f)));
     }
-}
 
-impl Drop for ThreadRunner {
-    fn drop(&mut self) {
+    /// Wait for all jobs to finish.
+    pub fn join(&mut self) {
         for thread in &mut self.threads {
             if let Some(thread) = thread.take() {
                 match thread.join() {
@@ -51,6 +50,13 @@ impl Drop for ThreadRunner {
                 }
             }
         }
+        self.threads.clear();
+    }
+}
+
+impl Drop for ThreadRunner {
+    fn drop(&mut self) {
+        self.join();
     }
 }
 
@@ -73,6 +79,15 @@ impl ThreadPoolRunner {
             Err(e) => error!("thread pool error: {}", e),
         }
     }
+    /// Wait for all jobs, including queued ones, to finish.
+    pub fn join(&mut self) {
+        // Dropping the pool terminates its workers once the queue is empty
+        let size = self.threadpool.size();
+        drop(std::mem::replace(
+            &mut self.threadpool,
+            ThreadPool::new(size),
+        ));
+    }
 }
 
 pub enum Runner {
@@ -93,6 +108,15 @@ impl Runner {
         }
     }
 
+    /// Wait for all jobs to finish, the runner can be used again after.
+    pub fn join(&mut self) {
+        match self {
+            Self::Simple(_) => (),
+            Self::Thread(runner) => runner.join(),
+            Self::ThreadPool(runner) => runner.join(),
+        }
+    }
+
     /// Create a new runner using the specified number of threads.
     /// 0 is infinite, a new thread will be created for each job.
     /// 1 runs in the main thread.
@@ -14,6 +14,7 @@ pub struct ThreadPool {
     sender: mpsc::Sender<Message>,
 }
 
+#[derive(Debug)]
 pub struct ExecutionError {
     message: String,
 }
@@ -32,6 +33,8 @@ impl fmt::Display for ExecutionError {
     }
 }
 
+impl std::error::Error for ExecutionError {}
+
 impl From<mpsc::SendError<Message>> for ExecutionError {
     fn from(send_error: mpsc::SendError<Message>) -> Self {
         let message = match send_error.0 {
@@ -65,6 +68,11 @@ impl ThreadPool {
         self.sender.send(Message::NewJob(job))?;
         Ok(())
     }
+
+    /// Number of worker threads in the pool.
+    pub fn size(&self) -> usize {
+        self.workers.len()
+    }
 }
 
 impl Drop for ThreadPool {
@@ -1,14 +1,22 @@
 //! HTTP Server components.
 use std::fmt;
 use std::io;
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::sync::Arc;
 
-use log::error;
+use log::{error, Level};
 
+use crate::request::HttpVersion;
+use crate::response::RawResponse;
+
+#[cfg(unix)]
+mod signal;
+mod spans;
 pub mod stream;
 pub mod tcp;
 
-pub use stream::StreamServer;
-pub use tcp::TcpServer;
+pub use stream::{run_cgi, serve_once, StreamServer};
+pub use tcp::{Accept, Connection, ResponseHook, TcpServer, TcpServerBuilder, Timeouts};
 
 #[derive(Debug)]
 pub struct ServerError {
@@ -29,12 +37,315 @@ impl fmt::Display for ServerError {
     }
 }
 
+impl std::error::Error for ServerError {}
+
 impl From<io::Error> for ServerError {
     fn from(err: io::Error) -> Self {
         ServerError::new(&format!("IOError({})", err))
     }
 }
 
+/// Log levels used for request outcomes, by response status class.
+///
+/// Defaults to `Info` for successful requests and client errors (4xx,
+/// including unparseable requests), and `Error` for server errors (5xx and
+/// IO errors while responding). Set `client_error` to `Debug` to silence the
+/// constant stream of garbage requests from bots on public servers.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct LogLevels {
+    pub success: Level,
+    pub client_error: Level,
+    pub server_error: Level,
+}
+
+impl LogLevels {
+    /// Get the log level for a response status code.
+    pub fn for_status(&self, status_code: u16) -> Level {
+        match status_code {
+            400..=499 => self.client_error,
+            500..=599 => self.server_error,
+            _ => self.success,
+        }
+    }
+}
+
+impl Default for LogLevels {
+    fn default() -> Self {
+        Self {
+            success: Level::Info,
+            client_error: Level::Info,
+            server_error: Level::Error,
+        }
+    }
+}
+
+/// Requests a graceful shutdown of a server, from another thread, see
+/// [`TcpServer::serve_until_shutdown`].
+#[derive(Debug, Clone, Default)]
+pub struct ShutdownHandle {
+    requested: Arc<AtomicBool>,
+}
+
+impl ShutdownHandle {
+    pub fn new() -> Self {
+        Self::default()
+    }
+    /// Ask the server to stop accepting connections; requests in progress
+    /// are completed.
+    pub fn shutdown(&self) {
+        self.requested.store(true, Ordering::SeqCst);
+    }
+    /// Whether shutdown was requested.
+    pub fn is_shutdown(&self) -> bool {
+        self.requested.load(Ordering::SeqCst)
+    }
+}
+
+/// Server activity counters, cheap enough to be always on.
+///
+/// # Example
+/// ```no_run
+/// use jbhttp::prelude::*;
+/// use jbhttp::server::TcpServer;
+///
+/// fn hello(req: RawRequest, _: &mut ()) -> Res<Vec<u8>, Vec<u8>> {
+///     Ok(Response::new(200))
+/// }
+///
+/// let server = TcpServer::new("127.0.0.1:8080", 4, None, hello).unwrap();
+/// let stats = server.stats();
+/// // ...serve_forever in another thread...
+/// println!("{} requests handled", stats.requests());
+/// ```
+#[derive(Debug, Default)]
+pub struct ServerStats {
+    connections: AtomicU64,
+    requests: AtomicU64,
+    active_requests: AtomicU64,
+}
+
+impl ServerStats {
+    pub fn new() -> Self {
+        Self::default()
+    }
+    /// Total number of connections accepted.
+    pub fn connections(&self) -> u64 {
+        self.connections.load(Ordering::Relaxed)
+    }
+    /// Total number of requests handled (completed).
+    pub fn requests(&self) -> u64 {
+        self.requests.load(Ordering::Relaxed)
+    }
+    /// Number of requests currently being handled.
+    pub fn active_requests(&self) -> u64 {
+        self.active_requests.load(Ordering::Relaxed)
+    }
+    pub(crate) fn connection_accepted(&self) {
+        self.connections.fetch_add(1, Ordering::Relaxed);
+    }
+    /// Count a request as active until the returned guard is dropped,
+    /// then count it as handled.
+    pub(crate) fn request_started(&self) -> ActiveRequest<'_> {
+        self.active_requests.fetch_add(1, Ordering::Relaxed);
+        ActiveRequest { stats: self }
+    }
+}
+
+pub(crate) struct ActiveRequest<'a> {
+    stats: &'a ServerStats,
+}
+
+impl Drop for ActiveRequest<'_> {
+    fn drop(&mut self) {
+        self.stats.active_requests.fetch_sub(1, Ordering::Relaxed);
+        self.stats.requests.fetch_add(1, Ordering::Relaxed);
+    }
+}
+
+/// A closure to run after the response is written, see [`Deferrals`].
+pub type Deferred = Box<dyn FnOnce(&RawResponse)>;
+
+/// Request contexts implement this trait to let handlers and filters defer
+/// work until after the response is written, like committing a transaction
+/// or flushing metrics. Servers take the deferred closures once the response
+/// is written, and run them in order with the final response.
+///
+/// Contexts which don't defer anything can use the default implementation,
+/// with an empty `impl Deferrals for MyContext {}`. Otherwise, keep a
+/// [`DeferralQueue`] in the context.
+///
+/// # Example
+/// ```
+/// use jbhttp::io::MemoryStream;
+/// use jbhttp::prelude::*;
+/// use jbhttp::server::{DeferralQueue, Deferrals, Deferred, StreamServer};
+///
+/// #[derive(Default)]
+/// struct Context {
+///     deferred: DeferralQueue,
+/// }
+///
+/// impl Deferrals for Context {
+///     fn take_deferrals(&mut self) -> Vec<Deferred> {
+///         self.deferred.take_deferrals()
+///     }
+/// }
+///
+/// fn handle(_req: RawRequest, context: &mut Context) -> Res<Vec<u8>, Vec<u8>> {
+///     context.deferred.defer(|response| {
+///         println!("sent {} response", response.status_code);
+///     });
+///     Ok(Response::new(200))
+/// }
+///
+/// let mut server = StreamServer::new(MemoryStream::new(b"GET / HTTP/1.1\r\n\r\n"), handle);
+/// Server::<Context>::serve_forever(&mut server);
+/// ```
+pub trait Deferrals {
+    /// Take all deferred closures, leaving none.
+    fn take_deferrals(&mut self) -> Vec<Deferred> {
+        vec![]
+    }
+    /// Called by servers once the response is written, before the deferred
+    /// closures run, with the bytes read and written for the request, e.g.
+    /// to bill by bandwidth or enforce quotas. Does nothing by default.
+    fn transferred(&mut self, _transfer: Transfer) {}
+}
+
+/// Bytes transferred for a request and its response, see
+/// [`Deferrals::transferred`].
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct Transfer {
+    /// Request bytes, request line and headers included, see
+    /// [`Request::bytes_read`](crate::request::Request::bytes_read); 0 if
+    /// the request couldn't be parsed.
+    pub bytes_read: usize,
+    /// Response bytes, status line and headers included (and interim
+    /// responses added with
+    /// [`Response::with_interim`](crate::response::Response::with_interim));
+    /// 0 if the response was aborted.
+    pub bytes_written: usize,
+}
+
+impl Deferrals for () {}
+
+/// A queue of deferred closures, to keep in a request context.
+#[derive(Default)]
+pub struct DeferralQueue {
+    deferred: Vec<Deferred>,
+}
+
+impl DeferralQueue {
+    pub fn new() -> Self {
+        Self::default()
+    }
+    /// Run `f` with the final response, after it is written.
+    pub fn defer<F>(&mut self, f: F)
+    where
+        F: FnOnce(&RawResponse) + 'static,
+    {
+        self.deferred.push(Box::new(f));
+    }
+}
+
+impl Deferrals for DeferralQueue {
+    fn take_deferrals(&mut self) -> Vec<Deferred> {
+        std::mem::take(&mut self.deferred)
+    }
+}
+
+impl fmt::Debug for DeferralQueue {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "DeferralQueue({} deferred)", self.deferred.len())
+    }
+}
+
+/// Report the transfer to a context, then run its deferred closures with
+/// the final response.
+pub(crate) fn run_deferrals<C: Deferrals>(
+    context: &mut C,
+    response: &RawResponse,
+    transfer: Transfer,
+) {
+    context.transferred(transfer);
+    for deferred in context.take_deferrals() {
+        deferred(response);
+    }
+}
+
+/// Set the *Connection* header of a response, and tell whether the
+/// connection must be closed after it. `keep_alive` is whether the client
+/// wants the connection kept open, see
+/// [`Request::keep_alive`](crate::request::Request::keep_alive). The
+/// connection is also closed when the handler's response has *Connection:
+/// close*, or to end a streamed body in HTTP/1.0, which has no chunked
+/// transfer coding.
+pub(crate) fn set_connection_header(response: &mut RawResponse, keep_alive: bool) -> bool {
+    let must_close =
+        !keep_alive || (response.version() == HttpVersion::Http10 && response.is_streaming());
+    if must_close && !response.closes_connection() {
+        response.set_header("Connection", "close");
+    }
+    let closed = response.closes_connection();
+    if !closed {
+        response.set_header("Connection", "keep-alive");
+    }
+    closed
+}
+
+/// Switch the process to an unprivileged user and group, e.g. after binding
+/// to a privileged port as root.
+///
+/// Call it after creating the server, which binds the listening socket (or
+/// after creating the listener, see [`TcpServer::from_listener`]), and before
+/// serving requests or opening anything the handlers must not be able to
+/// reach as root. When running as root, supplementary groups are cleared
+/// first; the group is changed before the user, since changing the group
+/// requires the privileges the user change gives up. It applies to all
+/// threads of the process, including the server's thread pool.
+///
+/// Fails if any change fails, or if root privileges could be regained
+/// afterwards; the process should then exit rather than serve requests.
+///
+/// # Example
+/// ```no_run
+/// use jbhttp::prelude::*;
+/// use jbhttp::server::{drop_privileges, TcpServer};
+///
+/// fn hello(req: RawRequest, _: &mut ()) -> Res<Vec<u8>, Vec<u8>> {
+///     Ok(Response::new(200))
+/// }
+///
+/// let mut server = TcpServer::new("0.0.0.0:80", 4, None, hello).unwrap();
+/// drop_privileges(65534, 65534).expect("failed to drop privileges");
+/// Server::<()>::serve_forever(&mut server);
+/// ```
+#[cfg(unix)]
+pub fn drop_privileges(uid: u32, gid: u32) -> io::Result<()> {
+    fn check(result: libc::c_int) -> io::Result<()> {
+        if result == 0 {
+            Ok(())
+        } else {
+            Err(io::Error::last_os_error())
+        }
+    }
+    // SAFETY: these calls only take integers, and a null list with size 0
+    unsafe {
+        if libc::geteuid() == 0 {
+            check(libc::setgroups(0, std::ptr::null()))?;
+        }
+        check(libc::setgid(gid as libc::gid_t))?;
+        check(libc::setuid(uid as libc::uid_t))?;
+        if uid != 0 && libc::setuid(0) == 0 {
+            return Err(io::Error::new(
+                io::ErrorKind::PermissionDenied,
+                "root privileges could be regained after dropping them",
+            ));
+        }
+    }
+    Ok(())
+}
+
 pub trait Server<C: Default> {
     /// Serve one request, must be implemented.
     fn serve_one(&mut self) -> Result<(), ServerError>;
@@ -48,3 +359,51 @@ pub trait Server<C: Default> {
         }
     }
 }
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_server_stats() {
+        let stats = ServerStats::new();
+        stats.connection_accepted();
+        let first = stats.request_started();
+        let second = stats.request_started();
+        assert_eq!(stats.connections(), 1);
+        assert_eq!(stats.active_requests(), 2);
+        assert_eq!(stats.requests(), 0);
+        drop(first);
+        assert_eq!(stats.active_requests(), 1);
+        assert_eq!(stats.requests(), 1);
+        drop(second);
+        assert_eq!(stats.active_requests(), 0);
+        assert_eq!(stats.requests(), 2);
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_drop_privileges_to_current_user() {
+        // Switching to the current user and group is allowed for anyone, and
+        // keeps the test process' user
+        let (uid, gid) = unsafe { (libc::getuid(), libc::getgid()) };
+        drop_privileges(uid, gid).unwrap();
+        assert_eq!(unsafe { libc::geteuid() }, uid);
+    }
+
+    #[test]
+    fn test_log_levels_for_status() {
+        let levels = LogLevels {
+            success: Level::Trace,
+            client_error: Level::Debug,
+            server_error: Level::Warn,
+        };
+        assert_eq!(levels.for_status(101), Level::Trace);
+        assert_eq!(levels.for_status(200), Level::Trace);
+        assert_eq!(levels.for_status(304), Level::Trace);
+        assert_eq!(levels.for_status(400), Level::Debug);
+        assert_eq!(levels.for_status(499), Level::Debug);
+        assert_eq!(levels.for_status(500), Level::Warn);
+        assert_eq!(levels.for_status(503), Level::Warn);
+    }
+}
@@ -0,0 +1,61 @@
+//! Minimal SIGINT/SIGTERM handling, for graceful shutdown.
+use std::io;
+use std::sync::atomic::{AtomicBool, Ordering};
+
+static RECEIVED: AtomicBool = AtomicBool::new(false);
+
+extern "C" fn on_signal(_: libc::c_int) {
+    // Only async-signal-safe operations are allowed here
+    RECEIVED.store(true, Ordering::SeqCst);
+}
+
+/// Catches SIGINT and SIGTERM while alive; the previous handlers are
+/// restored when dropped. Handlers are process-wide, so only one guard
+/// should exist at a time.
+pub(crate) struct SignalGuard {
+    previous: Vec<(libc::c_int, libc::sighandler_t)>,
+}
+
+impl SignalGuard {
+    pub(crate) fn install() -> io::Result<Self> {
+        RECEIVED.store(false, Ordering::SeqCst);
+        let mut guard = Self { previous: vec![] };
+        let handler = on_signal as extern "C" fn(libc::c_int) as libc::sighandler_t;
+        for &signum in &[libc::SIGINT, libc::SIGTERM] {
+            // SAFETY: the handler only stores to an atomic
+            let previous = unsafe { libc::signal(signum, handler) };
+            if previous == libc::SIG_ERR {
+                // Dropping the guard restores the handlers installed so far
+                return Err(io::Error::last_os_error());
+            }
+            guard.previous.push((signum, previous));
+        }
+        Ok(guard)
+    }
+    /// Whether SIGINT or SIGTERM was received since the guard was installed.
+    pub(crate) fn received(&self) -> bool {
+        RECEIVED.load(Ordering::SeqCst)
+    }
+}
+
+impl Drop for SignalGuard {
+    fn drop(&mut self) {
+        for &(signum, previous) in &self.previous {
+            // SAFETY: restores a handler previously returned by signal()
+            unsafe { libc::signal(signum, previous) };
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_signal_guard() {
+        let guard = SignalGuard::install().unwrap();
+        assert!(!guard.received());
+        unsafe { libc::raise(libc::SIGTERM) };
+        assert!(guard.received());
+    }
+}
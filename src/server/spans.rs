@@ -0,0 +1,73 @@
+//! Spans for connections and requests served by [`TcpServer`], with the
+//! `tracing` feature; without it, they do nothing.
+//!
+//! [`TcpServer`]: crate::server::TcpServer
+use std::net::SocketAddr;
+
+/// Spans are filtered with the server's log records.
+#[cfg(feature = "tracing")]
+const TARGET: &str = "jbhttp::server::tcp";
+
+/// A span, entered while serving a connection or request.
+pub(crate) struct Span {
+    #[cfg(feature = "tracing")]
+    span: tracing::Span,
+}
+
+impl Span {
+    /// A `connection` span, with the `peer` address.
+    #[allow(unused_variables)]
+    pub(crate) fn connection(peer: SocketAddr) -> Self {
+        Self {
+            #[cfg(feature = "tracing")]
+            span: tracing::info_span!(target: TARGET, "connection", peer = %peer),
+        }
+    }
+    /// A `request` span, in the current (connection) span, with `method`,
+    /// `path`, `status` and `duration_ms` recorded as they are known.
+    pub(crate) fn request() -> Self {
+        Self {
+            #[cfg(feature = "tracing")]
+            span: tracing::info_span!(
+                target: TARGET,
+                "request",
+                method = tracing::field::Empty,
+                path = tracing::field::Empty,
+                status = tracing::field::Empty,
+                duration_ms = tracing::field::Empty,
+            ),
+        }
+    }
+    #[allow(unused_variables)]
+    pub(crate) fn record_request(&self, method: &str, path: &str) {
+        #[cfg(feature = "tracing")]
+        {
+            self.span.record("method", method);
+            self.span.record("path", path);
+        }
+    }
+    #[allow(unused_variables)]
+    pub(crate) fn record_response(&self, status: u16, duration_ms: u64) {
+        #[cfg(feature = "tracing")]
+        {
+            self.span.record("status", status);
+            self.span.record("duration_ms", duration_ms);
+        }
+    }
+    /// Enter the span until the guard is dropped.
+    pub(crate) fn enter(&self) -> Entered<'_> {
+        Entered {
+            #[cfg(feature = "tracing")]
+            _entered: self.span.enter(),
+            #[cfg(not(feature = "tracing"))]
+            _span: std::marker::PhantomData,
+        }
+    }
+}
+
+pub(crate) struct Entered<'a> {
+    #[cfg(feature = "tracing")]
+    _entered: tracing::span::Entered<'a>,
+    #[cfg(not(feature = "tracing"))]
+    _span: std::marker::PhantomData<&'a Span>,
+}
@@ -1,16 +1,22 @@
 //! Generic IO Stream HTTP server.
+use std::io;
 use std::io::prelude::*;
+use std::process::ExitCode;
+
+use log::error;
 
 use crate::{
     handler::Handler,
-    request::parser::RequestParser,
-    response::Response,
-    server::{Server, ServerError},
+    io::{CountingWriter, ReadWriteAdapter},
+    request::{parser::RequestParser, HttpVersion, Method, RawRequest},
+    response::{RawResponse, Response},
+    server::{run_deferrals, set_connection_header, Deferrals, Server, ServerError, Transfer},
     VERSION,
 };
 
 /// Serve HTTP requests over a generic stream. The stream is not closed,
-/// multiple requests can be served.
+/// multiple requests can be served; `serve_forever` returns once the
+/// stream reaches EOF.
 ///
 /// # Example
 /// ```
@@ -40,48 +46,647 @@ use crate::{
 ///     )
 /// );
 /// ```
-pub struct StreamServer<H, S> {
+pub struct StreamServer<H, S: Read> {
     handler: H,
-    stream: S,
+    // The parser owns the stream and keeps its read buffer between requests,
+    // so that pipelined requests are not lost.
+    parser: RequestParser<S>,
     prompt: Option<String>,
+    closed: bool,
 }
 
-impl<H, S> StreamServer<H, S> {
+impl<H, S: Read> StreamServer<H, S> {
     pub fn new(stream: S, handler: H) -> Self {
         Self {
             handler,
-            stream,
+            parser: RequestParser::new(stream),
             prompt: None,
+            closed: false,
         }
     }
     pub fn set_prompt(&mut self, prompt: &str) {
         self.prompt = Some(prompt.to_string());
     }
+    /// Also accept `;` as a query parameter separator, see
+    /// [`RequestParser::with_semicolon_separator`].
+    pub fn with_semicolon_separator(mut self, enabled: bool) -> Self {
+        self.parser = self.parser.with_semicolon_separator(enabled);
+        self
+    }
+    /// Reject requests with a body for these methods, see
+    /// [`RequestParser::reject_body_on`].
+    pub fn reject_body_on(mut self, methods: &[Method]) -> Self {
+        self.parser = self.parser.reject_body_on(methods);
+        self
+    }
+    /// Maximum number of request parameters, see
+    /// [`RequestParser::with_max_params`].
+    pub fn with_max_params(mut self, max_params: usize) -> Self {
+        self.parser = self.parser.with_max_params(max_params);
+        self
+    }
+    /// Keep the raw header block of requests, see
+    /// [`RequestParser::with_raw_headers`].
+    pub fn with_raw_headers(mut self, enabled: bool) -> Self {
+        self.parser = self.parser.with_raw_headers(enabled);
+        self
+    }
+    /// Also accept bare LF line endings, as typed in a terminal, see
+    /// [`RequestParser::lenient_line_endings`].
+    pub fn lenient_line_endings(mut self, enabled: bool) -> Self {
+        self.parser = self.parser.lenient_line_endings(enabled);
+        self
+    }
+    /// Whether the connection was closed, by a request or a handler's
+    /// response with *Connection: close*, an HTTP/1.0 request without
+    /// *Connection: keep-alive*, a request which couldn't be parsed, or a
+    /// handler aborting. No more requests are served after that.
+    pub fn is_closed(&self) -> bool {
+        self.closed
+    }
+    /// Get a reference to the underlying stream.
+    pub fn get_ref(&self) -> &S {
+        self.parser.get_ref()
+    }
 }
 
 impl<H, S, C> Server<C> for StreamServer<H, S>
 where
-    C: Default,
+    C: Default + Deferrals,
     H: Handler<Vec<u8>, Vec<u8>, Vec<u8>, C>,
     S: Read + Write,
 {
     fn serve_one(&mut self) -> Result<(), ServerError> {
         if let Some(prompt) = &self.prompt {
-            self.stream.write_all(prompt.as_bytes())?;
+            self.parser.get_mut().write_all(prompt.as_bytes())?;
         }
-        let mut parser = RequestParser::new(&mut self.stream);
-        let response = match parser.parse() {
-            Ok(request) => self.handler.handle(request, &mut C::default()),
-            Err(e) => Err(Response::new(400).with_payload(format!("{}", e).as_bytes().to_vec())),
+        let mut is_head = false;
+        let mut version = HttpVersion::Http11;
+        let mut keep_alive = true;
+        let mut transfer = Transfer::default();
+        let mut context = C::default();
+        let response = match self.parser.parse_with_continue() {
+            Ok(request) => {
+                transfer.bytes_read = request.bytes_read().unwrap_or(0);
+                is_head = request.method == Method::HEAD;
+                version = request.version;
+                keep_alive = request.keep_alive();
+                self.handler.handle(request, &mut context)
+            }
+            Err(e) => {
+                let status_code = if e.is_expectation_failed() {
+                    417
+                } else if e.is_timeout() {
+                    408
+                } else {
+                    400
+                };
+                // Where the next request starts is unknown (for a 417, the
+                // body is still in the stream), so no more requests can be read
+                Err(Response::new(status_code)
+                    .with_header("Connection", "close")
+                    .with_payload(format!("{}", e).as_bytes().to_vec()))
+            }
         };
         let response = match response {
             Ok(response) => response,
             Err(response) => response,
+        };
+        if response.is_aborted() {
+            self.closed = true;
+            run_deferrals(&mut context, &response, transfer);
+            return Ok(());
+        }
+        let mut response = response
+            .with_header("Server", &format!("jbhttp::StreamServer/{}", VERSION))
+            .with_version(version);
+        self.closed = set_connection_header(&mut response, keep_alive);
+        if is_head {
+            response = response.without_body();
+        }
+        let mut stream = CountingWriter::new(self.parser.get_mut());
+        let written = response.write_to(&mut stream).and_then(|_| stream.flush());
+        if written.is_err() {
+            // A streamed body may have been cut short
+            self.closed = true;
+        }
+        transfer.bytes_written = stream.count();
+        run_deferrals(&mut context, &response, transfer);
+        Ok(written?)
+    }
+    /// Serve requests until the stream reaches EOF, or a handler responds
+    /// with *Connection: close* or aborts.
+    fn serve_forever(&mut self) {
+        while !self.closed {
+            match self.parser.is_eof() {
+                Ok(true) => return,
+                Ok(false) => (),
+                Err(e) => {
+                    error!("{}", e);
+                    return;
+                }
+            }
+            if let Err(e) = Server::<C>::serve_one(self) {
+                error!("{}", e);
+            }
+        }
+    }
+}
+
+/// Serve a single request read from `input`, writing the response to
+/// `output`, with *Connection: close* (unless the handler set a
+/// *Connection* header).
+///
+/// # Example
+/// ```
+/// use jbhttp::prelude::*;
+/// use jbhttp::server::serve_once;
+///
+/// fn hello(req: RawRequest, _: &mut ()) -> Res<Vec<u8>, Vec<u8>> {
+///     Ok(Response::new(200).with_payload(b"Hello!".to_vec()))
+/// }
+///
+/// let mut output = vec![];
+/// serve_once(hello, &b"GET / HTTP/1.1\r\n\r\n"[..], &mut output).unwrap();
+///
+/// let output = std::str::from_utf8(&output).unwrap();
+/// assert!(output.starts_with("HTTP/1.1 200 OK\r\n"));
+/// assert!(output.contains("Connection: close\r\n"));
+/// assert!(output.ends_with("\r\n\r\nHello!"));
+/// ```
+pub fn serve_once<H, C, R, W>(handler: H, input: R, output: W) -> Result<(), ServerError>
+where
+    C: Default + Deferrals,
+    H: Handler<Vec<u8>, Vec<u8>, Vec<u8>, C>,
+    R: Read,
+    W: Write,
+{
+    fn close(response: RawResponse) -> RawResponse {
+        if response.header("Connection").is_none() {
+            response.with_header("Connection", "close")
+        } else {
+            response
+        }
+    }
+    let handler = move |request: RawRequest, context: &mut C| match handler.handle(request, context)
+    {
+        Ok(response) => Ok(close(response)),
+        Err(response) => Err(close(response)),
+    };
+    let mut server = StreamServer::new(ReadWriteAdapter::new(input, output), handler);
+    Server::<C>::serve_one(&mut server)
+}
+
+/// Run a handler as a one-shot, inetd-style process: serve a single HTTP
+/// request read from stdin, writing the response to stdout, see
+/// [`serve_once`]. This is raw HTTP over stdio, for `xinetd`, systemd socket
+/// activation with `Accept=yes`, or shell pipelines; not the CGI/1.1
+/// environment variable protocol.
+///
+/// Returns a failure exit code if the response couldn't be written.
+///
+/// # Example
+/// ```no_run
+/// use std::process::ExitCode;
+/// use jbhttp::prelude::*;
+/// use jbhttp::server::run_cgi;
+///
+/// fn hello(req: RawRequest, _: &mut ()) -> Res<Vec<u8>, Vec<u8>> {
+///     Ok(Response::new(200).with_payload(b"Hello!".to_vec()))
+/// }
+///
+/// fn main() -> ExitCode {
+///     run_cgi(hello)
+/// }
+/// ```
+pub fn run_cgi<H, C>(handler: H) -> ExitCode
+where
+    C: Default + Deferrals,
+    H: Handler<Vec<u8>, Vec<u8>, Vec<u8>, C>,
+{
+    let stdin = io::stdin();
+    let stdout = io::stdout();
+    match serve_once(handler, stdin.lock(), stdout.lock()) {
+        Ok(()) => ExitCode::SUCCESS,
+        Err(e) => {
+            error!("{}", e);
+            ExitCode::FAILURE
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::handler::RawResult;
+    use crate::io::{MemoryStream, TimeoutReader};
+    use crate::request::RawRequest;
+    use crate::response::BodyStream;
+
+    fn echo(
+        request: RawRequest,
+        _context: &mut (),
+    ) -> Result<Response<Vec<u8>>, Response<Vec<u8>>> {
+        let mut body = request.path.into_bytes();
+        if let Some(payload) = request.payload {
+            body.extend(payload);
+        }
+        Ok(Response::new(200).with_payload(body))
+    }
+
+    fn serve(input: &[u8]) -> String {
+        let mut server = StreamServer::new(MemoryStream::new(input), echo);
+        Server::<()>::serve_forever(&mut server);
+        String::from_utf8(server.get_ref().output().to_vec()).unwrap()
+    }
+
+    fn response(body: &str) -> String {
+        format!(
+            "HTTP/1.1 200 OK\r\n\
+             Server: jbhttp::StreamServer/{}\r\n\
+             Connection: keep-alive\r\n\
+             Content-Length: {}\r\n\
+             \r\n\
+             {}",
+            VERSION,
+            body.len(),
+            body
+        )
+    }
+
+    #[test]
+    fn test_serve_pipelined() {
+        let output = serve(
+            b"GET /a HTTP/1.1\r\n\r\n\
+              POST /b HTTP/1.1\r\nContent-Length: 5\r\n\r\n body\
+              GET /c HTTP/1.1\r\nHost: localhost\r\n\r\n",
+        );
+        assert_eq!(
+            output,
+            format!(
+                "{}{}{}",
+                response("/a"),
+                response("/b body"),
+                response("/c")
+            )
+        );
+    }
+
+    #[test]
+    fn test_serve_pipelined_empty_body() {
+        let handler = |request: RawRequest, context: &mut ()| match request.path.as_str() {
+            "/missing" => Err(Response::new(404)),
+            "/none" => Ok(Response::new(204)),
+            _ => echo(request, context),
+        };
+        let mut server = StreamServer::new(
+            MemoryStream::new(
+                b"GET /missing HTTP/1.1\r\n\r\n\
+                  GET /none HTTP/1.1\r\n\r\n\
+                  GET /a HTTP/1.1\r\n\r\n",
+            ),
+            handler,
+        );
+        Server::<()>::serve_forever(&mut server);
+        let output = String::from_utf8(server.get_ref().output().to_vec()).unwrap();
+        assert_eq!(
+            output,
+            format!(
+                "HTTP/1.1 404 Not Found\r\n\
+                 Server: jbhttp::StreamServer/{0}\r\n\
+                 Connection: keep-alive\r\n\
+                 Content-Length: 0\r\n\
+                 \r\n\
+                 HTTP/1.1 204 No Content\r\n\
+                 Server: jbhttp::StreamServer/{0}\r\n\
+                 Connection: keep-alive\r\n\
+                 \r\n\
+                 {1}",
+                VERSION,
+                response("/a")
+            )
+        );
+    }
+
+    #[test]
+    fn test_serve_head() {
+        let output = serve(b"GET /abc HTTP/1.1\r\n\r\nHEAD /abc HTTP/1.1\r\n\r\n");
+        let get = response("/abc");
+        let head = get.strip_suffix("/abc").unwrap();
+        assert_eq!(output, format!("{}{}", get, head));
+    }
+
+    #[test]
+    fn test_serve_empty() {
+        assert_eq!(serve(b""), "");
+    }
+
+    #[test]
+    fn test_serve_obs_fold() {
+        let output = serve(b"GET / HTTP/1.1\r\nX-Long: a\r\n b\r\n\r\nGET /next HTTP/1.1\r\n\r\n");
+        assert!(
+            output.starts_with("HTTP/1.1 400 Bad Request\r\n"),
+            "{}",
+            output
+        );
+        assert!(!output.contains("/next"), "{}", output);
+    }
+
+    #[test]
+    fn test_serve_lenient_line_endings() {
+        let input = b"GET /a HTTP/1.1\nHost: localhost\n\nGET /b HTTP/1.1\r\n\r\n";
+        let mut server =
+            StreamServer::new(MemoryStream::new(input), echo).lenient_line_endings(true);
+        Server::<()>::serve_forever(&mut server);
+        let output = std::str::from_utf8(server.get_ref().output()).unwrap();
+        assert_eq!(output, format!("{}{}", response("/a"), response("/b")));
+    }
+
+    #[test]
+    fn test_serve_invalid_header_name() {
+        let output = serve(b"GET / HTTP/1.1\r\nBad Name: x\r\n\r\n");
+        assert!(
+            output.starts_with("HTTP/1.1 400 Bad Request\r\n"),
+            "{}",
+            output
+        );
+    }
+
+    #[test]
+    fn test_serve_timeout() {
+        let stream = TimeoutReader::new(
+            MemoryStream::new(b"GET / HTTP/1.1\r\n\r\n"),
+            std::time::Duration::ZERO,
+        );
+        let mut server = StreamServer::new(stream, echo);
+        Server::<()>::serve_one(&mut server).unwrap();
+        let output = String::from_utf8(server.get_ref().get_ref().output().to_vec()).unwrap();
+        assert!(output.starts_with("HTTP/1.1 408 Request Timeout\r\n"));
+    }
+
+    #[test]
+    fn test_serve_connection_close() {
+        fn close_on_b(request: RawRequest, context: &mut ()) -> RawResult {
+            let close = request.path == "/b";
+            let response = echo(request, context)?;
+            Ok(if close {
+                response.with_header("Connection", "close")
+            } else {
+                response
+            })
+        }
+        let input = b"GET /a HTTP/1.1\r\n\r\nGET /b HTTP/1.1\r\n\r\nGET /c HTTP/1.1\r\n\r\n";
+        let mut server = StreamServer::new(MemoryStream::new(input), close_on_b);
+        Server::<()>::serve_forever(&mut server);
+        assert!(server.is_closed());
+        let output = String::from_utf8(server.get_ref().output().to_vec()).unwrap();
+        let closed = format!(
+            "HTTP/1.1 200 OK\r\n\
+             Connection: close\r\n\
+             Server: jbhttp::StreamServer/{}\r\n\
+             Content-Length: 2\r\n\
+             \r\n\
+             /b",
+            VERSION
+        );
+        assert_eq!(output, format!("{}{}", response("/a"), closed));
+    }
+
+    #[test]
+    fn test_serve_expect() {
+        let output =
+            serve(b"POST /a HTTP/1.1\r\nExpect: 100-continue\r\nContent-Length: 5\r\n\r\n body");
+        assert_eq!(
+            output,
+            format!("HTTP/1.1 100 Continue\r\n\r\n{}", response("/a body"))
+        );
+
+        let input = b"POST /a HTTP/1.1\r\nExpect: nothing\r\nContent-Length: 5\r\n\r\n body\
+                      GET /b HTTP/1.1\r\n\r\n";
+        let mut server = StreamServer::new(MemoryStream::new(input), echo);
+        Server::<()>::serve_forever(&mut server);
+        assert!(server.is_closed());
+        let output = String::from_utf8(server.get_ref().output().to_vec()).unwrap();
+        assert!(output.starts_with("HTTP/1.1 417 Expectation Failed\r\n"));
+        assert!(!output.contains("/b"));
+    }
+
+    #[test]
+    fn test_serve_stream() {
+        fn stream(request: RawRequest, _: &mut ()) -> RawResult {
+            let body = BodyStream::from_chunks(vec![request.path.into_bytes()].into_iter());
+            Ok(Response::new(200).with_stream(body))
         }
-        .with_header("Server", &format!("jbhttp::StreamServer/{}", VERSION))
-        .with_header("Connection", "keep-alive");
-        self.stream.write_all(&response.into_bytes())?;
-        self.stream.flush()?;
-        Ok(())
+        let input = b"GET /a HTTP/1.1\r\n\r\nGET /bc HTTP/1.1\r\n\r\n";
+        let mut server = StreamServer::new(MemoryStream::new(input), stream);
+        Server::<()>::serve_forever(&mut server);
+        let output = String::from_utf8(server.get_ref().output().to_vec()).unwrap();
+        let streamed = |body: &str| {
+            format!(
+                "HTTP/1.1 200 OK\r\n\
+                 Server: jbhttp::StreamServer/{}\r\n\
+                 Connection: keep-alive\r\n\
+                 Transfer-Encoding: chunked\r\n\
+                 \r\n\
+                 {:x}\r\n{}\r\n0\r\n\r\n",
+                VERSION,
+                body.len(),
+                body
+            )
+        };
+        assert_eq!(output, format!("{}{}", streamed("/a"), streamed("/bc")));
+    }
+
+    #[test]
+    fn test_serve_http10() {
+        let response_10 = |body: &str, connection: &str| {
+            format!(
+                "HTTP/1.0 200 OK\r\n\
+                 Server: jbhttp::StreamServer/{}\r\n\
+                 Connection: {}\r\n\
+                 Content-Length: {}\r\n\
+                 \r\n\
+                 {}",
+                VERSION,
+                connection,
+                body.len(),
+                body
+            )
+        };
+        // Closed unless the client asks for keep-alive
+        let output = serve(b"GET /a HTTP/1.0\r\n\r\nGET /b HTTP/1.0\r\n\r\n");
+        assert_eq!(output, response_10("/a", "close"));
+
+        let output = serve(
+            b"GET /a HTTP/1.0\r\nConnection: Keep-Alive\r\n\r\n\
+              GET /b HTTP/1.0\r\n\r\nGET /c HTTP/1.1\r\n\r\n",
+        );
+        assert_eq!(
+            output,
+            format!(
+                "{}{}",
+                response_10("/a", "keep-alive"),
+                response_10("/b", "close")
+            )
+        );
+
+        // HTTP/1.1 clients can also close
+        let output = serve(b"GET /a HTTP/1.1\r\nConnection: close\r\n\r\nGET /b HTTP/1.1\r\n\r\n");
+        assert!(output.contains("Connection: close\r\n"));
+        assert!(output.ends_with("\r\n\r\n/a"));
+    }
+
+    #[test]
+    fn test_serve_stream_http10() {
+        fn stream(_: RawRequest, _: &mut ()) -> RawResult {
+            let body = BodyStream::from_chunks(vec![b"ab".to_vec(), b"c".to_vec()].into_iter());
+            Ok(Response::new(200).with_stream(body))
+        }
+        let input = b"GET / HTTP/1.0\r\nConnection: keep-alive\r\n\r\nGET / HTTP/1.0\r\n\r\n";
+        let mut server = StreamServer::new(MemoryStream::new(input), stream);
+        Server::<()>::serve_forever(&mut server);
+        assert!(server.is_closed());
+        let output = String::from_utf8(server.get_ref().output().to_vec()).unwrap();
+        assert_eq!(
+            output,
+            format!(
+                "HTTP/1.0 200 OK\r\n\
+                 Server: jbhttp::StreamServer/{}\r\n\
+                 Connection: close\r\n\
+                 \r\n\
+                 abc",
+                VERSION
+            )
+        );
+    }
+
+    #[test]
+    fn test_serve_interim() {
+        fn hints(request: RawRequest, context: &mut ()) -> RawResult {
+            let hint = Response::new(103).with_header("Link", "</a.css>; rel=preload");
+            Ok(echo(request, context)?.with_interim(hint))
+        }
+        let input = b"GET /a HTTP/1.1\r\n\r\nGET /b HTTP/1.0\r\n\r\n";
+        let mut server = StreamServer::new(MemoryStream::new(input), hints);
+        Server::<()>::serve_forever(&mut server);
+        let output = String::from_utf8(server.get_ref().output().to_vec()).unwrap();
+        let (first, second) = output.split_at(output.find("HTTP/1.0").unwrap());
+        assert_eq!(
+            first,
+            format!(
+                "HTTP/1.1 103 Early Hints\r\n\
+                 Link: </a.css>; rel=preload\r\n\
+                 \r\n\
+                 {}",
+                response("/a")
+            )
+        );
+        // Not for HTTP/1.0 clients
+        assert!(second.starts_with("HTTP/1.0 200 OK\r\n"));
+        assert!(!second.contains("103"));
+    }
+
+    #[test]
+    fn test_serve_abort() {
+        fn abort_on_b(request: RawRequest, context: &mut ()) -> RawResult {
+            if request.path == "/b" {
+                return Err(Response::abort());
+            }
+            echo(request, context)
+        }
+        let input = b"GET /a HTTP/1.1\r\n\r\nGET /b HTTP/1.1\r\n\r\nGET /c HTTP/1.1\r\n\r\n";
+        let mut server = StreamServer::new(MemoryStream::new(input), abort_on_b);
+        Server::<()>::serve_forever(&mut server);
+        assert!(server.is_closed());
+        let output = String::from_utf8(server.get_ref().output().to_vec()).unwrap();
+        assert_eq!(output, response("/a"));
+    }
+
+    #[test]
+    fn test_serve_transfer() {
+        use crate::server::Transfer;
+        use std::cell::RefCell;
+
+        thread_local! {
+            static TRANSFERS: RefCell<Vec<Transfer>> = const { RefCell::new(vec![]) };
+        }
+
+        #[derive(Default)]
+        struct Context;
+
+        impl Deferrals for Context {
+            fn transferred(&mut self, transfer: Transfer) {
+                TRANSFERS.with(|t| t.borrow_mut().push(transfer));
+            }
+        }
+
+        let get = b"GET /a HTTP/1.1\r\nHost: localhost\r\n\r\n";
+        let post = b"POST /bc HTTP/1.1\r\nContent-Length: 5\r\n\r\n body";
+        let handler = |request: RawRequest, _: &mut Context| echo(request, &mut ());
+        let input = [&get[..], post].concat();
+        let mut server = StreamServer::new(MemoryStream::new(&input), handler);
+        Server::<Context>::serve_forever(&mut server);
+        assert_eq!(
+            TRANSFERS.with(|t| t.take()),
+            vec![
+                Transfer {
+                    bytes_read: get.len(),
+                    bytes_written: response("/a").len(),
+                },
+                Transfer {
+                    bytes_read: post.len(),
+                    bytes_written: response("/bc body").len(),
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn test_serve_deferrals() {
+        use crate::server::{DeferralQueue, Deferred};
+        use std::sync::{Arc, Mutex};
+
+        #[derive(Default)]
+        struct Context {
+            deferred: DeferralQueue,
+        }
+
+        impl Deferrals for Context {
+            fn take_deferrals(&mut self) -> Vec<Deferred> {
+                self.deferred.take_deferrals()
+            }
+        }
+
+        let seen = Arc::new(Mutex::new(vec![]));
+        let handler = {
+            let seen = seen.clone();
+            move |request: RawRequest, context: &mut Context| -> RawResult {
+                for n in 1..=2 {
+                    let seen = seen.clone();
+                    let path = request.path.clone();
+                    context.deferred.defer(move |response| {
+                        let server = response.header("Server").unwrap().to_string();
+                        seen.lock()
+                            .unwrap()
+                            .push((path, n, response.status_code, server));
+                    });
+                }
+                Err(Response::new(404))
+            }
+        };
+        let input = b"GET /a HTTP/1.1\r\n\r\nGET /b HTTP/1.1\r\n\r\n";
+        let mut server = StreamServer::new(MemoryStream::new(input), handler);
+        Server::<Context>::serve_one(&mut server).unwrap();
+        assert!(!server.get_ref().output().is_empty());
+        let server_header = format!("jbhttp::StreamServer/{}", VERSION);
+        assert_eq!(
+            *seen.lock().unwrap(),
+            vec![
+                ("/a".to_string(), 1, 404, server_header.clone()),
+                ("/a".to_string(), 2, 404, server_header.clone()),
+            ]
+        );
+        Server::<Context>::serve_forever(&mut server);
+        assert_eq!(seen.lock().unwrap().len(), 4);
     }
 }
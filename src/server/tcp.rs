@@ -1,30 +1,235 @@
 //! TCP HTTP server.
+use std::io;
 use std::io::prelude::*;
-use std::net::TcpListener;
-use std::sync::Arc;
+use std::net::{SocketAddr, TcpListener, TcpStream};
+use std::sync::{Arc, Mutex};
+use std::thread;
 use std::time::{Duration, Instant};
 
 use log::*;
 
 use crate::{
     handler::Handler,
-    request::parser::RequestParser,
-    response::Response,
+    io::{BufferPool, CountingWriter, TimeoutReader},
+    request::{
+        body::BodyReader,
+        parser::{RequestParser, DEFAULT_MAX_PARAMS},
+        Deadline, HttpVersion, InterimWriter, Method, PeerAddr, UnreadBody,
+    },
+    response::{RawResponse, Response},
     runner::Runner,
-    server::{Server, ServerError},
+    server::{
+        run_deferrals, set_connection_header, spans::Span, Deferrals, LogLevels, Server,
+        ServerError, ServerStats, ShutdownHandle, Transfer,
+    },
     VERSION,
 };
 
+/// How often the listener is polled for connections while waiting for
+/// shutdown.
+const SHUTDOWN_POLL_INTERVAL: Duration = Duration::from_millis(50);
+
+/// How long kept-alive connections wait for the next request, when
+/// [`Timeouts::idle_keepalive`] is not set.
+const DEFAULT_IDLE_KEEPALIVE: Duration = Duration::from_secs(5);
+
+/// A source of connections served by a [`TcpServer`], implemented by
+/// [`TcpListener`]. Other implementations can serve connections from
+/// elsewhere, such as scripted in-memory connections in tests.
+pub trait Accept {
+    type Connection: Connection;
+    /// Wait for the next connection. In non-blocking mode, fail with
+    /// [`io::ErrorKind::WouldBlock`] if there is none waiting.
+    fn accept(&self) -> io::Result<(Self::Connection, SocketAddr)>;
+    fn local_addr(&self) -> io::Result<SocketAddr>;
+    fn set_nonblocking(&self, nonblocking: bool) -> io::Result<()>;
+}
+
+/// A connection accepted by an [`Accept`] implementation.
+pub trait Connection: Read + Write + Send + 'static {
+    /// Set the timeout of each read, none to block indefinitely.
+    fn set_read_timeout(&self, timeout: Option<Duration>) -> io::Result<()>;
+    /// Set the timeout of each write, none to block indefinitely.
+    fn set_write_timeout(&self, timeout: Option<Duration>) -> io::Result<()>;
+    /// Connections accepted in non-blocking mode are switched back to
+    /// blocking mode with this before being served.
+    fn set_nonblocking(&self, nonblocking: bool) -> io::Result<()>;
+    /// Another handle to write to the connection, for handlers to send
+    /// interim responses while the request is handled, see
+    /// [`Request::send_interim`](crate::request::Request::send_interim).
+    /// Unsupported by default.
+    fn try_clone_writer(&self) -> io::Result<Box<dyn Write + Send>> {
+        Err(io::ErrorKind::Unsupported.into())
+    }
+    /// Get another handle to read from the connection, for handlers to read
+    /// request bodies as they arrive, see
+    /// [`TcpServer::with_streamed_multipart`]. Unsupported by default.
+    fn try_clone_reader(&self) -> io::Result<Box<dyn Read + Send>> {
+        Err(io::ErrorKind::Unsupported.into())
+    }
+}
+
+impl Accept for TcpListener {
+    type Connection = TcpStream;
+    fn accept(&self) -> io::Result<(TcpStream, SocketAddr)> {
+        TcpListener::accept(self)
+    }
+    fn local_addr(&self) -> io::Result<SocketAddr> {
+        TcpListener::local_addr(self)
+    }
+    fn set_nonblocking(&self, nonblocking: bool) -> io::Result<()> {
+        TcpListener::set_nonblocking(self, nonblocking)
+    }
+}
+
+impl Connection for TcpStream {
+    fn set_read_timeout(&self, timeout: Option<Duration>) -> io::Result<()> {
+        TcpStream::set_read_timeout(self, timeout)
+    }
+    fn set_write_timeout(&self, timeout: Option<Duration>) -> io::Result<()> {
+        TcpStream::set_write_timeout(self, timeout)
+    }
+    fn set_nonblocking(&self, nonblocking: bool) -> io::Result<()> {
+        TcpStream::set_nonblocking(self, nonblocking)
+    }
+    fn try_clone_writer(&self) -> io::Result<Box<dyn Write + Send>> {
+        Ok(Box::new(self.try_clone()?))
+    }
+    fn try_clone_reader(&self) -> io::Result<Box<dyn Read + Send>> {
+        Ok(Box::new(self.try_clone()?))
+    }
+}
+
+/// Timeouts for each phase of serving a connection, to defend against slow
+/// or idle clients. Each is optional, none by default; the socket blocks
+/// indefinitely for a phase without a timeout.
+///
+/// A single duration converts to the same timeout for every phase.
+///
+/// # Example
+/// ```no_run
+/// use std::time::Duration;
+/// use jbhttp::prelude::*;
+/// use jbhttp::server::{TcpServer, Timeouts};
+///
+/// fn hello(req: RawRequest, _: &mut ()) -> Res<Vec<u8>, Vec<u8>> {
+///     Ok(Response::new(200))
+/// }
+///
+/// let mut server = TcpServer::builder(hello)
+///     .timeout(Timeouts {
+///         header: Some(Duration::from_secs(5)),
+///         body: Some(Duration::from_secs(60)),
+///         ..Duration::from_secs(10).into()
+///     })
+///     .build()
+///     .unwrap();
+/// ```
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct Timeouts {
+    /// Time to wait for a request to start arriving, once connected (and
+    /// between requests on a kept-alive connection). A connection idle for
+    /// longer is closed without a response. Between requests, the wait is
+    /// 5 seconds when not set, so that idle connections don't hold a thread
+    /// forever.
+    pub idle_keepalive: Option<Duration>,
+    /// Time allowed to read the request line and headers, from the first
+    /// byte; slower requests get `408 Request Timeout`.
+    pub header: Option<Duration>,
+    /// Time allowed to read the request body, after the headers; slower
+    /// requests get `408 Request Timeout`.
+    pub body: Option<Duration>,
+    /// Time the handler should take. Handlers can't be interrupted; the
+    /// deadline is given to them with [`Request::deadline`], and the server
+    /// logs a warning when it is overrun. Handlers can override it with
+    /// [`Handler::with_timeout`].
+    ///
+    /// [`Request::deadline`]: crate::request::Request::deadline
+    pub handler: Option<Duration>,
+    /// Time allowed for each write to the socket, including the response.
+    pub write: Option<Duration>,
+}
+
+impl From<Duration> for Timeouts {
+    fn from(timeout: Duration) -> Self {
+        Some(timeout).into()
+    }
+}
+
+/// The same timeout, or none, for every phase.
+impl From<Option<Duration>> for Timeouts {
+    fn from(timeout: Option<Duration>) -> Self {
+        Self {
+            idle_keepalive: timeout,
+            header: timeout,
+            body: timeout,
+            handler: timeout,
+            write: timeout,
+        }
+    }
+}
+
+/// A function to inspect or modify responses right before they are written,
+/// see [`TcpServer::with_response_hook`]. Implemented for closures taking the
+/// response and the request context, and for `()`, which does nothing.
+pub trait ResponseHook<C>: Send + Sync {
+    fn call(&self, response: &mut RawResponse, context: &C);
+}
+
+impl<C, F> ResponseHook<C> for F
+where
+    F: Fn(&mut RawResponse, &C) + Send + Sync,
+{
+    fn call(&self, response: &mut RawResponse, context: &C) {
+        self(response, context)
+    }
+}
+
+impl<C> ResponseHook<C> for () {
+    fn call(&self, _response: &mut RawResponse, _context: &C) {}
+}
+
 /// A single or multi-threaded TCP server.
-pub struct TcpServer<H> {
-    listener: TcpListener,
+///
+/// Each request is logged as a human-readable line, which also carries
+/// structured key-values for loggers supporting them (see the `log` crate's
+/// `kv` module): `method`, `path`, `status`, `bytes_in`, `bytes_out`,
+/// `duration_ms` and `peer`. With the `tracing` feature, each connection is
+/// also served in a `connection` span, with the `peer` address, and each
+/// request in a `request` span within it, with `method`, `path`, `status`
+/// and `duration_ms`, to correlate events; see the `tracing` example.
+///
+/// Connections are kept alive like with a [`StreamServer`], see
+/// [`Request::keep_alive`]: HTTP/1.1 connections until the client or handler
+/// sends *Connection: close*, HTTP/1.0 connections only when the client sends
+/// *Connection: keep-alive*. A kept-alive connection is served by the same
+/// thread, waiting for the next request for up to the `idle_keepalive`
+/// timeout; it is closed once shutdown is requested.
+///
+/// [`StreamServer`]: crate::server::StreamServer
+/// [`Request::keep_alive`]: crate::request::Request::keep_alive
+pub struct TcpServer<H, L = TcpListener, K = ()> {
+    listener: L,
     runner: Runner,
     handler: Arc<H>,
-    timeout: Option<Duration>,
+    timeouts: Timeouts,
+    log_levels: LogLevels,
+    stats: Arc<ServerStats>,
+    semicolon_separator: bool,
+    raw_headers: bool,
+    streamed_multipart: bool,
+    reject_body_on: Vec<Method>,
+    max_params: usize,
+    buffer_pool: bool,
+    shutdown: ShutdownHandle,
+    drain_period: Duration,
+    retry_after: Duration,
+    draining: bool,
+    response_hook: Arc<K>,
 }
 
 impl<H> TcpServer<H> {
-    /// Create a new TCP server
+    /// Create a new TCP server, see [`TcpServerBuilder`] for more options.
     ///
     /// # Arguments
     /// * `bind_addr`: Address to listen on, such as "0.0.0.0:8080"
@@ -32,7 +237,7 @@ impl<H> TcpServer<H> {
     ///   - 0: create a new thread for each request (not recommended)
     ///   - 1: single-threaded
     ///   - 2+: threadpool with n threads
-    /// * `timeout`: network socket timeout
+    /// * `timeout`: timeout for every phase, see [`Timeouts`]
     /// * `handler`: request handler
     pub fn new(
         bind_addr: &str,
@@ -40,84 +245,1260 @@ impl<H> TcpServer<H> {
         timeout: Option<Duration>,
         handler: H,
     ) -> Result<Self, std::io::Error> {
-        Ok(Self {
-            listener: TcpListener::bind(bind_addr)?,
-            runner: Runner::new(n_threads),
-            timeout,
-            handler: Arc::new(handler),
-        })
+        Self::builder(handler)
+            .bind(bind_addr)
+            .threads(n_threads)
+            .timeout(timeout)
+            .build()
+    }
+    /// Create a TCP server on an already bound listener, with the default
+    /// options, see [`TcpServerBuilder::listener`].
+    ///
+    /// # Example
+    /// Under systemd socket activation, the first socket is file descriptor 3:
+    /// ```no_run
+    /// use std::net::{SocketAddr, TcpListener};
+    /// use std::os::unix::io::FromRawFd;
+    /// use jbhttp::prelude::*;
+    /// use jbhttp::server::TcpServer;
+    ///
+    /// fn hello(req: RawRequest, _: &mut ()) -> Res<Vec<u8>, Vec<u8>> {
+    ///     Ok(Response::new(200))
+    /// }
+    ///
+    /// let listener = match std::env::var("LISTEN_FDS").as_deref() {
+    ///     Ok("1") => unsafe { TcpListener::from_raw_fd(3) },
+    ///     _ => TcpListener::bind("127.0.0.1:8080").unwrap(),
+    /// };
+    /// let mut server = TcpServer::from_listener(listener, hello);
+    /// Server::<()>::serve_forever(&mut server);
+    /// ```
+    pub fn from_listener(listener: TcpListener, handler: H) -> Self {
+        Self::builder(handler).build_with(listener)
+    }
+    /// Configure a new TCP server, see [`TcpServerBuilder`].
+    pub fn builder(handler: H) -> TcpServerBuilder<H> {
+        TcpServerBuilder::new(handler)
     }
 }
 
-impl<H, C> Server<C> for TcpServer<H>
+impl<H, L: Accept, K> TcpServer<H, L, K> {
+    /// Get the address the server is listening on.
+    pub fn local_addr(&self) -> Result<SocketAddr, std::io::Error> {
+        self.listener.local_addr()
+    }
+    /// Get a handle to stop
+    /// [`serve_until_shutdown`](TcpServer::serve_until_shutdown) from another
+    /// thread.
+    pub fn shutdown_handle(&self) -> ShutdownHandle {
+        self.shutdown.clone()
+    }
+    /// Get the server's activity counters, see [`ServerStats`].
+    pub fn stats(&self) -> Arc<ServerStats> {
+        self.stats.clone()
+    }
+    /// Also accept `;` as a query parameter separator, see
+    /// [`RequestParser::with_semicolon_separator`].
+    pub fn with_semicolon_separator(mut self, enabled: bool) -> Self {
+        self.semicolon_separator = enabled;
+        self
+    }
+    /// Keep the raw header block of requests, see
+    /// [`RequestParser::with_raw_headers`].
+    pub fn with_raw_headers(mut self, enabled: bool) -> Self {
+        self.raw_headers = enabled;
+        self
+    }
+    /// Leave `multipart/*` request bodies unread, for handlers to read them
+    /// from the connection as they arrive, with [`Request::multipart`] or
+    /// [`Request::body_reader`], instead of buffering them in memory, so
+    /// large uploads can be written to disk part by part. Such requests have
+    /// no payload, and their connection is closed after the response. The
+    /// body timeout applies to each read. Disabled by default.
+    ///
+    /// [`Request::multipart`]: crate::request::Request::multipart
+    /// [`Request::body_reader`]: crate::request::Request::body_reader
+    pub fn with_streamed_multipart(mut self, enabled: bool) -> Self {
+        self.streamed_multipart = enabled;
+        self
+    }
+    /// Set log levels used for request outcomes, see [`LogLevels`].
+    pub fn with_log_levels(mut self, log_levels: LogLevels) -> Self {
+        self.log_levels = log_levels;
+        self
+    }
+    /// Call a function with every response right before it is written,
+    /// after the handler and its filters, and after the server added its own
+    /// headers, like *Server* and *Connection*, and removed the body of
+    /// responses to HEAD requests. Unlike filters, it sees the final response,
+    /// including responses the server makes itself, like 400s for requests
+    /// that can't be parsed; aborted responses are not written, and not
+    /// passed to the hook.
+    ///
+    /// The hook gets the request context, so the server can only be served
+    /// with the context type of the hook.
+    ///
+    /// # Example
+    /// ```no_run
+    /// use jbhttp::prelude::*;
+    /// use jbhttp::server::TcpServer;
+    ///
+    /// fn hello(req: RawRequest, _: &mut ()) -> Res<Vec<u8>, Vec<u8>> {
+    ///     Ok(Response::new(200).with_header("X-Debug", "secret"))
+    /// }
+    ///
+    /// let mut server = TcpServer::new("127.0.0.1:8080", 4, None, hello)
+    ///     .unwrap()
+    ///     .with_response_hook(|response: &mut RawResponse, _: &()| {
+    ///         response.set_header("X-Debug", "");
+    ///         response.set_header("Server", "jbhttp");
+    ///     });
+    /// Server::<()>::serve_forever(&mut server);
+    /// ```
+    pub fn with_response_hook<C, F>(self, hook: F) -> TcpServer<H, L, F>
+    where
+        F: Fn(&mut RawResponse, &C) + Send + Sync,
+    {
+        TcpServer {
+            listener: self.listener,
+            runner: self.runner,
+            handler: self.handler,
+            timeouts: self.timeouts,
+            log_levels: self.log_levels,
+            stats: self.stats,
+            semicolon_separator: self.semicolon_separator,
+            raw_headers: self.raw_headers,
+            streamed_multipart: self.streamed_multipart,
+            reject_body_on: self.reject_body_on,
+            max_params: self.max_params,
+            buffer_pool: self.buffer_pool,
+            shutdown: self.shutdown,
+            drain_period: self.drain_period,
+            retry_after: self.retry_after,
+            draining: self.draining,
+            response_hook: Arc::new(hook),
+        }
+    }
+}
+
+/// Configure and create a [`TcpServer`].
+///
+/// By default, the server listens on 127.0.0.1:8080 with a single thread and
+/// no socket timeout.
+///
+/// # Example
+/// ```no_run
+/// use std::time::Duration;
+/// use jbhttp::prelude::*;
+/// use jbhttp::server::TcpServer;
+///
+/// fn hello(req: RawRequest, _: &mut ()) -> Res<Vec<u8>, Vec<u8>> {
+///     Ok(Response::new(200).with_payload(b"Hello!".to_vec()))
+/// }
+///
+/// let mut server = TcpServer::builder(hello)
+///     .bind("0.0.0.0:8080")
+///     .threads(4)
+///     .timeout(Some(Duration::from_secs(30)))
+///     .build()
+///     .unwrap();
+/// Server::<()>::serve_forever(&mut server);
+/// ```
+pub struct TcpServerBuilder<H> {
+    handler: H,
+    bind_addr: String,
+    listener: Option<TcpListener>,
+    n_threads: usize,
+    timeouts: Timeouts,
+    log_levels: LogLevels,
+    semicolon_separator: bool,
+    raw_headers: bool,
+    streamed_multipart: bool,
+    reject_body_on: Vec<Method>,
+    max_params: usize,
+    buffer_pool: bool,
+    drain_period: Duration,
+    retry_after: Duration,
+}
+
+impl<H> TcpServerBuilder<H> {
+    pub fn new(handler: H) -> Self {
+        Self {
+            handler,
+            bind_addr: "127.0.0.1:8080".to_string(),
+            listener: None,
+            n_threads: 1,
+            timeouts: Timeouts::default(),
+            log_levels: LogLevels::default(),
+            semicolon_separator: false,
+            raw_headers: false,
+            streamed_multipart: false,
+            reject_body_on: vec![],
+            max_params: DEFAULT_MAX_PARAMS,
+            buffer_pool: false,
+            drain_period: Duration::ZERO,
+            retry_after: Duration::from_secs(5),
+        }
+    }
+    /// Address to listen on, such as "0.0.0.0:8080".
+    pub fn bind(mut self, bind_addr: &str) -> Self {
+        self.bind_addr = bind_addr.to_string();
+        self
+    }
+    /// Serve on an already bound listener, instead of binding the address
+    /// set with `bind`. This allows binding before dropping privileges, or
+    /// getting the listener from systemd socket activation.
+    pub fn listener(mut self, listener: TcpListener) -> Self {
+        self.listener = Some(listener);
+        self
+    }
+    /// Number of threads.
+    ///   - 0: create a new thread for each request (not recommended)
+    ///   - 1: single-threaded
+    ///   - 2+: threadpool with n threads
+    pub fn threads(mut self, n_threads: usize) -> Self {
+        self.n_threads = n_threads;
+        self
+    }
+    /// Timeouts for each phase of serving a connection, see [`Timeouts`];
+    /// a duration (or `Option<Duration>`) applies to every phase.
+    pub fn timeout<T: Into<Timeouts>>(mut self, timeouts: T) -> Self {
+        self.timeouts = timeouts.into();
+        self
+    }
+    /// Log levels used for request outcomes, see [`LogLevels`].
+    pub fn log_levels(mut self, log_levels: LogLevels) -> Self {
+        self.log_levels = log_levels;
+        self
+    }
+    /// Also accept `;` as a query parameter separator, see
+    /// [`RequestParser::with_semicolon_separator`].
+    pub fn semicolon_separator(mut self, enabled: bool) -> Self {
+        self.semicolon_separator = enabled;
+        self
+    }
+    /// Keep the raw header block of requests, see
+    /// [`RequestParser::with_raw_headers`].
+    pub fn raw_headers(mut self, enabled: bool) -> Self {
+        self.raw_headers = enabled;
+        self
+    }
+    /// Leave multipart request bodies unread, see
+    /// [`TcpServer::with_streamed_multipart`].
+    pub fn streamed_multipart(mut self, enabled: bool) -> Self {
+        self.streamed_multipart = enabled;
+        self
+    }
+    /// Reject requests with a body for these methods, see
+    /// [`RequestParser::reject_body_on`].
+    pub fn reject_body_on(mut self, methods: &[Method]) -> Self {
+        self.reject_body_on = methods.to_vec();
+        self
+    }
+    /// Maximum number of request parameters, see
+    /// [`RequestParser::with_max_params`].
+    pub fn max_params(mut self, max_params: usize) -> Self {
+        self.max_params = max_params;
+        self
+    }
+    /// Serialize responses into buffers reused across requests handled by
+    /// the same worker thread, see [`BufferPool`], instead of allocating a
+    /// new one for each response. Disabled by default. The request parser
+    /// reads into a fixed-size array, which is not allocated, so it needs
+    /// no pooling; see the `buffer_pool` example to count allocations.
+    pub fn buffer_pool(mut self, enabled: bool) -> Self {
+        self.buffer_pool = enabled;
+        self
+    }
+    /// After shutdown is requested, keep accepting connections for this
+    /// long, answering `503 Service Unavailable` with a *Retry-After* header
+    /// instead of handling requests, so load balancers route them elsewhere.
+    /// Defaults to 0, closing the listener right away.
+    pub fn drain_period(mut self, period: Duration) -> Self {
+        self.drain_period = period;
+        self
+    }
+    /// *Retry-After* sent while draining, see `drain_period`. Defaults to 5
+    /// seconds.
+    pub fn retry_after(mut self, retry_after: Duration) -> Self {
+        self.retry_after = retry_after;
+        self
+    }
+    /// Bind the listening socket, unless a listener was given, and create
+    /// the server.
+    pub fn build(mut self) -> Result<TcpServer<H>, std::io::Error> {
+        let listener = match self.listener.take() {
+            Some(listener) => listener,
+            None => TcpListener::bind(&self.bind_addr)?,
+        };
+        Ok(self.build_with(listener))
+    }
+    /// Create the server on another source of connections than a
+    /// [`TcpListener`], see [`Accept`]. The `bind` address and `listener`
+    /// are ignored.
+    pub fn build_with<L: Accept>(self, listener: L) -> TcpServer<H, L> {
+        TcpServer {
+            listener,
+            runner: Runner::new(self.n_threads),
+            timeouts: self.timeouts,
+            handler: Arc::new(self.handler),
+            log_levels: self.log_levels,
+            stats: Arc::new(ServerStats::new()),
+            semicolon_separator: self.semicolon_separator,
+            raw_headers: self.raw_headers,
+            streamed_multipart: self.streamed_multipart,
+            reject_body_on: self.reject_body_on,
+            max_params: self.max_params,
+            buffer_pool: self.buffer_pool,
+            shutdown: ShutdownHandle::new(),
+            drain_period: self.drain_period,
+            retry_after: self.retry_after,
+            draining: false,
+            response_hook: Arc::new(()),
+        }
+    }
+}
+
+impl<H, L, K, C> Server<C> for TcpServer<H, L, K>
 where
-    C: std::fmt::Debug + Default,
+    L: Accept,
+    K: 'static + ResponseHook<C>,
+    C: 'static + std::fmt::Debug + Default + Deferrals,
     H: 'static + Handler<Vec<u8>, Vec<u8>, Vec<u8>, C>,
 {
-    /// Serve one request.
+    /// Accept one connection, and serve the requests on it.
     fn serve_one(&mut self) -> Result<(), ServerError> {
-        // TODO: keep-alive
-        let (mut stream, addr) = self.listener.accept()?;
+        let (stream, addr) = self.listener.accept()?;
+        self.serve_connection::<C>(stream, addr);
+        Ok(())
+    }
+}
+
+impl<H, L: Accept, K> TcpServer<H, L, K> {
+    /// Serve requests until shutdown is requested with a [`ShutdownHandle`],
+    /// then wait for requests in progress to finish. With a
+    /// [drain period](TcpServerBuilder::drain_period), new requests get 503
+    /// responses for a while before the listener is closed.
+    ///
+    /// The listener is polled for new connections, so shutdown is noticed
+    /// within 50ms. Once shutdown was requested, the server stops again
+    /// immediately if served with this method.
+    ///
+    /// # Example
+    /// ```no_run
+    /// use jbhttp::prelude::*;
+    /// use jbhttp::server::TcpServer;
+    ///
+    /// fn hello(req: RawRequest, _: &mut ()) -> Res<Vec<u8>, Vec<u8>> {
+    ///     Ok(Response::new(200))
+    /// }
+    ///
+    /// let mut server = TcpServer::new("127.0.0.1:8080", 4, None, hello).unwrap();
+    /// let shutdown = server.shutdown_handle();
+    /// std::thread::spawn(move || {
+    ///     std::thread::sleep(std::time::Duration::from_secs(60));
+    ///     shutdown.shutdown();
+    /// });
+    /// server.serve_until_shutdown::<()>().unwrap();
+    /// ```
+    pub fn serve_until_shutdown<C>(&mut self) -> Result<(), ServerError>
+    where
+        K: 'static + ResponseHook<C>,
+        C: 'static + std::fmt::Debug + Default + Deferrals,
+        H: 'static + Handler<Vec<u8>, Vec<u8>, Vec<u8>, C>,
+    {
+        let shutdown = self.shutdown.clone();
+        self.serve_until::<C, _>(|| shutdown.is_shutdown())
+    }
+    /// Like [`serve_until_shutdown`](TcpServer::serve_until_shutdown), also
+    /// shutting down when the process receives SIGINT or SIGTERM.
+    ///
+    /// Limitations: signal handlers are process-wide, so they replace any
+    /// other SIGINT/SIGTERM handlers while serving (the previous ones are
+    /// restored on return), and only one server should be served this way at
+    /// a time. A second signal does not force the server to stop; requests in
+    /// progress, or stuck until the socket timeout, are still waited for.
+    #[cfg(unix)]
+    pub fn serve_forever_until_signal<C>(&mut self) -> Result<(), ServerError>
+    where
+        K: 'static + ResponseHook<C>,
+        C: 'static + std::fmt::Debug + Default + Deferrals,
+        H: 'static + Handler<Vec<u8>, Vec<u8>, Vec<u8>, C>,
+    {
+        let signals = super::signal::SignalGuard::install()?;
+        let shutdown = self.shutdown.clone();
+        self.serve_until::<C, _>(|| signals.received() || shutdown.is_shutdown())
+    }
+    fn serve_until<C, F>(&mut self, stop: F) -> Result<(), ServerError>
+    where
+        K: 'static + ResponseHook<C>,
+        C: 'static + std::fmt::Debug + Default + Deferrals,
+        H: 'static + Handler<Vec<u8>, Vec<u8>, Vec<u8>, C>,
+        F: Fn() -> bool,
+    {
+        self.listener.set_nonblocking(true)?;
+        while !stop() {
+            self.poll_connection::<C>();
+        }
+        if self.drain_period > Duration::ZERO {
+            info!("shutting down, draining for {:?}", self.drain_period);
+            let deadline = Instant::now() + self.drain_period;
+            self.draining = true;
+            while Instant::now() < deadline {
+                self.poll_connection::<C>();
+            }
+            self.draining = false;
+        }
+        info!("shutting down, waiting for requests in progress");
+        self.listener.set_nonblocking(false)?;
+        self.runner.join();
+        Ok(())
+    }
+    /// Serve a connection if there is one waiting on the non-blocking
+    /// listener, otherwise sleep for the poll interval.
+    fn poll_connection<C>(&mut self)
+    where
+        K: 'static + ResponseHook<C>,
+        C: 'static + std::fmt::Debug + Default + Deferrals,
+        H: 'static + Handler<Vec<u8>, Vec<u8>, Vec<u8>, C>,
+    {
+        match self.listener.accept() {
+            Ok((stream, addr)) => match stream.set_nonblocking(false) {
+                Ok(()) => self.serve_connection::<C>(stream, addr),
+                Err(e) => error!("{}", ServerError::from(e)),
+            },
+            Err(e) if e.kind() == io::ErrorKind::WouldBlock => {
+                thread::sleep(SHUTDOWN_POLL_INTERVAL)
+            }
+            Err(e) => error!("{}", ServerError::from(e)),
+        }
+    }
+    fn serve_connection<C>(&mut self, mut stream: L::Connection, addr: SocketAddr)
+    where
+        K: 'static + ResponseHook<C>,
+        C: 'static + std::fmt::Debug + Default + Deferrals,
+        H: 'static + Handler<Vec<u8>, Vec<u8>, Vec<u8>, C>,
+    {
         debug!("accepted connection from {:?}", addr);
-        stream.set_read_timeout(self.timeout).unwrap();
-        stream.set_write_timeout(self.timeout).unwrap();
+        self.stats.connection_accepted();
+        let timeouts = self.timeouts;
+        stream.set_write_timeout(timeouts.write).unwrap();
         let handler = self.handler.clone();
+        let log_levels = self.log_levels;
+        let stats = self.stats.clone();
+        let semicolon_separator = self.semicolon_separator;
+        let raw_headers = self.raw_headers;
+        let streamed_multipart = self.streamed_multipart;
+        let reject_body_on = self.reject_body_on.clone();
+        let max_params = self.max_params;
+        let buffer_pool = self.buffer_pool;
+        let shutdown = self.shutdown.clone();
+        let response_hook = self.response_hook.clone();
+        let retry_after = if self.draining {
+            Some(self.retry_after.as_secs().to_string())
+        } else {
+            None
+        };
+        let connection_span = Span::connection(addr);
         self.runner.run(move || {
-            let start = Instant::now();
-            let mut context = C::default();
-            trace!("CONTEXT {:?}", &context);
-            debug!("parsing request");
-            let mut parser = RequestParser::new(&mut stream);
-            let response;
-            let path;
-            let method;
-            let content_length;
-            match parser.parse() {
-                Ok(request) => {
-                    debug!("done parsing request");
-                    trace!("REQUEST {:?}", &request);
-                    content_length = request.content_length;
-                    path = request.path.clone();
-                    method = format!("{:?}", request.method);
-                    debug!("running request handler");
-                    response = handler.handle(request, &mut context);
+            let _connection = connection_span.enter();
+            // The socket timeout applies to each read; the reader also
+            // applies it as a deadline for the whole phase.
+            let phase = |reader: &mut TimeoutReader<&mut L::Connection>,
+                         timeout: Option<Duration>| {
+                reader.set_timeout(timeout.unwrap_or(Duration::MAX));
+                reader.get_ref().set_read_timeout(timeout)
+            };
+            let interim_writer = stream.try_clone_writer().ok().map(InterimWriter::new);
+            // Bodies are only left unread if they can be read by handlers;
+            // the connection is closed after one
+            let mut body_stream = if streamed_multipart {
+                stream.try_clone_reader().ok()
+            } else {
+                None
+            };
+            // The parser keeps its read buffer between requests, so that
+            // pipelined requests are not lost
+            let mut parser = RequestParser::new(TimeoutReader::new(&mut stream, Duration::MAX))
+                .with_semicolon_separator(semicolon_separator)
+                .with_raw_headers(raw_headers)
+                .with_streamed_multipart(body_stream.is_some())
+                .reject_body_on(&reject_body_on)
+                .with_max_params(max_params);
+            let mut kept_alive = false;
+            loop {
+                let idle = match timeouts.idle_keepalive {
+                    None if kept_alive => Some(DEFAULT_IDLE_KEEPALIVE),
+                    idle => idle,
+                };
+                phase(parser.get_mut(), idle).unwrap();
+                if kept_alive {
+                    // Poll while waiting for the next request, so that idle
+                    // connections don't hold up shutdown
+                    let poll =
+                        idle.map_or(SHUTDOWN_POLL_INTERVAL, |t| t.min(SHUTDOWN_POLL_INTERVAL));
+                    parser
+                        .get_ref()
+                        .get_ref()
+                        .set_read_timeout(Some(poll))
+                        .unwrap();
                 }
-                Err(e) => {
-                    error!("{}", e);
-                    response = Err(Response::new(400));
-                    path = "<none>".to_string();
-                    method = "<none>".to_string();
-                    content_length = 0;
+                let idle_deadline = idle.and_then(|timeout| Instant::now().checked_add(timeout));
+                let eof = loop {
+                    match parser.is_eof() {
+                        Ok(eof) => break eof,
+                        Err(e)
+                            if kept_alive
+                                && e.is_timeout()
+                                && !shutdown.is_shutdown()
+                                && idle_deadline.is_none_or(|d| Instant::now() < d) => {}
+                        Err(e) => {
+                            debug!("closing idle connection: {}", e);
+                            return;
+                        }
+                    }
+                };
+                if eof && kept_alive {
+                    debug!("connection closed by the client");
+                    return;
+                } else if eof {
+                    debug!("connection closed without a request");
+                    return;
                 }
+                let request_span = Span::request();
+                let _request = request_span.enter();
+                let _active = stats.request_started();
+                let start = Instant::now();
+                let mut context = C::default();
+                trace!("CONTEXT {:?}", &context);
+                phase(parser.get_mut(), timeouts.header).unwrap();
+                debug!("parsing request");
+                let response;
+                let path;
+                let method;
+                let content_length;
+                let mut is_head = false;
+                let mut version = HttpVersion::Http11;
+                let mut keep_alive = false;
+                let mut transfer = Transfer::default();
+                let parsed = parser.parse_with_continue_then(|reader| {
+                    if let Err(e) = phase(reader, timeouts.body) {
+                        error!("{}", e);
+                    }
+                });
+                match parsed {
+                    Ok(mut request) => {
+                        debug!("done parsing request");
+                        request.extensions.insert(PeerAddr(addr));
+                        if let Some(interim_writer) = &interim_writer {
+                            request.extensions.insert(interim_writer.clone());
+                        }
+                        trace!("REQUEST {:?}", &request);
+                        transfer.bytes_read = request.bytes_read().unwrap_or(0);
+                        content_length = request.content_length;
+                        path = request.path.clone();
+                        method = format!("{:?}", request.method);
+                        is_head = request.method == Method::HEAD;
+                        version = request.version;
+                        keep_alive = request.keep_alive();
+                        if let Some((framing, buffered)) = parser.take_unread_body() {
+                            let stream =
+                                io::Cursor::new(buffered).chain(body_stream.take().unwrap());
+                            let body = BodyReader::new(io::BufReader::new(stream), framing);
+                            request
+                                .extensions
+                                .insert(UnreadBody(Mutex::new(Some(Box::new(body)))));
+                            keep_alive = false;
+                        }
+                        response = match &retry_after {
+                            Some(retry_after) => {
+                                debug!("draining, not handling request");
+                                keep_alive = false;
+                                Err(Response::new(503).with_header("Retry-After", retry_after))
+                            }
+                            None => {
+                                debug!("running request handler");
+                                let deadline = timeouts
+                                    .handler
+                                    .and_then(|timeout| Instant::now().checked_add(timeout));
+                                if let Some(deadline) = deadline {
+                                    request.extensions.insert(Deadline(deadline));
+                                }
+                                let response = handler.handle(request, &mut context);
+                                if deadline.is_some_and(|deadline| Instant::now() > deadline) {
+                                    warn!("{} {} - handler overran its timeout", method, path);
+                                }
+                                response
+                            }
+                        };
+                    }
+                    Err(e) => {
+                        // Where the next request starts is unknown, so no
+                        // more requests can be read
+                        log!(log_levels.client_error, "{}", e);
+                        let status_code = if e.is_timeout() {
+                            408
+                        } else if e.is_expectation_failed() {
+                            417
+                        } else {
+                            400
+                        };
+                        response = Err(Response::new(status_code));
+                        path = "<none>".to_string();
+                        method = "<none>".to_string();
+                        content_length = 0;
+                    }
+                };
+                request_span.record_request(&method, &path);
+                let (variant, response) = match response {
+                    Ok(response) => ("Ok".to_string(), response),
+                    Err(response) => ("Err".to_string(), response),
+                };
+                if response.is_aborted() {
+                    log!(log_levels.server_error, "{} {} - aborted", method, path);
+                    run_deferrals(&mut context, &response, transfer);
+                    return;
+                }
+                let mut response = response
+                    .with_header("Server", &format!("jbhttp::TcpServer/{}", VERSION))
+                    .with_version(version);
+                // Kept-alive connections are closed once shutdown is requested
+                let mut closed =
+                    set_connection_header(&mut response, keep_alive && !shutdown.is_shutdown());
+                if is_head {
+                    response = response.without_body();
+                }
+                response_hook.call(&mut response, &context);
+                closed = closed || response.closes_connection();
+                trace!("CONTEXT: {:?}", &context);
+                trace!("RESPONSE: {:?}", &response);
+                let duration_ms = start.elapsed().as_millis() as u64;
+                request_span.record_response(response.status_code, duration_ms);
+                // Streamed bodies are counted as written, see Deferrals::transferred
+                let bytes_out = response.content_length().unwrap_or(0);
+                log!(
+                    log_levels.for_status(response.status_code),
+                    method = method.as_str(),
+                    path = path.as_str(),
+                    status = response.status_code,
+                    bytes_in = content_length,
+                    bytes_out = bytes_out,
+                    duration_ms = duration_ms,
+                    peer:% = addr;
+                    "{:?} - {}ms - {} {} {} ({} bytes) -> {} {} {} ({} bytes)",
+                    std::thread::current().id(),
+                    duration_ms,
+                    addr,
+                    method,
+                    path,
+                    content_length,
+                    variant,
+                    response.status_code,
+                    &response.status,
+                    bytes_out,
+                );
+                debug!("writing response");
+                let mut stream = CountingWriter::new(parser.get_mut().get_mut());
+                let written = if buffer_pool {
+                    let mut buffer = BufferPool::take();
+                    let written = response.write_to_buffered(&mut stream, &mut buffer);
+                    BufferPool::give(buffer);
+                    written
+                } else {
+                    response.write_to(&mut stream)
+                };
+                if let Err(e) = &written {
+                    log!(log_levels.server_error, "IO error: {}", e);
+                }
+                transfer.bytes_written = stream.count();
+                run_deferrals(&mut context, &response, transfer);
+                // A streamed body may have been cut short
+                if closed || written.is_err() {
+                    return;
+                }
+                kept_alive = true;
+            }
+        });
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::handler::RawResult;
+    use crate::request::RawRequest;
+    use std::collections::VecDeque;
+    use std::sync::Mutex;
+
+    fn hello(_: RawRequest, _: &mut ()) -> RawResult {
+        Ok(Response::new(200).with_payload(b"Hello!".to_vec()))
+    }
+
+    fn get(addr: SocketAddr) -> String {
+        let mut stream = TcpStream::connect(addr).unwrap();
+        stream
+            .write_all(b"GET / HTTP/1.1\r\nConnection: close\r\n\r\n")
+            .unwrap();
+        let mut response = String::new();
+        stream.read_to_string(&mut response).unwrap();
+        response
+    }
+
+    #[test]
+    fn test_from_listener() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let mut server = TcpServer::from_listener(listener, hello);
+        let addr = server.local_addr().unwrap();
+        let client = std::thread::spawn(move || get(addr));
+        Server::<()>::serve_one(&mut server).unwrap();
+        let response = client.join().unwrap();
+        assert!(response.starts_with("HTTP/1.1 200 OK\r\n"));
+        assert!(response.ends_with("\r\n\r\nHello!"));
+    }
+
+    #[test]
+    fn test_keep_alive() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let mut server = TcpServer::from_listener(listener, hello);
+        let addr = server.local_addr().unwrap();
+        let client = std::thread::spawn(move || {
+            let mut stream = TcpStream::connect(addr).unwrap();
+            stream.write_all(b"GET / HTTP/1.1\r\n\r\n").unwrap();
+            let mut first = vec![];
+            while !first.ends_with(b"Hello!") {
+                let mut buf = [0; 512];
+                let n = stream.read(&mut buf).unwrap();
+                first.extend(&buf[..n]);
+            }
+            let first = String::from_utf8(first).unwrap();
+            // Pipelined
+            stream
+                .write_all(b"GET / HTTP/1.1\r\n\r\nGET / HTTP/1.1\r\nConnection: close\r\n\r\n")
+                .unwrap();
+            let mut rest = String::new();
+            stream.read_to_string(&mut rest).unwrap();
+            (first, rest)
+        });
+        Server::<()>::serve_one(&mut server).unwrap();
+        let (first, rest) = client.join().unwrap();
+        assert!(first.contains("\r\nConnection: keep-alive\r\n"));
+        assert!(first.ends_with("\r\n\r\nHello!"));
+        assert_eq!(rest.matches("HTTP/1.1 200 OK\r\n").count(), 2);
+        assert!(rest.ends_with("\r\nConnection: close\r\nContent-Length: 6\r\n\r\nHello!"));
+        assert_eq!(server.stats().connections(), 1);
+        assert_eq!(server.stats().requests(), 3);
+    }
+
+    #[test]
+    fn test_http10_keep_alive() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let mut server = TcpServer::from_listener(listener, hello);
+        let addr = server.local_addr().unwrap();
+        let client = std::thread::spawn(move || {
+            // Closed after the response
+            let mut stream = TcpStream::connect(addr).unwrap();
+            stream.write_all(b"GET / HTTP/1.0\r\n\r\n").unwrap();
+            let mut closed = String::new();
+            stream.read_to_string(&mut closed).unwrap();
+            // Kept alive only when asked
+            let mut stream = TcpStream::connect(addr).unwrap();
+            stream
+                .write_all(
+                    b"GET / HTTP/1.0\r\nConnection: keep-alive\r\n\r\nGET / HTTP/1.0\r\n\r\n",
+                )
+                .unwrap();
+            let mut kept_alive = String::new();
+            stream.read_to_string(&mut kept_alive).unwrap();
+            (closed, kept_alive)
+        });
+        Server::<()>::serve_one(&mut server).unwrap();
+        Server::<()>::serve_one(&mut server).unwrap();
+        let (closed, kept_alive) = client.join().unwrap();
+        assert!(closed.starts_with("HTTP/1.0 200 OK\r\n"));
+        assert!(closed.contains("\r\nConnection: close\r\n"));
+        assert_eq!(kept_alive.matches("HTTP/1.0 200 OK\r\n").count(), 2);
+        let (first, second) = kept_alive.split_at(kept_alive.rfind("HTTP/1.0").unwrap());
+        assert!(first.contains("\r\nConnection: keep-alive\r\n"));
+        assert!(second.contains("\r\nConnection: close\r\n"));
+        assert_eq!(server.stats().requests(), 3);
+    }
+
+    #[test]
+    fn test_send_interim() {
+        // The handler finishes once the client got the hints
+        let (received, wait_received) = std::sync::mpsc::channel();
+        let wait_received = Mutex::new(wait_received);
+        let page = move |request: RawRequest, _: &mut ()| -> RawResult {
+            let hints = Response::new(103).with_header("Link", "</style.css>; rel=preload");
+            request.send_interim(&hints).unwrap();
+            wait_received.lock().unwrap().recv().unwrap();
+            Ok(Response::new(200).with_payload(b"page".to_vec()))
+        };
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let mut server = TcpServer::from_listener(listener, page);
+        let addr = server.local_addr().unwrap();
+        let client = std::thread::spawn(move || {
+            let mut stream = TcpStream::connect(addr).unwrap();
+            stream
+                .write_all(b"GET / HTTP/1.1\r\nConnection: close\r\n\r\n")
+                .unwrap();
+            let mut hints = vec![];
+            while !hints.ends_with(b"\r\n\r\n") {
+                let mut byte = [0];
+                stream.read_exact(&mut byte).unwrap();
+                hints.push(byte[0]);
+            }
+            received.send(()).unwrap();
+            let mut response = String::new();
+            stream.read_to_string(&mut response).unwrap();
+            (String::from_utf8(hints).unwrap(), response)
+        });
+        Server::<()>::serve_one(&mut server).unwrap();
+        let (hints, response) = client.join().unwrap();
+        assert_eq!(
+            hints,
+            "HTTP/1.1 103 Early Hints\r\nLink: </style.css>; rel=preload\r\n\r\n"
+        );
+        assert!(response.starts_with("HTTP/1.1 200 OK\r\n"));
+        assert!(response.ends_with("\r\n\r\npage"));
+        // No writer, as when served by a StreamServer
+        let request = RawRequest::default();
+        let error = request.send_interim(&Response::new(103)).unwrap_err();
+        assert_eq!(error.kind(), io::ErrorKind::Unsupported);
+    }
+
+    #[test]
+    fn test_streamed_multipart() {
+        // The client sends the file once the handler got to it
+        let (first_read, wait_first_read) = std::sync::mpsc::channel();
+        let first_read = Mutex::new(first_read);
+        let upload = move |request: RawRequest, _: &mut ()| -> RawResult {
+            assert_eq!(request.payload, None);
+            let mut multipart = request.multipart().unwrap();
+            let mut first = multipart.next_part().unwrap().unwrap();
+            let mut value = String::new();
+            first.read_to_string(&mut value).unwrap();
+            let mut file = multipart.next_part().unwrap().unwrap();
+            let _ = first_read.lock().unwrap().send(());
+            let size = io::copy(&mut file, &mut io::sink()).unwrap();
+            assert!(multipart.next_part().unwrap().is_none());
+            Ok(Response::new(200).with_payload(format!("{} {}", value, size).into_bytes()))
+        };
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let mut server = TcpServer::from_listener(listener, upload).with_streamed_multipart(true);
+        let addr = server.local_addr().unwrap();
+        let first = b"--xyz\r\n\
+            Content-Disposition: form-data; name=\"field\"\r\n\r\n\
+            value\r\n\
+            --xyz\r\n\
+            Content-Disposition: form-data; name=\"file\"; filename=\"a.bin\"\r\n\r\n"
+            .to_vec();
+        let mut rest = vec![b'x'; 1 << 20];
+        rest.extend(b"\r\n--xyz--\r\n");
+        let client = std::thread::spawn(move || {
+            let mut stream = TcpStream::connect(addr).unwrap();
+            write!(
+                stream,
+                "POST /upload HTTP/1.1\r\n\
+                 Content-Type: multipart/form-data; boundary=xyz\r\n\
+                 Content-Length: {}\r\n\r\n",
+                first.len() + rest.len()
+            )
+            .unwrap();
+            stream.write_all(&first).unwrap();
+            wait_first_read.recv().unwrap();
+            stream.write_all(&rest).unwrap();
+            let mut response = String::new();
+            stream.read_to_string(&mut response).unwrap();
+
+            // Chunked, followed by a request which is not read
+            let mut stream = TcpStream::connect(addr).unwrap();
+            stream
+                .write_all(
+                    b"POST /upload HTTP/1.1\r\n\
+                      Content-Type: multipart/form-data; boundary=xyz\r\n\
+                      Transfer-Encoding: chunked\r\n\r\n",
+                )
+                .unwrap();
+            for chunk in [&first, &rest] {
+                write!(stream, "{:x}\r\n", chunk.len()).unwrap();
+                stream.write_all(chunk).unwrap();
+                stream.write_all(b"\r\n").unwrap();
+            }
+            stream
+                .write_all(b"0\r\n\r\nGET / HTTP/1.1\r\n\r\n")
+                .unwrap();
+            let mut chunked_response = String::new();
+            stream.read_to_string(&mut chunked_response).unwrap();
+            (response, chunked_response)
+        });
+        Server::<()>::serve_one(&mut server).unwrap();
+        Server::<()>::serve_one(&mut server).unwrap();
+        let (response, chunked_response) = client.join().unwrap();
+        for response in [response, chunked_response] {
+            assert!(response.starts_with("HTTP/1.1 200 OK\r\n"));
+            assert!(response.contains("\r\nConnection: close\r\n"));
+            assert!(response.ends_with("\r\n\r\nvalue 1048576"));
+        }
+    }
+
+    /// Span names and fields.
+    #[cfg(feature = "tracing")]
+    type Spans = Vec<(String, Vec<String>)>;
+
+    /// Records spans.
+    #[cfg(feature = "tracing")]
+    #[derive(Clone, Default)]
+    struct SpanRecorder(Arc<Mutex<Spans>>);
+
+    #[cfg(feature = "tracing")]
+    struct Fields<'a>(&'a mut Vec<String>);
+
+    #[cfg(feature = "tracing")]
+    impl tracing::field::Visit for Fields<'_> {
+        fn record_debug(&mut self, field: &tracing::field::Field, value: &dyn std::fmt::Debug) {
+            self.0.push(format!("{}={:?}", field.name(), value));
+        }
+    }
+
+    #[cfg(feature = "tracing")]
+    impl tracing::Subscriber for SpanRecorder {
+        fn enabled(&self, _: &tracing::Metadata<'_>) -> bool {
+            true
+        }
+        fn new_span(&self, attrs: &tracing::span::Attributes<'_>) -> tracing::span::Id {
+            let mut fields = vec![];
+            attrs.record(&mut Fields(&mut fields));
+            let mut spans = self.0.lock().unwrap();
+            spans.push((attrs.metadata().name().to_string(), fields));
+            tracing::span::Id::from_u64(spans.len() as u64)
+        }
+        fn record(&self, id: &tracing::span::Id, values: &tracing::span::Record<'_>) {
+            let mut spans = self.0.lock().unwrap();
+            values.record(&mut Fields(&mut spans[id.into_u64() as usize - 1].1));
+        }
+        fn record_follows_from(&self, _: &tracing::span::Id, _: &tracing::span::Id) {}
+        fn event(&self, _: &tracing::Event<'_>) {}
+        fn enter(&self, _: &tracing::span::Id) {}
+        fn exit(&self, _: &tracing::span::Id) {}
+    }
+
+    #[cfg(feature = "tracing")]
+    #[test]
+    fn test_tracing_spans() {
+        let recorder = SpanRecorder::default();
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let mut server = TcpServer::from_listener(listener, hello);
+        let addr = server.local_addr().unwrap();
+        let client = std::thread::spawn(move || get(addr));
+        // Single-threaded, the connection is served on this thread
+        tracing::subscriber::with_default(recorder.clone(), || {
+            Server::<()>::serve_one(&mut server).unwrap();
+        });
+        client.join().unwrap();
+        let spans = recorder.0.lock().unwrap();
+        assert_eq!(spans.len(), 2);
+        let (name, fields) = &spans[0];
+        assert_eq!(name, "connection");
+        assert!(fields[0].starts_with("peer=127.0.0.1:"));
+        let (name, fields) = &spans[1];
+        assert_eq!(name, "request");
+        assert_eq!(fields[..3], ["method=\"GET\"", "path=\"/\"", "status=200"]);
+        assert!(fields[3].starts_with("duration_ms="));
+    }
+
+    #[test]
+    fn test_response_hook() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let mut server = TcpServer::from_listener(listener, hello).with_response_hook(
+            |response: &mut RawResponse, _: &()| {
+                let server = format!("{} (hooked)", response.header("Server").unwrap());
+                response.set_header("Server", &server);
+                response.set_header("X-Nonce", &response.status_code.to_string());
+            },
+        );
+        let addr = server.local_addr().unwrap();
+        let client = std::thread::spawn(move || {
+            let ok = get(addr);
+            let mut stream = TcpStream::connect(addr).unwrap();
+            stream
+                .write_all(b"GET / HTTP/1.1\r\nbad header\r\n\r\n")
+                .unwrap();
+            let mut bad_request = String::new();
+            stream.read_to_string(&mut bad_request).unwrap();
+            (ok, bad_request)
+        });
+        Server::<()>::serve_one(&mut server).unwrap();
+        Server::<()>::serve_one(&mut server).unwrap();
+        let (ok, bad_request) = client.join().unwrap();
+        assert!(ok.starts_with("HTTP/1.1 200 OK\r\n"));
+        assert!(ok.contains(&format!(
+            "\r\nServer: jbhttp::TcpServer/{} (hooked)\r\n",
+            VERSION
+        )));
+        assert!(ok.contains("\r\nX-Nonce: 200\r\n"));
+        assert!(ok.ends_with("\r\n\r\nHello!"));
+        assert!(bad_request.starts_with("HTTP/1.1 400 Bad Request\r\n"));
+        assert!(bad_request.contains("\r\nX-Nonce: 400\r\n"));
+    }
+
+    #[test]
+    fn test_serve_until_shutdown() {
+        let mut server = TcpServer::builder(hello)
+            .listener(TcpListener::bind("127.0.0.1:0").unwrap())
+            .threads(2)
+            .build()
+            .unwrap();
+        let addr = server.local_addr().unwrap();
+        let shutdown = server.shutdown_handle();
+        let client = std::thread::spawn(move || {
+            let responses = (get(addr), get(addr));
+            shutdown.shutdown();
+            responses
+        });
+        server.serve_until_shutdown::<()>().unwrap();
+        let (first, second) = client.join().unwrap();
+        assert!(first.ends_with("Hello!"));
+        assert!(second.ends_with("Hello!"));
+        assert_eq!(server.stats().requests(), 2);
+    }
+
+    #[test]
+    fn test_drain_period() {
+        let mut server = TcpServer::builder(hello)
+            .listener(TcpListener::bind("127.0.0.1:0").unwrap())
+            .drain_period(Duration::from_millis(500))
+            .retry_after(Duration::from_secs(10))
+            .build()
+            .unwrap();
+        let addr = server.local_addr().unwrap();
+        server.shutdown_handle().shutdown();
+        let client = std::thread::spawn(move || get(addr));
+        server.serve_until_shutdown::<()>().unwrap();
+        let response = client.join().unwrap();
+        assert!(response.starts_with("HTTP/1.1 503 Service Unavailable\r\n"));
+        assert!(response.contains("\r\nRetry-After: 10\r\n"));
+    }
+
+    #[test]
+    fn test_buffer_pool() {
+        let mut server = TcpServer::builder(hello)
+            .listener(TcpListener::bind("127.0.0.1:0").unwrap())
+            .buffer_pool(true)
+            .build()
+            .unwrap();
+        let addr = server.local_addr().unwrap();
+        let shutdown = server.shutdown_handle();
+        let client = std::thread::spawn(move || {
+            let responses: Vec<String> = (0..3).map(|_| get(addr)).collect();
+            shutdown.shutdown();
+            responses
+        });
+        server.serve_until_shutdown::<()>().unwrap();
+        for response in client.join().unwrap() {
+            assert!(response.starts_with("HTTP/1.1 200 OK\r\n"));
+            assert!(response.ends_with("\r\n\r\nHello!"));
+        }
+    }
+
+    #[test]
+    fn test_timeouts() {
+        let timeouts = Timeouts {
+            idle_keepalive: Some(Duration::from_millis(100)),
+            header: Some(Duration::from_millis(100)),
+            ..Default::default()
+        };
+        let mut server = TcpServer::builder(hello)
+            .listener(TcpListener::bind("127.0.0.1:0").unwrap())
+            .threads(2)
+            .timeout(timeouts)
+            .build()
+            .unwrap();
+        let addr = server.local_addr().unwrap();
+        let shutdown = server.shutdown_handle();
+        let client = std::thread::spawn(move || {
+            // Idle, closed without a response
+            let mut idle = TcpStream::connect(addr).unwrap();
+            let mut idle_response = String::new();
+            idle.read_to_string(&mut idle_response).unwrap();
+            // Slow headers
+            let mut slow = TcpStream::connect(addr).unwrap();
+            slow.write_all(b"GET / HTTP/1.1\r\n").unwrap();
+            let mut slow_response = String::new();
+            slow.read_to_string(&mut slow_response).unwrap();
+            shutdown.shutdown();
+            (idle_response, slow_response)
+        });
+        server.serve_until_shutdown::<()>().unwrap();
+        let (idle, slow) = client.join().unwrap();
+        assert_eq!(idle, "");
+        assert!(slow.starts_with("HTTP/1.1 408 Request Timeout\r\n"));
+    }
+
+    #[test]
+    fn test_keep_alive_idle_default() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let mut server = TcpServer::from_listener(listener, hello);
+        let addr = server.local_addr().unwrap();
+        let client = std::thread::spawn(move || {
+            let mut idle = TcpStream::connect(addr).unwrap();
+            idle.write_all(b"GET / HTTP/1.1\r\n\r\n").unwrap();
+            let mut buf = [0; 1024];
+            let mut idle_response = vec![];
+            while !idle_response.ends_with(b"Hello!") {
+                let n = idle.read(&mut buf).unwrap();
+                idle_response.extend(&buf[..n]);
+            }
+            // Waits for the idle connection to be closed
+            let start = Instant::now();
+            let response = get(addr);
+            let waited = start.elapsed();
+            let mut rest = vec![];
+            idle.read_to_end(&mut rest).unwrap();
+            (
+                String::from_utf8(idle_response).unwrap(),
+                waited,
+                response,
+                rest,
+            )
+        });
+        // A single thread, without timeouts
+        Server::<()>::serve_one(&mut server).unwrap();
+        Server::<()>::serve_one(&mut server).unwrap();
+        let (idle, waited, response, rest) = client.join().unwrap();
+        assert!(idle.contains("\r\nConnection: keep-alive\r\n"));
+        assert!(waited < DEFAULT_IDLE_KEEPALIVE * 2);
+        assert!(response.starts_with("HTTP/1.1 200 OK\r\n"));
+        assert!(rest.is_empty());
+    }
+
+    #[test]
+    fn test_timeouts_from_duration() {
+        let timeouts = Timeouts::from(Duration::from_secs(1));
+        assert_eq!(timeouts.header, Some(Duration::from_secs(1)));
+        assert_eq!(timeouts.write, Some(Duration::from_secs(1)));
+        assert_eq!(Timeouts::from(None), Timeouts::default());
+    }
+
+    #[test]
+    fn test_handler_deadline() {
+        fn deadline(request: RawRequest, _: &mut ()) -> RawResult {
+            let status = if request.deadline().is_some() {
+                200
+            } else {
+                500
             };
-            let (variant, response) = match response {
-                Ok(response) => ("Ok".to_string(), response),
-                Err(response) => ("Err".to_string(), response),
-            };
-            let response = response
-                .with_header("Server", &format!("jbhttp::TcpServer/{}", VERSION))
-                .with_header("Connection", "closed");
-            trace!("CONTEXT: {:?}", &context);
-            trace!("RESPONSE: {:?}", &response);
-            info!(
-                "{:?} - {}ms - {} {} {} ({} bytes) -> {} {} {} ({} bytes)",
-                std::thread::current().id(),
-                start.elapsed().as_millis(),
-                addr,
-                method,
-                path,
-                content_length,
-                variant,
-                response.status_code,
-                &response.status,
-                response.content_length(),
-            );
-            debug!("writing response");
-            match stream.write_all(&response.into_bytes()) {
-                Ok(_) => (),
-                Err(e) => error!("IO error: {}", e),
+            Ok(Response::new(status))
+        }
+        let mut server = TcpServer::builder(deadline)
+            .listener(TcpListener::bind("127.0.0.1:0").unwrap())
+            .timeout(Timeouts {
+                handler: Some(Duration::from_secs(5)),
+                ..Default::default()
+            })
+            .build()
+            .unwrap();
+        let addr = server.local_addr().unwrap();
+        let client = std::thread::spawn(move || get(addr));
+        Server::<()>::serve_one(&mut server).unwrap();
+        assert!(client.join().unwrap().starts_with("HTTP/1.1 200 OK\r\n"));
+    }
+
+    /// An in-memory connection; the output is shared so it can be checked
+    /// after the connection was served on a worker thread.
+    struct MockConnection {
+        input: io::Cursor<Vec<u8>>,
+        output: Arc<Mutex<Vec<u8>>>,
+    }
+
+    impl Read for MockConnection {
+        fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+            self.input.read(buf)
+        }
+    }
+
+    impl Write for MockConnection {
+        fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+            self.output.lock().unwrap().write(buf)
+        }
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    impl Connection for MockConnection {
+        fn set_read_timeout(&self, _: Option<Duration>) -> io::Result<()> {
+            Ok(())
+        }
+        fn set_write_timeout(&self, _: Option<Duration>) -> io::Result<()> {
+            Ok(())
+        }
+        fn set_nonblocking(&self, _: bool) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    /// Yields scripted connections, then would block.
+    struct MockListener {
+        connections: Arc<Mutex<VecDeque<MockConnection>>>,
+    }
+
+    impl Accept for MockListener {
+        type Connection = MockConnection;
+        fn accept(&self) -> io::Result<(MockConnection, SocketAddr)> {
+            match self.connections.lock().unwrap().pop_front() {
+                Some(connection) => Ok((connection, self.local_addr()?)),
+                None => Err(io::ErrorKind::WouldBlock.into()),
             }
+        }
+        fn local_addr(&self) -> io::Result<SocketAddr> {
+            Ok(SocketAddr::from(([127, 0, 0, 1], 0)))
+        }
+        fn set_nonblocking(&self, _: bool) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn test_accept_mock() {
+        let requests: [&[u8]; 3] = [
+            b"GET / HTTP/1.1\r\n\r\n",
+            b"HEAD / HTTP/1.0\r\n\r\n",
+            b"garbage\r\n\r\n",
+        ];
+        let outputs: Vec<Arc<Mutex<Vec<u8>>>> = requests.iter().map(|_| Arc::default()).collect();
+        let connections: VecDeque<MockConnection> = requests
+            .iter()
+            .zip(&outputs)
+            .map(|(input, output)| MockConnection {
+                input: io::Cursor::new(input.to_vec()),
+                output: output.clone(),
+            })
+            .collect();
+        let connections = Arc::new(Mutex::new(connections));
+        let listener = MockListener {
+            connections: connections.clone(),
+        };
+        let mut server = TcpServer::builder(hello).threads(2).build_with(listener);
+        let shutdown = server.shutdown_handle();
+        let stopper = std::thread::spawn(move || {
+            while !connections.lock().unwrap().is_empty() {
+                std::thread::sleep(Duration::from_millis(10));
+            }
+            shutdown.shutdown();
         });
-        Ok(())
+        server.serve_until_shutdown::<()>().unwrap();
+        stopper.join().unwrap();
+
+        let outputs: Vec<String> = outputs
+            .iter()
+            .map(|output| String::from_utf8(output.lock().unwrap().clone()).unwrap())
+            .collect();
+        assert!(outputs[0].starts_with("HTTP/1.1 200 OK\r\n"));
+        assert!(outputs[0].ends_with("\r\n\r\nHello!"));
+        assert!(outputs[1].starts_with("HTTP/1.0 200 OK\r\n"));
+        assert!(outputs[1].ends_with("Content-Length: 6\r\n\r\n"));
+        assert!(outputs[2].starts_with("HTTP/1.1 400 Bad Request\r\n"));
+        assert_eq!(server.stats().connections(), 3);
     }
 }
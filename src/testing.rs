@@ -0,0 +1,174 @@
+//! Helpers for testing handlers, with the `test-util` feature.
+use std::io::prelude::*;
+
+use crate::handler::Handler;
+use crate::request::RawRequest;
+
+/// The outcome of a request made with [`request_accepting`].
+#[derive(Debug, Clone, PartialEq)]
+pub struct Negotiated {
+    pub status_code: u16,
+    pub content_type: Option<String>,
+    /// The payload, or the streamed body read to the end.
+    pub body: Vec<u8>,
+}
+
+impl Negotiated {
+    /// The body as text, replacing invalid UTF-8.
+    pub fn text(&self) -> String {
+        String::from_utf8_lossy(&self.body).into_owned()
+    }
+}
+
+/// Make a GET request to a handler, with an *Accept* header if given, and
+/// get the resulting status, *Content-Type* and body, whether the response
+/// is `Ok` or `Err`.
+pub fn request_accepting<H, C>(handler: &H, accept: Option<&str>) -> Negotiated
+where
+    H: Handler<Vec<u8>, Vec<u8>, Vec<u8>, C>,
+    C: Default,
+{
+    let mut request = RawRequest::default();
+    if let Some(accept) = accept {
+        request = request.with_header("Accept", accept);
+    }
+    let mut response = match handler.handle(request, &mut C::default()) {
+        Ok(response) => response,
+        Err(response) => response,
+    };
+    let body = match response.take_stream() {
+        Some(mut stream) => {
+            let mut body = vec![];
+            stream
+                .read_to_end(&mut body)
+                .expect("error reading streamed body");
+            body
+        }
+        None => response.payload.take().unwrap_or_default(),
+    };
+    Negotiated {
+        status_code: response.status_code,
+        content_type: response.header("Content-Type").map(|s| s.to_string()),
+        body,
+    }
+}
+
+/// Assert that a request with the given *Accept* header gets a successful
+/// response with the expected media type (ignoring parameters like
+/// `charset`), and return it.
+///
+/// # Example
+/// ```
+/// use jbhttp::prelude::*;
+/// use jbhttp::testing::{assert_negotiates, assert_not_acceptable};
+///
+/// struct Greeting;
+///
+/// impl Serialize<TextHtml> for Greeting {
+///     fn serialize(self) -> Result<Vec<u8>, SerializationError> {
+///         Ok(b"<p>Hello!</p>".to_vec())
+///     }
+/// }
+///
+/// impl Serialize<TextPlain> for Greeting {
+///     fn serialize(self) -> Result<Vec<u8>, SerializationError> {
+///         Ok(b"Hello!".to_vec())
+///     }
+/// }
+///
+/// fn hello(_: RawRequest, _: &mut ()) -> Res<Greeting, Vec<u8>> {
+///     Ok(Response::new(200).with_payload(Greeting))
+/// }
+///
+/// let handler = hello
+///     .serialized()
+///     .with_media_type::<TextHtml>()
+///     .with_media_type::<TextPlain>();
+///
+/// let html = assert_negotiates(&handler, "*/*", "text/html");
+/// assert_eq!(html.text(), "<p>Hello!</p>");
+/// assert_negotiates(&handler, "text/html;q=0.5, text/plain", "text/plain");
+/// assert_not_acceptable(&handler, "application/json");
+/// ```
+pub fn assert_negotiates<H, C>(handler: &H, accept: &str, expected: &str) -> Negotiated
+where
+    H: Handler<Vec<u8>, Vec<u8>, Vec<u8>, C>,
+    C: Default,
+{
+    let negotiated = request_accepting(handler, Some(accept));
+    assert!(
+        (200..300).contains(&negotiated.status_code),
+        "Accept: {}\nexpected {}, got status {}",
+        accept,
+        expected,
+        negotiated.status_code
+    );
+    let media_type = negotiated
+        .content_type
+        .as_deref()
+        .map(|ct| ct.split(';').next().unwrap_or("").trim());
+    assert_eq!(
+        media_type,
+        Some(expected),
+        "Accept: {}\nexpected {}, got Content-Type {:?}",
+        accept,
+        expected,
+        negotiated.content_type
+    );
+    negotiated
+}
+
+/// Assert that a request with the given *Accept* header gets 406 Not
+/// Acceptable.
+pub fn assert_not_acceptable<H, C>(handler: &H, accept: &str)
+where
+    H: Handler<Vec<u8>, Vec<u8>, Vec<u8>, C>,
+    C: Default,
+{
+    let negotiated = request_accepting(handler, Some(accept));
+    assert_eq!(
+        negotiated.status_code, 406,
+        "Accept: {}\nexpected 406, got status {} with Content-Type {:?}",
+        accept, negotiated.status_code, negotiated.content_type
+    );
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::handler::RawResult;
+    use crate::response::{BodyStream, Response};
+
+    #[test]
+    fn test_request_accepting() {
+        fn handle(request: RawRequest, _: &mut ()) -> RawResult {
+            match request.accept().unwrap() {
+                Some(_) => Ok(Response::new(200)
+                    .with_header("Content-Type", "text/plain; charset=utf-8")
+                    .with_stream(BodyStream::from_chunks(
+                        vec![b"a".to_vec(), b"b".to_vec()].into_iter(),
+                    ))),
+                None => Err(Response::new(400).with_payload(b"no accept".to_vec())),
+            }
+        }
+        let negotiated = assert_negotiates(&handle, "*/*", "text/plain");
+        assert_eq!(negotiated.text(), "ab");
+        assert_eq!(
+            request_accepting(&handle, None),
+            Negotiated {
+                status_code: 400,
+                content_type: None,
+                body: b"no accept".to_vec(),
+            }
+        );
+    }
+
+    #[test]
+    #[should_panic(expected = "expected text/html")]
+    fn test_assert_negotiates_fails() {
+        fn handle(_: RawRequest, _: &mut ()) -> RawResult {
+            Ok(Response::new(200).with_header("Content-Type", "text/plain"))
+        }
+        assert_negotiates(&handle, "text/html", "text/html");
+    }
+}